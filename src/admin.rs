@@ -0,0 +1,142 @@
+use std::sync::LazyLock;
+
+use axum::{
+    extract::Query,
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+
+use crate::{
+    cameras, load, player_api,
+    render_cache::RENDER_CACHE,
+    render_worker::{self, Maintenance},
+    settings, support_bundle,
+};
+
+/// `ADMIN_TOKEN`: bearer token for the admin api, which is disabled if unset
+pub static ADMIN_TOKEN: LazyLock<Option<String>> =
+    LazyLock::new(|| std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()));
+
+async fn require_admin<B>(req: Request<B>, next: Next<B>) -> Response {
+    let Some(token) = ADMIN_TOKEN.as_ref() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let authorized = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| v == token);
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(req).await
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerQuery {
+    player_name: String,
+}
+
+async fn flush_player(Query(query): Query<PlayerQuery>) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "flushed": player_api::flush(&query.player_name),
+    }))
+}
+
+async fn flush_players() -> impl IntoResponse {
+    Json(serde_json::json!({ "flushed": player_api::flush_all() }))
+}
+
+async fn flush_renders() -> impl IntoResponse {
+    Json(serde_json::json!({ "flushed": RENDER_CACHE.evict(|_| true) }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SkinQuery {
+    skin_name: String,
+}
+
+/// Removes the renders of the skin and unloads it, so the next render loads it again.
+async fn evict_skin(Query(query): Query<SkinQuery>) -> Response {
+    let renders = RENDER_CACHE.evict_param("skin_name", &query.skin_name);
+    match render_worker::maintain(Maintenance::UnloadSkins).await {
+        Ok(_) => Json(serde_json::json!({ "renders": renders })).into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MapQuery {
+    map_name: Option<String>,
+}
+
+/// Unloads the map and removes its renders, all maps without `map_name`.
+async fn evict_map(Query(query): Query<MapQuery>) -> Response {
+    let renders = match &query.map_name {
+        Some(map_name) => RENDER_CACHE.evict_param("map_name", map_name),
+        None => RENDER_CACHE.evict(|_| true),
+    };
+    match render_worker::maintain(Maintenance::EvictMaps(query.map_name)).await {
+        Ok(renderers) => {
+            // every renderer loads its own maps
+            let maps: u64 = renderers
+                .iter()
+                .filter_map(|renderer| renderer["evicted"].as_u64())
+                .sum();
+            Json(serde_json::json!({ "renders": renders, "maps": maps })).into_response()
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+/// Recreates the client between renders, cached renders may show the old containers.
+async fn reload_containers() -> Response {
+    match render_worker::maintain(Maintenance::ReloadContainers).await {
+        Ok(_) => {
+            Json(serde_json::json!({ "renders": RENDER_CACHE.evict(|_| true) })).into_response()
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+async fn cache_stats() -> Response {
+    let (entries, bytes, max_bytes) = RENDER_CACHE.stats();
+    match render_worker::maintain(Maintenance::Stats).await {
+        Ok(mut renderers) => {
+            // a pool lists the maps of every renderer
+            let maps = match renderers.len() {
+                1 => renderers.remove(0),
+                _ => serde_json::Value::from(renderers),
+            };
+            Json(serde_json::json!({
+                "renders": { "entries": entries, "bytes": bytes, "max_bytes": max_bytes },
+                "player_api": { "entries": player_api::cache_len() },
+                "maps": maps,
+            }))
+            .into_response()
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+/// All routes require the `ADMIN_TOKEN` as bearer token.
+pub fn router() -> Router {
+    Router::new()
+        .route("/player_cache/flush", post(flush_player))
+        .route("/caches", get(cache_stats))
+        .route("/caches/players/flush", post(flush_players))
+        .route("/caches/renders/flush", post(flush_renders))
+        .route("/skins/evict", post(evict_skin))
+        .route("/maps/evict", post(evict_map))
+        .route("/containers/reload", post(reload_containers))
+        .route("/cameras/reload", post(cameras::reload_cameras))
+        .route("/load/reload", post(load::reload_thresholds))
+        .route("/config", get(settings::config))
+        .route("/support-bundle", get(support_bundle::support_bundle))
+        .layer(middleware::from_fn(require_admin))
+}
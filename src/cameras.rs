@@ -0,0 +1,100 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+
+/// The default camera of a map, in tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MapCamera {
+    pub x: f32,
+    pub y: f32,
+    pub zoom: Option<f32>,
+}
+
+/// Where the camera position of a render comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraSource {
+    /// `x` or `y` was given
+    Query,
+    /// The map has an entry in the cameras file
+    File,
+    /// The center of the map, known once the map was rendered
+    MapCenter,
+}
+
+impl CameraSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            CameraSource::Query => "query",
+            CameraSource::File => "file",
+            CameraSource::MapCenter => "map_center",
+        }
+    }
+}
+
+fn builtin() -> BTreeMap<String, MapCamera> {
+    BTreeMap::from([(
+        "ctf1".to_string(),
+        MapCamera {
+            x: 173.12,
+            y: 688.96,
+            zoom: None,
+        },
+    )])
+}
+
+fn load() -> anyhow::Result<BTreeMap<String, MapCamera>> {
+    let mut cameras = builtin();
+    let path = std::env::var("CAMERAS_FILE").unwrap_or_else(|_| "cameras.json".to_string());
+    match std::fs::read_to_string(&path) {
+        Ok(file) => {
+            let file_cameras: BTreeMap<String, MapCamera> = serde_json::from_str(&file)
+                .map_err(|err| anyhow::anyhow!("cameras file {path} is invalid: {err}"))?;
+            cameras.extend(file_cameras);
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(anyhow::anyhow!("cameras file {path} can't be read: {err}")),
+    }
+    Ok(cameras)
+}
+
+/// Default cameras of maps without `x` and `y`, other maps are centered.
+/// `CAMERAS_FILE` json file of map name to camera, e.g. `{"ctf1": {"x": 173.12, "y": 688.96, "zoom": 0.5}}`,
+/// that replace the built-in ones (`cameras.json` is default)
+static CAMERAS: LazyLock<parking_lot::RwLock<BTreeMap<String, MapCamera>>> = LazyLock::new(|| {
+    parking_lot::RwLock::new(load().unwrap_or_else(|err| {
+        println!("{err}");
+        builtin()
+    }))
+});
+
+/// The default camera of the map, if it has one.
+pub fn lookup(map_name: &str) -> Option<MapCamera> {
+    CAMERAS.read().get(map_name).copied()
+}
+
+/// Loads the cameras file again, an invalid file keeps the current cameras.
+pub fn reload() -> anyhow::Result<usize> {
+    let cameras = load()?;
+    let count = cameras.len();
+    *CAMERAS.write() = cameras;
+    Ok(count)
+}
+
+/// `POST /admin/cameras/reload`
+pub async fn reload_cameras() -> impl IntoResponse {
+    match tokio::task::spawn_blocking(reload).await {
+        Ok(Ok(count)) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "cameras": count })),
+        ),
+        Ok(Err(err)) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": err.to_string() })),
+        ),
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({ "error": err.to_string() })),
+        ),
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use clap::{Args, Parser, Subcommand};
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    frame,
+    params::{self, OutputFormat, RenderParams},
+    render_worker::Priority,
+    signed_url::{DEFAULT_KEY_ID, SIGNING},
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Renders Tees and maps over http and discord")]
+pub struct Cli {
+    /// The config file, `DDPG_CONFIG` or `config.toml` if not given
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// `serve` if none is given
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Runs the http server and, if `DISCORD_TOKEN` is set, the discord bot
+    Serve,
+    /// Renders one image into a file and exits, non-zero if the render failed
+    Render(RenderArgs),
+    /// Renders the JSON lines of stdin one after another and writes a JSON line
+    /// per render to stdout, non-zero if any render failed
+    Batch,
+    /// Prints the query of a signed url with a key of `SIGNING_KEYS`
+    Sign(SignArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct RenderArgs {
+    /// Where the image is written
+    #[arg(long, short)]
+    pub out: PathBuf,
+    /// Render parameters like the http query, e.g. `--skin default --weapon hammer`.
+    /// Dashes in names are underscores, `--flag` without value is `true`
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "PARAMS"
+    )]
+    pub params: Vec<String>,
+}
+
+impl RenderArgs {
+    /// The parameters as query pairs, so they are parsed like http requests.
+    pub fn pairs(&self) -> anyhow::Result<Vec<(String, String)>> {
+        param_pairs(&self.params)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SignArgs {
+    /// The key id, the key must be in `SIGNING_KEYS`
+    #[arg(long, default_value = DEFAULT_KEY_ID)]
+    pub kid: String,
+    /// How long the url stays valid
+    #[arg(long, default_value_t = 3600)]
+    pub expires_in_secs: u64,
+    /// Render parameters like for `render`, e.g. `--skin default`
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "PARAMS"
+    )]
+    pub params: Vec<String>,
+}
+
+impl SignArgs {
+    /// The query of the signed url, without the leading `?`.
+    pub fn signed_query(&self) -> anyhow::Result<String> {
+        let query = serde_urlencoded::to_string(param_pairs(&self.params)?)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        SIGNING.sign_query(&self.kid, &query, now.as_secs() + self.expires_in_secs)
+    }
+}
+
+/// `--name value`, `--name=value` and `--flag` arguments as query pairs.
+fn param_pairs(params: &[String]) -> anyhow::Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut args = params.iter().peekable();
+    while let Some(arg) = args.next() {
+        let name = arg
+            .strip_prefix("--")
+            .ok_or_else(|| anyhow!("expected a parameter like --skin, got {arg}"))?;
+        let (name, value) = match name.split_once('=') {
+            Some((name, value)) => (name, value.to_string()),
+            None => match args.next_if(|value| !value.starts_with("--")) {
+                Some(value) => (name, value.clone()),
+                None => (name, "true".to_string()),
+            },
+        };
+        pairs.push((name.replace('-', "_"), value));
+    }
+    Ok(pairs)
+}
+
+/// Renders the params like an http request would and writes the image to `out`.
+/// Returns the warnings of the render.
+pub async fn render_to_file(
+    pairs: Vec<(String, String)>,
+    out: &Path,
+) -> anyhow::Result<Vec<String>> {
+    let (params, deprecated) = RenderParams::from_pairs(pairs)?;
+    let mut preview =
+        crate::render_parsed(params, deprecated, Priority::High, Instant::now()).await?;
+    // raw pixels are rendered as pam, like for http the size is not part of the file
+    if preview.format == OutputFormat::Raw {
+        if let Some((_, _, offset)) = frame::split_pam(&preview.img) {
+            preview.img.drain(..offset);
+        }
+    }
+    tokio::fs::write(out, &preview.img)
+        .await
+        .map_err(|err| anyhow!("writing {} failed: {err}", out.display()))?;
+    Ok(preview.warnings)
+}
+
+/// A line of the batch input, the parameters of a POST request with the path of the image.
+#[derive(Deserialize)]
+struct BatchRequest {
+    output: PathBuf,
+    #[serde(flatten)]
+    params: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Renders a line of the batch input, `output` is set once the line was parsed.
+async fn batch_item(line: &str, output: &mut Option<PathBuf>) -> anyhow::Result<Vec<String>> {
+    let request: BatchRequest = serde_json::from_str(line)?;
+    *output = Some(request.output.clone());
+    let pairs = params::json_pairs(request.params)?;
+    render_to_file(pairs, &request.output).await
+}
+
+/// Renders every line of stdin, e.g. `{"output": "avatars/nameless.png", "preset": "avatar", "skin": "nameless tee"}`.
+/// Every line gets a result line on stdout as soon as it is done, failures don't stop the batch.
+/// Returns the number of lines that failed.
+pub async fn batch() -> usize {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut failures = 0;
+    let mut line_number = 0;
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                eprintln!("reading stdin failed: {err}");
+                failures += 1;
+                break;
+            }
+        };
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let start = Instant::now();
+        let mut output = None;
+        let result = batch_item(&line, &mut output).await;
+        let ms = start.elapsed().as_millis() as u64;
+        let record = match result {
+            Ok(warnings) => serde_json::json!({
+                "line": line_number,
+                "ok": true,
+                "output": output,
+                "ms": ms,
+                "warnings": warnings,
+            }),
+            Err(err) => {
+                failures += 1;
+                serde_json::json!({
+                    "line": line_number,
+                    "ok": false,
+                    "output": output,
+                    "ms": ms,
+                    "error": err.to_string(),
+                })
+            }
+        };
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{record}");
+        let _ = stdout.flush();
+    }
+    failures
+}
@@ -0,0 +1,166 @@
+use client_render_base::render::tee::TeeRenderSkinColor;
+use graphics_types::rendering::ColorRgba;
+use palette::convert::FromColorUnclamped;
+
+use crate::params::{self, TeeColor};
+
+/// The parts of a Tee that can be colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeePart {
+    Body,
+    Feet,
+}
+
+impl TeePart {
+    /// The lightness a lightness of 0 is raised to, like `DARKEST_LGT` of the game.
+    /// The game uses the same floor for 0.6 body and feet colors.
+    pub fn darkest(self) -> f64 {
+        match self {
+            TeePart::Body => 0.5,
+            TeePart::Feet => 0.5,
+        }
+    }
+}
+
+/// The hsl colors of the game are relative to linear rgb.
+type LinHsl = palette::Hsl<palette::encoding::Linear<palette::encoding::Srgb>, f64>;
+
+fn hsl_to_linear(mut hsl: LinHsl, part: TeePart, a: f32) -> ColorRgba {
+    let darkest = part.darkest();
+    hsl.lightness = darkest + hsl.lightness * (1.0 - darkest);
+
+    let rgb = palette::rgb::LinSrgb::from_color_unclamped(hsl);
+    ColorRgba {
+        r: rgb.red as f32,
+        g: rgb.green as f32,
+        b: rgb.blue as f32,
+        a,
+    }
+}
+
+/// The packed hsl color of the game, darkened like in the game.
+fn legacy_color_to_linear(color: i32, part: TeePart) -> ColorRgba {
+    let h = ((color >> 16) & 0xFF) as f64 / 255.0;
+    let s = ((color >> 8) & 0xFF) as f64 / 255.0;
+    let l = ((color) & 0xFF) as f64 / 255.0;
+    let hsl = LinHsl::new_const((h * 360.0).into(), s, l);
+    hsl_to_linear(hsl, part, params::color_alpha(color))
+}
+
+/// The linear color a part is rendered with.
+/// Rgb colors are only darkened if `ddnet_color_clamp` is set.
+fn linear_color(color: TeeColor, part: TeePart, ddnet_color_clamp: bool) -> ColorRgba {
+    match color {
+        TeeColor::Legacy(color) => legacy_color_to_linear(color, part),
+        TeeColor::Rgba([r, g, b, a]) => {
+            let rgb = palette::Srgb::new(r, g, b)
+                .into_format::<f64>()
+                .into_linear();
+            if ddnet_color_clamp {
+                hsl_to_linear(
+                    LinHsl::from_color_unclamped(rgb),
+                    part,
+                    params::rgba_alpha(a),
+                )
+            } else {
+                ColorRgba {
+                    r: rgb.red as f32,
+                    g: rgb.green as f32,
+                    b: rgb.blue as f32,
+                    a: params::rgba_alpha(a),
+                }
+            }
+        }
+    }
+}
+
+pub fn render_color(color: TeeColor, part: TeePart, ddnet_color_clamp: bool) -> TeeRenderSkinColor {
+    TeeRenderSkinColor::Colorable(linear_color(color, part, ddnet_color_clamp))
+}
+
+/// The color a part is rendered with as `#rrggbbaa`.
+pub fn render_color_hex(color: TeeColor, part: TeePart, ddnet_color_clamp: bool) -> String {
+    let color = linear_color(color, part, ddnet_color_clamp);
+    let rgb = palette::rgb::LinSrgb::new(color.r, color.g, color.b);
+    let rgb = palette::Srgb::<f32>::from_linear(rgb).into_format::<u8>();
+    let a = (color.a.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!("#{}", hex::encode([rgb.red, rgb.green, rgb.blue, a]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(color: TeeColor, ddnet_color_clamp: bool) -> String {
+        render_color_hex(color, TeePart::Body, ddnet_color_clamp)
+    }
+
+    #[test]
+    fn rgba_colors_round_trip_unclamped() {
+        for rgba in [
+            [0, 0, 0, 255],
+            [255, 255, 255, 255],
+            [12, 200, 99, 255],
+            [1, 2, 3, 128],
+            [254, 1, 128, 26],
+        ] {
+            assert_eq!(
+                hex(TeeColor::Rgba(rgba), false),
+                format!("#{}", hex::encode(rgba)),
+                "{rgba:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn too_transparent_colors_are_raised() {
+        assert_eq!(hex(TeeColor::Rgba([10, 20, 30, 0]), false), "#0a141e1a");
+        assert_eq!(hex(TeeColor::Legacy(0x0100_00ff), false), "#ffffff1a");
+    }
+
+    #[test]
+    fn legacy_colors_are_never_darker_than_the_floor() {
+        // black is raised to the darkest lightness, a mid gray
+        let darkest = hex(TeeColor::Legacy(0), false);
+        assert_eq!(darkest, hex(TeeColor::Legacy(0x00ff_0000), false));
+        assert_eq!(darkest, hex(TeeColor::Rgba([0, 0, 0, 255]), true));
+        assert_ne!(darkest, "#000000ff");
+        assert_eq!(hex(TeeColor::Legacy(0xff), false), "#ffffffff");
+    }
+
+    #[test]
+    fn legacy_hues_cover_the_circle() {
+        // full saturation at the darkest lightness is the pure hue
+        assert_eq!(hex(TeeColor::Legacy(0x00_ff00), false), "#ff0000ff");
+        assert_eq!(hex(TeeColor::Legacy(0x55_ff00), false), "#00ff00ff");
+        assert_eq!(hex(TeeColor::Legacy(0xaa_ff00), false), "#0000ffff");
+        // the last hue wraps around to red
+        assert_eq!(hex(TeeColor::Legacy(0xff_ff00), false), "#ff0000ff");
+    }
+
+    #[test]
+    fn clamped_rgba_colors_match_their_legacy_color() {
+        for (rgba, legacy) in [([255, 255, 255, 255], 0xff), ([0, 0, 0, 255], 0x00_0000)] {
+            assert_eq!(
+                hex(TeeColor::Rgba(rgba), true),
+                hex(TeeColor::Legacy(legacy), false),
+                "{rgba:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn clamped_rgba_colors_are_lightened() {
+        assert_eq!(hex(TeeColor::Rgba([255, 0, 0, 255]), false), "#ff0000ff");
+        assert_eq!(hex(TeeColor::Rgba([255, 0, 0, 255]), true), "#ffbcbcff");
+    }
+
+    #[test]
+    fn every_part_has_the_same_floor() {
+        let color = TeeColor::Legacy(0x12_3400);
+        assert_eq!(
+            render_color_hex(color, TeePart::Body, false),
+            render_color_hex(color, TeePart::Feet, false)
+        );
+    }
+}
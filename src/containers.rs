@@ -0,0 +1,397 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+use crate::params::AssetKeys;
+
+/// The containers that load entries by name, beside their default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ContainerKind {
+    Skins,
+    Weapons,
+    Hooks,
+    Emoticons,
+    Entities,
+}
+
+pub const CONTAINER_KINDS: [ContainerKind; 5] = [
+    ContainerKind::Skins,
+    ContainerKind::Weapons,
+    ContainerKind::Hooks,
+    ContainerKind::Emoticons,
+    ContainerKind::Entities,
+];
+
+pub fn container_name(kind: ContainerKind) -> &'static str {
+    match kind {
+        ContainerKind::Skins => "skins",
+        ContainerKind::Weapons => "weapons",
+        ContainerKind::Hooks => "hooks",
+        ContainerKind::Emoticons => "emoticons",
+        ContainerKind::Entities => "entities",
+    }
+}
+
+/// - `CONTAINER_KEEP_ALIVE_SECS` how long every container keeps an unused entry loaded (5 is default)
+/// - `CONTAINER_MAX_ENTRIES` loaded entries of all containers together, defaults don't count.
+///   Above it the least recently used entries are unloaded right after the render (64 is default).
+///   The containers don't report their memory, so the budget is in entries
+pub struct ContainerConfig {
+    pub keep_alive: Duration,
+    pub max_entries: usize,
+}
+
+pub static CONTAINERS: LazyLock<ContainerConfig> = LazyLock::new(|| {
+    let keep_alive: u64 = std::env::var("CONTAINER_KEEP_ALIVE_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(5);
+    let max_entries: usize = std::env::var("CONTAINER_MAX_ENTRIES")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(64);
+    ContainerConfig {
+        keep_alive: Duration::from_secs(keep_alive),
+        max_entries: max_entries.max(1),
+    }
+});
+
+/// How often a loading entry is checked while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Checks `loaded` until it reports the entry as loaded or the deadline passed,
+/// returns whether it was loaded in time. The containers only wait without a timeout,
+/// so a stalled load can't hold the render past the deadline this way.
+pub fn wait_loaded(deadline: Instant, mut loaded: impl FnMut() -> bool) -> bool {
+    loop {
+        if loaded() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        std::thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+/// The containers the assets of a render are loaded from, see [`wait_assets`].
+pub trait AssetContainers {
+    /// Starts loading the entry, invalid names are ignored.
+    fn request(&mut self, kind: ContainerKind, name: &str);
+    /// Whether the entry is loaded, invalid names and the default count as loaded.
+    fn loaded(&mut self, kind: ContainerKind, name: &str) -> bool;
+}
+
+/// Requests all assets the render uses first, so they load concurrently,
+/// then waits for each until the deadline. Assets that are not loaded
+/// in time are rendered with their default, which is returned as warnings.
+pub fn wait_assets(
+    assets: &AssetKeys,
+    containers: &mut impl AssetContainers,
+    deadline: Instant,
+) -> Vec<String> {
+    let assets = [
+        (ContainerKind::Skins, "skin", &assets.skin),
+        (ContainerKind::Weapons, "weapon skin", &assets.weapon),
+        (ContainerKind::Hooks, "hook skin", &assets.hook),
+        (ContainerKind::Emoticons, "emoticon skin", &assets.emoticon),
+        (ContainerKind::Entities, "entities", &assets.entities),
+    ];
+    for (kind, _, name) in assets {
+        if let Some(name) = name {
+            containers.request(kind, name);
+        }
+    }
+    // every wait gets the time that is left, so all of them end by the deadline
+    let mut warnings = Vec::new();
+    for (kind, asset, name) in assets {
+        let Some(name) = name else {
+            continue;
+        };
+        if !wait_loaded(deadline, || containers.loaded(kind, name)) {
+            warnings.push(format!(
+                "{asset} {name} was not loaded in time, the default is used"
+            ));
+        }
+    }
+    warnings
+}
+
+/// When the renderer last used an entry, which mirrors
+/// what the containers have loaded.
+static USED: LazyLock<parking_lot::Mutex<HashMap<(ContainerKind, String), Instant>>> =
+    LazyLock::new(Default::default);
+
+/// Must be called by the renderer for every entry it uses.
+pub fn mark_used(kind: ContainerKind, name: &str) {
+    let now = Instant::now();
+    let mut used = USED.lock();
+    used.retain(|_, at| now.saturating_duration_since(*at) < CONTAINERS.keep_alive);
+    used.insert((kind, name.to_string()), now);
+}
+
+pub fn is_loaded(kind: ContainerKind, name: &str) -> bool {
+    USED.lock()
+        .get(&(kind, name.to_string()))
+        .is_some_and(|at| at.elapsed() < CONTAINERS.keep_alive)
+}
+
+/// Forgets the entries of a container, after it unloaded its unused entries.
+pub fn forget(kind: ContainerKind) {
+    USED.lock().retain(|(used, _), _| *used != kind);
+}
+
+/// Forgets every entry, after the containers were loaded again.
+pub fn forget_all() {
+    USED.lock().clear();
+}
+
+/// If the loaded entries exceed the budget, the entries of every container that stay loaded,
+/// the most recently used ones. The others are forgotten, since the caller unloads them.
+pub fn fit_budget() -> Option<BTreeMap<ContainerKind, Vec<String>>> {
+    let now = Instant::now();
+    let mut used = USED.lock();
+    used.retain(|_, at| now.saturating_duration_since(*at) < CONTAINERS.keep_alive);
+    if used.len() <= CONTAINERS.max_entries {
+        return None;
+    }
+    let mut entries: Vec<_> = used.iter().map(|(key, at)| (key.clone(), *at)).collect();
+    entries.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (key, _) in &entries[CONTAINERS.max_entries..] {
+        used.remove(key);
+    }
+    let mut keep: BTreeMap<ContainerKind, Vec<String>> = BTreeMap::new();
+    for ((kind, name), _) in entries.into_iter().take(CONTAINERS.max_entries) {
+        keep.entry(kind).or_default().push(name);
+    }
+    Some(keep)
+}
+
+/// `container_entries{container}` for every container.
+pub fn write_metrics(out: &mut String) {
+    let now = Instant::now();
+    let mut counts: BTreeMap<ContainerKind, usize> =
+        CONTAINER_KINDS.iter().map(|kind| (*kind, 0)).collect();
+    for ((kind, _), at) in USED.lock().iter() {
+        if now.saturating_duration_since(*at) < CONTAINERS.keep_alive {
+            *counts.entry(*kind).or_default() += 1;
+        }
+    }
+    let _ = writeln!(
+        out,
+        "# HELP container_entries Loaded entries of a container beside its default\n\
+        # TYPE container_entries gauge"
+    );
+    for (kind, count) in counts {
+        let _ = writeln!(
+            out,
+            "container_entries{{container=\"{}\"}} {count}",
+            container_name(kind)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counted(polls: &mut usize, loaded: bool) -> impl FnMut() -> bool + '_ {
+        move || {
+            *polls += 1;
+            loaded
+        }
+    }
+
+    #[test]
+    fn loaded_entries_do_not_wait() {
+        let mut polls = 0;
+        assert!(wait_loaded(
+            Instant::now() + Duration::from_secs(10),
+            counted(&mut polls, true)
+        ));
+        assert_eq!(polls, 1);
+    }
+
+    #[test]
+    fn loaded_entries_are_in_time_even_past_the_deadline() {
+        assert!(wait_loaded(Instant::now() - Duration::from_secs(1), || {
+            true
+        }));
+    }
+
+    #[test]
+    fn entries_are_polled_until_they_are_loaded() {
+        let mut polls = 0;
+        assert!(wait_loaded(
+            Instant::now() + Duration::from_secs(10),
+            || {
+                polls += 1;
+                polls == 3
+            }
+        ));
+        assert_eq!(polls, 3);
+    }
+
+    /// Sleeps are never shorter than asked for, so the polls are bounded
+    /// by the poll interval, however late the thread wakes up.
+    #[test]
+    fn a_stalled_load_ends_at_the_deadline() {
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(30);
+        let mut polls = 0;
+        assert!(!wait_loaded(deadline, counted(&mut polls, false)));
+        assert!(Instant::now() >= deadline);
+        let max_polls = (30 / POLL_INTERVAL.as_millis() + 1) as usize;
+        assert!((1..=max_polls).contains(&polls), "{polls}");
+    }
+
+    #[test]
+    fn a_past_deadline_checks_once() {
+        let mut polls = 0;
+        assert!(!wait_loaded(Instant::now(), counted(&mut polls, false)));
+        assert_eq!(polls, 1);
+    }
+
+    /// Every wait only gets the time that is left, so stalled loads
+    /// one after another still end by the deadline.
+    #[test]
+    fn waits_share_the_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(30);
+        assert!(!wait_loaded(deadline, || false));
+        for _ in 0..4 {
+            let mut polls = 0;
+            assert!(!wait_loaded(deadline, counted(&mut polls, false)));
+            assert_eq!(polls, 1);
+        }
+    }
+
+    /// Entries that finish loading a while after they were requested,
+    /// `None` never does.
+    #[derive(Default)]
+    struct MockContainers {
+        delays: HashMap<(ContainerKind, String), Option<Duration>>,
+        requested: HashMap<(ContainerKind, String), Instant>,
+        polls: HashMap<(ContainerKind, String), usize>,
+    }
+
+    impl MockContainers {
+        fn new(delays: &[(ContainerKind, &str, Option<u64>)]) -> Self {
+            Self {
+                delays: delays
+                    .iter()
+                    .map(|(kind, name, ms)| {
+                        ((*kind, name.to_string()), ms.map(Duration::from_millis))
+                    })
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        fn polls(&self, kind: ContainerKind, name: &str) -> usize {
+            self.polls
+                .get(&(kind, name.to_string()))
+                .copied()
+                .unwrap_or_default()
+        }
+    }
+
+    impl AssetContainers for MockContainers {
+        fn request(&mut self, kind: ContainerKind, name: &str) {
+            assert!(self.polls.is_empty(), "{name} was requested after a wait");
+            self.requested
+                .insert((kind, name.to_string()), Instant::now());
+        }
+
+        fn loaded(&mut self, kind: ContainerKind, name: &str) -> bool {
+            let key = (kind, name.to_string());
+            *self.polls.entry(key.clone()).or_default() += 1;
+            let requested = self.requested[&key];
+            match self.delays.get(&key) {
+                Some(Some(delay)) => requested.elapsed() >= *delay,
+                Some(None) => false,
+                // unknown entries are the default
+                None => true,
+            }
+        }
+    }
+
+    fn assets(skin: &str, weapon: Option<&str>, hook: Option<&str>) -> AssetKeys {
+        AssetKeys {
+            skin: Some(skin.to_string()),
+            weapon: weapon.map(str::to_string),
+            hook: hook.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn assets_that_load_in_time_have_no_warnings() {
+        let mut containers = MockContainers::new(&[
+            (ContainerKind::Skins, "santa", Some(5)),
+            (ContainerKind::Weapons, "pixel", Some(1)),
+            (ContainerKind::Hooks, "neon", Some(0)),
+        ]);
+        let assets = assets("santa", Some("pixel"), Some("neon"));
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert!(wait_assets(&assets, &mut containers, deadline).is_empty());
+        assert_eq!(containers.requested.len(), 3);
+    }
+
+    /// The other assets load while the render waits for a stalled one.
+    #[test]
+    fn only_stalled_assets_are_degraded() {
+        let mut containers = MockContainers::new(&[
+            (ContainerKind::Skins, "santa", None),
+            (ContainerKind::Weapons, "pixel", Some(5)),
+            (ContainerKind::Hooks, "neon", Some(0)),
+        ]);
+        let assets = assets("santa", Some("pixel"), Some("neon"));
+        let deadline = Instant::now() + Duration::from_millis(30);
+        let warnings = wait_assets(&assets, &mut containers, deadline);
+        assert_eq!(
+            warnings,
+            ["skin santa was not loaded in time, the default is used"]
+        );
+        assert_eq!(containers.polls(ContainerKind::Weapons, "pixel"), 1);
+        assert_eq!(containers.polls(ContainerKind::Hooks, "neon"), 1);
+    }
+
+    #[test]
+    fn stalled_assets_share_the_deadline() {
+        let mut containers = MockContainers::new(&[
+            (ContainerKind::Skins, "santa", None),
+            (ContainerKind::Weapons, "pixel", None),
+            (ContainerKind::Hooks, "neon", None),
+        ]);
+        let assets = assets("santa", Some("pixel"), Some("neon"));
+        let deadline = Instant::now() + Duration::from_millis(30);
+        let warnings = wait_assets(&assets, &mut containers, deadline);
+        assert_eq!(
+            warnings,
+            [
+                "skin santa was not loaded in time, the default is used",
+                "weapon skin pixel was not loaded in time, the default is used",
+                "hook skin neon was not loaded in time, the default is used",
+            ]
+        );
+        // the deadline passed while waiting for the skin
+        assert_eq!(containers.polls(ContainerKind::Weapons, "pixel"), 1);
+        assert_eq!(containers.polls(ContainerKind::Hooks, "neon"), 1);
+    }
+
+    #[test]
+    fn unset_assets_are_not_requested() {
+        let mut containers = MockContainers::default();
+        let assets = assets("default", None, None);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        assert!(wait_assets(&assets, &mut containers, deadline).is_empty());
+        assert_eq!(containers.requested.len(), 1);
+        assert_eq!(containers.polls(ContainerKind::Skins, "default"), 1);
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::LazyLock;
+
+use anyhow::anyhow;
+use axum::{
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+/// The headers of our responses that browsers hide from scripts otherwise.
+const EXPOSE_HEADERS: &str = "x-cache, x-render-time-ms, x-queue-wait-ms, x-image-width, \
+    x-image-height, x-skin-resolved, x-skin-suggestions, x-degraded, x-sheet-cell, x-sheet-grid, \
+    x-sheet-cells, x-sheet-padding, x-crop, x-map-size, x-markers-clamped, warning, deprecation, \
+    retry-after, etag";
+
+/// - `CORS_ORIGINS` comma separated origins that may fetch from browsers, e.g.
+///   `https://example.com`, `*` allows every origin. Without it no CORS headers are sent
+/// - `CORS_MAX_AGE_SECS` how long browsers cache a preflight (600 is default)
+struct CorsConfig {
+    origins: Vec<String>,
+    any_origin: bool,
+    max_age: u64,
+}
+
+static CORS: LazyLock<CorsConfig> = LazyLock::new(|| {
+    let origins: Vec<String> = std::env::var("CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|origin| origin.trim().trim_end_matches('/').to_string())
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    let max_age: u64 = std::env::var("CORS_MAX_AGE_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(600);
+    CorsConfig {
+        any_origin: origins.iter().any(|origin| origin == "*"),
+        origins,
+        max_age,
+    }
+});
+
+/// The `Access-Control-Allow-Origin` for the request's origin, if it's allowed.
+fn allow_origin(headers: &HeaderMap) -> Option<HeaderValue> {
+    let origin = headers.get(header::ORIGIN)?;
+    if CORS.any_origin {
+        return Some(HeaderValue::from_static("*"));
+    }
+    let allowed = origin
+        .to_str()
+        .is_ok_and(|origin| CORS.origins.iter().any(|allowed| allowed == origin));
+    allowed.then(|| origin.clone())
+}
+
+/// Answers preflights and adds the CORS headers for allowed origins, only GET and POST
+/// are allowed. Must be the outermost layer, since preflights don't pass the other layers.
+pub async fn cors<B>(req: Request<B>, next: Next<B>) -> Response {
+    if CORS.origins.is_empty() {
+        return next.run(req).await;
+    }
+    let allow_origin = allow_origin(req.headers());
+    let preflight = req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    let mut response = match (preflight, &allow_origin) {
+        (true, Some(_)) => {
+            let mut response = StatusCode::NO_CONTENT.into_response();
+            let headers = response.headers_mut();
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                HeaderValue::from_static("GET, POST"),
+            );
+            // JSON bodies and conditional requests are the non-simple parts of our requests
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                HeaderValue::from_static("content-type, if-none-match"),
+            );
+            headers.insert(
+                header::ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from(CORS.max_age),
+            );
+            response
+        }
+        _ => next.run(req).await,
+    };
+    let headers = response.headers_mut();
+    if !CORS.any_origin {
+        // the response differs by origin, caches must not share it
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+    }
+    if let Some(allow_origin) = allow_origin {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if !preflight {
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSE_HEADERS),
+            );
+        }
+    }
+    response
+}
@@ -0,0 +1,59 @@
+use std::{
+    collections::VecDeque,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+/// - `DEVICE_RECOVERY_MAX` recoveries from a lost graphics device within the window,
+///   the process exits once more are needed, so an orchestrator restarts it (3 is default)
+/// - `DEVICE_RECOVERY_WINDOW_SECS` (600 is default)
+struct RecoveryConfig {
+    max: usize,
+    window: Duration,
+}
+
+static RECOVERY_CONFIG: LazyLock<RecoveryConfig> = LazyLock::new(|| {
+    let max: usize = std::env::var("DEVICE_RECOVERY_MAX")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(3);
+    let window: u64 = std::env::var("DEVICE_RECOVERY_WINDOW_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(600);
+    RecoveryConfig {
+        max,
+        window: Duration::from_secs(window.max(1)),
+    }
+});
+
+/// When the recoveries within the window started.
+static ATTEMPTS: LazyLock<parking_lot::Mutex<VecDeque<Instant>>> = LazyLock::new(Default::default);
+
+/// Whether a backend error means the device is gone, e.g. after a driver reset.
+/// The backend only reports errors as text, vulkan names it `ERROR_DEVICE_LOST`.
+pub fn is_device_lost(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        let cause = cause.to_string().to_ascii_lowercase();
+        cause.contains("device_lost") || cause.contains("device lost")
+    })
+}
+
+/// Takes a recovery attempt from the budget, false if it is used up.
+pub fn take_attempt() -> bool {
+    let now = Instant::now();
+    let mut attempts = ATTEMPTS.lock();
+    while attempts
+        .front()
+        .is_some_and(|at| now.duration_since(*at) > RECOVERY_CONFIG.window)
+    {
+        attempts.pop_front();
+    }
+    if attempts.len() >= RECOVERY_CONFIG.max {
+        return false;
+    }
+    attempts.push_back(now);
+    true
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use reqwest::StatusCode;
+
+use crate::upstream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Download {
+    /// The file exists now, downloaded by this or a concurrent request
+    Stored,
+    /// The remote does not have the file
+    NotFound,
+}
+
+/// Downloads by target path, so concurrent requests for the same file share one download.
+static IN_FLIGHT: LazyLock<parking_lot::Mutex<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// Downloads `url` to `path`, unless the file exists already.
+/// The file is written to a temporary file first, so readers
+/// never see a partial download.
+pub async fn download(
+    url: &str,
+    path: &Path,
+    max_bytes: u64,
+    timeout: Duration,
+) -> anyhow::Result<Download> {
+    let lock = IN_FLIGHT
+        .lock()
+        .entry(path.to_path_buf())
+        .or_default()
+        .clone();
+    let res = {
+        let _guard = lock.lock().await;
+        if tokio::fs::try_exists(path).await.unwrap_or_default() {
+            Ok(Download::Stored)
+        } else {
+            tokio::time::timeout(timeout, fetch(url, path, max_bytes))
+                .await
+                .map_err(|_| anyhow!("download timed out after {}s", timeout.as_secs()))
+                .and_then(|res| res)
+        }
+    };
+    // the last waiter cleans up
+    let mut in_flight = IN_FLIGHT.lock();
+    if Arc::strong_count(&lock) <= 2 {
+        in_flight.remove(path);
+    }
+    res
+}
+
+async fn fetch(url: &str, path: &Path, max_bytes: u64) -> anyhow::Result<Download> {
+    let Some(data) = fetch_bytes(url, max_bytes).await? else {
+        return Ok(Download::NotFound);
+    };
+
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let tmp = path.with_extension("download");
+    tokio::fs::write(&tmp, &data).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(Download::Stored)
+}
+
+/// Fetches `url` into memory, `None` if the remote does not have it.
+pub async fn fetch_bytes(url: &str, max_bytes: u64) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut res = upstream::get(url).await?;
+    if res.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    res.error_for_status_ref()?;
+    if res.content_length().is_some_and(|len| len > max_bytes) {
+        return Err(anyhow!("file exceeds {max_bytes} bytes"));
+    }
+
+    let mut data = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        data.extend_from_slice(&chunk);
+        if data.len() as u64 > max_bytes {
+            return Err(anyhow!("file exceeds {max_bytes} bytes"));
+        }
+    }
+    Ok(Some(data))
+}
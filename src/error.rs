@@ -0,0 +1,193 @@
+use std::{fmt, time::Duration};
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::params::ParamError;
+
+/// Everything that can go wrong between receiving a request and serving the image.
+#[derive(Debug)]
+pub enum RenderError {
+    /// The query could not be parsed at all
+    InvalidQuery(String),
+    InvalidParam(ParamError),
+    SkinNotFound {
+        name: String,
+        suggestions: Vec<String>,
+    },
+    MapNotLoaded(String),
+    MapNotFound(String),
+    /// The map is still loading and the render didn't wait for it
+    MapLoading {
+        name: String,
+        /// Estimated, only known for maps that were loaded before
+        progress: Option<f64>,
+        retry_after: Duration,
+    },
+    /// The graphics backend or the screenshot failed
+    Gpu(String),
+    /// The player api or a download failed
+    Upstream(String),
+    /// The player api answered, but its response could not be parsed
+    PlayerApiInvalid(String),
+    /// A url that must not be fetched
+    Forbidden(String),
+    /// The render panicked, with the panic message
+    Panicked(String),
+    /// The render job was lost, e.g. because the render worker stopped
+    Internal(String),
+    RateLimited {
+        retry_after: Duration,
+    },
+    /// The render queue is full
+    Busy,
+    /// No new renders are accepted while shutting down
+    ShuttingDown,
+    /// The render did not finish in time
+    Timeout(Duration),
+}
+
+impl RenderError {
+    /// Every [`RenderError::kind`], for the API description.
+    pub const KINDS: [&'static str; 16] = [
+        "invalid_query",
+        "invalid_param",
+        "skin_not_found",
+        "map_not_loaded",
+        "map_not_found",
+        "map_loading",
+        "gpu",
+        "upstream",
+        "player_api_invalid",
+        "forbidden",
+        "panicked",
+        "internal",
+        "rate_limited",
+        "busy",
+        "shutting_down",
+        "timeout",
+    ];
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RenderError::InvalidQuery(_) | RenderError::InvalidParam(_) => StatusCode::BAD_REQUEST,
+            RenderError::SkinNotFound { .. } | RenderError::MapNotFound(_) => StatusCode::NOT_FOUND,
+            RenderError::MapNotLoaded(_) | RenderError::Busy | RenderError::ShuttingDown => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            RenderError::Gpu(_) | RenderError::Panicked(_) | RenderError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            RenderError::Upstream(_) | RenderError::PlayerApiInvalid(_) => StatusCode::BAD_GATEWAY,
+            RenderError::Forbidden(_) => StatusCode::FORBIDDEN,
+            RenderError::MapLoading { .. } => StatusCode::ACCEPTED,
+            RenderError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            RenderError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// Machine readable error kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RenderError::InvalidQuery(_) => "invalid_query",
+            RenderError::InvalidParam(_) => "invalid_param",
+            RenderError::SkinNotFound { .. } => "skin_not_found",
+            RenderError::MapNotLoaded(_) => "map_not_loaded",
+            RenderError::MapNotFound(_) => "map_not_found",
+            RenderError::MapLoading { .. } => "map_loading",
+            RenderError::Gpu(_) => "gpu",
+            RenderError::Upstream(_) => "upstream",
+            RenderError::PlayerApiInvalid(_) => "player_api_invalid",
+            RenderError::Forbidden(_) => "forbidden",
+            RenderError::Panicked(_) => "panicked",
+            RenderError::Internal(_) => "internal",
+            RenderError::RateLimited { .. } => "rate_limited",
+            RenderError::Busy => "busy",
+            RenderError::ShuttingDown => "shutting_down",
+            RenderError::Timeout(_) => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::InvalidQuery(err) => write!(f, "invalid query: {err}"),
+            RenderError::InvalidParam(err) => write!(f, "{}: {}", err.field, err.reason),
+            RenderError::SkinNotFound { name, suggestions } if suggestions.is_empty() => {
+                write!(f, "skin {name} not found")
+            }
+            RenderError::SkinNotFound { name, suggestions } => write!(
+                f,
+                "skin {name} not found, did you mean: {}",
+                suggestions.join(", ")
+            ),
+            RenderError::MapNotLoaded(name) => write!(f, "map {name} is not loaded"),
+            RenderError::MapNotFound(name) => write!(f, "map {name} not found"),
+            RenderError::MapLoading { name, .. } => write!(f, "map {name} is still loading"),
+            RenderError::Gpu(err) => write!(f, "rendering failed: {err}"),
+            RenderError::Upstream(err) => write!(f, "{err}"),
+            RenderError::PlayerApiInvalid(err) => {
+                write!(f, "the player api response could not be read: {err}")
+            }
+            RenderError::Forbidden(err) => write!(f, "forbidden: {err}"),
+            RenderError::Panicked(err) => write!(f, "rendering panicked: {err}"),
+            RenderError::Internal(err) => write!(f, "internal error: {err}"),
+            RenderError::RateLimited { retry_after } => write!(
+                f,
+                "rate limited, retry in {:.1}s",
+                retry_after.as_secs_f64()
+            ),
+            RenderError::Busy => write!(f, "too many renders are queued, try again later"),
+            RenderError::ShuttingDown => write!(f, "the server is shutting down"),
+            RenderError::Timeout(timeout) => {
+                write!(f, "rendering took longer than {}s", timeout.as_secs_f64())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<ParamError> for RenderError {
+    fn from(err: ParamError) -> Self {
+        Self::InvalidParam(err)
+    }
+}
+
+impl IntoResponse for RenderError {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::json!({
+            "error": self.kind(),
+            "message": self.to_string(),
+        });
+        match &self {
+            RenderError::InvalidParam(err) => {
+                body["field"] = err.field.into();
+                body["value"] = err.value.clone().into();
+                body["accepted"] = err.accepted.clone().into();
+            }
+            RenderError::SkinNotFound { suggestions, .. } => {
+                body["suggestions"] = suggestions.clone().into();
+            }
+            RenderError::MapLoading { progress, .. } => {
+                body["progress"] = (*progress).into();
+            }
+            _ => {}
+        }
+        let mut response = (self.status(), Json(body)).into_response();
+        if let RenderError::RateLimited { retry_after }
+        | RenderError::MapLoading { retry_after, .. } = self
+        {
+            // retry-after only supports whole seconds
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs_f64().ceil() as u64),
+            );
+        }
+        response
+    }
+}
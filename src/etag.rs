@@ -0,0 +1,137 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::SystemTime,
+};
+
+use anyhow::anyhow;
+use axum::http::{header, HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256};
+
+use crate::{maps, skins, version};
+
+/// - `HTTP_CACHE_MAX_AGE_SECS` how long browsers and proxies may reuse an image (3600 is default)
+/// - `HTTP_CACHE_PLAYER_API_MAX_AGE_SECS` the same for images whose skin was resolved
+///   by the player api, since players change their skin (60 is default)
+pub struct HttpCacheConfig {
+    pub max_age: u64,
+    pub player_api_max_age: u64,
+}
+
+pub static HTTP_CACHE: LazyLock<HttpCacheConfig> = LazyLock::new(|| {
+    let max_age: u64 = std::env::var("HTTP_CACHE_MAX_AGE_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(3600);
+    let player_api_max_age: u64 = std::env::var("HTTP_CACHE_PLAYER_API_MAX_AGE_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(60);
+    HttpCacheConfig {
+        max_age,
+        player_api_max_age,
+    }
+});
+
+/// What identifies the content of an asset without reading it, its files with size and mtime.
+type Fingerprint = Vec<(PathBuf, u64, Option<SystemTime>)>;
+
+/// The hashes of the assets, rehashed when their fingerprint changes.
+static ASSET_HASHES: LazyLock<parking_lot::Mutex<HashMap<PathBuf, (Fingerprint, String)>>> =
+    LazyLock::new(Default::default);
+
+/// A file or the files of a directory, like skins.
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let mut files: Fingerprint = if metadata.is_dir() {
+        std::fs::read_dir(path)
+            .ok()?
+            .flatten()
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                metadata
+                    .is_file()
+                    .then(|| (entry.path(), metadata.len(), metadata.modified().ok()))
+            })
+            .collect()
+    } else {
+        vec![(path.to_path_buf(), metadata.len(), metadata.modified().ok())]
+    };
+    files.sort();
+    Some(files)
+}
+
+/// The sha256 of the asset's content, `None` if it doesn't exist.
+fn asset_hash(path: &Path) -> Option<String> {
+    let fingerprint = fingerprint(path)?;
+    if let Some((known, hash)) = ASSET_HASHES.lock().get(path) {
+        if *known == fingerprint {
+            return Some(hash.clone());
+        }
+    }
+    let mut hasher = Sha256::new();
+    for (file, _, _) in &fingerprint {
+        hasher.update(file.file_name()?.as_encoded_bytes());
+        hasher.update(std::fs::read(file).ok()?);
+    }
+    let hash = hex::encode(hasher.finalize());
+    let mut hashes = ASSET_HASHES.lock();
+    // assets that were deleted are never looked up again
+    hashes.retain(|path, _| path.exists());
+    hashes.insert(path.to_path_buf(), (fingerprint, hash.clone()));
+    Some(hash)
+}
+
+fn skin_path(name: &str) -> PathBuf {
+    let dir = skins::skins_dir().join(name);
+    if dir.is_dir() {
+        dir
+    } else {
+        skins::skins_dir().join(format!("{name}.png"))
+    }
+}
+
+/// A strong ETag of an image, from its normalized parameters, the skin and map it shows
+/// and the build that renders it. Known before rendering, so a match skips the render.
+pub async fn etag(params: &str, skin: &str, map: &str) -> String {
+    let (skin_path, map_path) = (skin_path(skin), maps::disk_path(map));
+    let (skin_hash, map_hash) =
+        tokio::task::spawn_blocking(move || (asset_hash(&skin_path), asset_hash(&map_path)))
+            .await
+            .unwrap_or_default();
+    let mut hasher = Sha256::new();
+    for part in [
+        params,
+        skin_hash.as_deref().unwrap_or("missing"),
+        map_hash.as_deref().unwrap_or("missing"),
+        version::GIT_COMMIT,
+    ] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    // 128 bits are plenty to tell images apart
+    format!("\"{}\"", hex::encode(&hasher.finalize()[..16]))
+}
+
+/// Whether `If-None-Match` lists the ETag, weak ETags compare like strong ones for GET.
+pub fn matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// `Cache-Control` of an image.
+pub fn cache_control(skin_from_player_api: bool) -> HeaderValue {
+    let max_age = if skin_from_player_api {
+        HTTP_CACHE.player_api_max_age
+    } else {
+        HTTP_CACHE.max_age
+    };
+    HeaderValue::from_str(&format!("public, max-age={max_age}"))
+        .unwrap_or(HeaderValue::from_static("no-cache"))
+}
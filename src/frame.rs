@@ -0,0 +1,639 @@
+use std::io::Cursor;
+
+use anyhow::anyhow;
+
+use crate::quantize;
+
+/// Pixels per meter of a 96 dpi image, the css reference density.
+const CSS_PIXELS_PER_METER: f32 = 96.0 / 0.0254;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// A rectangle in frame pixels, it may reach outside of the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// A square around the center of the rect with a margin of 8%.
+    pub fn padded_square(self) -> Self {
+        let side = self.width.max(self.height);
+        let side = side + (side as f32 * 0.08).ceil().max(4.0) as u32 * 2;
+        Self {
+            x: self.x + self.width as i64 / 2 - side as i64 / 2,
+            y: self.y + self.height as i64 / 2 - side as i64 / 2,
+            width: side,
+            height: side,
+        }
+    }
+}
+
+/// A captured frame as tightly packed RGBA8 pixels.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn decode_png(png: &[u8]) -> anyhow::Result<Self> {
+        let mut decoder = png::Decoder::new(Cursor::new(png));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+            png::ColorType::Indexed => return Err(anyhow!("indexed png was not expanded")),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    pub fn transparent(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Copies `other` to `x`, `y`, parts outside of the frame are clipped.
+    pub fn paste(&mut self, other: &Frame, x: u32, y: u32) {
+        let width = other.width.min(self.width.saturating_sub(x)) as usize;
+        for row in 0..other.height.min(self.height.saturating_sub(y)) {
+            let src = row as usize * other.width as usize * 4;
+            let dst = ((y + row) as usize * self.width as usize + x as usize) * 4;
+            self.pixels[dst..dst + width * 4].copy_from_slice(&other.pixels[src..src + width * 4]);
+        }
+    }
+
+    /// Blends `other` over the frame at `x`, `y`, parts outside are clipped.
+    pub fn blend(&mut self, other: &Frame, x: i64, y: i64) {
+        for row in 0..other.height as i64 {
+            let dst_y = y + row;
+            if dst_y < 0 || dst_y >= self.height as i64 {
+                continue;
+            }
+            for column in 0..other.width as i64 {
+                let dst_x = x + column;
+                if dst_x < 0 || dst_x >= self.width as i64 {
+                    continue;
+                }
+                let src = (row as usize * other.width as usize + column as usize) * 4;
+                let color = &other.pixels[src..src + 4];
+                let alpha = color[3] as u32;
+                let i = (dst_y as usize * self.width as usize + dst_x as usize) * 4;
+                let pixel = &mut self.pixels[i..i + 4];
+                for c in 0..3 {
+                    pixel[c] =
+                        ((color[c] as u32 * alpha + pixel[c] as u32 * (255 - alpha)) / 255) as u8;
+                }
+                pixel[3] = (alpha + pixel[3] as u32 * (255 - alpha) / 255) as u8;
+            }
+        }
+    }
+
+    /// Crops a `width` x `height` rect around the center.
+    /// The size is clamped to the frame size.
+    pub fn crop_center(&self, width: u32, height: u32) -> Self {
+        let width = width.min(self.width);
+        let height = height.min(self.height);
+        let x = (self.width - width) / 2;
+        let y = (self.height - height) / 2;
+        self.crop(x, y, width, height)
+    }
+
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in y..y + height {
+            let start = (row as usize * self.width as usize + x as usize) * 4;
+            pixels.extend_from_slice(&self.pixels[start..start + width as usize * 4]);
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The smallest rect containing all pixels that are not fully transparent.
+    pub fn opaque_bounds(&self) -> Option<Rect> {
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+        for (i, pixel) in self.pixels.chunks_exact(4).enumerate() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let x = (i % self.width as usize) as u32;
+            let y = (i / self.width as usize) as u32;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        (min_x <= max_x).then_some(Rect {
+            x: min_x as i64,
+            y: min_y as i64,
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+        })
+    }
+
+    /// Like [`Self::crop`], but parts outside of the frame are transparent.
+    pub fn crop_padded(&self, rect: Rect) -> Self {
+        let mut pixels = vec![0; rect.width as usize * rect.height as usize * 4];
+        for row in 0..rect.height as i64 {
+            let src_y = rect.y + row;
+            if src_y < 0 || src_y >= self.height as i64 {
+                continue;
+            }
+            let src_x_start = rect.x.max(0);
+            let src_x_end = (rect.x + rect.width as i64).min(self.width as i64);
+            if src_x_start >= src_x_end {
+                continue;
+            }
+            let src = (src_y as usize * self.width as usize + src_x_start as usize) * 4;
+            let dst = (row as usize * rect.width as usize + (src_x_start - rect.x) as usize) * 4;
+            let len = (src_x_end - src_x_start) as usize * 4;
+            pixels[dst..dst + len].copy_from_slice(&self.pixels[src..src + len]);
+        }
+        Self {
+            width: rect.width,
+            height: rect.height,
+            pixels,
+        }
+    }
+
+    /// Bilinear scaling with premultiplied alpha,
+    /// so transparent pixels don't bleed their color.
+    pub fn resize(&self, width: u32, height: u32) -> Self {
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        let scale_x = self.width as f32 / width as f32;
+        let scale_y = self.height as f32 / height as f32;
+        let pixel = |x: u32, y: u32| {
+            let i = (y as usize * self.width as usize + x as usize) * 4;
+            let p = &self.pixels[i..i + 4];
+            let a = p[3] as f32 / 255.0;
+            [
+                p[0] as f32 * a,
+                p[1] as f32 * a,
+                p[2] as f32 * a,
+                p[3] as f32,
+            ]
+        };
+        for y in 0..height {
+            let src_y = ((y as f32 + 0.5) * scale_y - 0.5).clamp(0.0, (self.height - 1) as f32);
+            let (y0, fy) = (src_y.floor() as u32, src_y.fract());
+            let y1 = (y0 + 1).min(self.height - 1);
+            for x in 0..width {
+                let src_x = ((x as f32 + 0.5) * scale_x - 0.5).clamp(0.0, (self.width - 1) as f32);
+                let (x0, fx) = (src_x.floor() as u32, src_x.fract());
+                let x1 = (x0 + 1).min(self.width - 1);
+                let (p00, p10, p01, p11) =
+                    (pixel(x0, y0), pixel(x1, y0), pixel(x0, y1), pixel(x1, y1));
+                let mut out = [0.0; 4];
+                for c in 0..4 {
+                    let top = p00[c] + (p10[c] - p00[c]) * fx;
+                    let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+                    out[c] = top + (bottom - top) * fy;
+                }
+                let a = out[3] / 255.0;
+                for color in &out[..3] {
+                    let color = if a > 0.0 { color / a } else { 0.0 };
+                    pixels.push(color.round().clamp(0.0, 255.0) as u8);
+                }
+                pixels.push(out[3].round().clamp(0.0, 255.0) as u8);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Lanczos (a = 3) scaling with premultiplied alpha, sharper than [`Self::resize`]
+    /// when downscaling by large factors, e.g. supersampled renders.
+    pub fn resize_lanczos(&self, width: u32, height: u32) -> Self {
+        const LOBES: f32 = 3.0;
+        let lanczos = |x: f32| {
+            if x.abs() < f32::EPSILON {
+                1.0
+            } else if x.abs() >= LOBES {
+                0.0
+            } else {
+                let px = std::f32::consts::PI * x;
+                LOBES * px.sin() * (px / LOBES).sin() / (px * px)
+            }
+        };
+        // the source pixels and normalized weights of every destination pixel of an axis
+        let weights = |src: u32, dst: u32| -> Vec<(usize, Vec<f32>)> {
+            let scale = src as f32 / dst as f32;
+            // downscaling widens the filter, so every source pixel contributes
+            let support = LOBES * scale.max(1.0);
+            (0..dst)
+                .map(|i| {
+                    let center = (i as f32 + 0.5) * scale;
+                    let start = (center - support).floor().max(0.0) as usize;
+                    let end = ((center + support).ceil() as usize).min(src as usize);
+                    let mut weights: Vec<f32> = (start..end)
+                        .map(|j| lanczos((j as f32 + 0.5 - center) / scale.max(1.0)))
+                        .collect();
+                    let sum: f32 = weights.iter().sum();
+                    if sum != 0.0 {
+                        weights.iter_mut().for_each(|w| *w /= sum);
+                    }
+                    (start, weights)
+                })
+                .collect()
+        };
+
+        let premultiplied: Vec<f32> = self
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let a = p[3] as f32 / 255.0;
+                [
+                    p[0] as f32 * a,
+                    p[1] as f32 * a,
+                    p[2] as f32 * a,
+                    p[3] as f32,
+                ]
+            })
+            .collect();
+
+        let columns = weights(self.width, width);
+        let mut horizontal = vec![0.0; width as usize * self.height as usize * 4];
+        for y in 0..self.height as usize {
+            for (x, (start, weights)) in columns.iter().enumerate() {
+                let out = (y * width as usize + x) * 4;
+                for (k, w) in weights.iter().enumerate() {
+                    let i = (y * self.width as usize + start + k) * 4;
+                    for c in 0..4 {
+                        horizontal[out + c] += premultiplied[i + c] * w;
+                    }
+                }
+            }
+        }
+
+        let rows = weights(self.height, height);
+        let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+        for (start, weights) in &rows {
+            for x in 0..width as usize {
+                let mut out = [0.0; 4];
+                for (k, w) in weights.iter().enumerate() {
+                    let i = ((start + k) * width as usize + x) * 4;
+                    for c in 0..4 {
+                        out[c] += horizontal[i + c] * w;
+                    }
+                }
+                // the negative lobes overshoot at hard edges
+                let a = out[3].clamp(0.0, 255.0) / 255.0;
+                for color in &out[..3] {
+                    let color = if a > 0.0 { color / a } else { 0.0 };
+                    pixels.push(color.round().clamp(0.0, 255.0) as u8);
+                }
+                pixels.push(out[3].round().clamp(0.0, 255.0) as u8);
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Turns the frame by `angle` radians clockwise around its center and magnifies it by `zoom`,
+    /// bilinear like [`Self::resize`]. Parts outside of the frame are transparent.
+    pub fn rotate(&self, angle: f32, zoom: f32) -> Self {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        let (sin, cos) = (-angle).sin_cos();
+        let center_x = self.width as f32 / 2.0;
+        let center_y = self.height as f32 / 2.0;
+        let pixel = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                return [0.0; 4];
+            }
+            let i = (y as usize * self.width as usize + x as usize) * 4;
+            let p = &self.pixels[i..i + 4];
+            let a = p[3] as f32 / 255.0;
+            [
+                p[0] as f32 * a,
+                p[1] as f32 * a,
+                p[2] as f32 * a,
+                p[3] as f32,
+            ]
+        };
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let dx = (x as f32 + 0.5 - center_x) / zoom;
+                let dy = (y as f32 + 0.5 - center_y) / zoom;
+                let src_x = center_x + dx * cos - dy * sin - 0.5;
+                let src_y = center_y + dx * sin + dy * cos - 0.5;
+                let (x0, fx) = (src_x.floor() as i64, src_x - src_x.floor());
+                let (y0, fy) = (src_y.floor() as i64, src_y - src_y.floor());
+                let (p00, p10, p01, p11) = (
+                    pixel(x0, y0),
+                    pixel(x0 + 1, y0),
+                    pixel(x0, y0 + 1),
+                    pixel(x0 + 1, y0 + 1),
+                );
+                let mut out = [0.0; 4];
+                for c in 0..4 {
+                    let top = p00[c] + (p10[c] - p00[c]) * fx;
+                    let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+                    out[c] = top + (bottom - top) * fy;
+                }
+                let a = out[3] / 255.0;
+                for color in &out[..3] {
+                    let color = if a > 0.0 { color / a } else { 0.0 };
+                    pixels.push(color.round().clamp(0.0, 255.0) as u8);
+                }
+                pixels.push(out[3].round().clamp(0.0, 255.0) as u8);
+            }
+        }
+        Self {
+            width: self.width,
+            height: self.height,
+            pixels,
+        }
+    }
+
+    /// Blends a filled circle over the frame, parts outside are clipped.
+    pub fn fill_circle(&mut self, center_x: f32, center_y: f32, radius: f32, color: [u8; 4]) {
+        let min_x = (center_x - radius).floor().max(0.0) as u32;
+        let min_y = (center_y - radius).floor().max(0.0) as u32;
+        let max_x = ((center_x + radius).ceil().max(0.0) as u32).min(self.width);
+        let max_y = ((center_y + radius).ceil().max(0.0) as u32).min(self.height);
+        let alpha = color[3] as u32;
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let dx = x as f32 + 0.5 - center_x;
+                let dy = y as f32 + 0.5 - center_y;
+                if dx * dx + dy * dy > radius * radius {
+                    continue;
+                }
+                let i = (y as usize * self.width as usize + x as usize) * 4;
+                let pixel = &mut self.pixels[i..i + 4];
+                for c in 0..3 {
+                    pixel[c] =
+                        ((color[c] as u32 * alpha + pixel[c] as u32 * (255 - alpha)) / 255) as u8;
+                }
+                pixel[3] = (alpha + pixel[3] as u32 * (255 - alpha) / 255) as u8;
+            }
+        }
+    }
+
+    /// Blends a filled rectangle over the frame, parts outside are clipped.
+    pub fn fill_rect(&mut self, rect: Rect, color: [u8; 4]) {
+        let min_x = rect.x.clamp(0, self.width as i64) as u32;
+        let min_y = rect.y.clamp(0, self.height as i64) as u32;
+        let max_x = (rect.x + rect.width as i64).clamp(0, self.width as i64) as u32;
+        let max_y = (rect.y + rect.height as i64).clamp(0, self.height as i64) as u32;
+        let alpha = color[3] as u32;
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let i = (y as usize * self.width as usize + x as usize) * 4;
+                let pixel = &mut self.pixels[i..i + 4];
+                for c in 0..3 {
+                    pixel[c] =
+                        ((color[c] as u32 * alpha + pixel[c] as u32 * (255 - alpha)) / 255) as u8;
+                }
+                pixel[3] = (alpha + pixel[3] as u32 * (255 - alpha) / 255) as u8;
+            }
+        }
+    }
+
+    /// Composites the frame over a color, which may be translucent.
+    pub fn fill_behind(&mut self, color: [u8; 4]) {
+        let back_alpha = color[3] as u32;
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            // the background shows through what is left of the pixel
+            let behind = back_alpha * (255 - alpha) / 255;
+            let out_alpha = alpha + behind;
+            if out_alpha == 0 {
+                continue;
+            }
+            for c in 0..3 {
+                pixel[c] = ((pixel[c] as u32 * alpha + color[c] as u32 * behind) / out_alpha) as u8;
+            }
+            pixel[3] = out_alpha as u8;
+        }
+    }
+
+    /// Composites the frame over an opaque color, which makes it opaque.
+    pub fn composite_over(&mut self, matte: [u8; 3]) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            for c in 0..3 {
+                pixel[c] =
+                    ((pixel[c] as u32 * alpha + matte[c] as u32 * (255 - alpha)) / 255) as u8;
+            }
+            pixel[3] = 255;
+        }
+    }
+
+    /// Alpha is dropped, see [`Self::composite_over`].
+    pub fn encode_jpeg(&self, dpr: Option<f32>) -> anyhow::Result<Vec<u8>> {
+        let rgb: Vec<u8> = self
+            .pixels
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect();
+        let mut jpeg = Vec::new();
+        let mut encoder = jpeg_encoder::Encoder::new(&mut jpeg, JPEG_QUALITY);
+        if let Some(dpr) = dpr {
+            let dpi = (96.0 * dpr).round() as u16;
+            encoder.set_density(jpeg_encoder::Density::Inch { x: dpi, y: dpi });
+        }
+        encoder.encode(
+            &rgb,
+            self.width.try_into()?,
+            self.height.try_into()?,
+            jpeg_encoder::ColorType::Rgb,
+        )?;
+        Ok(jpeg)
+    }
+
+    /// The pixels in a PAM container, which has the size in its header unlike raw pixels.
+    /// Raw responses serve the pixels after the header, see [`split_pam`].
+    pub fn encode_pam(&self) -> Vec<u8> {
+        let header = format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.width, self.height
+        );
+        let mut pam = Vec::with_capacity(header.len() + self.pixels.len());
+        pam.extend_from_slice(header.as_bytes());
+        pam.extend_from_slice(&self.pixels);
+        pam
+    }
+
+    /// `dpr` is written as pHYs chunk, so browsers know the intended density.
+    /// `fast` compresses less, but faster.
+    pub fn encode_png(&self, dpr: Option<f32>, fast: bool) -> anyhow::Result<Vec<u8>> {
+        self.encode_png_with(dpr, fast, PngOptions::default())
+    }
+
+    /// Like [`Self::encode_png`], a compression level of the options replaces `fast`.
+    pub fn encode_png_with(
+        &self,
+        dpr: Option<f32>,
+        fast: bool,
+        options: PngOptions,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, self.width, self.height);
+        encoder.set_depth(png::BitDepth::Eight);
+        let indexed = options.quantize.then(|| quantize::quantize(self));
+        match &indexed {
+            Some(indexed) => {
+                let (plte, trns) = indexed.chunks();
+                encoder.set_color(png::ColorType::Indexed);
+                encoder.set_palette(plte);
+                if !trns.is_empty() {
+                    encoder.set_trns(trns);
+                }
+            }
+            None => encoder.set_color(png::ColorType::Rgba),
+        }
+        match options.compression {
+            Some(level) => encoder.set_compression(png_compression(level)),
+            None if fast => encoder.set_compression(png::Compression::Fast),
+            None => {}
+        }
+        if let Some(dpr) = dpr {
+            let ppu = (CSS_PIXELS_PER_METER * dpr).round() as u32;
+            encoder.set_pixel_dims(Some(png::PixelDimensions {
+                xppu: ppu,
+                yppu: ppu,
+                unit: png::Unit::Meter,
+            }));
+        }
+        let mut writer = encoder.write_header()?;
+        match &indexed {
+            Some(indexed) => writer.write_image_data(&indexed.indices)?,
+            None => writer.write_image_data(&self.pixels)?,
+        }
+        writer.finish()?;
+        Ok(png)
+    }
+}
+
+/// How pngs are encoded beside the dpr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PngOptions {
+    /// zlib like level 0 to 9, `None` is the encoder's default
+    pub compression: Option<u8>,
+    /// Reduce to a palette of 256 colors, see [`quantize::quantize`]
+    pub quantize: bool,
+}
+
+/// The encoder only has presets, so levels are mapped to the closest one.
+fn png_compression(level: u8) -> png::Compression {
+    match level {
+        0..=3 => png::Compression::Fast,
+        4..=6 => png::Compression::Default,
+        _ => png::Compression::Best,
+    }
+}
+
+/// The width, height and pixel offset of a PAM of [`Frame::encode_pam`].
+pub fn split_pam(pam: &[u8]) -> Option<(u32, u32, usize)> {
+    const END: &[u8] = b"ENDHDR\n";
+    let end = pam.windows(END.len()).position(|w| w == END)? + END.len();
+    let header = std::str::from_utf8(&pam[..end]).ok()?;
+    let field = |name: &str| {
+        header
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.trim().parse::<u32>().ok())
+    };
+    Some((field("WIDTH ")?, field("HEIGHT ")?, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATTE: [u8; 3] = [10, 120, 250];
+
+    fn pixel(frame: &Frame, x: u32, y: u32) -> [u8; 4] {
+        let i = (y * frame.width + x) as usize * 4;
+        frame.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    /// A transparent frame with an opaque red pixel in its center.
+    fn sticker() -> Frame {
+        let mut frame = Frame::transparent(5, 5);
+        let i = (2 * 5 + 2) * 4;
+        frame.pixels[i..i + 4].copy_from_slice(&[255, 0, 0, 255]);
+        frame
+    }
+
+    #[test]
+    fn transparent_corners_become_the_matte() {
+        let mut frame = sticker();
+        frame.composite_over(MATTE);
+        for (x, y) in [(0, 0), (4, 0), (0, 4), (4, 4)] {
+            assert_eq!(pixel(&frame, x, y), [10, 120, 250, 255], "{x},{y}");
+        }
+        assert_eq!(pixel(&frame, 2, 2), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn translucent_pixels_are_mixed_with_the_matte() {
+        let mut frame = Frame::transparent(1, 1);
+        frame.pixels.copy_from_slice(&[255, 255, 255, 51]);
+        frame.composite_over([0, 0, 0]);
+        assert_eq!(pixel(&frame, 0, 0), [51, 51, 51, 255]);
+    }
+
+    #[test]
+    fn the_background_only_fills_transparent_pixels() {
+        let mut frame = sticker();
+        frame.fill_behind([0, 255, 0, 255]);
+        assert_eq!(pixel(&frame, 0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&frame, 2, 2), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn a_translucent_background_stays_translucent() {
+        let mut frame = sticker();
+        frame.fill_behind([0, 255, 0, 128]);
+        assert_eq!(pixel(&frame, 0, 0), [0, 255, 0, 128]);
+        // a matte afterwards makes it opaque, like for jpeg
+        frame.composite_over([0, 0, 0]);
+        assert_eq!(pixel(&frame, 0, 0), [0, 128, 0, 255]);
+    }
+
+    #[test]
+    fn jpegs_have_the_size_and_density() {
+        let mut frame = sticker();
+        frame.composite_over(MATTE);
+        let jpeg = frame.encode_jpeg(Some(2.0)).unwrap();
+        assert!(jpeg.starts_with(b"\xff\xd8"));
+        assert!(jpeg.ends_with(b"\xff\xd9"));
+        // the JFIF header: density unit inch, 192 dpi
+        let jfif = jpeg.windows(5).position(|w| w == b"JFIF\0").unwrap();
+        assert_eq!(&jpeg[jfif + 7..jfif + 12], &[1, 0, 192, 0, 192]);
+    }
+}
@@ -0,0 +1,258 @@
+use map::skeleton::groups::layers::physics::MapLayerPhysicsSkeleton;
+use math::math::vector::vec2;
+
+use crate::{frame::Rect, physics};
+
+/// What can be drawn over the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapOverlay {
+    /// The game, front, tele and speedup layers, see [`TileCategory`]
+    Physics,
+}
+
+pub const MAP_OVERLAYS: [MapOverlay; 1] = [MapOverlay::Physics];
+
+pub fn map_overlay_name(overlay: MapOverlay) -> &'static str {
+    match overlay {
+        MapOverlay::Physics => "physics",
+    }
+}
+
+/// Tiles that matter for routes, other tiles are not drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileCategory {
+    /// Hookable
+    Solid,
+    Unhook,
+    /// Freeze and deep freeze
+    Freeze,
+    Tele,
+    Speedup,
+}
+
+impl TileCategory {
+    /// Flat colors, roughly those of the entities textures.
+    pub fn color(self) -> [u8; 3] {
+        match self {
+            TileCategory::Solid => [150, 110, 60],
+            TileCategory::Unhook => [90, 100, 120],
+            TileCategory::Freeze => [20, 20, 30],
+            TileCategory::Tele => [170, 60, 220],
+            TileCategory::Speedup => [255, 200, 0],
+        }
+    }
+}
+
+/// Tile indices of the game and front layer, like the game's `mapitems.h`.
+const TILE_SOLID: u8 = 1;
+const TILE_NOHOOK: u8 = 3;
+const TILE_FREEZE: u8 = 9;
+const TILE_DFREEZE: u8 = 12;
+
+fn game_tile_category(index: u8) -> Option<TileCategory> {
+    match index {
+        TILE_SOLID => Some(TileCategory::Solid),
+        TILE_NOHOOK => Some(TileCategory::Unhook),
+        TILE_FREEZE | TILE_DFREEZE => Some(TileCategory::Freeze),
+        _ => None,
+    }
+}
+
+/// The category of every tile of the physics group, row by row.
+/// Later layers win, e.g. a tele on the front of a solid tile is a tele.
+pub fn tile_categories<T>(
+    layers: &[MapLayerPhysicsSkeleton<T>],
+    width: usize,
+    height: usize,
+) -> Vec<Option<TileCategory>> {
+    let mut categories = vec![None; width * height];
+    let mut apply = |tiles: &mut dyn Iterator<Item = Option<TileCategory>>| {
+        for (category, tile) in categories.iter_mut().zip(tiles) {
+            if tile.is_some() {
+                *category = tile;
+            }
+        }
+    };
+    for layer in layers {
+        match layer {
+            MapLayerPhysicsSkeleton::Game(layer) | MapLayerPhysicsSkeleton::Front(layer) => apply(
+                &mut layer
+                    .layer
+                    .tiles
+                    .iter()
+                    .map(|tile| game_tile_category(tile.index)),
+            ),
+            MapLayerPhysicsSkeleton::Tele(layer) => apply(
+                &mut layer
+                    .layer
+                    .base
+                    .tiles
+                    .iter()
+                    .map(|tile| (tile.base.index != 0).then_some(TileCategory::Tele)),
+            ),
+            MapLayerPhysicsSkeleton::Speedup(layer) => {
+                apply(&mut layer.layer.tiles.iter().map(|tile| {
+                    (tile.base.index != 0 && tile.force > 0).then_some(TileCategory::Speedup)
+                }))
+            }
+            _ => {}
+        }
+    }
+    categories
+}
+
+/// Whether projectiles collide with each tile of the game layer, row by row,
+/// like `IsSolid` of the game.
+pub fn solid_tiles<T>(layers: &[MapLayerPhysicsSkeleton<T>]) -> Vec<bool> {
+    layers
+        .iter()
+        .find_map(|layer| match layer {
+            MapLayerPhysicsSkeleton::Game(layer) => Some(
+                layer
+                    .layer
+                    .tiles
+                    .iter()
+                    .map(|tile| matches!(tile.index, TILE_SOLID | TILE_NOHOOK))
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Whether the tile at `pos` (in tiles) is solid, see [`solid_tiles`].
+/// The border tiles continue outside of the map like in the game.
+pub fn is_solid(solid: &[bool], width: usize, pos: vec2) -> bool {
+    if width == 0 || solid.len() < width {
+        return false;
+    }
+    let height = solid.len() / width;
+    // negative positions saturate to the first tile
+    let x = (pos.x.floor() as usize).min(width - 1);
+    let y = (pos.y.floor() as usize).min(height - 1);
+    solid[y * width + x]
+}
+
+/// The visible tiles as frame rects of a `canvas_width` x `canvas_height` frame
+/// with the camera at `camera` (in tiles) in its center.
+/// Neighboring tiles of a row with the same category are one rect.
+pub fn visible_rects(
+    categories: &[Option<TileCategory>],
+    width: usize,
+    camera: (f32, f32),
+    zoom: f32,
+    canvas_width: u32,
+    canvas_height: u32,
+) -> Vec<(Rect, TileCategory)> {
+    if width == 0 {
+        return Vec::new();
+    }
+    let height = categories.len() / width;
+    let aspect = canvas_width as f32 / canvas_height as f32;
+    let visible_width = physics::visible_width(aspect, zoom);
+    let visible_height = visible_width / aspect;
+    let pixels_per_tile = canvas_width as f32 / visible_width;
+    let range = |center: f32, visible: f32, len: usize| {
+        let start = (center - visible / 2.0).floor().clamp(0.0, len as f32) as usize;
+        let end = (center + visible / 2.0).ceil().clamp(0.0, len as f32) as usize;
+        start..end
+    };
+    let (columns, rows) = (
+        range(camera.0, visible_width, width),
+        range(camera.1, visible_height, height),
+    );
+    // tile edges are rounded, so neighboring rects don't overlap or leave gaps
+    let to_frame_x = |tile: usize| {
+        ((tile as f32 - camera.0) * pixels_per_tile + canvas_width as f32 / 2.0).round() as i64
+    };
+    let to_frame_y = |tile: usize| {
+        ((tile as f32 - camera.1) * pixels_per_tile + canvas_height as f32 / 2.0).round() as i64
+    };
+
+    let mut rects = Vec::new();
+    for row in rows {
+        let (y, bottom) = (to_frame_y(row), to_frame_y(row + 1));
+        if bottom <= y {
+            continue;
+        }
+        let mut column = columns.start;
+        while column < columns.end {
+            let Some(category) = categories[row * width + column] else {
+                column += 1;
+                continue;
+            };
+            let start = column;
+            while column < columns.end && categories[row * width + column] == Some(category) {
+                column += 1;
+            }
+            let (x, right) = (to_frame_x(start), to_frame_x(column));
+            if right > x {
+                rects.push((
+                    Rect {
+                        x,
+                        y,
+                        width: (right - x) as u32,
+                        height: (bottom - y) as u32,
+                    },
+                    category,
+                ));
+            }
+        }
+    }
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x3 map with a solid column at x = 2 and a solid bottom row.
+    fn solid() -> Vec<bool> {
+        [
+            [false, false, true, false],
+            [false, false, true, false],
+            [true, true, true, true],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn positions_are_rounded_down_to_their_tile() {
+        let solid = solid();
+        assert!(!is_solid(&solid, 4, vec2::new(1.99, 0.5)));
+        assert!(is_solid(&solid, 4, vec2::new(2.0, 0.5)));
+        assert!(is_solid(&solid, 4, vec2::new(2.99, 1.99)));
+        assert!(!is_solid(&solid, 4, vec2::new(3.0, 1.99)));
+        assert!(is_solid(&solid, 4, vec2::new(0.5, 2.0)));
+    }
+
+    #[test]
+    fn the_border_continues_outside_of_the_map() {
+        let solid = solid();
+        assert!(!is_solid(&solid, 4, vec2::new(-5.0, 0.5)));
+        assert!(is_solid(&solid, 4, vec2::new(2.5, -5.0)));
+        assert!(!is_solid(&solid, 4, vec2::new(10.0, 1.0)));
+        assert!(is_solid(&solid, 4, vec2::new(0.5, 10.0)));
+    }
+
+    #[test]
+    fn nothing_is_solid_without_a_game_layer() {
+        assert!(!is_solid(&[], 0, vec2::new(0.0, 0.0)));
+        assert!(!is_solid(&[], 4, vec2::new(0.0, 0.0)));
+    }
+
+    #[test]
+    fn projectiles_stop_at_the_first_wall() {
+        let solid = solid();
+        // fired to the right from the first column
+        let shooter = vec2::new(0.5, 0.5);
+        let (points, hit) = physics::simulate(
+            physics::Projectile::Laser,
+            vec2::new(1.0, 0.0),
+            1.0,
+            |pos| is_solid(&solid, 4, shooter + pos),
+        );
+        assert!(hit);
+        assert_eq!(points.last().map(|pos| shooter.x + pos.x), Some(2.5));
+    }
+}
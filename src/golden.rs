@@ -0,0 +1,213 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use palette::{color_difference::Ciede2000, FromColor, Lab, Srgb};
+use tokio::sync::oneshot;
+
+use crate::{
+    containers,
+    frame::Frame,
+    load_client, maps,
+    params::{AssetKeys, RenderParams},
+    Client,
+};
+
+/// The rendered parameters by reference image name.
+/// `time` is pinned, so map animations render the same image every run.
+const CASES: [(&str, &str); 11] = [
+    ("default", "time=1000"),
+    (
+        "custom_colors",
+        "time=1000&body_color=2817920&feet_color=10076256",
+    ),
+    ("eyes_normal", "time=1000&eyes=normal"),
+    ("eyes_angry", "time=1000&eyes=angry"),
+    ("eyes_pain", "time=1000&eyes=pain"),
+    ("eyes_happy", "time=1000&eyes=happy"),
+    ("eyes_surprised", "time=1000&eyes=surprised"),
+    ("eyes_blink", "time=1000&eyes=blink"),
+    (
+        "hook_weapon",
+        "time=1000&weapon=grenade&hook_x=160&hook_y=-96",
+    ),
+    ("emoticon", "time=1000&emoticon=hearts"),
+    ("zoom", "time=1000&zoom=2"),
+];
+
+/// Pixels differ if their CIEDE2000 distance is larger,
+/// 2.3 is about the smallest difference that can be noticed.
+const MAX_DELTA_E: f32 = 2.3;
+/// The share of pixels that may differ, e.g. by the antialiasing of another driver.
+const MAX_DIFFERENT: f64 = 0.001;
+/// Translucent pixels are compared as they appear on a gray background.
+const MATTE: f32 = 0.5;
+/// Asset loads of a case are waited for this long.
+const ASSET_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn reference_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"))
+}
+
+fn lab(pixel: &[u8]) -> Lab {
+    let alpha = pixel[3] as f32 / 255.0;
+    let [r, g, b] = [0, 1, 2].map(|i| pixel[i] as f32 / 255.0 * alpha + MATTE * (1.0 - alpha));
+    Lab::from_color(Srgb::new(r, g, b))
+}
+
+/// The share of pixels whose colors differ noticeably.
+fn different_pixels(expected: &Frame, actual: &Frame) -> Result<f64, String> {
+    if (expected.width, expected.height) != (actual.width, actual.height) {
+        return Err(format!(
+            "the size {}x{} is not {}x{}",
+            actual.width, actual.height, expected.width, expected.height
+        ));
+    }
+    let pixels = expected.pixels.len() / 4;
+    if pixels == 0 {
+        return Ok(0.0);
+    }
+    let different = expected
+        .pixels
+        .chunks_exact(4)
+        .zip(actual.pixels.chunks_exact(4))
+        .filter(|(expected, actual)| lab(expected).difference(lab(actual)) > MAX_DELTA_E)
+        .count();
+    Ok(different as f64 / pixels as f64)
+}
+
+/// Renders the query like the render thread and returns the png.
+fn render(client: &mut Client, query: &str) -> Vec<u8> {
+    let (params, _) = RenderParams::from_query(query).unwrap();
+    client.start_map_load(params.map_name()).unwrap();
+    client.finish_map_loads(Instant::now() + maps::LOAD_TIMEOUT);
+    let warnings = containers::wait_assets(
+        &AssetKeys::new(&params),
+        client,
+        Instant::now() + ASSET_TIMEOUT,
+    );
+    assert!(warnings.is_empty(), "{query}: {warnings:?}");
+    let (sender, mut receiver) = oneshot::channel();
+    assert!(
+        client.render(params, Vec::new(), sender).is_none(),
+        "{query}: the graphics device was lost"
+    );
+    // the screenshot is taken before render returns
+    receiver
+        .try_recv()
+        .expect("the screenshot was never taken")
+        .unwrap_or_else(|err| panic!("{query}: {err}"))
+        .png
+}
+
+/// Compares every case to its reference in `tests/golden`,
+/// `GOLDEN_BLESS=1` writes the renders as new references instead.
+#[test]
+#[ignore = "needs a vulkan device"]
+fn golden_images() {
+    let bless = std::env::var("GOLDEN_BLESS").is_ok_and(|bless| bless == "1");
+    let mut client = load_client().unwrap();
+    let mut failures = Vec::new();
+    for (name, query) in CASES {
+        let png = render(&mut client, query);
+        let path = reference_path(name);
+        if bless {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, &png).unwrap();
+            continue;
+        }
+        let expected = std::fs::read(&path).unwrap_or_else(|err| {
+            panic!(
+                "{}: {err}, GOLDEN_BLESS=1 creates the references",
+                path.display()
+            )
+        });
+        let expected = Frame::decode_png(&expected).unwrap();
+        let actual = Frame::decode_png(&png).unwrap();
+        let failure = match different_pixels(&expected, &actual) {
+            Ok(different) if different <= MAX_DIFFERENT => continue,
+            Ok(different) => format!("{:.2}% of the pixels differ", different * 100.0),
+            Err(err) => err,
+        };
+        let actual_path = std::env::temp_dir().join(format!("golden_{name}.png"));
+        std::fs::write(&actual_path, &png).unwrap();
+        failures.push(format!(
+            "{name} ({query}): {failure}, the render is at {}",
+            actual_path.display()
+        ));
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+/// Names in scripts of the fonts in `data/fonts`, see the nameplate section of the README.
+const SCRIPT_NAMES: [&str; 4] = ["漢字名", "テスト", "مرحبا", "שלום"];
+/// A private use character no font has a glyph for.
+const MISSING_GLYPH: char = '\u{10fffd}';
+
+/// Names of the fonts render glyphs, not the missing glyph of every character.
+#[test]
+#[ignore = "needs a vulkan device"]
+fn names_render_the_glyphs_of_their_script() {
+    let mut client = load_client().unwrap();
+    let query = |name: &str| format!("time=1000&player_name={}", urlencoding::encode(name));
+    for name in SCRIPT_NAMES {
+        let missing: String = name.chars().map(|_| MISSING_GLYPH).collect();
+        let expected = Frame::decode_png(&render(&mut client, &query(&missing))).unwrap();
+        let actual = Frame::decode_png(&render(&mut client, &query(name))).unwrap();
+        let different = different_pixels(&expected, &actual).unwrap();
+        assert!(different > 0.0, "{name} renders as missing glyphs");
+    }
+}
+
+fn filled(width: u32, height: u32, pixel: [u8; 4]) -> Frame {
+    Frame {
+        width,
+        height,
+        pixels: pixel.repeat((width * height) as usize),
+    }
+}
+
+#[test]
+fn equal_frames_do_not_differ() {
+    let frame = filled(8, 8, [200, 40, 90, 255]);
+    assert_eq!(different_pixels(&frame, &frame.clone()), Ok(0.0));
+}
+
+#[test]
+fn small_color_changes_are_tolerated() {
+    let expected = filled(8, 8, [200, 40, 90, 255]);
+    let actual = filled(8, 8, [201, 40, 89, 255]);
+    assert_eq!(different_pixels(&expected, &actual), Ok(0.0));
+}
+
+#[test]
+fn noticeable_changes_are_counted() {
+    let expected = filled(10, 10, [200, 40, 90, 255]);
+    let mut actual = expected.clone();
+    actual.pixels[..4].copy_from_slice(&[40, 200, 90, 255]);
+    assert_eq!(different_pixels(&expected, &actual), Ok(0.01));
+}
+
+#[test]
+fn invisible_pixels_do_not_differ() {
+    let expected = filled(4, 4, [255, 0, 0, 0]);
+    let actual = filled(4, 4, [0, 0, 255, 0]);
+    assert_eq!(different_pixels(&expected, &actual), Ok(0.0));
+}
+
+#[test]
+fn translucent_pixels_differ_by_alpha() {
+    let expected = filled(4, 4, [0, 0, 0, 255]);
+    let actual = filled(4, 4, [0, 0, 0, 128]);
+    assert_eq!(different_pixels(&expected, &actual), Ok(1.0));
+}
+
+#[test]
+fn other_sizes_fail() {
+    let expected = filled(4, 4, [0, 0, 0, 255]);
+    let actual = filled(4, 2, [0, 0, 0, 255]);
+    assert!(different_pixels(&expected, &actual).is_err());
+}
@@ -0,0 +1,126 @@
+use std::{
+    path::Path,
+    sync::{LazyLock, OnceLock},
+};
+
+use anyhow::anyhow;
+use serde::Serialize;
+
+/// - `GPU_DEVICE` the vulkan device as `vendor_id:device_id` in hex, e.g. `10de:2484`
+///   (`vulkaninfo --summary` lists them). It's passed to the vulkan loader and mesa's
+///   device select layer, without it the backend picks a device itself
+/// - `GPU_SOFTWARE_FALLBACK` retry with a software renderer if the gpu can't be initialized
+///   (`true` is default)
+/// - `GPU_SOFTWARE_ICD` the vulkan driver manifest of the software renderer
+///   (`/usr/share/vulkan/icd.d/lvp_icd.x86_64.json` is default, mesa's lavapipe)
+struct GpuConfig {
+    device: Option<String>,
+    software_fallback: bool,
+    software_icd: String,
+}
+
+static GPU: LazyLock<GpuConfig> = LazyLock::new(|| {
+    let device = std::env::var("GPU_DEVICE")
+        .ok()
+        .map(|device| device.trim().to_ascii_lowercase())
+        .filter(|device| !device.is_empty());
+    let software_fallback: bool = std::env::var("GPU_SOFTWARE_FALLBACK")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true);
+    let software_icd = std::env::var("GPU_SOFTWARE_ICD")
+        .unwrap_or_else(|_| "/usr/share/vulkan/icd.d/lvp_icd.x86_64.json".to_string());
+    GpuConfig {
+        device,
+        software_fallback,
+        software_icd,
+    }
+});
+
+/// How the graphics backend was initialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendPath {
+    Hardware,
+    /// The gpu failed, the software renderer is used
+    Software,
+}
+
+static BACKEND_PATH: OnceLock<BackendPath> = OnceLock::new();
+
+/// Exports the device selection for the vulkan loader.
+/// Must run before the first backend is loaded and before other threads start.
+pub fn select_device() {
+    let Some(device) = &GPU.device else {
+        return;
+    };
+    let valid = device.split_once(':').is_some_and(|(vendor, id)| {
+        u32::from_str_radix(vendor, 16).is_ok() && u32::from_str_radix(id, 16).is_ok()
+    });
+    if !valid {
+        println!("GPU_DEVICE {device:?} is not vendor_id:device_id in hex, it's ignored");
+        return;
+    }
+    println!("selecting the gpu {device}");
+    // the loader's selection applies to every driver, mesa's layer to older loaders
+    unsafe {
+        std::env::set_var("VK_LOADER_DEVICE_SELECT", device);
+        std::env::set_var("MESA_VK_DEVICE_SELECT", device);
+    }
+}
+
+/// Switches the vulkan loader to the software renderer for backends loaded afterwards.
+/// Must run before other threads start.
+pub fn use_software() -> anyhow::Result<()> {
+    if !GPU.software_fallback {
+        return Err(anyhow!(
+            "the software fallback is disabled (GPU_SOFTWARE_FALLBACK)"
+        ));
+    }
+    if !Path::new(&GPU.software_icd).exists() {
+        return Err(anyhow!(
+            "the software renderer {} is not installed, install mesa's lavapipe \
+            (e.g. mesa-vulkan-drivers) or set GPU_SOFTWARE_ICD",
+            GPU.software_icd
+        ));
+    }
+    unsafe {
+        std::env::set_var("VK_ICD_FILENAMES", &GPU.software_icd);
+        std::env::remove_var("VK_LOADER_DEVICE_SELECT");
+        std::env::remove_var("MESA_VK_DEVICE_SELECT");
+    }
+    Ok(())
+}
+
+pub fn set_backend_path(path: BackendPath) {
+    let _ = BACKEND_PATH.set(path);
+    println!("graphics backend: {path:?}");
+}
+
+/// What to do about a failed backend initialization, with the error.
+/// The backend only reports vulkan's error names as text.
+pub fn explain(err: &anyhow::Error) -> String {
+    let text = format!("{err:#}");
+    let hint = if text.contains("ERROR_INCOMPATIBLE_DRIVER") || text.contains("LoadingError") {
+        "no vulkan driver was found, install the driver of the gpu or mesa's vulkan drivers, \
+        VK_ICD_FILENAMES selects a driver manifest"
+    } else if text.contains("ERROR_INITIALIZATION_FAILED") {
+        "the vulkan driver failed to initialize, check that the gpu is accessible \
+        (e.g. /dev/dri in containers)"
+    } else if text.to_ascii_lowercase().contains("device") {
+        "no usable vulkan device was found, `vulkaninfo --summary` lists the devices, \
+        GPU_DEVICE selects one"
+    } else {
+        "the graphics backend could not be initialized"
+    };
+    format!("{hint}: {text}")
+}
+
+/// The backend path and the device selection, for `GET /version`.
+pub fn info() -> serde_json::Value {
+    serde_json::json!({
+        "path": BACKEND_PATH.get(),
+        "device": GPU.device,
+        "driver_manifest": std::env::var("VK_ICD_FILENAMES").ok(),
+    })
+}
@@ -0,0 +1,171 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{http::StatusCode, response::IntoResponse, Json};
+
+use crate::{
+    load::LOAD,
+    params::RenderParams,
+    render_worker::{self, Priority},
+};
+
+/// - `HEALTH_PROBE_INTERVAL_SECS` how often a test frame is rendered (30 is default)
+/// - `HEALTH_PROBE_MAX_AGE_SECS` `/readyz` fails if the last successful
+///   test frame is older (90 is default)
+/// - `HEALTH_STALL_SECS` `/healthz` fails if the render thread made no progress
+///   for this long while renders are waiting (60 is default)
+struct HealthConfig {
+    probe_interval: Duration,
+    probe_max_age: Duration,
+    stall: Duration,
+}
+
+static HEALTH_CONFIG: LazyLock<HealthConfig> = LazyLock::new(|| {
+    let probe_interval: u64 = std::env::var("HEALTH_PROBE_INTERVAL_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(30);
+    let probe_max_age: u64 = std::env::var("HEALTH_PROBE_MAX_AGE_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(90);
+    let stall: u64 = std::env::var("HEALTH_STALL_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(60);
+    HealthConfig {
+        probe_interval: Duration::from_secs(probe_interval.max(1)),
+        probe_max_age: Duration::from_secs(probe_max_age.max(1)),
+        stall: Duration::from_secs(stall.max(1)),
+    }
+});
+
+/// Set once the client and the default map are loaded.
+static CLIENT_READY: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+struct Activity {
+    /// When a render thread last picked up or finished a job
+    last_progress: Instant,
+    /// Jobs the renderers are working on
+    running: usize,
+}
+
+static ACTIVITY: LazyLock<parking_lot::Mutex<Activity>> = LazyLock::new(|| {
+    parking_lot::Mutex::new(Activity {
+        last_progress: Instant::now(),
+        running: 0,
+    })
+});
+
+#[derive(Debug, Default)]
+struct ProbeState {
+    last_success: Option<Instant>,
+    last_error: Option<String>,
+}
+
+static PROBE: LazyLock<parking_lot::Mutex<ProbeState>> = LazyLock::new(Default::default);
+
+/// Must be called once the clients of all renderers are loaded.
+pub fn set_client_ready() {
+    CLIENT_READY.store(true, Ordering::SeqCst);
+}
+
+/// Must be called by the render thread when it picks up a job.
+pub fn job_started() {
+    let mut activity = ACTIVITY.lock();
+    activity.last_progress = Instant::now();
+    activity.running += 1;
+}
+
+/// Must be called by the render thread when a job finished.
+pub fn job_finished() {
+    let mut activity = ACTIVITY.lock();
+    activity.last_progress = Instant::now();
+    activity.running = activity.running.saturating_sub(1);
+}
+
+/// Renders a test frame every probe interval, forever.
+/// Probes don't render per request, so they can't pile up in the queue.
+pub async fn probe() {
+    let mut interval = tokio::time::interval(HEALTH_CONFIG.probe_interval);
+    loop {
+        interval.tick().await;
+        let params = match RenderParams::from_query("") {
+            Ok((params, _)) => params,
+            Err(err) => {
+                PROBE.lock().last_error = Some(err.to_string());
+                continue;
+            }
+        };
+        let res = render_worker::render(params, Priority::Normal).await;
+        let mut probe = PROBE.lock();
+        match res {
+            Ok(_) => {
+                probe.last_success = Some(Instant::now());
+                probe.last_error = None;
+            }
+            Err(err) => probe.last_error = Some(err.to_string()),
+        }
+    }
+}
+
+/// Whether the render thread is wedged: renders are running or waiting,
+/// but no job was picked up or finished for too long.
+fn stalled() -> Option<Duration> {
+    let activity = ACTIVITY.lock();
+    let waiting = activity.running > 0 || LOAD.report().queue_depth > 0;
+    let since = activity.last_progress.elapsed();
+    (waiting && since > HEALTH_CONFIG.stall).then_some(since)
+}
+
+/// Liveness, fails only if the render thread is wedged,
+/// a full queue alone reports `degraded`, so load balancers can shift traffic.
+pub async fn health() -> impl IntoResponse {
+    let report = LOAD.report();
+    if let Some(since) = stalled() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": "stalled",
+                "secs_since_progress": since.as_secs(),
+                "pressure": report.pressure,
+            })),
+        );
+    }
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "status": if report.degraded { "degraded" } else { "ok" },
+            "pressure": report.pressure,
+        })),
+    )
+}
+
+/// Readiness, the client must be loaded and the last test frame recent.
+pub async fn ready() -> impl IntoResponse {
+    let client_ready = CLIENT_READY.load(Ordering::SeqCst);
+    let probe = PROBE.lock();
+    let age = probe.last_success.map(|at| at.elapsed());
+    let ready = client_ready && age.is_some_and(|age| age <= HEALTH_CONFIG.probe_max_age);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "ready": ready,
+            "client_ready": client_ready,
+            "last_render_age_secs": age.map(|age| age.as_secs()),
+            "last_error": probe.last_error,
+        })),
+    )
+}
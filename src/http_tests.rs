@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+
+use crate::{
+    error::RenderError,
+    frame::Frame,
+    render_worker::{self, Job, Rendered},
+    router, CANVAS_SIZE,
+};
+
+/// Renders of this player fail like a lost gpu.
+const GPU_ERROR_PLAYER: &str = "gpu_error";
+
+/// Answers the render jobs in place of the graphics backend,
+/// with a transparent png of the canvas size.
+fn spawn_mock_renderer() {
+    let mut jobs = render_worker::init(1).remove(0);
+    let (width, height) = *CANVAS_SIZE;
+    let png = Frame::transparent(width, height)
+        .encode_png(None, true)
+        .unwrap();
+    std::thread::spawn(move || {
+        while let Some(job) = jobs.blocking_recv() {
+            let job = match job {
+                Job::Render(job) => job,
+                // there is no client to maintain
+                Job::Maintain(job) => {
+                    let _ = job.sender.send(Ok(serde_json::Value::Null));
+                    continue;
+                }
+            };
+            let _running = job.queued.start(jobs.index());
+            let _ = job.started.set(Instant::now());
+            let player = job.params.player_name.as_ref().map(|name| name.as_str());
+            let rendered = if player == Some(GPU_ERROR_PLAYER) {
+                Err(RenderError::Gpu("the mocked device was lost".to_string()))
+            } else {
+                Ok(Rendered {
+                    png: png.clone(),
+                    warnings: Vec::new(),
+                    crop: None,
+                    timing: Default::default(),
+                    game_layer: Vec::new(),
+                    trajectory: None,
+                    scene: None,
+                })
+            };
+            let _ = job.sender.send(rendered);
+        }
+    });
+}
+
+/// A player api for both providers:
+/// - `fail_all` fails at every provider, `fail_first` only at ddstats
+/// - `broken` is answered with a response that is not json
+/// - `known` is known by every provider, other players by none
+fn upstream_player(provider: &str, player: &str) -> Response {
+    match (provider, player) {
+        (_, "fail_all") | ("ddstats", "fail_first") => {
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        (_, "broken") => "<html>maintenance</html>".into_response(),
+        (_, "known") | ("skins_tw", "fail_first") => Json(serde_json::json!({
+            "skin_name": "default",
+            "color_body": 2817920,
+            "color_feet": 10076256,
+        }))
+        .into_response(),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+fn upstream_router() -> Router {
+    Router::new()
+        .route(
+            "/ddstats",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                upstream_player("ddstats", query.get("player").map_or("", String::as_str))
+            }),
+        )
+        .route(
+            "/skins_tw/:player",
+            get(|Path(player): Path<String>| async move { upstream_player("skins_tw", &player) }),
+        )
+}
+
+/// The address of the app, which is served once for all tests
+/// with a mocked renderer and a mocked player api.
+fn server() -> SocketAddr {
+    static SERVER: OnceLock<SocketAddr> = OnceLock::new();
+    *SERVER.get_or_init(|| {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_multi_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(async {
+                let localhost = SocketAddr::from(([127, 0, 0, 1], 0));
+                let upstream =
+                    axum::Server::bind(&localhost).serve(upstream_router().into_make_service());
+                let upstream_addr = upstream.local_addr();
+                // before the settings are read by the first request
+                std::env::set_var(
+                    "PLAYER_API_DDSTATS_URL",
+                    format!("http://{upstream_addr}/ddstats?player="),
+                );
+                std::env::set_var(
+                    "PLAYER_API_SKINS_TW_URL",
+                    format!("http://{upstream_addr}/skins_tw/"),
+                );
+                std::env::set_var("PLAYER_API_PROVIDERS", "ddstats,skins_tw");
+                // every test is its own client
+                std::env::set_var("TRUST_FORWARDED_FOR", "true");
+                // the failing player api must not open the circuit for other tests
+                std::env::set_var("UPSTREAM_BREAKER_FAILURES", "1000");
+                spawn_mock_renderer();
+
+                let app = axum::Server::bind(&localhost)
+                    .serve(router().into_make_service_with_connect_info::<SocketAddr>());
+                sender.send(app.local_addr()).unwrap();
+                let (upstream, app) = tokio::join!(upstream, app);
+                upstream.unwrap();
+                app.unwrap();
+            });
+        });
+        receiver.recv().unwrap()
+    })
+}
+
+/// A client ip no other request used, so the rate limit doesn't interfere.
+fn next_client_ip() -> String {
+    static NEXT: AtomicU32 = AtomicU32::new(1);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    format!("10.{}.{}.{}", (n >> 16) & 0xff, (n >> 8) & 0xff, n & 0xff)
+}
+
+async fn get_as(client_ip: &str, path_and_query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("http://{}{path_and_query}", server()))
+        .header("x-forwarded-for", client_ip)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn request(path_and_query: &str) -> reqwest::Response {
+    get_as(&next_client_ip(), path_and_query).await
+}
+
+fn content_type(res: &reqwest::Response) -> &str {
+    res.headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// The status and the json body of a response.
+async fn json(res: reqwest::Response) -> (StatusCode, serde_json::Value) {
+    let status = StatusCode::from_u16(res.status().as_u16()).unwrap();
+    let text = res.text().await.unwrap();
+    let body = serde_json::from_str(&text).unwrap_or_else(|err| panic!("{text}: {err}"));
+    (status, body)
+}
+
+#[tokio::test]
+async fn missing_params_render_the_defaults() {
+    let res = request("/").await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(content_type(&res), "image/png");
+    let frame = Frame::decode_png(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!((frame.width, frame.height), *CANVAS_SIZE);
+}
+
+#[tokio::test]
+async fn a_missing_json_body_is_rejected() {
+    let res = reqwest::Client::new()
+        .post(format!("http://{}/", server()))
+        .header("x-forwarded-for", next_client_ip())
+        .send()
+        .await
+        .unwrap();
+    let (status, body) = json(res).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_query");
+}
+
+#[tokio::test]
+async fn invalid_enum_values_are_rejected_if_strict() {
+    for (query, field, accepted) in [
+        ("eyes=sleepy", "eyes", "angry"),
+        ("weapon=sword", "weapon", "grenade"),
+        ("format=bmp", "format", "jpeg"),
+    ] {
+        let (status, body) = json(request(&format!("/?strict=true&{query}")).await).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{query}");
+        assert_eq!(body["error"], "invalid_param", "{query}");
+        assert_eq!(body["field"], field, "{query}");
+        let accepted_values = body["accepted"].as_array().unwrap();
+        assert!(
+            accepted_values.contains(&accepted.into()),
+            "{query}: {body}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn invalid_enum_values_fall_back_if_not_strict() {
+    let res = request("/?eyes=sleepy&format=bmp").await;
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(content_type(&res), "image/png");
+}
+
+#[tokio::test]
+async fn the_format_picks_the_content_type() {
+    let (width, height) = *CANVAS_SIZE;
+    for (format, content, magic) in [
+        ("png", "image/png", &b"\x89PNG"[..]),
+        ("jpeg", "image/jpeg", &b"\xff\xd8"[..]),
+        ("jpg", "image/jpeg", &b"\xff\xd8"[..]),
+        ("pam", "image/x-portable-arbitrarymap", &b"P7\n"[..]),
+    ] {
+        let res = request(&format!("/?format={format}")).await;
+        assert_eq!(res.status().as_u16(), 200, "{format}");
+        assert_eq!(content_type(&res), content, "{format}");
+        let body = res.bytes().await.unwrap();
+        assert!(body.starts_with(magic), "{format}");
+    }
+
+    let res = request("/?format=raw").await;
+    assert_eq!(content_type(&res), "application/octet-stream");
+    assert_eq!(res.headers()["x-image-width"], width.to_string().as_str());
+    assert_eq!(res.headers()["x-image-height"], height.to_string().as_str());
+    let body = res.bytes().await.unwrap();
+    assert_eq!(body.len(), (width * height * 4) as usize);
+}
+
+#[tokio::test]
+async fn a_client_is_limited_after_its_burst() {
+    let client_ip = next_client_ip();
+    let mut statuses = Vec::new();
+    for _ in 0..20 {
+        statuses.push(get_as(&client_ip, "/resolve").await.status().as_u16());
+    }
+    assert_eq!(statuses[0], 200);
+    assert!(statuses.contains(&429), "{statuses:?}");
+
+    let res = get_as(&client_ip, "/resolve").await;
+    assert!(res.headers().contains_key("retry-after"));
+    let (status, body) = json(res).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"], "rate_limited");
+
+    // other clients are not limited
+    assert_eq!(request("/resolve").await.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_next_provider_is_asked_if_one_fails() {
+    let (status, body) =
+        json(request("/resolve?player_name=fail_first&use_player_api=true").await).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["skin"]["from_player_api"], true);
+    assert_eq!(body["body_color"]["legacy"], 2817920);
+}
+
+#[tokio::test]
+async fn failed_player_apis_only_fail_strict_requests() {
+    let (status, body) =
+        json(request("/resolve?player_name=fail_all&use_player_api=true").await).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["skin"]["from_player_api"], false);
+    assert_eq!(body["skin"]["player_api_error"]["error"], "upstream");
+
+    let res = request("/?player_name=fail_all&use_player_api=true&strict=true").await;
+    let (status, body) = json(res).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["error"], "upstream");
+}
+
+#[tokio::test]
+async fn unreadable_player_api_responses_are_reported() {
+    let res = request("/?player_name=broken&use_player_api=true&strict=true").await;
+    let (status, body) = json(res).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body["error"], "player_api_invalid");
+}
+
+#[tokio::test]
+async fn render_failures_are_server_errors() {
+    let (status, body) = json(request(&format!("/?player_name={GPU_ERROR_PLAYER}")).await).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(body["error"], "gpu");
+}
@@ -0,0 +1,217 @@
+use std::{collections::HashMap, hash::Hash};
+
+/// The running task per key, a newer task of the same key supersedes the older one.
+/// Whoever removes the entry of a task owns its response.
+pub struct InFlight<K, I, V> {
+    tasks: parking_lot::Mutex<HashMap<K, (I, V)>>,
+}
+
+impl<K, I, V> Default for InFlight<K, I, V> {
+    fn default() -> Self {
+        Self {
+            tasks: Default::default(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, I: PartialEq + Clone, V> InFlight<K, I, V> {
+    /// Registers the task `id` for `key`, returns the task it superseded.
+    /// The entry is removed once the guard is dropped, unless a newer task superseded it.
+    pub fn insert(&self, key: K, id: I, task: V) -> (InFlightGuard<'_, K, I, V>, Option<V>) {
+        let superseded = self
+            .tasks
+            .lock()
+            .insert(key.clone(), (id.clone(), task))
+            .map(|(_, task)| task);
+        let guard = InFlightGuard {
+            in_flight: self,
+            key,
+            id,
+        };
+        (guard, superseded)
+    }
+
+    /// Registers the task `id` for `key` unless a task of `key` is running,
+    /// for tasks that must not run twice at a time. See [`InFlight::insert`].
+    pub fn try_insert(&self, key: K, id: I, task: V) -> Option<InFlightGuard<'_, K, I, V>> {
+        let mut tasks = self.tasks.lock();
+        if tasks.contains_key(&key) {
+            return None;
+        }
+        tasks.insert(key.clone(), (id.clone(), task));
+        Some(InFlightGuard {
+            in_flight: self,
+            key,
+            id,
+        })
+    }
+
+    /// Removes the entry of `id`, returns whether it was still registered.
+    fn remove(&self, key: &K, id: &I) -> bool {
+        let mut tasks = self.tasks.lock();
+        let owned = tasks.get(key).is_some_and(|(task_id, _)| task_id == id);
+        if owned {
+            tasks.remove(key);
+        }
+        owned
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.tasks.lock().len()
+    }
+}
+
+/// Removes the entry of a task on drop, so a panicked or cancelled task doesn't
+/// stay registered and supersede nothing but a dead entry.
+pub struct InFlightGuard<'a, K: Hash + Eq + Clone, I: PartialEq + Clone, V> {
+    in_flight: &'a InFlight<K, I, V>,
+    key: K,
+    id: I,
+}
+
+impl<K: Hash + Eq + Clone, I: PartialEq + Clone, V> InFlightGuard<'_, K, I, V> {
+    /// Removes the entry, returns whether the task still owns the response,
+    /// `false` if a newer task superseded it and already responded.
+    pub fn finish(self) -> bool {
+        self.in_flight.remove(&self.key, &self.id)
+    }
+}
+
+impl<K: Hash + Eq + Clone, I: PartialEq + Clone, V> Drop for InFlightGuard<'_, K, I, V> {
+    fn drop(&mut self) {
+        self.in_flight.remove(&self.key, &self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[test]
+    fn a_finished_task_owns_its_response() {
+        let in_flight = InFlight::default();
+        let (guard, superseded) = in_flight.insert("user", 1, "first");
+        assert!(superseded.is_none());
+        assert!(guard.finish());
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[test]
+    fn a_newer_task_supersedes_the_older_one() {
+        let in_flight = InFlight::default();
+        let (older, _) = in_flight.insert("user", 1, "first");
+        let (newer, superseded) = in_flight.insert("user", 2, "second");
+        assert_eq!(superseded, Some("first"));
+        assert!(!older.finish());
+        // the superseded task must not remove the newer entry
+        assert_eq!(in_flight.len(), 1);
+        assert!(newer.finish());
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[test]
+    fn other_keys_are_not_superseded() {
+        let in_flight = InFlight::default();
+        let (first, _) = in_flight.insert("user", 1, ());
+        let (other, superseded) = in_flight.insert("other user", 2, ());
+        assert!(superseded.is_none());
+        assert!(first.finish());
+        assert!(other.finish());
+    }
+
+    #[test]
+    fn a_running_task_is_not_started_twice() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.try_insert("user", 1, ()).unwrap();
+        assert!(in_flight.try_insert("user", 2, ()).is_none());
+        assert!(in_flight.try_insert("other user", 3, ()).is_some());
+        drop(guard);
+        assert!(in_flight.try_insert("user", 4, ()).is_some());
+    }
+
+    #[test]
+    fn a_dropped_guard_removes_the_entry() {
+        let in_flight = InFlight::default();
+        let (guard, _) = in_flight.insert("user", 1, ());
+        drop(guard);
+        assert_eq!(in_flight.len(), 0);
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let (_guard, _) = in_flight.insert("user", 2, ());
+            panic!("the render panicked");
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[test]
+    fn a_cancelled_task_removes_the_entry() {
+        let in_flight = Arc::new(InFlight::default());
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let task = tokio::spawn({
+                let in_flight = in_flight.clone();
+                async move {
+                    let (_guard, _) = in_flight.insert("user", 1, ());
+                    std::future::pending::<()>().await;
+                }
+            });
+            tokio::task::yield_now().await;
+            assert_eq!(in_flight.len(), 1);
+            task.abort();
+            assert!(task.await.unwrap_err().is_cancelled());
+        });
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    /// Every task either owns its response or was superseded by a newer one,
+    /// never both and never neither, however the tasks interleave.
+    #[test]
+    fn concurrent_tasks_respond_exactly_once() {
+        const TASKS: usize = 64;
+        let in_flight = Arc::new(InFlight::default());
+        let responses: Arc<Vec<AtomicUsize>> =
+            Arc::new((0..TASKS).map(|_| AtomicUsize::new(0)).collect());
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(4)
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let tasks: Vec<_> = (0..TASKS)
+                .map(|id| {
+                    let in_flight = in_flight.clone();
+                    let responses = responses.clone();
+                    tokio::spawn(async move {
+                        let (guard, superseded) = in_flight.insert("user", id, id);
+                        // the newer task responds for the superseded one
+                        if let Some(superseded) = superseded {
+                            responses[superseded].fetch_add(1, Ordering::SeqCst);
+                        }
+                        for _ in 0..id % 4 {
+                            tokio::task::yield_now().await;
+                        }
+                        if guard.finish() {
+                            responses[id].fetch_add(1, Ordering::SeqCst);
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        for (id, responses) in responses.iter().enumerate() {
+            assert_eq!(responses.load(Ordering::SeqCst), 1, "task {id}");
+        }
+        assert_eq!(in_flight.len(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::LazyLock,
+    task::{Context, Poll},
+};
+
+use anyhow::anyhow;
+use axum::Router;
+use tokio::{
+    net::{UnixListener, UnixStream},
+    task::JoinSet,
+};
+
+use crate::shutdown;
+
+/// An address the http server listens on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listener {
+    Tcp(SocketAddr),
+    /// For proxies on the same host, clients are only known by `X-Forwarded-For`
+    Unix(PathBuf),
+}
+
+/// `host:port`, `[ipv6]:port`, a host without port or `unix:<path>`.
+pub fn parse_listener(value: &str, default_port: u16) -> anyhow::Result<Listener> {
+    let value = value.trim();
+    if let Some(path) = value.strip_prefix("unix:") {
+        if path.is_empty() {
+            return Err(anyhow!("the unix socket path is empty"));
+        }
+        return Ok(Listener::Unix(PathBuf::from(path)));
+    }
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Ok(Listener::Tcp(addr));
+    }
+    let host = value.trim_start_matches('[').trim_end_matches(']');
+    host.parse::<std::net::IpAddr>()
+        .map(|ip| Listener::Tcp(SocketAddr::new(ip, default_port)))
+        .map_err(|_| anyhow!("{value} is not an address"))
+}
+
+/// - `BIND` comma separated addresses, e.g. `0.0.0.0:3002,[::]:3002,unix:/run/dd-pg-web.sock`,
+///   addresses without port use `PORT` (`127.0.0.1` is default)
+/// - `PORT` (3002 is default)
+pub static LISTENERS: LazyLock<Vec<Listener>> = LazyLock::new(|| {
+    let port: u16 = std::env::var("PORT")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u16>().map_err(|err| anyhow!(err)))
+        .unwrap_or(3002);
+    let bind = std::env::var("BIND").unwrap_or_else(|_| "127.0.0.1".to_string());
+    let listeners: Vec<Listener> = bind
+        .split(',')
+        .filter(|value| !value.trim().is_empty())
+        .filter_map(|value| match parse_listener(value, port) {
+            Ok(listener) => Some(listener),
+            Err(err) => {
+                println!("ignoring bind address {value}: {err}");
+                None
+            }
+        })
+        .collect();
+    if listeners.is_empty() {
+        vec![Listener::Tcp(SocketAddr::from(([127, 0, 0, 1], port)))]
+    } else {
+        listeners
+    }
+});
+
+struct UnixAccept(UnixListener);
+
+impl hyper::server::accept::Accept for UnixAccept {
+    type Conn = UnixStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _)| stream)))
+    }
+}
+
+async fn serve_one(app: Router, listener: Listener) -> anyhow::Result<()> {
+    match listener {
+        Listener::Tcp(addr) => {
+            let server = axum::Server::try_bind(&addr)?;
+            println!("listening on {addr}");
+            server
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown::requested())
+                .await?;
+        }
+        Listener::Unix(path) => {
+            // a previous run may have left the socket behind
+            if tokio::fs::try_exists(&path).await.unwrap_or_default() {
+                tokio::fs::remove_file(&path).await?;
+            }
+            let listener = UnixListener::bind(&path)?;
+            println!("listening on unix:{}", path.display());
+            axum::Server::builder(UnixAccept(listener))
+                .serve(app.into_make_service())
+                .with_graceful_shutdown(shutdown::requested())
+                .await?;
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+    }
+    Ok(())
+}
+
+/// Serves the app on all [`LISTENERS`] until shutdown or until one of them fails.
+pub async fn serve(app: Router) {
+    let mut servers = JoinSet::new();
+    for listener in LISTENERS.iter() {
+        let app = app.clone();
+        let listener = listener.clone();
+        servers.spawn(async move {
+            let res = serve_one(app, listener.clone()).await;
+            (listener, res)
+        });
+    }
+    while let Some(res) = servers.join_next().await {
+        match res {
+            Ok((_, Ok(()))) => {}
+            Ok((listener, Err(err))) => {
+                println!("serving {listener:?} failed: {err}");
+                return;
+            }
+            Err(err) => {
+                println!("a server panicked: {err}");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+use crate::{render_worker::RENDERER_POOL_SIZE, settings};
+
+/// The window over which queue waits and utilization are aggregated.
+const WINDOW: Duration = Duration::from_secs(60);
+
+pub static LOAD: LazyLock<LoadTracker> =
+    LazyLock::new(|| LoadTracker::new(LoadThresholds::from_env()));
+
+/// Keeps timestamped samples of the last `window`.
+#[derive(Debug)]
+pub struct SlidingWindow {
+    window: Duration,
+    samples: VecDeque<(Instant, Duration)>,
+}
+
+impl SlidingWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: Default::default(),
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.saturating_duration_since(*at) > self.window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn push(&mut self, now: Instant, value: Duration) {
+        self.prune(now);
+        self.samples.push_back((now, value));
+    }
+
+    /// The `p` (0..=1) percentile of all samples in the window.
+    pub fn percentile(&mut self, now: Instant, p: f64) -> Duration {
+        self.prune(now);
+        let mut values: Vec<Duration> = self.samples.iter().map(|(_, v)| *v).collect();
+        if values.is_empty() {
+            return Duration::ZERO;
+        }
+        values.sort_unstable();
+        let index = ((values.len() - 1) as f64 * p.clamp(0.0, 1.0)).round() as usize;
+        values[index]
+    }
+
+    /// Sum of all samples in the window, where samples are
+    /// busy periods that ended at their timestamp.
+    /// Periods that started before the window are clipped.
+    pub fn busy_ratio(&mut self, now: Instant) -> f64 {
+        self.prune(now);
+        let window_start = now.checked_sub(self.window);
+        let busy: Duration = self
+            .samples
+            .iter()
+            .map(|(end, busy)| match window_start {
+                Some(window_start) => (*busy).min(end.saturating_duration_since(window_start)),
+                None => *busy,
+            })
+            .sum();
+        (busy.as_secs_f64() / self.window.as_secs_f64()).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadThresholds {
+    /// Queue depth that counts as fully loaded
+    pub queue_full: usize,
+    /// p95 queue wait that counts as fully loaded
+    pub queue_wait_full: Duration,
+    /// Pressure above which the health check reports `degraded`
+    pub degraded: f64,
+    /// Whether load shedding may engage at all
+    pub shed_enabled: bool,
+    /// Pressure above which load shedding engages
+    pub shed_engage: f64,
+    /// Pressure below which load shedding disengages again
+    pub shed_disengage: f64,
+}
+
+/// The settings of [`LoadThresholds`].
+pub const LOAD_SETTINGS: [&str; 6] = [
+    "LOAD_QUEUE_FULL",
+    "LOAD_QUEUE_WAIT_FULL_MS",
+    "LOAD_DEGRADED_THRESHOLD",
+    "LOAD_SHED_ENABLED",
+    "LOAD_SHED_ENGAGE",
+    "LOAD_SHED_DISENGAGE",
+];
+
+impl LoadThresholds {
+    /// - `LOAD_QUEUE_FULL` (16 is default)
+    /// - `LOAD_QUEUE_WAIT_FULL_MS` (2000 is default)
+    /// - `LOAD_DEGRADED_THRESHOLD` (0.8 is default)
+    /// - `LOAD_SHED_ENABLED` (true is default)
+    /// - `LOAD_SHED_ENGAGE` (0.9 is default)
+    /// - `LOAD_SHED_DISENGAGE` (0.7 is default)
+    pub fn from_env() -> Self {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Like [`LoadThresholds::from_env`] with the settings of `var`.
+    pub fn from_lookup(var: impl Fn(&str) -> Option<String>) -> Self {
+        let queue_full: usize = var("LOAD_QUEUE_FULL")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(16);
+        let queue_wait_full: u64 = var("LOAD_QUEUE_WAIT_FULL_MS")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(2000);
+        let degraded: f64 = var("LOAD_DEGRADED_THRESHOLD")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.8);
+        let shed_enabled: bool = var("LOAD_SHED_ENABLED")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(true);
+        let shed_engage: f64 = var("LOAD_SHED_ENGAGE")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.9);
+        let shed_disengage: f64 = var("LOAD_SHED_DISENGAGE")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.7);
+        let shed_engage = shed_engage.clamp(0.0, 1.0);
+        Self {
+            queue_full: queue_full.max(1),
+            queue_wait_full: Duration::from_millis(queue_wait_full.max(1)),
+            degraded: degraded.clamp(0.0, 1.0),
+            shed_enabled,
+            shed_engage,
+            shed_disengage: shed_disengage.clamp(0.0, shed_engage),
+        }
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "queue_full": self.queue_full,
+            "queue_wait_full_ms": self.queue_wait_full.as_millis() as u64,
+            "degraded": self.degraded,
+            "shed_enabled": self.shed_enabled,
+            "shed_engage": self.shed_engage,
+            "shed_disengage": self.shed_disengage,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadReport {
+    pub queue_depth: usize,
+    pub p95_queue_wait_ms: u64,
+    /// Render worker utilization in percent
+    pub utilization: f64,
+    /// Overall pressure in 0..=1
+    pub pressure: f64,
+    pub degraded: bool,
+    /// Whether load shedding is engaged
+    pub shedding: bool,
+    /// How often load shedding engaged since the start
+    pub shed_activations: u64,
+}
+
+/// The pressure is the highest of the normalized queue depth,
+/// normalized p95 queue wait and worker utilization,
+/// so saturating any of them counts as full load.
+pub fn pressure(
+    thresholds: &LoadThresholds,
+    queue_depth: usize,
+    p95_wait: Duration,
+    utilization: f64,
+) -> f64 {
+    let depth = queue_depth as f64 / thresholds.queue_full as f64;
+    let wait = p95_wait.as_secs_f64() / thresholds.queue_wait_full.as_secs_f64();
+    depth.max(wait).max(utilization).clamp(0.0, 1.0)
+}
+
+pub struct LoadTracker {
+    queue_depth: AtomicUsize,
+    queue_waits: parking_lot::Mutex<SlidingWindow>,
+    /// Busy periods by renderer
+    busy: parking_lot::Mutex<Vec<SlidingWindow>>,
+    thresholds: parking_lot::RwLock<LoadThresholds>,
+    shedding: AtomicBool,
+    shed_activations: AtomicU64,
+}
+
+/// A job waiting for the renderer.
+pub struct QueuedJob {
+    tracker: &'static LoadTracker,
+    enqueued: Instant,
+    started: bool,
+}
+
+impl QueuedJob {
+    /// Must be called once a renderer picked up the job, `renderer` is its index in the pool.
+    pub fn start(mut self, renderer: usize) -> RunningJob {
+        let now = Instant::now();
+        self.started = true;
+        self.tracker.queue_depth.fetch_sub(1, Ordering::SeqCst);
+        self.tracker
+            .queue_waits
+            .lock()
+            .push(now, now.saturating_duration_since(self.enqueued));
+        RunningJob {
+            tracker: self.tracker,
+            renderer,
+            started: now,
+        }
+    }
+}
+
+impl Drop for QueuedJob {
+    fn drop(&mut self) {
+        // the request was dropped before it was rendered
+        if !self.started {
+            self.tracker.queue_depth.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A job that is currently rendered, records its busy time on drop.
+pub struct RunningJob {
+    tracker: &'static LoadTracker,
+    renderer: usize,
+    started: Instant,
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        let now = Instant::now();
+        let mut busy = self.tracker.busy.lock();
+        if busy.len() <= self.renderer {
+            busy.resize_with(self.renderer + 1, || SlidingWindow::new(WINDOW));
+        }
+        busy[self.renderer].push(now, now.saturating_duration_since(self.started));
+    }
+}
+
+impl LoadTracker {
+    pub fn new(thresholds: LoadThresholds) -> Self {
+        Self {
+            queue_depth: Default::default(),
+            queue_waits: parking_lot::Mutex::new(SlidingWindow::new(WINDOW)),
+            busy: Default::default(),
+            thresholds: parking_lot::RwLock::new(thresholds),
+            shedding: Default::default(),
+            shed_activations: Default::default(),
+        }
+    }
+
+    /// Applies from the next [`LoadTracker::shedding`] and [`LoadTracker::report`] on,
+    /// the shedding state is kept until the new thresholds cross it.
+    pub fn set_thresholds(&self, thresholds: LoadThresholds) {
+        *self.thresholds.write() = thresholds;
+    }
+
+    pub fn enqueue(&'static self) -> QueuedJob {
+        self.queue_depth.fetch_add(1, Ordering::SeqCst);
+        QueuedJob {
+            tracker: self,
+            enqueued: Instant::now(),
+            started: false,
+        }
+    }
+
+    pub fn report(&self) -> LoadReport {
+        let now = Instant::now();
+        let thresholds = self.thresholds.read().clone();
+        let queue_depth = self.queue_depth.load(Ordering::SeqCst);
+        let p95_wait = self.queue_waits.lock().percentile(now, 0.95);
+        let renderers = self.renderer_utilization();
+        let utilization = renderers.iter().sum::<f64>() / renderers.len().max(1) as f64;
+        let pressure = pressure(&thresholds, queue_depth, p95_wait, utilization);
+        LoadReport {
+            queue_depth,
+            p95_queue_wait_ms: p95_wait.as_millis() as u64,
+            utilization: utilization * 100.0,
+            pressure,
+            degraded: pressure > thresholds.degraded,
+            shedding: self.shedding.load(Ordering::SeqCst),
+            shed_activations: self.shed_activations.load(Ordering::SeqCst),
+        }
+    }
+
+    /// The busy ratio (0..=1) of every renderer that rendered yet, by its index in the pool.
+    pub fn renderer_utilization(&self) -> Vec<f64> {
+        let now = Instant::now();
+        let mut busy = self.busy.lock();
+        let pool = *RENDERER_POOL_SIZE;
+        if busy.len() < pool {
+            busy.resize_with(pool, || SlidingWindow::new(WINDOW));
+        }
+        busy.iter_mut()
+            .map(|window| window.busy_ratio(now))
+            .collect()
+    }
+
+    /// Whether requests should be degraded to keep up.
+    /// Shedding engages above `shed_engage` and only disengages below
+    /// `shed_disengage`, so it doesn't flap around a single threshold.
+    pub fn shedding(&self) -> bool {
+        let thresholds = self.thresholds.read().clone();
+        if !thresholds.shed_enabled {
+            self.shedding.store(false, Ordering::SeqCst);
+            return false;
+        }
+        let pressure = self.report().pressure;
+        let shedding = self.shedding.load(Ordering::SeqCst);
+        if !shedding && pressure > thresholds.shed_engage {
+            if !self.shedding.swap(true, Ordering::SeqCst) {
+                self.shed_activations.fetch_add(1, Ordering::SeqCst);
+                println!("load shedding engaged at pressure {pressure:.2}");
+            }
+            true
+        } else if shedding && pressure < thresholds.shed_disengage {
+            if self.shedding.swap(false, Ordering::SeqCst) {
+                println!("load shedding disengaged at pressure {pressure:.2}");
+            }
+            false
+        } else {
+            shedding
+        }
+    }
+}
+
+/// `POST /admin/load/reload`, reads the [`LOAD_SETTINGS`] again, see [`settings::reread`].
+pub async fn reload_thresholds() -> Response {
+    match settings::reread(&LOAD_SETTINGS) {
+        Ok(values) => {
+            let thresholds = LoadThresholds::from_lookup(|name| values.get(name).cloned());
+            let json = thresholds.json();
+            LOAD.set_thresholds(thresholds);
+            println!("load thresholds reloaded: {json}");
+            Json(serde_json::json!({ "thresholds": json })).into_response()
+        }
+        Err(problems) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "errors": problems })),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn thresholds() -> LoadThresholds {
+        LoadThresholds {
+            queue_full: 10,
+            queue_wait_full: ms(1000),
+            degraded: 0.8,
+            shed_enabled: true,
+            shed_engage: 0.9,
+            shed_disengage: 0.7,
+        }
+    }
+
+    #[test]
+    fn samples_expire_after_the_window() {
+        let start = Instant::now();
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        window.push(start, ms(500));
+        window.push(start + Duration::from_secs(30), ms(100));
+        assert_eq!(
+            window.percentile(start + Duration::from_secs(60), 1.0),
+            ms(500)
+        );
+        // the first sample left the window, the second is still in it
+        assert_eq!(
+            window.percentile(start + Duration::from_secs(61), 1.0),
+            ms(100)
+        );
+        assert_eq!(
+            window.percentile(start + Duration::from_secs(91), 1.0),
+            Duration::ZERO
+        );
+    }
+
+    #[test]
+    fn an_empty_window_is_zero() {
+        let now = Instant::now();
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        assert_eq!(window.percentile(now, 0.95), Duration::ZERO);
+        assert_eq!(window.busy_ratio(now), 0.0);
+    }
+
+    #[test]
+    fn the_p95_of_few_samples_is_one_of_them() {
+        let now = Instant::now();
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        window.push(now, ms(40));
+        assert_eq!(window.percentile(now, 0.95), ms(40));
+        window.push(now, ms(10));
+        assert_eq!(window.percentile(now, 0.95), ms(40));
+        for value in [30, 20, 50] {
+            window.push(now, ms(value));
+        }
+        assert_eq!(window.percentile(now, 0.95), ms(50));
+        assert_eq!(window.percentile(now, 0.5), ms(30));
+        assert_eq!(window.percentile(now, 0.0), ms(10));
+    }
+
+    #[test]
+    fn percentiles_out_of_range_are_clamped() {
+        let now = Instant::now();
+        let mut window = SlidingWindow::new(Duration::from_secs(60));
+        for value in [10, 20, 30] {
+            window.push(now, ms(value));
+        }
+        assert_eq!(window.percentile(now, -1.0), ms(10));
+        assert_eq!(window.percentile(now, 2.0), ms(30));
+    }
+
+    #[test]
+    fn busy_periods_are_clipped_to_the_window() {
+        let start = Instant::now();
+        let mut window = SlidingWindow::new(Duration::from_secs(10));
+        window.push(start + Duration::from_secs(10), Duration::from_secs(4));
+        assert_eq!(window.busy_ratio(start + Duration::from_secs(10)), 0.4);
+        // 2 of the 4 seconds started before the window
+        assert_eq!(window.busy_ratio(start + Duration::from_secs(18)), 0.2);
+    }
+
+    #[test]
+    fn the_busy_ratio_is_at_most_one() {
+        let now = Instant::now() + Duration::from_secs(10);
+        let mut window = SlidingWindow::new(Duration::from_secs(10));
+        window.push(now, Duration::from_secs(8));
+        window.push(now, Duration::from_secs(8));
+        assert_eq!(window.busy_ratio(now), 1.0);
+    }
+
+    #[test]
+    fn the_pressure_is_the_highest_load() {
+        let thresholds = thresholds();
+        assert_eq!(pressure(&thresholds, 0, Duration::ZERO, 0.0), 0.0);
+        assert_eq!(pressure(&thresholds, 5, ms(200), 0.1), 0.5);
+        assert_eq!(pressure(&thresholds, 1, ms(600), 0.1), 0.6);
+        assert_eq!(pressure(&thresholds, 1, ms(200), 0.7), 0.7);
+    }
+
+    #[test]
+    fn the_pressure_is_clamped_to_one() {
+        let thresholds = thresholds();
+        assert_eq!(pressure(&thresholds, 100, Duration::ZERO, 0.0), 1.0);
+        assert_eq!(pressure(&thresholds, 0, Duration::from_secs(60), 0.0), 1.0);
+        assert_eq!(pressure(&thresholds, 0, Duration::ZERO, 1.5), 1.0);
+        assert_eq!(pressure(&thresholds, 0, Duration::ZERO, -0.5), 0.0);
+    }
+
+    fn tracker(thresholds: LoadThresholds) -> &'static LoadTracker {
+        Box::leak(Box::new(LoadTracker::new(thresholds)))
+    }
+
+    /// Queues `depth` jobs in total, the pressure is `depth / 10` with the test thresholds.
+    fn queue(tracker: &'static LoadTracker, jobs: &mut Vec<QueuedJob>, depth: usize) {
+        jobs.truncate(depth);
+        while jobs.len() < depth {
+            jobs.push(tracker.enqueue());
+        }
+    }
+
+    #[test]
+    fn shedding_engages_and_disengages_with_hysteresis() {
+        let tracker = tracker(thresholds());
+        let mut jobs = Vec::new();
+        assert!(!tracker.shedding());
+
+        queue(tracker, &mut jobs, 9);
+        assert!(!tracker.shedding(), "0.9 is not above the engage threshold");
+        queue(tracker, &mut jobs, 10);
+        assert!(tracker.shedding());
+        // between the thresholds the state stays as it is
+        queue(tracker, &mut jobs, 8);
+        assert!(tracker.shedding());
+        queue(tracker, &mut jobs, 7);
+        assert!(
+            tracker.shedding(),
+            "0.7 is not below the disengage threshold"
+        );
+        queue(tracker, &mut jobs, 6);
+        assert!(!tracker.shedding());
+        queue(tracker, &mut jobs, 8);
+        assert!(!tracker.shedding());
+
+        let report = tracker.report();
+        assert_eq!(report.shed_activations, 1);
+        assert!(!report.shedding);
+    }
+
+    #[test]
+    fn every_engagement_is_counted() {
+        let tracker = tracker(thresholds());
+        let mut jobs = Vec::new();
+        for _ in 0..3 {
+            queue(tracker, &mut jobs, 10);
+            assert!(tracker.shedding());
+            assert!(tracker.shedding());
+            queue(tracker, &mut jobs, 0);
+            assert!(!tracker.shedding());
+        }
+        assert_eq!(tracker.report().shed_activations, 3);
+    }
+
+    #[test]
+    fn disabled_shedding_never_engages() {
+        let tracker = tracker(LoadThresholds {
+            shed_enabled: false,
+            ..thresholds()
+        });
+        let mut jobs = Vec::new();
+        queue(tracker, &mut jobs, 20);
+        assert!(!tracker.shedding());
+        let report = tracker.report();
+        assert_eq!(report.pressure, 1.0);
+        assert!(report.degraded);
+        assert_eq!(report.shed_activations, 0);
+    }
+
+    #[test]
+    fn shedding_uses_reloaded_thresholds() {
+        let tracker = tracker(thresholds());
+        let mut jobs = Vec::new();
+        queue(tracker, &mut jobs, 9);
+        assert!(!tracker.shedding());
+
+        tracker.set_thresholds(LoadThresholds {
+            shed_engage: 0.8,
+            shed_disengage: 0.5,
+            ..thresholds()
+        });
+        assert!(tracker.shedding(), "0.9 is above the new engage threshold");
+        queue(tracker, &mut jobs, 6);
+        assert!(
+            tracker.shedding(),
+            "0.6 is not below the new disengage threshold"
+        );
+
+        tracker.set_thresholds(LoadThresholds {
+            shed_enabled: false,
+            ..thresholds()
+        });
+        assert!(!tracker.shedding());
+        assert!(!tracker.report().shedding);
+    }
+
+    #[test]
+    fn thresholds_are_read_from_the_settings() {
+        let settings = [("LOAD_SHED_ENGAGE", "0.5"), ("LOAD_QUEUE_FULL", "0")];
+        let thresholds = LoadThresholds::from_lookup(|name| {
+            settings
+                .iter()
+                .find(|(setting, _)| *setting == name)
+                .map(|(_, value)| value.to_string())
+        });
+        assert_eq!(thresholds.shed_engage, 0.5);
+        // the disengage default is above the engage threshold
+        assert_eq!(thresholds.shed_disengage, 0.5);
+        assert_eq!(thresholds.queue_full, 1);
+        assert_eq!(thresholds.queue_wait_full, ms(2000));
+        assert!(thresholds.shed_enabled);
+    }
+
+    #[test]
+    fn dropped_and_started_jobs_leave_the_queue() {
+        let tracker = tracker(thresholds());
+        let queued = tracker.enqueue();
+        let dropped = tracker.enqueue();
+        assert_eq!(tracker.report().queue_depth, 2);
+        drop(dropped);
+        assert_eq!(tracker.report().queue_depth, 1);
+        let running = queued.start(0);
+        assert_eq!(tracker.report().queue_depth, 0);
+        drop(running);
+        assert_eq!(tracker.report().queue_depth, 0);
+    }
+}
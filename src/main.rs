@@ -1,12 +1,71 @@
+mod admin;
+mod cameras;
+mod cli;
+mod color;
+mod containers;
+mod cors;
+mod device;
+mod download;
+mod error;
+mod etag;
+mod frame;
+mod game_layer;
+#[cfg(test)]
+mod golden;
+mod gpu;
+mod health;
+#[cfg(test)]
+mod http_tests;
+mod in_flight;
+mod listen;
+mod load;
+mod map_preview;
+mod maps;
+mod metrics;
+mod minimap;
+mod names;
+mod openapi;
+mod options;
+mod overlay;
+mod params;
+mod physics;
+mod player_api;
+mod playground;
+mod prefs;
+mod presets;
+mod quantize;
+mod rate_limit;
+mod render_cache;
+mod render_worker;
+mod resolve;
+mod scene;
+mod settings;
+mod sheet;
+mod shutdown;
+mod signed_url;
+mod skins;
+mod support_bundle;
+mod upload;
+mod upstream;
+mod version;
+
 use anyhow::anyhow;
 use axum::{
-    async_trait, body::StreamBody, extract::Query, http::header, response::IntoResponse,
-    routing::get, Router,
+    async_trait,
+    body::{Bytes, StreamBody},
+    extract::RawQuery,
+    handler::Handler,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
 };
 use base::system::{System, SystemTimeInterface};
 use base_fs::filesys::FileSystem;
 use base_http::http::HttpClient;
 use base_io::io::Io;
+use clap::Parser;
 use client_containers::{
     emoticons::{EmoticonsContainer, EMOTICONS_CONTAINER_PATH},
     entities::{EntitiesContainer, ENTITIES_CONTAINER_PATH},
@@ -26,7 +85,9 @@ use client_render_base::{
     render::{
         animation::AnimState,
         canvas_mapping::CanvasMappingIngame,
-        default_anim::{base_anim, idle_anim, inair_anim},
+        default_anim::{
+            base_anim, idle_anim, inair_anim, run_left_anim, run_right_anim, walk_anim,
+        },
         tee::{RenderTee, RenderTeeHandMath, TeeRenderHands, TeeRenderInfo, TeeRenderSkinColor},
         toolkit::ToolkitRender,
     },
@@ -36,9 +97,10 @@ use config::config::{ConfigBackend, ConfigDebug, ConfigGfx, ConfigSound};
 use game_interface::types::{
     emoticons::{EmoticonType, IntoEnumIterator},
     network_string::NetworkString,
-    render::character::{CharacterRenderInfo, TeeEye},
+    render::character::{
+        CharacterBuff, CharacterBuffInfo, CharacterDebuff, CharacterDebuffInfo, CharacterRenderInfo,
+    },
     resource_key::{NetworkResourceKey, ResourceKey},
-    weapons::WeaponType,
 };
 use graphics::graphics::graphics::{Graphics, ScreenshotCb};
 use graphics_backend::{
@@ -50,109 +112,82 @@ use graphics_backend::{
 
 use graphics_backend_traits::traits::GraphicsBackendInterface;
 
-use graphics_types::rendering::{ColorRgba, State};
+use cameras::CameraSource;
+use color::TeePart;
+use containers::ContainerKind;
+use error::RenderError;
+use frame::{Frame, PngOptions, Rect};
+use game_layer::{MapOverlay, TileCategory};
+use graphics_types::rendering::State;
+use hashlink::LruCache;
+use in_flight::InFlight;
+use load::LOAD;
 use math::math::{
     normalize,
     vector::{dvec2, vec2},
 };
-use palette::convert::FromColorUnclamped;
+use params::{AssetKeys, Buff, Deprecation, OutputFormat, OutputSize, ParamError, RenderParams};
 use pool::datatypes::PoolLinkedHashMap;
 use rayon::{ThreadPool, ThreadPoolBuilder};
-use serde::{Deserialize, Serialize};
+use render_cache::{CachePolicy, Lookup, DEFAULT_CACHE_POLICY, RENDER_CACHE};
+use render_worker::{Maintenance, Priority, RenderTiming, Rendered};
+use resolve::Resolved;
+use scene::{NameplateMark, SceneItem, SceneProjectile};
 use serenity::all::{
-    Context, CreateAttachment, CreateCommand, CreateCommandOption, CreateInteractionResponse,
-    CreateInteractionResponseMessage, EventHandler, GatewayIntents, GuildId, Interaction, Mention,
-    Ready, StandardFramework,
+    ButtonStyle, Command, CommandDataOptionValue, CommandInteraction, ComponentInteraction,
+    ComponentInteractionDataKind, Context, CreateActionRow, CreateAttachment,
+    CreateAutocompleteResponse, CreateButton, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseFollowup, CreateInteractionResponseMessage,
+    CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption, EditInteractionResponse,
+    EventHandler, GatewayIntents, GuildId, Interaction, InteractionId, Member, Mention, MessageId,
+    Ready, RoleId, StandardFramework, UserId,
 };
+use sheet::Sheet;
+use skins::SkinResolution;
 use sound::sound::SoundManager;
 use sound_backend::sound_backend::SoundBackend;
 use std::{
     cell::RefCell,
+    collections::HashMap,
+    future::Future,
     io::Cursor,
-    net::SocketAddr,
     rc::Rc,
-    sync::{Arc, LazyLock},
-    time::Duration,
-};
-use tokio::sync::{
-    oneshot::{self, Sender},
-    Mutex,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock,
+    },
+    time::{Duration, Instant},
 };
+use tokio::{sync::oneshot::Sender, task::AbortHandle};
 use tokio_util::io::ReaderStream;
 use ui_base::{
     font_data::{UiFontData, UiFontDataLoading},
     ui::UiCreator,
 };
-use urlencoding::encode;
-
-pub struct ClientWrapper(Client);
-
-unsafe impl Sync for ClientWrapper {}
-unsafe impl Send for ClientWrapper {}
-
-static CLIENT: Mutex<Option<ClientWrapper>> = Mutex::const_new(None);
-static HTTP: LazyLock<Arc<reqwest::Client>> = LazyLock::new(Default::default);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Skin {
-    #[serde(rename = "skin_name")]
-    name: NetworkString<24>,
-    #[serde(alias = "skin_color_body")]
-    color_body: Option<i32>,
-    #[serde(alias = "skin_color_feet")]
-    color_feet: Option<i32>,
-}
-
-type PlayerApiState = Arc<parking_lot::Mutex<std::time::Instant>>;
-static PLAYERS: LazyLock<PlayerApiState> = LazyLock::new(|| {
-    Arc::new(parking_lot::Mutex::new(
-        std::time::Instant::now() - Duration::from_secs(60 * 60 * 60),
-    ))
+/// The shared client of upstream calls, see [`upstream::UPSTREAM`] for the timeouts.
+static HTTP: LazyLock<Arc<reqwest::Client>> = LazyLock::new(|| {
+    Arc::new(
+        reqwest::Client::builder()
+            .connect_timeout(upstream::UPSTREAM.connect_timeout)
+            .timeout(upstream::UPSTREAM.request_timeout)
+            .build()
+            .unwrap_or_default(),
+    )
 });
 
-#[derive(Debug, Default, Deserialize)]
-struct RenderParams {
-    /// Name of the skin to draw
-    skin_name: NetworkString<24>,
-    /// Optional player name to render as nameplate
-    player_name: Option<NetworkString<128>>,
-    /// Camera zoom
-    zoom: Option<f32>,
-    /// Camera pos x
-    x: Option<f32>,
-    /// Camera pos y
-    y: Option<f32>,
-    /// Legacy color body
-    body: Option<i32>,
-    /// Legacy color feet
-    feet: Option<i32>,
-    /// Cursor dir x
-    dir_x: Option<f32>,
-    /// Cursor dir y
-    dir_y: Option<f32>,
-    /// Tee eyes
-    eyes: Option<String>,
-    /// Tee weapon
-    weapon: Option<String>,
-    /// Tee emoticon
-    emoticon: Option<String>,
-    /// Whether the Tee used its double jump
-    used_air_jump: Option<bool>,
-    /// Whether the Tee is in the air right now
-    in_air: Option<bool>,
-    /// The x position of the hook relative to the Tee
-    hook_x: Option<f32>,
-    /// The y position of the hook relative to the Tee
-    hook_y: Option<f32>,
-    /// The current time of the rendering (e.g. for map animations) in ms.
-    time: Option<u64>,
-
-    /// Name of the map to render
-    map_name: Option<String>,
-    /// Use skins.tw player api to fetch latest
-    /// skin of the player
-    use_player_api: Option<bool>,
-}
+/// The size of the offscreen canvas
+static CANVAS_SIZE: LazyLock<(u32, u32)> = LazyLock::new(|| {
+    let width: u32 = std::env::var("WIDTH")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u32>().map_err(|err| anyhow!(err)))
+        .unwrap_or(800);
+    let height: u32 = std::env::var("HEIGHT")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u32>().map_err(|err| anyhow!(err)))
+        .unwrap_or(600);
+    (width, height)
+});
 
 struct ClientLoad {
     backend_loading: GraphicsBackendLoading,
@@ -162,9 +197,19 @@ struct ClientLoad {
     tp: Arc<ThreadPool>,
 }
 
+/// `WARM_UP`: creates all pipelines when the graphics backend is loaded and renders
+/// test frames before the server accepts connections, so the first requests aren't slow.
+/// Disable it where boot time matters more than the first requests (`true` is default).
+static WARM_UP: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("WARM_UP")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true)
+});
+
 fn config_gl() -> ConfigBackend {
     ConfigBackend {
-        full_pipeline_creation: false,
+        full_pipeline_creation: *WARM_UP,
         ..Default::default()
     }
 }
@@ -191,7 +236,15 @@ struct Client {
     hooks_container: HookContainer,
 
     sys: System,
-    client_map: ClientMapRender,
+    io: Io,
+    tp: Arc<ThreadPool>,
+    sound: SoundManager,
+    /// Loaded maps by name, bounded by [`maps::MAX_LOADED_MAPS`]
+    maps: LruCache<String, ClientMapRender>,
+    /// Maps that are still loading, by name
+    loading_maps: HashMap<String, ClientMapRender>,
+    /// A render panicked, the next job first renders a test frame
+    suspect: bool,
 }
 
 impl Client {
@@ -206,50 +259,36 @@ impl Client {
             .map_canvas_for_ingame_items(state, center_x, center_y, zoom);
     }
 
-    pub fn render(&mut self, params: RenderParams, sender: Sender<anyhow::Result<Vec<u8>>>) {
-        let skin_name = params.skin_name;
-
-        let map_name = params.map_name.unwrap_or("ctf1".to_string());
-        let is_ctf1 = map_name == "ctf1";
-
-        let default_x = if is_ctf1 { 173.12 } else { 1358.08 };
-        let default_y = if is_ctf1 { 688.96 } else { 24240.96 };
-
-        let mut zoom = params.zoom.unwrap_or(0.5);
-        let mut x = params.x.unwrap_or(default_x);
-        let mut y = params.y.unwrap_or(default_y);
-        let mut dir_x = params.dir_x.unwrap_or(1.0);
-        let mut dir_y = params.dir_y.unwrap_or(0.0);
-
-        if zoom.is_nan() || zoom.is_infinite() {
-            zoom = 1.0;
-        }
-        zoom = zoom.clamp(0.001, 20.0);
-
-        if x.is_nan() || x.is_infinite() {
-            x = 0.0;
-        }
-        x = x.clamp(0.0, 300000.0);
-
-        if y.is_nan() || y.is_infinite() {
-            y = 0.0;
-        }
-        y = y.clamp(0.0, 300000.0);
-
-        if dir_x.is_nan() || dir_x.is_infinite() {
-            dir_x = 0.0;
-        }
-        dir_x = dir_x.clamp(-1.0, 1.0);
-
-        if dir_y.is_nan() || dir_y.is_infinite() {
-            dir_y = 0.0;
-        }
-        dir_y = dir_y.clamp(-1.0, 1.0);
-
-        let custom_color = params.body.is_some();
-
-        let color_body = params.body.unwrap_or(0);
-        let color_feet = params.feet.unwrap_or(0);
+    /// Renders the job and sends the result, unless the graphics device was lost:
+    /// then the sender is returned, so the job can be retried on a new client.
+    #[must_use]
+    pub fn render(
+        &mut self,
+        params: RenderParams,
+        warnings: Vec<String>,
+        sender: Sender<Result<Rendered, RenderError>>,
+    ) -> Option<Sender<Result<Rendered, RenderError>>> {
+        let skin_name = params.render_skin().to_string();
+        let buffs = params.buffs();
+        let layers = params.map_layers();
+
+        let map_name = params.map_name().to_string();
+
+        // a higher dpr or supersampling shows the same scene with more pixels
+        // (output_size and fit_supersample already sanitized both)
+        let mut zoom = params.zoom() / params.dpr.unwrap_or(1.0) / params.supersample() as f32;
+        let (mut x, mut y) = params.camera_pos();
+        let overview = params.overview();
+        let mut dir_x = params::DIR_X_RANGE.sanitize(params.dir_x, 0.0);
+        let dir_y = params::DIR_Y_RANGE.sanitize(params.dir_y, 0.0);
+        let tee_size = params.size();
+
+        // the ninja skin is never colored
+        let (color_body, color_feet) = if skin_name != params::NINJA_SKIN {
+            (params.body_color, params.feet_color)
+        } else {
+            (None, None)
+        };
 
         if dir_x.abs() < 0.001 && dir_y.abs() < 0.001 {
             dir_x = 1.0;
@@ -257,306 +296,552 @@ impl Client {
 
         let dir = normalize(&vec2::new(dir_x, dir_y));
 
-        let hook_pos = params.hook_x.zip(params.hook_y).map(|(mut x, mut y)| {
-            if x.is_nan() || x.is_infinite() {
-                x = 0.0;
-            }
-            x = x.clamp(-10000.0, 10000.0);
+        let tee_eyes = params.eyes();
+        let weapon = params.weapon();
+        let emoticon = params.emoticon();
 
-            if y.is_nan() || y.is_infinite() {
-                y = 0.0;
-            }
-            y = y.clamp(-10000.0, 10000.0);
-            vec2::new(x, y)
-        });
+        let cur_time = Duration::from_millis(params.time.unwrap_or_default().min(params::MAX_TIME));
 
-        let tee_eyes = match params
-            .eyes
-            .unwrap_or("normal".to_string())
-            .to_lowercase()
-            .as_str()
-        {
-            "normal" => TeeEye::Normal,
-            "angry" => TeeEye::Angry,
-            "pain" => TeeEye::Pain,
-            "happy" => TeeEye::Happy,
-            "surprised" => TeeEye::Surprised,
-            "blink" => TeeEye::Blink,
-            _ => TeeEye::Normal,
+        let map = self
+            .maps
+            .get_mut(&map_name)
+            .and_then(|map_file| map_file.try_get());
+        // custom keys fall back to the default if they fail to load
+        let custom_key = |name: &Option<String>| -> Option<ResourceKey> {
+            name.as_deref().and_then(|name| name.try_into().ok())
         };
-
-        let weapon = params
-            .weapon
-            .map(|weapon| match weapon.to_lowercase().as_str() {
-                "gun" => WeaponType::Gun,
-                "shotgun" => WeaponType::Shotgun,
-                "grenade" => WeaponType::Grenade,
-                "laser" => WeaponType::Laser,
-                _ => WeaponType::Hammer,
-            });
-
-        let emoticon = params.emoticon.and_then(|emoticon| {
-            EmoticonType::iter().find(|e| {
-                let e_str: &'static str = e.into();
-
-                e_str.to_lowercase() == emoticon.to_lowercase()
-            })
-        });
-
-        // at most 1 years
-        let cur_time = Duration::from_millis(params.time.unwrap_or_default().clamp(0, 31536000000));
-
-        let map_file = &mut self.client_map;
-        let map = map_file.try_get();
-        let default_key = self.entities_container.default_key.clone();
+        let entities_key = custom_key(&params.entities)
+            .unwrap_or_else(|| self.entities_container.default_key.clone());
+        let weapon_key = custom_key(&params.weapon_skin)
+            .unwrap_or_else(|| self.weapon_container.default_key.clone());
+        let hook_key = custom_key(&params.hook_skin);
+        let emoticon_key = custom_key(&params.emoticon_skin)
+            .unwrap_or_else(|| self.emoticon_container.default_key.clone());
+        let entities_game = params.entities_game();
+        // drawn over the image by post processing, see `overlay::draw_game_layer`
+        let mut game_layer_rects = Vec::new();
+        let mut trajectory = None;
+        let mut scene_items = Vec::new();
         if let Some(map) = map {
-            map.render.render_background(&mut RenderPipeline::new(
-                &map.data.buffered_map.map_visual,
-                &map.data.buffered_map,
-                &Default::default(),
-                &cur_time,
-                &cur_time,
-                &Camera {
-                    pos: vec2::new(x, y),
-                    zoom,
-                },
-                &mut self.entities_container,
-                Some(&default_key),
-                "ddnet",
-                1.0,
-            ));
-
-            let mut state = State::new();
-            Self::map_canvas_for_players(&self.graphics, &mut state, 0.0, 0.0, zoom);
-            let mut anim_state = AnimState::default();
-            anim_state.set(&base_anim(), &Duration::from_millis(0));
-            if params.in_air.unwrap_or_default() {
-                anim_state.add(&inair_anim(), &Duration::from_millis(0), 1.0);
-            } else {
-                anim_state.add(&idle_anim(), &Duration::from_millis(0), 1.0);
-            }
-            let skin_name: Option<NetworkResourceKey<24>> = skin_name.as_str().try_into().ok();
-            let skin = self.skin_container.get_or_default_opt(skin_name.as_ref());
-
-            let mut render_info = CharacterRenderInfo {
-                lerped_pos: Default::default(),
-                lerped_vel: Default::default(),
-                lerped_hook_pos: Default::default(),
-                has_air_jump: Default::default(),
-                cursor_pos: dvec2::new(dir.x as f64, dir.y as f64),
-                move_dir: Default::default(),
-                cur_weapon: Default::default(),
-                recoil_ticks_passed: Default::default(),
-                left_eye: Default::default(),
-                right_eye: Default::default(),
-                buffs: PoolLinkedHashMap::new_without_pool(),
-                debuffs: PoolLinkedHashMap::new_without_pool(),
-                animation_ticks_passed: Default::default(),
-                game_ticks_passed: Default::default(),
-                game_round_ticks: Default::default(),
-                emoticon: Default::default(),
-            };
-            // tee info
-            let color_body = if !custom_color {
-                TeeRenderSkinColor::Original
-            } else {
-                let _a = ((color_body >> 24) & 0xFF) as f64 / 255.0;
-                let h = ((color_body >> 16) & 0xFF) as f64 / 255.0;
-                let s = ((color_body >> 8) & 0xFF) as f64 / 255.0;
-                let l = ((color_body) & 0xFF) as f64 / 255.0;
-                let mut hsl = palette::Hsl::new_const((h * 360.0).into(), s, l);
-                let darkest = 0.5;
-                hsl.lightness = darkest + hsl.lightness * (1.0 - darkest);
-
-                let rgb = palette::rgb::LinSrgb::from_color_unclamped(hsl);
-                TeeRenderSkinColor::Colorable(ColorRgba {
-                    r: rgb.red as f32,
-                    g: rgb.green as f32,
-                    b: rgb.blue as f32,
-                    a: 1.0,
-                })
+            // the map size in tiles
+            let physics_layer = &map.data.buffered_map.map_visual.groups.physics.attr;
+            let map_size = vec2::new(
+                physics_layer.width.get() as f32,
+                physics_layer.height.get() as f32,
+            );
+            maps::record_size(&map_name, map_size.x, map_size.y);
+            if params.camera_source() == CameraSource::MapCenter {
+                (x, y) = params.camera_pos();
+            }
+            // an explicit camera shows a part of the overview, see `map_preview`
+            if overview && params.x.is_none() && params.y.is_none() && params.zoom.is_none() {
+                let (canvas_width, canvas_height) = *CANVAS_SIZE;
+                (x, y, zoom) = params::overview_camera(
+                    map_size.x,
+                    map_size.y,
+                    canvas_width as f32 / canvas_height as f32,
+                );
+            }
+            // the captured frame is rotated by post processing, see `render_single`
+            let (canvas_width, canvas_height) = *CANVAS_SIZE;
+            zoom *= params::rotation_cover(params.angle(), canvas_width, canvas_height);
+
+            // a tee with a map position is drawn in the camera's coordinates,
+            // otherwise it's the origin of a canvas centered on the camera
+            let (center, tee_pos, tee_world_pos) = match params.tee_pos() {
+                Some((tee_x, tee_y)) => {
+                    let tee_pos =
+                        vec2::new(tee_x.clamp(0.0, map_size.x), tee_y.clamp(0.0, map_size.y));
+                    (vec2::new(x, y), tee_pos, tee_pos)
+                }
+                None => (vec2::default(), vec2::default(), vec2::new(x, y)),
             };
+            let hook_pos = params
+                .hook_offset(tee_world_pos.x, tee_world_pos.y)
+                .map(|offset| tee_pos + offset);
+
+            if layers.background() {
+                map.render.render_background(&mut RenderPipeline::new(
+                    &map.data.buffered_map.map_visual,
+                    &map.data.buffered_map,
+                    &Default::default(),
+                    &cur_time,
+                    &cur_time,
+                    &Camera {
+                        pos: vec2::new(x, y),
+                        zoom,
+                    },
+                    &mut self.entities_container,
+                    Some(&entities_key),
+                    entities_game,
+                    1.0,
+                ));
+            }
 
-            let color_feet = if !custom_color {
-                TeeRenderSkinColor::Original
-            } else {
-                let _a = ((color_feet >> 24) & 0xFF) as f64 / 255.0;
-                let h = ((color_feet >> 16) & 0xFF) as f64 / 255.0;
-                let s = ((color_feet >> 8) & 0xFF) as f64 / 255.0;
-                let l = ((color_feet) & 0xFF) as f64 / 255.0;
-                let mut hsl = palette::Hsl::new_const((h * 360.0).into(), s, l);
-                let darkest = 0.5;
-                hsl.lightness = darkest + hsl.lightness * (1.0 - darkest);
-
-                let rgb = palette::rgb::LinSrgb::from_color_unclamped(hsl);
-                TeeRenderSkinColor::Colorable(ColorRgba {
-                    r: rgb.red as f32,
-                    g: rgb.green as f32,
-                    b: rgb.blue as f32,
-                    a: 1.0,
-                })
-            };
+            // flags, pickups and projectiles are drawn over the image by post processing,
+            // see `overlay::draw_scene`
+            let camera = vec2::new(x, y);
+            let to_camera = |pos: vec2| vec2::new(pos.x - camera.x, pos.y - camera.y);
+            scene_items.extend(
+                params
+                    .pickups()
+                    .into_iter()
+                    .map(|pickup| SceneItem::Pickup {
+                        kind: pickup.kind,
+                        pos: to_camera(pickup.pos),
+                    }),
+            );
+            // lasers are drawn over the other projectiles like ingame
+            let projectiles = params.projectiles();
+            let (lasers, flying): (Vec<_>, Vec<_>) = projectiles
+                .into_iter()
+                .partition(|projectile| matches!(projectile, SceneProjectile::Laser { .. }));
+            scene_items.extend(
+                flying
+                    .into_iter()
+                    .chain(lasers)
+                    .map(|projectile| SceneItem::Projectile(projectile.moved(vec2::new(-x, -y)))),
+            );
+            if let Some(flag) = params.flag().filter(|flag| !overview || flag.pos.is_some()) {
+                let (pos, angle) = flag.render_pos(tee_world_pos, dir.x);
+                scene_items.push(SceneItem::Flag {
+                    team: flag.team,
+                    pos: to_camera(pos),
+                    angle,
+                });
+            }
 
-            let tee_render_info = TeeRenderInfo {
-                eye_left: tee_eyes,
-                eye_right: tee_eyes,
-                color_body,
-                color_feet,
-                got_air_jump: !params.used_air_jump.unwrap_or_default(),
-                feet_flipped: false,
-                size: 2.0,
-            };
+            // an overview shows just the map
+            if !overview {
+                let mut state = State::new();
+                Self::map_canvas_for_players(&self.graphics, &mut state, center.x, center.y, zoom);
+                let mut anim_state = AnimState::default();
+                anim_state.set(&base_anim(), &Duration::from_millis(0));
+                // like the game picks the animations of a character
+                let vel = params.velocity();
+                let move_dir = params.move_dir();
+                let pos_x = tee_world_pos.x * physics::TILE_SIZE;
+                let stationary = vel.x.abs() <= 1.0;
+                let running = vel.x.abs() >= 5000.0 / 256.0;
+                let want_other_dir =
+                    (move_dir == -1 && vel.x > 0.0) || (move_dir == 1 && vel.x < 0.0);
+                if params.in_air.unwrap_or_default() {
+                    anim_state.add(&inair_anim(), &Duration::from_millis(0), 1.0);
+                } else if stationary {
+                    anim_state.add(&idle_anim(), &Duration::from_millis(0), 1.0);
+                } else if !want_other_dir {
+                    let walk_time = pos_x.rem_euclid(100.0) / 100.0;
+                    anim_state.add(&walk_anim(), &Duration::from_secs_f32(walk_time), 1.0);
+                }
+                if running {
+                    let run_time = pos_x.rem_euclid(200.0) / 200.0;
+                    let run_anim = if vel.x < 0.0 {
+                        run_left_anim()
+                    } else {
+                        run_right_anim()
+                    };
+                    anim_state.add(&run_anim, &Duration::from_secs_f32(run_time), 1.0);
+                }
+                containers::mark_used(ContainerKind::Skins, &skin_name);
+                let skin_name: Option<NetworkResourceKey<24>> = skin_name.as_str().try_into().ok();
+                let skin = self.skin_container.get_or_default_opt(skin_name.as_ref());
+
+                let mut render_info = CharacterRenderInfo {
+                    lerped_pos: tee_pos,
+                    lerped_vel: vel,
+                    lerped_hook_pos: Default::default(),
+                    has_air_jump: Default::default(),
+                    cursor_pos: dvec2::new(dir.x as f64, dir.y as f64),
+                    move_dir,
+                    cur_weapon: Default::default(),
+                    recoil_ticks_passed: params.recoil_ticks(),
+                    left_eye: Default::default(),
+                    right_eye: Default::default(),
+                    buffs: PoolLinkedHashMap::new_without_pool(),
+                    debuffs: PoolLinkedHashMap::new_without_pool(),
+                    animation_ticks_passed: params.game_ticks(),
+                    game_ticks_passed: params.game_ticks(),
+                    game_round_ticks: Default::default(),
+                    emoticon: Default::default(),
+                };
+                for buff in &buffs {
+                    match buff {
+                        Buff::Ninja => {
+                            render_info.buffs.insert(
+                                CharacterBuff::Ninja,
+                                CharacterBuffInfo {
+                                    remaining_time: None,
+                                },
+                            );
+                        }
+                        Buff::Ghost => {
+                            render_info.buffs.insert(
+                                CharacterBuff::Ghost,
+                                CharacterBuffInfo {
+                                    remaining_time: None,
+                                },
+                            );
+                        }
+                        Buff::Freeze => {
+                            render_info.debuffs.insert(
+                                CharacterDebuff::Freeze,
+                                CharacterDebuffInfo {
+                                    remaining_time: None,
+                                },
+                            );
+                        }
+                    }
+                }
+                // ghosts are rendered translucent
+                let tee_alpha = if buffs.contains(&Buff::Ghost) {
+                    0.5
+                } else {
+                    1.0
+                };
+                // tee info
+                let ddnet_color_clamp = params.ddnet_color_clamp.unwrap_or_default();
+                let color_body = color_body.map_or(TeeRenderSkinColor::Original, |color| {
+                    color::render_color(color, TeePart::Body, ddnet_color_clamp)
+                });
+                let color_feet = color_feet.map_or(TeeRenderSkinColor::Original, |color| {
+                    color::render_color(color, TeePart::Feet, ddnet_color_clamp)
+                });
 
-            // hook
-            let hook_hand = hook_pos.and_then(|hook_pos| {
-                render_info.lerped_hook_pos = Some(hook_pos);
-                self.toolkit_renderer.render_hook_for_player(
-                    &mut self.hooks_container,
-                    None,
-                    vec2::default(),
-                    &render_info,
-                    state,
-                )
-            });
-            if let Some(hook_hand) = hook_hand {
-                self.tee_renderer.render_tee_hand(
-                    &RenderTeeHandMath::new(&vec2::default(), 2.0, &hook_hand),
-                    &color_body,
+                let tee_render_info = TeeRenderInfo {
+                    eye_left: tee_eyes,
+                    eye_right: tee_eyes,
+                    color_body,
+                    color_feet,
+                    got_air_jump: !params.used_air_jump.unwrap_or_default(),
+                    feet_flipped: params.feet_flipped.unwrap_or_default(),
+                    size: tee_size,
+                };
+
+                // hook
+                let hook_hand = hook_pos.and_then(|hook_pos| {
+                    render_info.lerped_hook_pos = Some(hook_pos);
+                    self.toolkit_renderer.render_hook_for_player(
+                        &mut self.hooks_container,
+                        hook_key.as_ref(),
+                        tee_pos,
+                        &render_info,
+                        state,
+                    )
+                });
+                if let Some(hook_hand) = hook_hand {
+                    self.tee_renderer.render_tee_hand(
+                        &RenderTeeHandMath::new(&tee_pos, tee_size, &hook_hand),
+                        &color_body,
+                        skin,
+                        tee_alpha,
+                        &state,
+                    );
+                }
+
+                let weapon_hand = if let Some(weapon_ty) = weapon {
+                    render_info.cur_weapon = weapon_ty;
+
+                    let weapons = self.weapon_container.get_or_default(&weapon_key);
+                    self.toolkit_renderer.render_weapon_for_player(
+                        weapons,
+                        &render_info,
+                        Default::default(),
+                        params::TICKS_PER_SECOND.try_into().unwrap(),
+                        &GameTimeInfo {
+                            ticks_per_second: params::TICKS_PER_SECOND.try_into().unwrap(),
+                            intra_tick_time: Duration::ZERO,
+                        },
+                        state,
+                        params.weapon_attack.unwrap_or_default(),
+                        params.weapon_muzzle.unwrap_or_default(),
+                    )
+                } else {
+                    None
+                };
+
+                self.tee_renderer.render_tee(
+                    &anim_state,
                     skin,
-                    1.0,
+                    &tee_render_info,
+                    &TeeRenderHands {
+                        left: None,
+                        right: weapon_hand,
+                    },
+                    &dir,
+                    &tee_pos,
+                    tee_alpha,
                     &state,
                 );
-            }
-
-            let weapon_hand = if let Some(weapon_ty) = weapon {
-                render_info.cur_weapon = weapon_ty;
 
-                let weapon = self.weapon_container.default_key.clone();
-                let weapons = self.weapon_container.get_or_default(&weapon);
-                self.toolkit_renderer.render_weapon_for_player(
-                    weapons,
-                    &render_info,
-                    Default::default(),
-                    50.try_into().unwrap(),
-                    &GameTimeInfo {
-                        ticks_per_second: 50.try_into().unwrap(),
+                // indicators are beside the Tee, emoticons above it
+                let indicators = [
+                    (
+                        params.chat_bubble.unwrap_or_default(),
+                        EmoticonType::DOTDOT,
+                        scene::INDICATOR_TICKS,
+                    ),
+                    (params.afk(), EmoticonType::ZZZ, scene::AFK_TICKS),
+                ];
+                for (_, indicator, ticks) in indicators.into_iter().filter(|(shown, ..)| *shown) {
+                    self.emoticon_renderer.render(&mut RenderEmoticonPipe {
+                        emoticon_container: &mut self.emoticon_container,
+                        pos: scene::indicator_pos(tee_pos, tee_size),
+                        state: &state,
+                        emoticon_key: Some(&emoticon_key),
+                        emoticon: indicator,
+                        emoticon_ticks: ticks,
                         intra_tick_time: Duration::ZERO,
-                    },
-                    state,
-                    false,
-                    false,
-                )
-            } else {
-                None
-            };
+                        ticks_per_second: params::TICKS_PER_SECOND.try_into().unwrap(),
+                    });
+                }
 
-            self.tee_renderer.render_tee(
-                &anim_state,
-                skin,
-                &tee_render_info,
-                &TeeRenderHands {
-                    left: None,
-                    right: weapon_hand,
-                },
-                &dir,
-                &vec2::new(0.0, 0.0),
-                1.0,
-                &state,
-            );
+                if let Some(emoticon) = emoticon {
+                    self.emoticon_renderer.render(&mut RenderEmoticonPipe {
+                        emoticon_container: &mut self.emoticon_container,
+                        pos: tee_pos,
+                        state: &state,
+                        emoticon_key: Some(&emoticon_key),
+                        emoticon,
+                        emoticon_ticks: params.emoticon_ticks(),
+                        intra_tick_time: params.intra_tick_time(),
+                        ticks_per_second: params::TICKS_PER_SECOND.try_into().unwrap(),
+                    });
+                }
 
-            if let Some(emoticon) = emoticon {
-                let emoticon_key = self.emoticon_container.default_key.clone();
-                self.emoticon_renderer.render(&mut RenderEmoticonPipe {
-                    emoticon_container: &mut self.emoticon_container,
-                    pos: vec2::new(0.0, 0.0),
-                    state: &state,
-                    emoticon_key: Some(&emoticon_key),
-                    emoticon,
-                    emoticon_ticks: 90,
-                    intra_tick_time: Duration::ZERO,
-                    ticks_per_second: 50.try_into().unwrap(),
-                });
+                if params.nameplate() {
+                    // a requested time makes the image reproducible
+                    let nameplate_time = if params.time.is_some() {
+                        cur_time
+                    } else {
+                        self.sys.time_get_nanoseconds()
+                    };
+                    let camera_zoom = params::nameplate_zoom(zoom, params.nameplate_size());
+                    let name = params.player_name.as_ref().and_then(|name| {
+                        NetworkString::new(&names::display_name(name.as_str())).ok()
+                    });
+                    if let Some(name) = &name {
+                        self.nameplate_renderer.render(&mut NameplateRenderPipe {
+                            cur_time: &nameplate_time,
+                            name,
+                            state: &state,
+                            pos: &tee_pos,
+                            camera_zoom,
+                        });
+                    }
+                    // a smaller line above the name, a clan without name is rendered alone
+                    let clan = params
+                        .clan_name
+                        .as_ref()
+                        .and_then(|clan| NetworkString::new(clan.as_str()).ok());
+                    if let Some(clan) = &clan {
+                        self.nameplate_renderer.render(&mut NameplateRenderPipe {
+                            cur_time: &nameplate_time,
+                            name: clan,
+                            state: &state,
+                            pos: &params::clan_line_pos(tee_pos, camera_zoom),
+                            camera_zoom: camera_zoom * params::CLAN_NAMEPLATE_SCALE,
+                        });
+                    }
+                    // like the client marks friends and authed players,
+                    // drawn by post processing side by side
+                    let marks = [
+                        (params.friend_mark, NameplateMark::Friend),
+                        (params.authed_mark, NameplateMark::Authed),
+                    ]
+                    .into_iter()
+                    .filter_map(|(shown, mark)| shown.unwrap_or_default().then_some(mark))
+                    .collect::<Vec<_>>();
+                    // relative to the camera, which is the center of the canvas
+                    let marks_pos =
+                        params::nameplate_marks_pos(tee_pos, camera_zoom, clan.is_some());
+                    let size = params::NAMEPLATE_MARK_SIZE * camera_zoom;
+                    let left = marks_pos.x - center.x - size * (marks.len() as f32 - 1.0) / 2.0;
+                    scene_items.extend(marks.into_iter().enumerate().map(|(i, mark)| {
+                        SceneItem::Mark {
+                            mark,
+                            pos: vec2::new(left + size * i as f32, marks_pos.y - center.y),
+                            size,
+                        }
+                    }));
+                }
             }
 
-            let name = if let Some(name) = &params.player_name {
-                Some(name)
-            } else {
-                None
-            };
-
-            if let Some(name) = name {
-                self.nameplate_renderer.render(&mut NameplateRenderPipe {
-                    cur_time: &self.sys.time_get_nanoseconds(),
-                    name,
-                    state: &state,
-                    pos: &vec2::new(0.0, 0.0),
-                    camera_zoom: zoom.clamp(0.3, f32::MAX),
-                });
+            // every pass maps the canvas itself, so skipping one leaves no state behind
+            if layers.foreground() {
+                map.render.render_foreground(&mut RenderPipeline::new(
+                    &map.data.buffered_map.map_visual,
+                    &map.data.buffered_map,
+                    &Default::default(),
+                    &cur_time,
+                    &cur_time,
+                    &Camera {
+                        pos: vec2::new(x, y),
+                        zoom,
+                    },
+                    &mut self.entities_container,
+                    Some(&entities_key),
+                    entities_game,
+                    1.0,
+                ));
             }
-
-            map.render.render_foreground(&mut RenderPipeline::new(
-                &map.data.buffered_map.map_visual,
-                &map.data.buffered_map,
-                &Default::default(),
-                &cur_time,
-                &cur_time,
-                &Camera {
-                    pos: vec2::new(x, y),
+            if params.map_overlay() == Some(MapOverlay::Physics) {
+                let (width, height) = (map_size.x as usize, map_size.y as usize);
+                let categories = game_layer::tile_categories(
+                    &map.data.buffered_map.map_visual.groups.physics.layers,
+                    width,
+                    height,
+                );
+                let (canvas_width, canvas_height) = *CANVAS_SIZE;
+                game_layer_rects = game_layer::visible_rects(
+                    &categories,
+                    width,
+                    (x, y),
                     zoom,
-                },
-                &mut self.entities_container,
-                Some(&default_key),
-                "ddnet",
-                1.0,
-            ));
+                    canvas_width,
+                    canvas_height,
+                );
+            }
+            if let Some((projectile, dir, speed)) = params.trajectory() {
+                let solid = game_layer::solid_tiles(
+                    &map.data.buffered_map.map_visual.groups.physics.layers,
+                );
+                let width = map_size.x as usize;
+                let (shooter_x, shooter_y) =
+                    params.tee_pos().unwrap_or_else(|| params.camera_pos());
+                let shooter = vec2::new(shooter_x, shooter_y);
+                trajectory = Some(physics::simulate(projectile, dir, speed, |pos| {
+                    game_layer::is_solid(&solid, width, shooter + pos)
+                }));
+            }
+        } else {
+            let _ = sender.send(Err(RenderError::MapNotLoaded(map_name)));
+            return None;
         }
 
+        type ScreenshotSender =
+            Arc<parking_lot::Mutex<Option<Sender<Result<Rendered, RenderError>>>>>;
         #[derive(Debug)]
         struct Screenshot {
-            sender: RefCell<Option<Sender<anyhow::Result<Vec<u8>>>>>,
+            /// Shared with the render, which takes it back if the device was lost
+            sender: ScreenshotSender,
+            device_lost: Arc<AtomicBool>,
+            warnings: RefCell<Vec<String>>,
+            game_layer: RefCell<Vec<(Rect, TileCategory)>>,
+            trajectory: RefCell<Option<(Vec<vec2>, bool)>>,
+            scene: RefCell<Option<(Vec<SceneItem>, f32)>>,
+            requested: Instant,
         }
         impl ScreenshotCb for Screenshot {
             fn on_screenshot(&self, png: anyhow::Result<Vec<u8>>) {
-                if let Some(sender) = self.sender.borrow_mut().take() {
-                    let _ = sender.send(png);
+                if let Err(err) = &png {
+                    if device::is_device_lost(err) {
+                        self.device_lost.store(true, Ordering::SeqCst);
+                        return;
+                    }
+                }
+                if let Some(sender) = self.sender.lock().take() {
+                    metrics::SCREENSHOT_DURATION.observe(self.requested.elapsed());
+                    let warnings = std::mem::take(&mut *self.warnings.borrow_mut());
+                    let game_layer = std::mem::take(&mut *self.game_layer.borrow_mut());
+                    let trajectory = self.trajectory.borrow_mut().take();
+                    let scene = self.scene.borrow_mut().take();
+                    let _ = sender.send(
+                        png.map(|png| Rendered {
+                            png,
+                            warnings,
+                            crop: None,
+                            timing: Default::default(),
+                            game_layer,
+                            trajectory,
+                            scene,
+                        })
+                        .map_err(|err| RenderError::Gpu(err.to_string())),
+                    );
                 }
             }
         }
+        let sender: ScreenshotSender = Arc::new(parking_lot::Mutex::new(Some(sender)));
+        let device_lost = Arc::new(AtomicBool::new(false));
         let cb = Screenshot {
-            sender: RefCell::new(Some(sender)),
+            sender: sender.clone(),
+            device_lost: device_lost.clone(),
+            warnings: RefCell::new(warnings),
+            game_layer: RefCell::new(game_layer_rects),
+            trajectory: RefCell::new(trajectory),
+            scene: RefCell::new((!scene_items.is_empty()).then_some((scene_items, zoom))),
+            requested: Instant::now(),
         };
-        self.graphics.do_screenshot(cb).unwrap();
+        let lost = |err: &anyhow::Error| {
+            if device::is_device_lost(err) {
+                device_lost.store(true, Ordering::SeqCst);
+            }
+        };
+        // if the screenshot fails the callback is dropped,
+        // which the waiting request sees as a gpu error
+        if let Err(err) = self.graphics.do_screenshot(cb) {
+            println!("screenshot failed: {err}");
+            lost(&err);
+        }
         self.graphics.swap();
-        self.graphics_backend.wait_idle().unwrap();
+        if let Err(err) = self.graphics_backend.wait_idle() {
+            println!("waiting for the backend failed: {err}");
+            lost(&err);
+        }
         self.graphics.check_pending_screenshot();
 
-        self.skin_container.update(
-            &self.sys.time_get_nanoseconds(),
-            &Duration::from_secs(5),
-            &Duration::from_secs(1),
-            [].into_iter(),
-        );
+        if device_lost.load(Ordering::SeqCst) {
+            metrics::DEVICE_LOST.fetch_add(1, Ordering::Relaxed);
+            return sender.lock().take();
+        }
+        // after the capture, so it's not part of the image
+        self.update_containers();
+        None
     }
 
-    pub fn wait_skin_loaded(&mut self, skin_name: &str) {
-        let Ok(skin_key): Result<ResourceKey, _> = skin_name.try_into() else {
-            return;
+    /// Unloads the entries every container didn't use for the keep alive.
+    /// Above the entry budget, the least recently used entries are unloaded right away:
+    /// the containers only unload by age, so the time is moved past the keep alive
+    /// and the entries that stay are passed as in use.
+    fn update_containers(&mut self) {
+        let now = self.sys.time_get_nanoseconds();
+        let keep_alive = containers::CONTAINERS.keep_alive;
+        let budget = containers::fit_budget();
+        let tick = |kind: ContainerKind| -> (Duration, Vec<ResourceKey>) {
+            match &budget {
+                Some(keep) => (
+                    now + keep_alive * 2,
+                    keep.get(&kind)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|name| name.as_str().try_into().ok())
+                        .collect(),
+                ),
+                None => (now, Vec::new()),
+            }
         };
-        self.skin_container.blocking_wait_loaded(&skin_key);
+        let grace = Duration::from_secs(1);
+        let (time, keep) = tick(ContainerKind::Skins);
+        self.skin_container
+            .update(&time, &keep_alive, &grace, keep.iter());
+        let (time, keep) = tick(ContainerKind::Weapons);
+        self.weapon_container
+            .update(&time, &keep_alive, &grace, keep.iter());
+        let (time, keep) = tick(ContainerKind::Hooks);
+        self.hooks_container
+            .update(&time, &keep_alive, &grace, keep.iter());
+        let (time, keep) = tick(ContainerKind::Emoticons);
+        self.emoticon_container
+            .update(&time, &keep_alive, &grace, keep.iter());
+        let (time, keep) = tick(ContainerKind::Entities);
+        self.entities_container
+            .update(&time, &keep_alive, &grace, keep.iter());
     }
 
     fn new(loading: ClientLoad) -> anyhow::Result<Self> {
         // then prepare components allocations etc.
         let tp = loading.tp.clone();
 
-        let width: u32 = std::env::var("WIDTH")
-            .map_err(|err| anyhow!(err))
-            .and_then(|s| s.parse::<u32>().map_err(|err| anyhow!(err)))
-            .unwrap_or(800);
-        let height: u32 = std::env::var("HEIGHT")
-            .map_err(|err| anyhow!(err))
-            .and_then(|s| s.parse::<u32>().map_err(|err| anyhow!(err)))
-            .unwrap_or(600);
+        let (width, height) = *CANVAS_SIZE;
 
         let (backend_base, streamed_data) = GraphicsBackendBase::new(
             loading.backend_loading_io,
@@ -572,6 +857,7 @@ impl Client {
         let tee_renderer = RenderTee::new(&graphics);
         let mut creator = UiCreator::default();
         let font_loading = UiFontDataLoading::new(&loading.io);
+        // the ui fonts of `data/fonts`, dd-pg has no way to add fallback fonts
         let font_data = UiFontData::new(font_loading)?;
         creator.load_font(&font_data);
         let nameplate_renderer = NameplateRender::new(&graphics, &creator);
@@ -653,35 +939,10 @@ impl Client {
             HOOK_CONTAINER_PATH.as_ref(),
         );
 
-        let fs = loading.io.fs.clone();
-        let ctf1 = loading
-            .io
-            .io_batcher
-            .spawn(async move { Ok(fs.read_file("map/maps/ctf1.twmap".as_ref()).await?) })
-            .get_storage()
-            .unwrap();
-
-        let mut client_map = ClientMapRender::new(RenderMapLoading::new(
-            tp.clone(),
-            ctf1,
-            None,
-            loading.io.clone(),
-            &sound,
-            Default::default(),
-            &graphics,
-            &Default::default(),
-        ));
-
-        while client_map.continue_loading(&Default::default()).is_none() {
-            std::thread::sleep(Duration::from_millis(10));
-            std::thread::yield_now();
-        }
-
-        println!("finished setup");
-
-        graphics.swap();
+        let default_map =
+            Self::read_map(&loading.io, &tp, &sound, &graphics, version::DEFAULT_MAP)?;
 
-        Ok(Self {
+        let mut client = Self {
             graphics_backend,
             graphics,
 
@@ -696,111 +957,936 @@ impl Client {
             weapon_container: weapons_container,
             hooks_container,
 
-            client_map,
             sys: loading.sys,
-        })
+            io: loading.io,
+            tp,
+            sound,
+            maps: LruCache::new_unbounded(),
+            loading_maps: HashMap::from([(version::DEFAULT_MAP.to_string(), default_map)]),
+            suspect: false,
+        };
+        // the default map is rendered right away
+        maps::load_started(version::DEFAULT_MAP);
+        client.finish_map_loads(Instant::now() + maps::LOAD_TIMEOUT);
+
+        println!("finished setup");
+
+        client.graphics.swap();
+
+        Ok(client)
+    }
+
+    /// Reads a map and starts loading it, see [`Client::continue_map_loads`].
+    fn read_map(
+        io: &Io,
+        tp: &Arc<ThreadPool>,
+        sound: &SoundManager,
+        graphics: &Graphics,
+        name: &str,
+    ) -> anyhow::Result<ClientMapRender> {
+        let fs = io.fs.clone();
+        let path = maps::map_path(name);
+        let file = io
+            .io_batcher
+            .spawn(async move { Ok(fs.read_file(&path).await?) })
+            .get_storage()?;
+
+        Ok(ClientMapRender::new(RenderMapLoading::new(
+            tp.clone(),
+            file,
+            None,
+            io.clone(),
+            sound,
+            Default::default(),
+            graphics,
+            &Default::default(),
+        )))
+    }
+
+    /// Starts loading the map on first use, it's rendered once [`Client::continue_map_loads`]
+    /// finished it. The least recently used map is unloaded once more than
+    /// [`maps::MAX_LOADED_MAPS`] are loaded.
+    fn start_map_load(&mut self, name: &str) -> Result<(), RenderError> {
+        if self.maps.contains_key(name) || self.loading_maps.contains_key(name) {
+            return Ok(());
+        }
+        if !maps::valid_map_name(name) {
+            return Err(RenderError::MapNotFound(name.to_string()));
+        }
+        let map = Self::read_map(&self.io, &self.tp, &self.sound, &self.graphics, name).map_err(
+            |err| {
+                println!("loading map {name} failed: {err}");
+                RenderError::MapNotFound(name.to_string())
+            },
+        )?;
+        maps::load_started(name);
+        self.loading_maps.insert(name.to_string(), map);
+        Ok(())
+    }
+
+    /// Advances every loading map once, finished maps can be rendered.
+    fn continue_map_loads(&mut self) {
+        let finished: Vec<String> = self
+            .loading_maps
+            .iter_mut()
+            .filter_map(|(name, map)| {
+                map.continue_loading(&Default::default())
+                    .is_some()
+                    .then(|| name.clone())
+            })
+            .collect();
+        for name in finished {
+            if let Some(map) = self.loading_maps.remove(&name) {
+                maps::load_finished(&name);
+                self.maps.insert(name, map);
+            }
+        }
+        while self.maps.len() > *maps::MAX_LOADED_MAPS {
+            self.maps.remove_lru();
+        }
+    }
+
+    /// Advances the loading maps until all are loaded, for renders outside of the queue.
+    /// Maps that are still loading at the deadline are dropped, their next use loads them again.
+    fn finish_map_loads(&mut self, deadline: Instant) {
+        containers::wait_loaded(deadline, || {
+            self.continue_map_loads();
+            self.loading_maps.is_empty()
+        });
+        for name in std::mem::take(&mut self.loading_maps).into_keys() {
+            println!("loading map {name} took too long, it was dropped");
+            maps::load_stopped(&name);
+        }
     }
 
-    fn run(self) {
-        *CLIENT.blocking_lock() = Some(ClientWrapper(self));
+    /// Runs the servers on a tokio runtime, while this thread owns
+    /// the client and renders the queued jobs in order.
+    /// The other renderers of the pool get their own thread and client,
+    /// the servers only start once all of them are loaded.
+    fn run(mut self) {
+        let mut jobs = render_worker::init(*render_worker::RENDERER_POOL_SIZE).into_iter();
+        let Some(own_jobs) = jobs.next() else {
+            return;
+        };
+        let (ready, loaded) = std::sync::mpsc::channel();
+        let renderers: Vec<_> = jobs
+            .map(|jobs| {
+                let ready = ready.clone();
+                std::thread::spawn(move || {
+                    let index = jobs.index();
+                    let mut client = match load_client() {
+                        Ok(client) => client,
+                        Err(err) => {
+                            let _ = ready.send(Err(format!("renderer {index}: {err}")));
+                            return;
+                        }
+                    };
+                    if *WARM_UP {
+                        client.warm_up();
+                    }
+                    let _ = ready.send(Ok(()));
+                    let client = client.render_jobs(jobs);
+                    if let Err(err) = client.graphics_backend.wait_idle() {
+                        println!("waiting for the backend failed: {err}");
+                    }
+                })
+            })
+            .collect();
+        drop(ready);
+        if *WARM_UP {
+            self.warm_up();
+        }
+        for result in loaded.iter() {
+            if let Err(err) = result {
+                println!(
+                    "creating the renderers failed, the graphics backend may not support \
+                    {} headless contexts (RENDERER_POOL_SIZE): {err}",
+                    *render_worker::RENDERER_POOL_SIZE
+                );
+                std::process::exit(1);
+            }
+        }
+        if !renderers.is_empty() {
+            println!("{} renderers loaded", renderers.len() + 1);
+        }
 
         let rt = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(2) // should be at least 2
-            .max_blocking_threads(2) // must be at least 2
+            .worker_threads(2)
             .enable_all()
             .build()
             .unwrap();
-        let _g = rt.enter();
 
         let init_discord = std::env::var("DISCORD_TOKEN").is_ok();
 
-        if init_discord {
-            rt.block_on(async move { tokio::join!(async_main(), async_main_discord()) });
-        } else {
-            rt.block_on(async_main());
+        let servers = rt.spawn(async move {
+            if init_discord {
+                tokio::join!(async_main(), async_main_discord());
+            } else {
+                async_main().await;
+            }
+        });
+        rt.spawn(shutdown::listen());
+        let servers = rt.spawn(async move {
+            let res = servers.await;
+            // otherwise the servers only stop if they failed
+            if !shutdown::is_shutting_down() {
+                if let Err(err) = res {
+                    println!("server stopped: {err}");
+                }
+                std::process::exit(1);
+            }
+        });
+
+        // the default map is loaded with the client and warmed up
+        health::set_client_ready();
+        let client = self.render_jobs(own_jobs);
+
+        // the queue was closed for shutdown and all queued jobs are rendered
+        if let Err(err) = client.graphics_backend.wait_idle() {
+            println!("waiting for the backend failed: {err}");
+        }
+        for renderer in renderers {
+            let _ = renderer.join();
         }
+        // the servers still send the last responses
+        let _ = rt.block_on(servers);
+        println!("shut down");
     }
-}
 
-fn main() {
-    if std::env::var("RUST_LOG").is_err() {
-        unsafe { std::env::set_var("RUST_LOG", "warn,df::tract=error") };
+    /// Renders the queued jobs in order until the queues are closed and empty.
+    /// Maps load between jobs, jobs for a loading map wait for it unless they asked not to.
+    /// A stuck render reinitializes the client, so the returned client may be a new one.
+    fn render_jobs(mut self, mut jobs: render_worker::JobReceiver) -> Self {
+        let watchdog = render_worker::Watchdog::spawn();
+        // jobs whose map is still loading
+        let mut waiting: Vec<render_worker::RenderJob> = Vec::new();
+        loop {
+            self.continue_map_loads();
+            let (ready, still_loading): (Vec<_>, Vec<_>) = waiting
+                .into_iter()
+                .partition(|job| !self.loading_maps.contains_key(job.params.map_name()));
+            waiting = still_loading;
+            for job in ready {
+                self = self.render_job(job, &watchdog, jobs.index());
+            }
+            // requests that gave up waiting, e.g. because they timed out
+            waiting.retain(|job| !job.sender.is_closed());
+
+            let job = if self.loading_maps.is_empty() {
+                jobs.blocking_recv()
+            } else {
+                // the loads are polled, but new jobs are picked up right away
+                match jobs.recv_timeout(maps::LOAD_POLL) {
+                    Ok(job) => job,
+                    Err(_) => continue,
+                }
+            };
+            let Some(job) = job else {
+                break;
+            };
+            let job = match job {
+                render_worker::Job::Render(job) => job,
+                render_worker::Job::Maintain(job) => {
+                    let (client, result) = self.maintain(job.task);
+                    self = client;
+                    let _ = job.sender.send(result);
+                    continue;
+                }
+            };
+            // the request was dropped while waiting, e.g. because it timed out
+            if job.sender.is_closed() {
+                continue;
+            }
+            let map_name = job.params.map_name().to_string();
+            if let Err(err) = self.start_map_load(&map_name) {
+                let _ = job.sender.send(Err(err));
+                continue;
+            }
+            if self.loading_maps.contains_key(&map_name) {
+                if job.params.map_wait() {
+                    waiting.push(job);
+                } else {
+                    let _ = job.sender.send(Err(maps::still_loading(&map_name)));
+                }
+                continue;
+            }
+            self = self.render_job(job, &watchdog, jobs.index());
+        }
+        // the queues are closed, the jobs that wait for maps are still rendered
+        self.finish_map_loads(Instant::now() + maps::LOAD_TIMEOUT);
+        for job in waiting {
+            self = self.render_job(job, &watchdog, jobs.index());
+        }
+        self
     }
-    env_logger::init();
 
-    dotenvy::dotenv().ok();
+    /// Renders a job whose map is loaded, `renderer` is the index of this renderer in the pool.
+    fn render_job(
+        mut self,
+        job: render_worker::RenderJob,
+        watchdog: &render_worker::Watchdog,
+        renderer: usize,
+    ) -> Self {
+        let _running = job.queued.start(renderer);
+        let _ = job.started.set(Instant::now());
+        health::job_started();
+        if self.suspect {
+            let watched = watchdog.watch();
+            self = self.self_test();
+            drop(watched);
+        }
+        // the render answers through its own channel, so a panic can still be answered
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let watched = watchdog.watch();
+        let retry = job.params.clone();
+        let mut rendered = self.try_render(job.params, job.deadline, sender);
+        drop(watched);
+        if let Ok(Some(sender)) = rendered {
+            // the job is retried once on the rebuilt client
+            self = self.recover();
+            let watched = watchdog.watch();
+            rendered = self.try_render(retry, job.deadline, sender);
+            drop(watched);
+            if let Ok(Some(sender)) = rendered {
+                let _ = sender.send(Err(RenderError::Gpu(
+                    "the graphics device was lost".to_string(),
+                )));
+                self = self.recover();
+                rendered = Ok(None);
+            }
+        }
+        match rendered {
+            Ok(_) => render_worker::forward(receiver, job.sender),
+            Err(message) => {
+                println!("render panicked: {message}");
+                let _ = job.sender.send(Err(RenderError::Panicked(message)));
+                self.suspect = true;
+            }
+        }
+        health::job_finished();
 
-    let io = Io::new(
-        |runtime| {
-            Arc::new(FileSystem::new(
-                runtime,
-                "org",
-                "",
-                "DDNet_Webservice",
-                "DDNet_Accounts_Dummy",
-            ))
-        },
-        Arc::new(HttpClient::new()),
-    );
-    let tp = Arc::new(ThreadPoolBuilder::new().build().unwrap());
+        if watchdog.take_stuck() {
+            println!("reinitializing the graphics backend after a stuck render");
+            match load_client() {
+                Ok(client) => self = client,
+                Err(err) => println!("reinitializing the graphics backend failed: {err}"),
+            }
+        }
+        self
+    }
 
-    let map_pipe = MapPipeline::new_boxed();
+    /// Waits for the assets and renders, like [`Client::render`].
+    /// A panic is caught and returned with its message, the job was not answered then.
+    fn try_render(
+        &mut self,
+        params: RenderParams,
+        deadline: Instant,
+        sender: Sender<Result<Rendered, RenderError>>,
+    ) -> Result<Option<Sender<Result<Rendered, RenderError>>>, String> {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let warnings = containers::wait_assets(&AssetKeys::new(&params), self, deadline);
+            self.render(params, warnings, sender)
+        }))
+        .map_err(|panic| {
+            panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string())
+        })
+    }
 
-    let config_gl = config_gl();
-    let config_gfx = config_gfx();
-    let loading = GraphicsBackendLoading::new(
-        &config_gfx,
-        &config_dbg(),
-        &config_gl,
-        graphics_backend::window::BackendRawDisplayHandle::Headless,
+    /// Renders the query outside of the queue and waits for the image.
+    fn test_frame(&mut self, query: &str, timeout: Duration) -> Result<(), String> {
+        let (params, _) = RenderParams::from_query(query).map_err(|err| err.to_string())?;
+        self.start_map_load(params.map_name())
+            .map_err(|err| err.to_string())?;
+        self.finish_map_loads(Instant::now() + timeout);
+        let (sender, mut receiver) = tokio::sync::oneshot::channel();
+        match self.try_render(params, Instant::now() + timeout, sender)? {
+            Some(_) => Err("the graphics device was lost".to_string()),
+            None => match receiver.try_recv() {
+                Ok(rendered) => rendered.map(|_| ()).map_err(|err| err.to_string()),
+                Err(_) => Err("the screenshot was never taken".to_string()),
+            },
+        }
+    }
+
+    /// Renders the default Tee on the default map and a Tee without map, so the buffers
+    /// are uploaded and the first requests don't wait for it, see [`WARM_UP`].
+    fn warm_up(&mut self) {
+        for query in ["", "transparent=true"] {
+            let start = Instant::now();
+            match self.test_frame(query, Duration::from_secs(30)) {
+                Ok(()) => println!("warm-up render {query:?} took {:?}", start.elapsed()),
+                Err(err) => println!("warm-up render {query:?} failed: {err}"),
+            }
+        }
+    }
+
+    /// Renders the default test frame after a render panicked, before serving the next job.
+    /// The client may have been left in a broken state, so it's reinitialized if the test fails.
+    fn self_test(mut self) -> Self {
+        if self.test_frame("", Duration::from_secs(5)).is_ok() {
+            self.suspect = false;
+            return self;
+        }
+        println!("the test frame after a panic failed, reinitializing the graphics backend");
+        match load_client() {
+            Ok(client) => {
+                containers::forget_all();
+                client
+            }
+            Err(err) => {
+                println!("reinitializing the graphics backend failed: {err}");
+                self
+            }
+        }
+    }
+
+    /// Rebuilds the client after the graphics device was lost, with the maps that were loaded.
+    /// The process exits once the recovery budget is used up, see [`device::take_attempt`].
+    fn recover(self) -> Self {
+        let maps: Vec<String> = self
+            .maps
+            .iter()
+            .map(|(name, _)| name)
+            .chain(self.loading_maps.keys())
+            .cloned()
+            .collect();
+        // the old device is unusable, its resources are freed first
+        drop(self);
+        loop {
+            if !device::take_attempt() {
+                println!("the graphics device was lost too often, exiting");
+                std::process::exit(1);
+            }
+            println!("the graphics device was lost, reinitializing the graphics backend");
+            match load_client() {
+                Ok(mut client) => {
+                    metrics::DEVICE_RECOVERIES.inc("ok");
+                    containers::forget_all();
+                    for name in maps {
+                        if let Err(err) = client.start_map_load(&name) {
+                            println!("reloading map {name} failed: {err}");
+                        }
+                    }
+                    // the failed job is retried right away
+                    client.finish_map_loads(Instant::now() + maps::LOAD_TIMEOUT);
+                    println!("recovered from the lost graphics device");
+                    return client;
+                }
+                Err(err) => {
+                    metrics::DEVICE_RECOVERIES.inc("failed");
+                    println!("reinitializing the graphics backend failed: {err}");
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    /// Runs a maintenance task between renders.
+    /// The reload replaces the client, like after a stuck render.
+    fn maintain(mut self, task: Maintenance) -> (Self, Result<serde_json::Value, String>) {
+        let result = match task {
+            Maintenance::EvictMaps(Some(name)) => {
+                if self.loading_maps.remove(&name).is_some() {
+                    maps::load_stopped(&name);
+                }
+                serde_json::json!({ "evicted": usize::from(self.maps.remove(&name).is_some()) })
+            }
+            Maintenance::EvictMaps(None) => {
+                let evicted = self.maps.len();
+                self.maps.clear();
+                for (name, _) in self.loading_maps.drain() {
+                    maps::load_stopped(&name);
+                }
+                serde_json::json!({ "evicted": evicted })
+            }
+            Maintenance::UnloadSkins => {
+                // the container has no way to remove a single skin, but all of them are unused
+                // between renders, so a time after their keep alive unloads them
+                let keep_alive = containers::CONTAINERS.keep_alive;
+                self.skin_container.update(
+                    &(self.sys.time_get_nanoseconds() + keep_alive * 2),
+                    &keep_alive,
+                    &Duration::from_secs(1),
+                    [].into_iter(),
+                );
+                containers::forget(ContainerKind::Skins);
+                serde_json::json!({ "unloaded": true })
+            }
+            Maintenance::Stats => serde_json::json!({
+                "maps": self.maps.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                "loading": self.loading_maps.keys().collect::<Vec<_>>(),
+                "max_maps": *maps::MAX_LOADED_MAPS,
+            }),
+            Maintenance::ReloadContainers => {
+                println!("reloading the containers");
+                match load_client() {
+                    Ok(client) => {
+                        self = client;
+                        containers::forget_all();
+                        serde_json::json!({ "reloaded": true })
+                    }
+                    Err(err) => {
+                        return (self, Err(format!("reloading the containers failed: {err}")))
+                    }
+                }
+            }
+        };
+        (self, Ok(result))
+    }
+
+    /// Runs `work` on a tokio runtime while this thread renders the jobs it queues,
+    /// without servers. The render thread stops once `work` is done.
+    fn run_offline<T: Send + 'static>(
+        self,
+        work: impl Future<Output = T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let Some(jobs) = render_worker::init(1).pop() else {
+            return Err(anyhow!("the render queue was not created"));
+        };
+        let rt = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let work = rt.spawn(async move {
+            let output = work.await;
+            // the render thread stops once its queue is closed
+            render_worker::close();
+            output
+        });
+        let client = self.render_jobs(jobs);
+        client.graphics_backend.wait_idle()?;
+        Ok(rt.block_on(work)?)
+    }
+}
+
+impl containers::AssetContainers for Client {
+    fn request(&mut self, kind: ContainerKind, name: &str) {
+        if kind == ContainerKind::Skins {
+            let skin: Option<NetworkResourceKey<24>> = name.try_into().ok();
+            if let Some(skin) = &skin {
+                self.skin_container.get_or_default_opt(Some(skin));
+            }
+            return;
+        }
+        containers::mark_used(kind, name);
+        let Ok(key) = ResourceKey::try_from(name) else {
+            return;
+        };
+        match kind {
+            ContainerKind::Skins => {}
+            ContainerKind::Weapons => {
+                self.weapon_container.get_or_default(&key);
+            }
+            ContainerKind::Hooks => {
+                self.hooks_container.get_or_default(&key);
+            }
+            ContainerKind::Emoticons => {
+                self.emoticon_container.get_or_default(&key);
+            }
+            ContainerKind::Entities => {
+                self.entities_container.get_or_default(&key);
+            }
+        }
+    }
+
+    fn loaded(&mut self, kind: ContainerKind, name: &str) -> bool {
+        let Ok(key) = ResourceKey::try_from(name) else {
+            return true;
+        };
+        // `get_or_default` takes over finished loads, until then it returns the default
+        macro_rules! loaded {
+            ($container:expr) => {{
+                let default_key = $container.default_key.clone();
+                let default: *const _ = $container.get_or_default(&default_key);
+                key == default_key || !std::ptr::eq($container.get_or_default(&key), default)
+            }};
+        }
+        match kind {
+            ContainerKind::Skins => loaded!(self.skin_container),
+            ContainerKind::Weapons => loaded!(self.weapon_container),
+            ContainerKind::Hooks => loaded!(self.hooks_container),
+            ContainerKind::Emoticons => loaded!(self.emoticon_container),
+            ContainerKind::Entities => loaded!(self.entities_container),
+        }
+    }
+}
+
+/// Loads the graphics backend and all containers.
+fn load_client() -> anyhow::Result<Client> {
+    let io = Io::new(
+        |runtime| {
+            Arc::new(FileSystem::new(
+                runtime,
+                "org",
+                "",
+                "DDNet_Webservice",
+                "DDNet_Accounts_Dummy",
+            ))
+        },
+        Arc::new(HttpClient::new()),
+    );
+    let tp = Arc::new(ThreadPoolBuilder::new().build()?);
+
+    let map_pipe = MapPipeline::new_boxed();
+
+    let config_gl = config_gl();
+    let config_gfx = config_gfx();
+    let loading = GraphicsBackendLoading::new(
+        &config_gfx,
+        &config_dbg(),
+        &config_gl,
+        graphics_backend::window::BackendRawDisplayHandle::Headless,
         Some(Arc::new(parking_lot::RwLock::new(vec![map_pipe]))),
         io.clone().into(),
-    )
-    .unwrap();
+    )?;
     let loading_io = GraphicsBackendIoLoading::new(&config_gfx, &io.clone().into());
 
     let sys = System::new();
 
-    let client = Client::new(ClientLoad {
+    Client::new(ClientLoad {
         backend_loading: loading,
         backend_loading_io: loading_io,
         sys,
         io,
         tp,
     })
-    .unwrap();
-    client.run();
+}
+
+/// Loads the first client on the selected gpu, if that fails with the software renderer.
+/// Later clients, e.g. of the renderer pool, use the same path.
+fn load_first_client() -> anyhow::Result<Client> {
+    gpu::select_device();
+    let err = match load_client() {
+        Ok(client) => {
+            gpu::set_backend_path(gpu::BackendPath::Hardware);
+            return Ok(client);
+        }
+        Err(err) => err,
+    };
+    println!("{}", gpu::explain(&err));
+    gpu::use_software().map_err(|fallback| anyhow!("{}, {fallback}", gpu::explain(&err)))?;
+    println!("retrying with the software renderer");
+    let client = load_client().map_err(|err| anyhow!(gpu::explain(&err)))?;
+    gpu::set_backend_path(gpu::BackendPath::Software);
+    Ok(client)
+}
+
+fn main() {
+    let cli = cli::Cli::parse();
+    dotenvy::dotenv().ok();
+    // validated once, before any setting is read
+    let config = match settings::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(problems) => {
+            eprintln!("invalid configuration:");
+            for problem in problems {
+                eprintln!("- {problem}");
+            }
+            std::process::exit(2);
+        }
+    };
+
+    if std::env::var("RUST_LOG").is_err() {
+        unsafe { std::env::set_var("RUST_LOG", "warn,request=info,df::tract=error") };
+    }
+    env_logger::init();
+    println!("config: {}", config.redacted());
+
+    match cli.command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => {
+            println!("{} presets loaded", presets::PARAM_PRESETS.len());
+
+            match load_first_client() {
+                Ok(client) => client.run(),
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        cli::Command::Render(args) => {
+            let rendered = args.pairs().and_then(|pairs| {
+                let out = args.out.clone();
+                load_first_client()?
+                    .run_offline(async move { cli::render_to_file(pairs, &out).await })?
+            });
+            match rendered {
+                Ok(warnings) => {
+                    for warning in warnings {
+                        println!("warning: {warning}");
+                    }
+                }
+                Err(err) => {
+                    eprintln!("render failed: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        cli::Command::Sign(args) => match args.signed_query() {
+            Ok(query) => println!("{query}"),
+            Err(err) => {
+                eprintln!("signing failed: {err}");
+                std::process::exit(1);
+            }
+        },
+        cli::Command::Batch => {
+            match load_first_client().and_then(|client| client.run_offline(cli::batch())) {
+                Ok(0) => {}
+                Ok(failures) => {
+                    eprintln!("{failures} renders failed");
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    eprintln!("batch failed: {err}");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
 }
 
 async fn async_main() {
-    let app = Router::new().route("/", get(generate_preview));
+    tokio::spawn(health::probe());
+    if upload::enabled() {
+        tokio::spawn(upload::collect_expired());
+    }
+
+    listen::serve(router()).await;
+}
+
+/// The routes of the http api, renders are queued for the render thread.
+fn router() -> Router {
+    let mut skins_route = get(skins::skins);
+    if upload::enabled() {
+        skins_route =
+            skins_route.post(upload::upload_skin.layer(middleware::from_fn(rate_limit::limit)));
+    }
+    let mut app = Router::new()
+        .route(
+            "/",
+            get(generate_preview)
+                .layer(middleware::from_fn(rate_limit::limit))
+                .layer(middleware::from_fn(signed_url::verify_signature))
+                .post(
+                    generate_preview_json
+                        .layer(middleware::from_fn(rate_limit::limit))
+                        .layer(middleware::from_fn(signed_url::require_anonymous)),
+                ),
+        )
+        .route(
+            "/emoticons_preview",
+            get(emoticons_preview)
+                .layer(middleware::from_fn(rate_limit::limit))
+                .layer(middleware::from_fn(signed_url::verify_signature)),
+        )
+        .route(
+            "/resolve",
+            get(resolve::resolve_params)
+                .layer(middleware::from_fn(rate_limit::limit))
+                .layer(middleware::from_fn(signed_url::verify_signature)),
+        )
+        .route(
+            "/map_preview",
+            get(map_preview::map_preview)
+                .layer(middleware::from_fn(rate_limit::limit))
+                .layer(middleware::from_fn(signed_url::verify_signature)),
+        )
+        .route(
+            "/minimap",
+            get(minimap::minimap)
+                .layer(middleware::from_fn(rate_limit::limit))
+                .layer(middleware::from_fn(signed_url::verify_signature))
+                .post(
+                    minimap::minimap_json
+                        .layer(middleware::from_fn(rate_limit::limit))
+                        .layer(middleware::from_fn(signed_url::require_anonymous)),
+                ),
+        )
+        .route("/maps", get(maps::maps))
+        .route("/maps/loading", get(maps::loading))
+        .route("/skins", skins_route)
+        .route("/options", get(options::options))
+        .route("/presets", get(presets::presets))
+        .route("/load", get(load_report))
+        .route("/version", get(version::version))
+        .route("/openapi.json", get(openapi::openapi))
+        .route("/healthz", get(health::health))
+        .route("/readyz", get(health::ready))
+        .route("/metrics", get(metrics::metrics));
+    if playground::enabled() {
+        app = app.route("/playground", get(playground::playground));
+    }
+    app
+        // the admin api is nested afterwards, so browsers never get to it
+        .layer(middleware::from_fn(cors::cors))
+        .nest("/admin", admin::router())
+}
+
+/// `DISCORD_MAINTAINERS`: comma separated user ids that may use `debug`
+static DISCORD_MAINTAINERS: LazyLock<Vec<UserId>> = LazyLock::new(|| {
+    std::env::var("DISCORD_MAINTAINERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<u64>().ok())
+        .map(UserId::new)
+        .collect()
+});
+
+/// Discord users are limited independently of http clients.
+/// - `DISCORD_COOLDOWN_SECS` seconds until a user gets another render (10 is default)
+/// - `DISCORD_COOLDOWN_BURST` renders a user can do at once (3 is default)
+/// - `DISCORD_GUILD_PER_MIN` renders per minute and guild (30 is default)
+/// - `DISCORD_GUILD_BURST` renders a guild can do at once (10 is default)
+/// - `DISCORD_MODERATOR_ROLES` comma separated role ids that are exempt, like maintainers
+struct DiscordCooldown {
+    users: rate_limit::RateLimiter<UserId>,
+    guilds: rate_limit::RateLimiter<GuildId>,
+    moderator_roles: Vec<RoleId>,
+}
+
+impl DiscordCooldown {
+    /// Takes a render of the user and guild, or returns how long to wait.
+    fn check(
+        &self,
+        user: UserId,
+        guild: Option<GuildId>,
+        member: Option<&Member>,
+    ) -> Result<(), Duration> {
+        let exempt = DISCORD_MAINTAINERS.contains(&user)
+            || member.is_some_and(|member| {
+                member
+                    .roles
+                    .iter()
+                    .any(|role| self.moderator_roles.contains(role))
+            });
+        if exempt {
+            return Ok(());
+        }
+        let now = Instant::now();
+        self.users.check(user, now)?;
+        match guild {
+            Some(guild) => self.guilds.check(guild, now),
+            None => Ok(()),
+        }
+    }
+}
 
-    let port: u16 = std::env::var("PORT")
+static DISCORD_COOLDOWN: LazyLock<DiscordCooldown> = LazyLock::new(|| {
+    let cooldown: f64 = std::env::var("DISCORD_COOLDOWN_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<f64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(10.0);
+    let burst: f64 = std::env::var("DISCORD_COOLDOWN_BURST")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<f64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(3.0);
+    let guild_per_min: f64 = std::env::var("DISCORD_GUILD_PER_MIN")
         .map_err(|err| anyhow!(err))
-        .and_then(|s| s.parse::<u16>().map_err(|err| anyhow!(err)))
-        .unwrap_or(3002);
+        .and_then(|s| s.parse::<f64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(30.0);
+    let guild_burst: f64 = std::env::var("DISCORD_GUILD_BURST")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<f64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(10.0);
+    let moderator_roles = std::env::var("DISCORD_MODERATOR_ROLES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|id| id.trim().parse::<u64>().ok())
+        .filter(|id| *id != 0)
+        .map(RoleId::new)
+        .collect();
+    DiscordCooldown {
+        users: rate_limit::RateLimiter::new(1.0 / cooldown.max(f64::EPSILON), burst),
+        guilds: rate_limit::RateLimiter::new(guild_per_min / 60.0, guild_burst),
+        moderator_roles,
+    }
+});
 
-    let addr: SocketAddr = format!("127.0.0.1:{port}").parse().unwrap();
+fn cooldown_message(retry_after: Duration) -> String {
+    format!(
+        "Slow down, you can render again in {}s",
+        retry_after.as_secs_f64().ceil() as u64
+    )
+}
 
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+/// Where the bot answers.
+/// - `GUILD_ID` comma separated guild ids
+/// - `DISCORD_GLOBAL_COMMANDS` registers the commands globally instead of per guild,
+///   all guilds are allowed unless `GUILD_ID` is set (false is default)
+struct DiscordGuilds {
+    guilds: Vec<GuildId>,
+    global: bool,
 }
 
-struct Handler;
+impl DiscordGuilds {
+    fn allowed(&self, guild_id: Option<GuildId>) -> bool {
+        match guild_id {
+            Some(guild_id) => {
+                self.guilds.contains(&guild_id) || (self.global && self.guilds.is_empty())
+            }
+            None => false,
+        }
+    }
+}
+
+static DISCORD_GUILDS: LazyLock<DiscordGuilds> = LazyLock::new(|| {
+    let guilds = std::env::var("GUILD_ID")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .filter_map(|id| match id.parse::<u64>() {
+            Ok(id) if id != 0 => Some(GuildId::new(id)),
+            _ => {
+                println!("Ignoring invalid guild id {id:?} in GUILD_ID");
+                None
+            }
+        })
+        .collect();
+    let global: bool = std::env::var("DISCORD_GLOBAL_COMMANDS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(false);
+    DiscordGuilds { guilds, global }
+});
+
+/// A `/skin` command that is still rendering.
+struct RunningCommand {
+    abort: AbortHandle,
+    command: CommandInteraction,
+}
+
+#[derive(Default)]
+struct Handler {
+    /// In flight commands by user and command name,
+    /// a newer command of the same user supersedes the older one.
+    in_flight: InFlight<(UserId, String), InteractionId, RunningCommand>,
+}
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Component(component) = &interaction {
+            if let Err(retry_after) = DISCORD_COOLDOWN.check(
+                component.user.id,
+                component.guild_id,
+                component.member.as_deref(),
+            ) {
+                return ephemeral_reply(&ctx, component, cooldown_message(retry_after)).await;
+            }
+            if component.data.custom_id.starts_with(SKIN_RETRY_ID) {
+                return retry_with_skin(&ctx, component).await;
+            }
+            return tweak_preview(&ctx, component).await;
+        }
+        if let Interaction::Autocomplete(autocomplete) = &interaction {
+            return complete_option(&ctx, autocomplete).await;
+        }
         if let Interaction::Command(command) = interaction {
-            let guild_id = GuildId::new(
-                std::env::var("GUILD_ID")
-                    .expect("Expected GUILD_ID in environment")
-                    .parse()
-                    .expect("GUILD_ID must be an integer"),
-            );
-
             let on_err = |err: String| {
                 Box::pin(async {
                     let _ = command
@@ -816,7 +1902,11 @@ impl EventHandler for Handler {
                 })
             };
 
-            if command.guild_id != Some(guild_id) {
+            if !DISCORD_GUILDS.global && DISCORD_GUILDS.guilds.is_empty() {
+                println!("Ignoring /{}: no GUILD_ID is configured", command.data.name);
+                return on_err("The bot is not configured for this discord".into()).await;
+            }
+            if !DISCORD_GUILDS.allowed(command.guild_id) {
                 return on_err("can only be used in the right discord".into()).await;
             }
 
@@ -829,97 +1919,791 @@ impl EventHandler for Handler {
                 _ => None,
             };
 
-            let player_name = if let Some(arg) = command
+            let player_name = command_option(&command, "player_name")
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let debug = command
                 .data
                 .options
-                .first()
-                .and_then(|arg| arg.value.as_str())
+                .iter()
+                .find(|arg| arg.name == "debug")
+                .and_then(|arg| arg.value.as_bool())
+                .unwrap_or_default();
+            if debug && !DISCORD_MAINTAINERS.contains(&command.user.id) {
+                return on_err("debug is only available to maintainers".into()).await;
+            }
+            match command.data.name.as_str() {
+                "link" => return link_player(&ctx, &command).await,
+                "unlink" => return unlink_player(&ctx, &command).await,
+                _ => {}
+            }
+            if let Err(retry_after) =
+                DISCORD_COOLDOWN.check(command.user.id, command.guild_id, command.member.as_deref())
             {
-                arg.to_string()
-            } else {
-                "".to_string()
-            };
+                return on_err(cooldown_message(retry_after)).await;
+            }
+            if command.data.name == "map" {
+                return post_map_overview(&ctx, &command).await;
+            }
 
             if let Some(content) = content {
-                let img =
-                    match HTTP
-                        .get(
-                            format!(
-                                "http://localhost:3002/?player_name={}\
-                                &skin_name=default\
-                                &zoom=0.25\
-                                &x=17.0\
-                                &y=25.5\
-                                &weapon=hammer\
-                                &emoticon=hearts\
-                                &use_player_api=true\
-                                &eyes=happy",
-                                encode(&player_name)
-                            )
-                            .as_str(),
-                        )
-                        .send()
-                        .await
-                        .and_then(|res| res.error_for_status())
-                    {
-                        Ok(skin) => {
-                            if skin.headers().get("content-type").is_some_and(|ty| {
-                                ty.to_str().is_ok_and(|ty| ty.contains("image/png"))
-                            }) {
-                                match skin.bytes().await {
-                                    Ok(skin) => skin,
-                                    Err(err) => return on_err(err.to_string()).await,
-                                }
-                            } else {
-                                return on_err(format!(
-                                    "Failed to fetch image: {}",
-                                    skin.text().await.unwrap_or_else(|err| err.to_string())
-                                ))
-                                .await;
-                            }
-                        }
-                        Err(err) => {
-                            return on_err(err.to_string()).await;
+                let key = (command.user.id, command.data.name.clone());
+                // without the store the preview is rendered as if nothing was linked
+                let prefs = prefs::get(command.user.id.get())
+                    .await
+                    .unwrap_or_else(|err| {
+                        println!("Could not load user preferences: {err}");
+                        None
+                    })
+                    .unwrap_or_default();
+                let player_name = if player_name.is_empty() {
+                    prefs.player_name.clone().unwrap_or_default()
+                } else {
+                    player_name
+                };
+                let skin_name = command_option(&command, "skin_name")
+                    .and_then(|value| value.as_str())
+                    .or(prefs.skin_name.as_deref());
+                // an explicit or preferred skin replaces the player's skin
+                let params = match discord_params(
+                    &player_name,
+                    skin_name.unwrap_or("default"),
+                    skin_name.is_none(),
+                    prefs.pairs(),
+                ) {
+                    Ok(mut params) => {
+                        apply_command_options(&mut params, &command);
+                        params
+                    }
+                    Err(err) => return on_err(discord_error(&err)).await,
+                };
+                // a cold render can take longer than discord waits for a response,
+                // from here on the deferred response is edited
+                if let Err(why) = command.defer(&ctx.http).await {
+                    println!("Could not defer slash command: {why}");
+                    return;
+                }
+                let edit_err = |err: String| {
+                    Box::pin(async {
+                        if let Err(why) = command
+                            .edit_response(&ctx.http, EditInteractionResponse::new().content(err))
+                            .await
+                        {
+                            println!("Could not respond to slash command: {why}");
                         }
-                    };
+                    })
+                };
+                let state_params = params.clone();
+                let task = tokio::spawn(async move {
+                    if debug {
+                        fetch_support_bundle(params)
+                            .await
+                            .map(|bundle| (bundle, Vec::new(), None))
+                    } else {
+                        fetch_preview(params).await
+                    }
+                });
+                let (in_flight, superseded) = self.in_flight.insert(
+                    key,
+                    command.id,
+                    RunningCommand {
+                        abort: task.abort_handle(),
+                        command: command.clone(),
+                    },
+                );
+                if let Some(superseded) = superseded {
+                    superseded.abort.abort();
+                    let _ = superseded
+                        .command
+                        .edit_response(
+                            &ctx.http,
+                            EditInteractionResponse::new()
+                                .content("Superseded by your newer request"),
+                        )
+                        .await;
+                }
+
+                let Ok(res) = task.await else {
+                    // aborted by a newer command, which already responded
+                    return;
+                };
+                // if the render finished just as a newer command arrived,
+                // the newer one already responded
+                if !in_flight.finish() {
+                    return;
+                }
+
+                let (img, suggestions, player_api_note) = match res {
+                    Ok(preview) => preview,
+                    Err(err) => return edit_err(err).await,
+                };
+                let content = match player_api_note {
+                    Some(note) => format!("{content}\n{note}"),
+                    None => content,
+                };
 
-                let data = CreateInteractionResponseMessage::new()
+                let edit = EditInteractionResponse::new()
                     .content(content)
-                    //.ephemeral(true)
-                    .add_file(CreateAttachment::bytes(img, "preview.png"));
-                let builder = CreateInteractionResponse::Message(data);
-                if let Err(why) = command.create_response(&ctx.http, builder).await {
-                    println!("Could not respond to slash command: {why}");
-                } else {
-                    let _ = ctx.data.write().await;
+                    .new_attachment(CreateAttachment::bytes(
+                        img,
+                        if debug {
+                            "support-bundle.zip"
+                        } else {
+                            "preview.png"
+                        },
+                    ))
+                    .components(if debug {
+                        Vec::new()
+                    } else {
+                        preview_components(&state_params, &suggestions)
+                    });
+                match command.edit_response(&ctx.http, edit).await {
+                    Ok(message) => {
+                        if !debug {
+                            store_preview_state(message.id, state_params);
+                        }
+                        let _ = ctx.data.write().await;
+                    }
+                    Err(why) => println!("Could not respond to slash command: {why}"),
                 }
             }
         }
     }
 
     async fn ready(&self, ctx: Context, _ready: Ready) {
-        let guild_id = GuildId::new(
-            std::env::var("GUILD_ID")
-                .expect("Expected GUILD_ID in environment")
-                .parse()
-                .expect("GUILD_ID must be an integer"),
-        );
-
         let skin_cmd = CreateCommand::new("skin")
             .description("Create a preview of that skin")
             .add_option(CreateCommandOption::new(
                 serenity::all::CommandOptionType::String,
                 "player_name",
-                "Name of the player to render",
+                "Name of the player to render, your linked player by default",
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    serenity::all::CommandOptionType::String,
+                    "skin_name",
+                    "Skin to render instead of the player's skin",
+                )
+                .set_autocomplete(true),
+            )
+            .add_option(string_choices(
+                "eyes",
+                "Eyes of the Tee",
+                params::EYES.into_iter().map(params::eye_name),
+            ))
+            .add_option(string_choices(
+                "weapon",
+                "Weapon the Tee holds",
+                params::WEAPONS.into_iter().map(params::weapon_name),
+            ))
+            .add_option(string_choices(
+                "emoticon",
+                "Emoticon above the Tee",
+                EmoticonType::iter().map(params::emoticon_name),
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    serenity::all::CommandOptionType::Number,
+                    "zoom",
+                    "Camera zoom",
+                )
+                .min_number_value(params::ZOOM_RANGE.min.into())
+                .max_number_value(params::ZOOM_RANGE.max.into()),
+            )
+            .add_option(CreateCommandOption::new(
+                serenity::all::CommandOptionType::Boolean,
+                "transparent",
+                "Render without map",
+            ))
+            .add_option(CreateCommandOption::new(
+                serenity::all::CommandOptionType::Boolean,
+                "debug",
+                "Attach a support bundle instead of the image (maintainers only)",
             ))
             .dm_permission(false);
 
-        if (guild_id.set_commands(&ctx.http, vec![skin_cmd]).await).is_err() {
-            // ignore for now
+        let map_cmd = CreateCommand::new("map")
+            .description("Create an overview of a map")
+            .add_option(
+                CreateCommandOption::new(
+                    serenity::all::CommandOptionType::String,
+                    "map_name",
+                    "Name of the map",
+                )
+                .required(true)
+                .set_autocomplete(true),
+            )
+            .dm_permission(false);
+        let link_cmd = CreateCommand::new("link")
+            .description("Link your player, /skin renders it by default")
+            .add_option(
+                CreateCommandOption::new(
+                    serenity::all::CommandOptionType::String,
+                    "player_name",
+                    "Name of your player",
+                )
+                .required(true),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    serenity::all::CommandOptionType::String,
+                    "skin_name",
+                    "Skin to render instead of your player's skin",
+                )
+                .set_autocomplete(true),
+            )
+            .add_option(CreateCommandOption::new(
+                serenity::all::CommandOptionType::String,
+                "body_color",
+                "Body color, e.g. #ff8800",
+            ))
+            .add_option(CreateCommandOption::new(
+                serenity::all::CommandOptionType::String,
+                "feet_color",
+                "Feet color, e.g. #ff8800",
+            ))
+            .add_option(string_choices(
+                "eyes",
+                "Eyes of the Tee",
+                params::EYES.into_iter().map(params::eye_name),
+            ))
+            .add_option(
+                CreateCommandOption::new(
+                    serenity::all::CommandOptionType::String,
+                    "map_name",
+                    "Map to render on",
+                )
+                .set_autocomplete(true),
+            )
+            .dm_permission(false);
+        let unlink_cmd = CreateCommand::new("unlink")
+            .description("Remove your linked player and preferences")
+            .dm_permission(false);
+        let commands = vec![skin_cmd, map_cmd, link_cmd, unlink_cmd];
+
+        if DISCORD_GUILDS.global {
+            if let Err(why) = Command::set_global_commands(&ctx.http, commands).await {
+                println!("Could not register global commands: {why}");
+            }
+            return;
+        }
+        if DISCORD_GUILDS.guilds.is_empty() {
+            println!("No GUILD_ID is configured, commands are not registered");
+        }
+        for guild_id in &DISCORD_GUILDS.guilds {
+            if let Err(why) = guild_id.set_commands(&ctx.http, commands.clone()).await {
+                println!("Could not register commands in guild {guild_id}: {why}");
+            }
+        }
+    }
+}
+
+/// The value of a slash command option.
+fn command_option<'a>(
+    command: &'a CommandInteraction,
+    name: &str,
+) -> Option<&'a CommandDataOptionValue> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|arg| arg.name == name)
+        .map(|arg| &arg.value)
+}
+
+/// A string option limited to the names the http api accepts,
+/// so both always offer the same values.
+fn string_choices(
+    name: &str,
+    description: &str,
+    values: impl IntoIterator<Item = &'static str>,
+) -> CreateCommandOption {
+    values.into_iter().fold(
+        CreateCommandOption::new(serenity::all::CommandOptionType::String, name, description),
+        |option, value| option.add_string_choice(value, value),
+    )
+}
+
+/// Omitted options keep the values of the discord preset.
+fn apply_command_options(params: &mut RenderParams, command: &CommandInteraction) {
+    let string = |name| {
+        command_option(command, name)
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string())
+    };
+    if let Some(eyes) = string("eyes") {
+        params.eyes = Some(eyes);
+    }
+    if let Some(weapon) = string("weapon") {
+        params.weapon = Some(weapon);
+    }
+    if let Some(emoticon) = string("emoticon") {
+        params.emoticon = Some(emoticon);
+    }
+    if let Some(zoom) = command_option(command, "zoom").and_then(|value| value.as_f64()) {
+        params.zoom = Some(zoom as f32);
+    }
+    if let Some(transparent) =
+        command_option(command, "transparent").and_then(|value| value.as_bool())
+    {
+        params.transparent = Some(transparent);
+    }
+}
+
+/// The params of a discord preview, names are set directly, so they need no encoding.
+/// `defaults` replace values of the preset, e.g. the user's preferences.
+fn discord_params(
+    player_name: &str,
+    skin_name: &str,
+    use_player_api: bool,
+    defaults: Vec<(String, String)>,
+) -> Result<RenderParams, RenderError> {
+    let mut pairs = vec![("preset".to_string(), presets::DISCORD_PRESET.to_string())];
+    pairs.extend(defaults);
+    let (mut params, _) = RenderParams::from_pairs(pairs)?;
+    let too_long = |field: &'static str, value: &str| ParamError {
+        field,
+        value: value.chars().take(MAX_LOGGED_NAME).collect(),
+        reason: "too long".to_string(),
+        accepted: Vec::new(),
+    };
+    if !player_name.is_empty() {
+        params.player_name = Some(
+            NetworkString::new(player_name).map_err(|_| too_long("player_name", player_name))?,
+        );
+    }
+    params.skin_name =
+        NetworkString::new(skin_name).map_err(|_| too_long("skin_name", skin_name))?;
+    params.use_player_api = Some(use_player_api);
+    Ok(params)
+}
+
+/// A message for discord users, who can't act on internals.
+/// Failures on our side name the build, so reports can be matched to it.
+fn discord_error(err: &RenderError) -> String {
+    let message = discord_message(err);
+    if err.status().is_server_error() {
+        format!("{message} (build {})", version::short_commit())
+    } else {
+        message
+    }
+}
+
+fn discord_message(err: &RenderError) -> String {
+    match err {
+        RenderError::InvalidQuery(_) | RenderError::InvalidParam(_) => {
+            format!("That doesn't work: {err}")
+        }
+        RenderError::SkinNotFound { .. }
+        | RenderError::MapNotFound(_)
+        | RenderError::Forbidden(_) => err.to_string(),
+        RenderError::Upstream(_) => {
+            "The player database could not be reached, try again later".to_string()
+        }
+        RenderError::PlayerApiInvalid(_) => {
+            "The player database sent a profile that could not be read".to_string()
+        }
+        RenderError::RateLimited { .. } | RenderError::Busy => {
+            "Too many previews are rendered right now, try again in a moment".to_string()
+        }
+        RenderError::MapNotLoaded(_) | RenderError::Timeout(_) => {
+            "Rendering took too long, try again".to_string()
+        }
+        RenderError::MapLoading { .. } => {
+            "The map is still loading, try again in a moment".to_string()
+        }
+        RenderError::ShuttingDown => "The bot is restarting, try again in a minute".to_string(),
+        RenderError::Gpu(_) | RenderError::Panicked(_) | RenderError::Internal(_) => {
+            "Rendering failed, try again later".to_string()
+        }
+    }
+}
+
+/// Renders a preview with high priority. Returns the png, the suggested
+/// skin names if the skin was not found and, if the player api failed,
+/// why the requested skin is rendered instead of the player's.
+async fn fetch_preview(
+    params: RenderParams,
+) -> Result<(Vec<u8>, Vec<String>, Option<String>), String> {
+    let preview = render_parsed(params, Vec::new(), Priority::High, Instant::now())
+        .await
+        .map_err(|err| discord_error(&err))?;
+    if preview.format != OutputFormat::Png {
+        return Err("Failed to fetch image: not a png".to_string());
+    }
+    let player_api_note = preview
+        .player_api_error
+        .as_ref()
+        .map(|err| format!("{}, the requested skin is shown", discord_error(err)));
+    Ok((preview.img, preview.skin_suggestions, player_api_note))
+}
+
+/// Creates a support bundle of the preview like the admin api.
+async fn fetch_support_bundle(params: RenderParams) -> Result<Vec<u8>, String> {
+    let preview = render_parsed(params, Vec::new(), Priority::High, Instant::now())
+        .await
+        .map_err(|err| discord_error(&err))?;
+    support_bundle::create_from(preview)
+        .await
+        .map_err(|err| discord_error(&err))
+}
+
+const SKIN_RETRY_ID: &str = "skin_retry";
+
+/// Quick retry buttons for skin names that were suggested instead of an unknown skin.
+/// The custom id is `skin_retry:<skin>:<player name>` and must not exceed 100 bytes.
+fn skin_retry_buttons(player_name: &str, suggestions: &[String]) -> Vec<CreateActionRow> {
+    let buttons: Vec<CreateButton> = suggestions
+        .iter()
+        .map(|skin| {
+            let mut id = format!("{SKIN_RETRY_ID}:{skin}:{player_name}");
+            while id.len() > 100 {
+                id.pop();
+            }
+            CreateButton::new(id)
+                .label(format!("Try {skin}"))
+                .style(ButtonStyle::Secondary)
+        })
+        .collect();
+    if buttons.is_empty() {
+        Vec::new()
+    } else {
+        vec![CreateActionRow::Buttons(buttons)]
+    }
+}
+
+async fn retry_with_skin(ctx: &Context, component: &ComponentInteraction) {
+    let mut id = component.data.custom_id.splitn(3, ':');
+    if id.next() != Some(SKIN_RETRY_ID) {
+        return;
+    }
+    let skin_name = id.next().unwrap_or("default");
+    let player_name = id.next().unwrap_or_default();
+
+    // keep the tweaks of the preview
+    let params = match preview_state(component.message.id) {
+        Some(mut params) => NetworkString::new(skin_name)
+            .map(|skin_name| {
+                params.skin_name = skin_name;
+                params.use_player_api = Some(false);
+                params
+            })
+            .map_err(|_| "That skin name is too long".to_string()),
+        None => discord_params(player_name, skin_name, false, Vec::new())
+            .map_err(|err| discord_error(&err)),
+    };
+    rerender(ctx, component, params).await;
+}
+
+/// How long the menus of a preview can re-render it.
+const PREVIEW_STATE_TTL: Duration = Duration::from_secs(5 * 60);
+
+const TWEAK_EYES_ID: &str = "tweak_eyes";
+const TWEAK_WEAPON_ID: &str = "tweak_weapon";
+
+/// The params of posted previews by message, so their menus can re-render them.
+static PREVIEW_STATES: LazyLock<parking_lot::Mutex<HashMap<MessageId, (Instant, RenderParams)>>> =
+    LazyLock::new(Default::default);
+
+fn store_preview_state(message: MessageId, params: RenderParams) {
+    let mut states = PREVIEW_STATES.lock();
+    states.retain(|_, (at, _)| at.elapsed() < PREVIEW_STATE_TTL);
+    // re-rendering doesn't extend the lifetime
+    let created = states.get(&message).map_or(Instant::now(), |(at, _)| *at);
+    states.insert(message, (created, params));
+}
+
+fn preview_state(message: MessageId) -> Option<RenderParams> {
+    PREVIEW_STATES
+        .lock()
+        .get(&message)
+        .filter(|(at, _)| at.elapsed() < PREVIEW_STATE_TTL)
+        .map(|(_, params)| params.clone())
+}
+
+/// A select menu of the names the http api accepts, the current one is preselected.
+fn tweak_menu(
+    id: &str,
+    placeholder: &str,
+    values: impl IntoIterator<Item = &'static str>,
+    current: &str,
+) -> CreateActionRow {
+    let options = values
+        .into_iter()
+        .map(|value| CreateSelectMenuOption::new(value, value).default_selection(value == current))
+        .collect();
+    CreateActionRow::SelectMenu(
+        CreateSelectMenu::new(id, CreateSelectMenuKind::String { options })
+            .placeholder(placeholder),
+    )
+}
+
+/// Retry buttons for suggested skins and menus to change the eyes or weapon.
+fn preview_components(params: &RenderParams, suggestions: &[String]) -> Vec<CreateActionRow> {
+    let player_name = params
+        .player_name
+        .as_ref()
+        .map(|name| name.as_str())
+        .unwrap_or_default();
+    let mut rows = skin_retry_buttons(player_name, suggestions);
+    rows.push(tweak_menu(
+        TWEAK_EYES_ID,
+        "Eyes",
+        params::EYES.into_iter().map(params::eye_name),
+        params::eye_name(params.eyes()),
+    ));
+    rows.push(tweak_menu(
+        TWEAK_WEAPON_ID,
+        "Weapon",
+        params::WEAPONS.into_iter().map(params::weapon_name),
+        params.weapon().map(params::weapon_name).unwrap_or_default(),
+    ));
+    rows
+}
+
+/// Answers a component interaction with a message only its user sees.
+async fn ephemeral_reply(ctx: &Context, component: &ComponentInteraction, content: String) {
+    let _ = component
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .ephemeral(true)
+                    .content(content),
+            ),
+        )
+        .await;
+}
+
+/// Re-renders a preview with the eyes or weapon picked in its menus.
+async fn tweak_preview(ctx: &Context, component: &ComponentInteraction) {
+    let ComponentInteractionDataKind::StringSelect { values } = &component.data.kind else {
+        return;
+    };
+    let Some(value) = values.first() else {
+        return;
+    };
+    let Some(mut params) = preview_state(component.message.id) else {
+        return ephemeral_reply(
+            ctx,
+            component,
+            "This preview expired, run the command again".into(),
+        )
+        .await;
+    };
+    match component.data.custom_id.as_str() {
+        TWEAK_EYES_ID => params.eyes = Some(value.clone()),
+        TWEAK_WEAPON_ID => params.weapon = Some(value.clone()),
+        _ => return,
+    }
+    rerender(ctx, component, Ok(params)).await;
+}
+
+/// Renders the params and replaces the image of the component's message.
+async fn rerender(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    params: Result<RenderParams, String>,
+) {
+    // a cold render can take longer than discord waits for a response
+    if let Err(why) = component.defer(&ctx.http).await {
+        println!("Could not defer component interaction: {why}");
+        return;
+    }
+    let res = match params {
+        Ok(params) => fetch_preview(params.clone())
+            .await
+            .map(|preview| (params, preview)),
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok((params, (img, suggestions, _))) => {
+            let edit = EditInteractionResponse::new()
+                .clear_attachments()
+                .new_attachment(CreateAttachment::bytes(img, "preview.png"))
+                .components(preview_components(&params, &suggestions));
+            match component.edit_response(&ctx.http, edit).await {
+                Ok(message) => store_preview_state(message.id, params),
+                Err(why) => println!("Could not respond to component interaction: {why}"),
+            }
+        }
+        // the previous preview stays
+        Err(err) => {
+            let _ = component
+                .create_followup(
+                    &ctx.http,
+                    CreateInteractionResponseFollowup::new()
+                        .ephemeral(true)
+                        .content(err),
+                )
+                .await;
         }
     }
 }
 
+/// Discord shows at most 25 choices.
+const MAX_AUTOCOMPLETE_CHOICES: usize = 25;
+
+/// Suggests skins containing the typed part of `skin_name`,
+/// the listing is cached, so typing doesn't read the filesystem.
+async fn complete_option(ctx: &Context, autocomplete: &CommandInteraction) {
+    let Some(focused) = autocomplete.data.autocomplete() else {
+        return;
+    };
+    let choices: Vec<String> = match focused.name {
+        "skin_name" => skins::skin_index()
+            .await
+            .search(focused.value, MAX_AUTOCOMPLETE_CHOICES)
+            .into_iter()
+            .map(|skin| skin.to_string())
+            .collect(),
+        "map_name" => {
+            let maps = tokio::task::spawn_blocking(maps::list_maps)
+                .await
+                .unwrap_or_default();
+            maps::search(&maps, focused.value, MAX_AUTOCOMPLETE_CHOICES)
+                .into_iter()
+                .map(|map| map.to_string())
+                .collect()
+        }
+        _ => return,
+    };
+    let response = choices
+        .into_iter()
+        .fold(CreateAutocompleteResponse::new(), |response, choice| {
+            response.add_string_choice(choice.clone(), choice)
+        });
+    if let Err(why) = autocomplete
+        .create_response(&ctx.http, CreateInteractionResponse::Autocomplete(response))
+        .await
+    {
+        println!("Could not respond to {} autocomplete: {why}", focused.name);
+    }
+}
+
+/// Answers a command with a message only its user sees.
+async fn ephemeral_command_reply(ctx: &Context, command: &CommandInteraction, content: String) {
+    if let Err(why) = command
+        .create_response(
+            &ctx.http,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .ephemeral(true)
+                    .content(content),
+            ),
+        )
+        .await
+    {
+        println!("Could not respond to slash command: {why}");
+    }
+}
+
+/// Stores the player and render preferences of the user, `/skin` uses them by default.
+async fn link_player(ctx: &Context, command: &CommandInteraction) {
+    let string = |name| {
+        command_option(command, name)
+            .and_then(|value| value.as_str())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    let prefs = prefs::Prefs {
+        player_name: string("player_name"),
+        skin_name: string("skin_name"),
+        body_color: string("body_color"),
+        feet_color: string("feet_color"),
+        eyes: string("eyes"),
+        map_name: string("map_name"),
+    };
+    // only preferences that render are stored
+    let player_name = prefs.player_name.clone().unwrap_or_default();
+    if let Err(err) = discord_params(
+        &player_name,
+        prefs.skin_name.as_deref().unwrap_or("default"),
+        prefs.skin_name.is_none(),
+        prefs.pairs(),
+    ) {
+        return ephemeral_command_reply(ctx, command, discord_error(&err)).await;
+    }
+    let content = match prefs::set(command.user.id.get(), prefs).await {
+        Ok(()) => format!("Linked to {player_name}, `/skin` renders your player by default"),
+        Err(err) => {
+            println!("Could not store user preferences: {err}");
+            "Your preferences could not be stored, try again later".to_string()
+        }
+    };
+    ephemeral_command_reply(ctx, command, content).await;
+}
+
+async fn unlink_player(ctx: &Context, command: &CommandInteraction) {
+    let content = match prefs::remove(command.user.id.get()).await {
+        Ok(true) => "Unlinked, your preferences were removed".to_string(),
+        Ok(false) => "You are not linked".to_string(),
+        Err(err) => {
+            println!("Could not remove user preferences: {err}");
+            "Your preferences could not be removed, try again later".to_string()
+        }
+    };
+    ephemeral_command_reply(ctx, command, content).await;
+}
+
+/// Close map names listed for unknown maps.
+const MAX_MAP_SUGGESTIONS: usize = 5;
+
+/// Posts an overview of the whole map, see [`map_preview::render`].
+async fn post_map_overview(ctx: &Context, command: &CommandInteraction) {
+    let map_name = command_option(command, "map_name")
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+        .to_string();
+    // a cold render or a map download can take longer than discord waits for a response
+    if let Err(why) = command.defer(&ctx.http).await {
+        println!("Could not defer slash command: {why}");
+        return;
+    }
+    let pairs = vec![("map_name".to_string(), map_name.clone())];
+    let res = match map_preview::render(pairs, None, None, Priority::High).await {
+        Ok(preview) => preview.encode_png().await,
+        Err(err) => Err(err),
+    };
+    let err = match res {
+        Ok(img) => {
+            let edit = EditInteractionResponse::new()
+                .content(format!("{}\n{map_name}", Mention::User(command.user.id)))
+                .new_attachment(CreateAttachment::bytes(img, "map.png"));
+            if let Err(why) = command.edit_response(&ctx.http, edit).await {
+                println!("Could not respond to slash command: {why}");
+            }
+            return;
+        }
+        Err(RenderError::MapNotFound(_) | RenderError::InvalidParam(_)) => {
+            let maps = tokio::task::spawn_blocking(maps::list_maps)
+                .await
+                .unwrap_or_default();
+            let suggestions = maps::suggest(&maps, &map_name, MAX_MAP_SUGGESTIONS);
+            if suggestions.is_empty() {
+                format!("There is no map {map_name}")
+            } else {
+                format!(
+                    "There is no map {map_name}, did you mean: {}",
+                    suggestions.join(", ")
+                )
+            }
+        }
+        Err(err) => discord_error(&err),
+    };
+    // the deferred response is public, so errors are only shown to the user
+    let _ = command.delete_response(&ctx.http).await;
+    let _ = command
+        .create_followup(
+            &ctx.http,
+            CreateInteractionResponseFollowup::new()
+                .ephemeral(true)
+                .content(err),
+        )
+        .await;
+}
+
 async fn async_main_discord() {
     let framework = StandardFramework::new();
 
@@ -927,82 +2711,648 @@ async fn async_main_discord() {
     let token = std::env::var("DISCORD_TOKEN").expect("token");
     let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
     let mut client = serenity::Client::builder(token, intents)
-        .event_handler(Handler)
+        .event_handler(Handler::default())
         .framework(framework)
         .await
         .expect("Error creating client");
 
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown::requested().await;
+        shard_manager.shutdown_all().await;
+    });
+
     // start listening for events by starting a single shard
     if let Err(why) = client.start().await {
         panic!("An error occurred while running the client: {why:?}");
     }
 }
 
-async fn generate_preview(params: Option<Query<RenderParams>>) -> impl IntoResponse {
-    if let Some(Query(mut params)) = params {
-        if params.use_player_api.is_some_and(|b| b) {
-            let can_update = {
-                let mut g = PLAYERS.lock();
-                let now = &mut *g;
-                let can_update =
-                    std::time::Instant::now().duration_since(*now) > Duration::from_millis(500);
-                if can_update {
-                    *now = std::time::Instant::now();
-                } else {
-                    return "Rate limited".into_response();
-                }
-                can_update
-            };
+/// Player names are logged with at most this many characters.
+const MAX_LOGGED_NAME: usize = 32;
 
-            if can_update && params.player_name.is_some() {
-                if let Ok(skin) = HTTP
-                    .get(
-                        format!(
-                            "https://ddstats.tw/profile/json?player={}",
-                            encode(params.player_name.as_ref().unwrap())
-                        )
-                        .as_str(),
-                    )
-                    .send()
-                    .await
-                {
-                    if let Ok(skin) =
-                        skin.text().await.map_err(|err| anyhow!(err)).and_then(|s| {
-                            serde_json::from_str::<Skin>(&s).map_err(|err| anyhow!(err))
-                        })
-                    {
-                        params.player_name = params.player_name.clone();
-                        params.skin_name = skin.name;
-                        params.body = skin.color_body;
-                        params.feet = skin.color_feet;
-                    }
-                }
-            };
+async fn generate_preview(RawQuery(query): RawQuery, headers: HeaderMap) -> Response {
+    let start = Instant::now();
+    let preview = async {
+        let (params, deprecated) = RenderParams::from_query(query.as_deref().unwrap_or_default())?;
+        render_conditional(params, deprecated, Priority::Normal, start, Some(&headers)).await
+    };
+    respond(preview.await, start)
+}
+
+/// Like [`generate_preview`] with the parameters in a JSON body,
+/// which can describe projectiles.
+async fn generate_preview_json(body: Bytes) -> Response {
+    let start = Instant::now();
+    let preview = async {
+        let (params, deprecated) = RenderParams::from_json(&body)?;
+        render_parsed(params, deprecated, Priority::Normal, start).await
+    };
+    respond(preview.await, start)
+}
+
+/// All emoticons next to the skin in a labeled grid, see [`sheet::EMOTICONS_PREVIEW`].
+async fn emoticons_preview(RawQuery(query): RawQuery, headers: HeaderMap) -> Response {
+    let start = Instant::now();
+    let preview = async {
+        let mut pairs: Vec<(String, String)> =
+            serde_urlencoded::from_str(query.as_deref().unwrap_or_default())
+                .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        sheet::emoticons_preview_pairs(&mut pairs);
+        let (params, deprecated) = RenderParams::from_pairs(pairs)?;
+        render_conditional(params, deprecated, Priority::Normal, start, Some(&headers)).await
+    };
+    respond(preview.await, start)
+}
+
+/// Logs the outcome of a render and updates the metrics.
+fn respond(preview: Result<Preview, RenderError>, start: Instant) -> Response {
+    match preview {
+        Ok(preview) => {
+            log_preview(&preview, start.elapsed());
+            metrics::PREVIEWS.inc("ok");
+            metrics::RENDER_CACHE_REQUESTS.inc(preview.cache.name());
+            metrics::BYTES_SERVED.fetch_add(preview.img.len() as u64, Ordering::Relaxed);
+            if let Some(timing) = preview.timing {
+                metrics::RENDER_DURATION.observe(timing.render);
+            }
+            preview.into_response()
         }
+        Err(err) => {
+            metrics::PREVIEWS.inc(err.kind());
+            // the query is not logged, it's unvalidated
+            log::info!(
+                target: "request",
+                outcome = err.kind(),
+                status = err.status().as_u16(),
+                total_ms = start.elapsed().as_millis() as u64;
+                "preview failed"
+            );
+            err.into_response()
+        }
+    }
+}
 
-        let (sender, receiver) = oneshot::channel();
-        tokio::task::spawn_blocking(|| {
-            let mut client = CLIENT.blocking_lock();
-            let client = client.as_mut().unwrap();
-            client.0.wait_skin_loaded(&params.skin_name);
-            client.0.render(params, sender)
-        })
-        .await
-        .unwrap();
+fn log_preview(preview: &Preview, total: Duration) {
+    let player: Option<String> = preview
+        .player_name
+        .as_ref()
+        .map(|name| name.chars().take(MAX_LOGGED_NAME).collect());
+    let timing = preview.timing.unwrap_or_default();
+    log::info!(
+        target: "request",
+        outcome = "ok",
+        skin = preview.skin_name.as_str(),
+        player:? = player,
+        map = preview.map_name.as_str(),
+        cache = preview.cache.name(),
+        degraded = preview.degraded,
+        bytes = preview.img.len(),
+        queue_wait_ms = timing.queue_wait.as_millis() as u64,
+        render_ms = timing.render.as_millis() as u64,
+        total_ms = total.as_millis() as u64;
+        "preview"
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CacheStatus {
+    Hit,
+    /// Served from the cache while it is rendered again
+    Stale,
+    Miss,
+    /// The client has the image already, see [`etag`]
+    NotModified,
+}
+
+impl CacheStatus {
+    fn name(self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Stale => "STALE",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::NotModified => "NOT_MODIFIED",
+        }
+    }
+}
+
+/// A rendered preview and how it came to be.
+struct Preview {
+    img: Vec<u8>,
+    format: OutputFormat,
+    /// The normalized parameters, see [`RenderParams::cache_key`]
+    params: String,
+    /// Only set if the image matches its parameters, e.g. not for degraded renders
+    etag: Option<String>,
+    warnings: Vec<String>,
+    deprecated: bool,
+    skin_suggestions: Vec<String>,
+    /// Whether the skin was resolved by the player api
+    skin_from_player_api: bool,
+    /// Why the player api didn't resolve the skin
+    player_api_error: Option<RenderError>,
+    map_name: String,
+    cache: CacheStatus,
+    /// Rendered with the low cost profile because of overload
+    degraded: bool,
+    skin_resolution: SkinResolution,
+    /// The part of the frame avatars were cropped to, only known if rendered
+    crop: Option<Rect>,
+    /// Only known if rendered
+    timing: Option<RenderTiming>,
+    /// The grid and cell size of sprite sheets
+    sheet: Option<(Sheet, (u32, u32))>,
+    /// The skin that was rendered
+    skin_name: String,
+    player_name: Option<String>,
+    /// Duration of each step
+    timings: Vec<(&'static str, Duration)>,
+}
+
+async fn render_preview(query: &str) -> Result<Preview, RenderError> {
+    let start = Instant::now();
+    let (params, deprecated) = RenderParams::from_query(query)?;
+    render_parsed(params, deprecated, Priority::Normal, start).await
+}
+
+/// Renders params that were parsed already, `start` is when the request started.
+async fn render_parsed(
+    params: RenderParams,
+    deprecated: Vec<&'static Deprecation>,
+    priority: Priority,
+    start: Instant,
+) -> Result<Preview, RenderError> {
+    render_conditional(params, deprecated, priority, start, None).await
+}
+
+/// Like [`render_parsed`], but nothing is rendered if the `If-None-Match`
+/// of the request `headers` lists the ETag of the image.
+async fn render_conditional(
+    params: RenderParams,
+    deprecated: Vec<&'static Deprecation>,
+    priority: Priority,
+    start: Instant,
+    headers: Option<&HeaderMap>,
+) -> Result<Preview, RenderError> {
+    let mut timings = Vec::new();
+    let mut step = start;
+    let mut timed = |name: &'static str| {
+        let now = Instant::now();
+        timings.push((name, now.saturating_duration_since(step)));
+        step = now;
+    };
+
+    timed("parse");
+    let resolved = resolve::resolve_parsed(params, deprecated, &mut timed).await?;
+    let skin_name = resolved.rendered_skin().to_string();
+    let Resolved {
+        mut params,
+        deprecated,
+        mut warnings,
+        skin_missing,
+        skin_suggestions,
+        skin_from_player_api,
+        player_api_error,
+        skin_resolution,
+        ..
+    } = resolved;
+    let player_name = params
+        .player_name
+        .as_ref()
+        .map(|name| name.as_str().to_string());
+
+    // if the directory can't be listed, the render thread reports missing maps
+    if let Some(map_name) = &params.map_name {
+        let available = tokio::task::spawn_blocking(maps::list_maps)
+            .await
+            .unwrap_or_default();
+        if !available.is_empty() && !available.contains(map_name) {
+            maps::fetch_map(map_name).await?;
+        }
+    }
+
+    let (canvas_width, canvas_height) = *CANVAS_SIZE;
+    let output_size = params.output_size(canvas_width, canvas_height, &mut warnings);
+    params.dpr = output_size.map(|size| size.dpr);
+    params.fit_supersample(output_size, canvas_width, canvas_height, &mut warnings);
+    let mut sheet = sheet_layout(&params, output_size);
+
+    let format = params.output_format();
+    if params.matte.is_none() {
+        if let Some(matte) = params.matte_color() {
+            warnings.push(format!(
+                "{} has no alpha, composited over #{}",
+                params::format_name(format),
+                hex::encode(matte)
+            ));
+        }
+    }
+
+    let normalized = params.cache_key(output_size);
+    let map_name = params.map_name().to_string();
+    let etag = etag::etag(&normalized, params.render_skin(), &map_name).await;
+    if headers.is_some_and(|headers| etag::matches(headers, &etag)) {
+        timed("cache");
+        return Ok(Preview {
+            img: Vec::new(),
+            format,
+            params: normalized,
+            etag: Some(etag),
+            warnings,
+            deprecated: !deprecated.is_empty(),
+            skin_suggestions,
+            skin_from_player_api,
+            player_api_error,
+            map_name,
+            cache: CacheStatus::NotModified,
+            degraded: false,
+            skin_resolution,
+            crop: None,
+            timing: None,
+            sheet,
+            skin_name,
+            player_name,
+            timings,
+        });
+    }
+    // unknown skins render the default skin until they are added
+    let cacheable = !skin_missing;
+    let policy = match params.cache.as_deref() {
+        Some(policy) => CachePolicy::parse(policy).unwrap_or_else(|| {
+            warnings.push(format!("unknown cache policy {policy}"));
+            *DEFAULT_CACHE_POLICY
+        }),
+        None => *DEFAULT_CACHE_POLICY,
+    };
+    let cached = if cacheable {
+        RENDER_CACHE.lookup(&normalized, Instant::now())
+    } else {
+        Lookup::Miss
+    };
+    let shedding = LOAD.shedding();
+    let mut degraded = false;
+    let mut crop = None;
+    let mut timing = None;
+    // a stale image may show older assets than the ETag
+    let mut current = true;
+    let (img, cache) = match cached {
+        Lookup::Fresh(img) => (img.as_ref().clone(), CacheStatus::Hit),
+        // under overload any cached image is better than a render
+        Lookup::Stale(img) if shedding => {
+            current = false;
+            (img.as_ref().clone(), CacheStatus::Stale)
+        }
+        Lookup::Stale(img) if policy == CachePolicy::StaleWhileRevalidate => {
+            // only one background render per key
+            if let Some(revalidation) = RENDER_CACHE.start_revalidation(&normalized) {
+                let key = normalized.clone();
+                tokio::spawn(async move {
+                    let _revalidation = revalidation;
+                    // after everything someone waits for
+                    match render_image(params, output_size, false, Priority::Batch).await {
+                        // degraded renders would replace a good image
+                        Ok(rendered) if rendered.warnings.is_empty() => {
+                            RENDER_CACHE.insert(key, Arc::new(rendered.png))
+                        }
+                        Ok(_) => {}
+                        Err(err) => println!("revalidating a cached preview failed: {err}"),
+                    }
+                });
+            }
+            current = false;
+            (img.as_ref().clone(), CacheStatus::Stale)
+        }
+        Lookup::Stale(_) | Lookup::Miss if shedding => {
+            // render a cheap thumbnail, which is not cached
+            // since it does not match the parameters
+            let output_size = params.apply_low_cost_profile(canvas_width, canvas_height);
+            sheet = sheet_layout(&params, Some(output_size));
+            let rendered = render_image(params, Some(output_size), true, priority).await?;
+            warnings.extend(rendered.warnings);
+            crop = rendered.crop;
+            timing = Some(rendered.timing);
+            degraded = true;
+            (rendered.png, CacheStatus::Miss)
+        }
+        Lookup::Stale(_) | Lookup::Miss => {
+            let rendered = render_image(params, output_size, false, priority).await?;
+            current = rendered.warnings.is_empty();
+            // degraded renders must not be served to later requests
+            if cacheable && current {
+                RENDER_CACHE.insert(normalized.clone(), Arc::new(rendered.png.clone()));
+            }
+            warnings.extend(rendered.warnings);
+            crop = rendered.crop;
+            timing = Some(rendered.timing);
+            (rendered.png, CacheStatus::Miss)
+        }
+    };
+    timed(match cache {
+        CacheStatus::Miss => "render",
+        CacheStatus::Hit | CacheStatus::Stale | CacheStatus::NotModified => "cache",
+    });
+
+    Ok(Preview {
+        img,
+        format,
+        params: normalized,
+        etag: (current && !degraded).then_some(etag),
+        warnings,
+        deprecated: !deprecated.is_empty(),
+        skin_suggestions,
+        skin_from_player_api,
+        player_api_error,
+        map_name,
+        cache,
+        degraded,
+        skin_resolution,
+        crop,
+        timing,
+        sheet,
+        skin_name,
+        player_name,
+        timings,
+    })
+}
 
-        let img = receiver.await.unwrap().unwrap();
+/// The grid and cell size of a sprite sheet, cells have the size of a single image.
+fn sheet_layout(
+    params: &RenderParams,
+    output_size: Option<OutputSize>,
+) -> Option<(Sheet, (u32, u32))> {
+    let sheet = params.sheet()?;
+    let cell = match (params.avatar_size(), output_size) {
+        (Some(size), _) => (size, size),
+        (None, Some(size)) => (size.width, size.height),
+        (None, None) => *CANVAS_SIZE,
+    };
+    Some((sheet, cell))
+}
 
-        let cursor = Cursor::new(img);
+impl IntoResponse for Preview {
+    fn into_response(mut self) -> Response {
+        let cache_control = match &self.etag {
+            Some(_) => etag::cache_control(self.skin_from_player_api),
+            // degraded and stale images should be replaced soon
+            None => HeaderValue::from_static("no-cache"),
+        };
+        let etag = self
+            .etag
+            .as_deref()
+            .and_then(|etag| HeaderValue::from_str(etag).ok());
+        if self.cache == CacheStatus::NotModified {
+            let mut response = StatusCode::NOT_MODIFIED.into_response();
+            let headers = response.headers_mut();
+            headers.insert(header::CACHE_CONTROL, cache_control);
+            if let Some(etag) = etag {
+                headers.insert(header::ETAG, etag);
+            }
+            headers.insert("x-cache", HeaderValue::from_static(self.cache.name()));
+            return response;
+        }
+        // raw pixels are rendered as pam, the size moves from its header to response headers
+        let mut image_size = None;
+        if self.format == OutputFormat::Raw {
+            if let Some((width, height, offset)) = frame::split_pam(&self.img) {
+                self.img.drain(..offset);
+                image_size = Some((width, height));
+            }
+        }
+        let cursor = Cursor::new(self.img);
         let stream = ReaderStream::new(cursor);
         // convert the `Stream` into an `axum::body::HttpBody`
         let body = StreamBody::new(stream);
-        let headers = [(header::CONTENT_TYPE, "image/png; charset=utf-8")];
-        (headers, body).into_response()
-    } else {
-        format!(
-            "Non optional render parameters missing: {:?}",
-            RenderParams::default()
-        )
-        .into_response()
+        let headers = [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(self.format.content_type()),
+            ),
+            (header::CACHE_CONTROL, cache_control),
+        ];
+        let mut response = (headers, body).into_response();
+        if let Some(etag) = etag {
+            response.headers_mut().insert(header::ETAG, etag);
+        }
+        if let Some((width, height)) = image_size {
+            response
+                .headers_mut()
+                .insert("x-image-width", HeaderValue::from(width));
+            response
+                .headers_mut()
+                .insert("x-image-height", HeaderValue::from(height));
+        }
+        for warning in &self.warnings {
+            response
+                .headers_mut()
+                .append(header::WARNING, warning_header(warning));
+        }
+        response
+            .headers_mut()
+            .insert("x-cache", HeaderValue::from_static(self.cache.name()));
+        if self.cache == CacheStatus::Stale {
+            response.headers_mut().append(
+                header::WARNING,
+                HeaderValue::from_static("110 - \"Response is Stale\""),
+            );
+        }
+        response.headers_mut().insert(
+            "x-skin-resolved",
+            HeaderValue::from_static(self.skin_resolution.name()),
+        );
+        if let Some(timing) = self.timing {
+            response.headers_mut().insert(
+                "x-render-time-ms",
+                HeaderValue::from(timing.render.as_millis() as u64),
+            );
+            response.headers_mut().insert(
+                "x-queue-wait-ms",
+                HeaderValue::from(timing.queue_wait.as_millis() as u64),
+            );
+        }
+        if self.degraded {
+            response
+                .headers_mut()
+                .insert("x-degraded", HeaderValue::from_static("overload"));
+        }
+        if let Some((sheet, (width, height))) = self.sheet {
+            let headers = response.headers_mut();
+            let cell = format!("{width}x{height}");
+            let grid = format!("{}x{}", sheet.columns, sheet.rows());
+            for (name, value) in [("x-sheet-cell", cell), ("x-sheet-grid", grid)] {
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    headers.insert(name, value);
+                }
+            }
+            headers.insert("x-sheet-cells", HeaderValue::from(sheet.cells));
+            headers.insert("x-sheet-padding", HeaderValue::from(sheet.padding));
+        }
+        if let Some(crop) = self.crop {
+            let crop = format!("{},{},{},{}", crop.x, crop.y, crop.width, crop.height);
+            if let Ok(crop) = HeaderValue::from_str(&crop) {
+                response.headers_mut().insert("x-crop", crop);
+            }
+        }
+        if self.deprecated {
+            response
+                .headers_mut()
+                .insert("deprecation", HeaderValue::from_static("true"));
+        }
+        if !self.skin_suggestions.is_empty() {
+            if let Ok(suggestions) = HeaderValue::from_str(&self.skin_suggestions.join(",")) {
+                response
+                    .headers_mut()
+                    .insert("x-skin-suggestions", suggestions);
+            }
+        }
+        response
+    }
+}
+
+/// Renders and post processes the image or sprite sheet,
+/// the warnings report assets that were not loaded in time.
+/// `fast` trades image size for encoding speed.
+async fn render_image(
+    params: RenderParams,
+    output_size: Option<OutputSize>,
+    fast: bool,
+    priority: Priority,
+) -> Result<Rendered, RenderError> {
+    match params.sheet() {
+        Some(layout) => sheet::render(params, layout, output_size, fast, priority).await,
+        None => render_single(params, output_size, fast, priority).await,
+    }
+}
+
+/// Renders and post processes a single image, see [`render_image`].
+async fn render_single(
+    params: RenderParams,
+    output_size: Option<OutputSize>,
+    fast: bool,
+    priority: Priority,
+) -> Result<Rendered, RenderError> {
+    let angle = params.angle();
+    let (canvas_width, canvas_height) = *CANVAS_SIZE;
+    let cover = params::rotation_cover(angle, canvas_width, canvas_height);
+    let supersample = params.supersample();
+    // the zoom the client renders with
+    let zoom =
+        params.zoom() / output_size.map_or(1.0, |size| size.dpr) / supersample as f32 * cover;
+    // the overlay is relative to the center of the camera
+    let (camera_x, camera_y) = params.camera_pos();
+    let trajectory_offset = params
+        .tee_pos()
+        .map(|(tee_x, tee_y)| vec2::new(tee_x - camera_x, tee_y - camera_y));
+
+    let format = params.output_format();
+    let matte = params.matte_color();
+    let background = params.background_color();
+    let avatar_size = params.avatar_size();
+    let overlay_opacity = params.overlay_opacity();
+    let png_options = params.png_options();
+
+    let Rendered {
+        png: mut img,
+        warnings,
+        mut crop,
+        mut timing,
+        game_layer,
+        mut trajectory,
+        scene,
+    } = render_worker::render(params, priority).await?;
+    if let (Some((points, _)), Some(offset)) = (&mut trajectory, trajectory_offset) {
+        points.iter_mut().for_each(|point| *point = *point + offset);
     }
+
+    // the client always captures png
+    let post_process = Instant::now();
+    if output_size.is_some()
+        || trajectory.is_some()
+        || scene.is_some()
+        || !game_layer.is_empty()
+        || angle != 0.0
+        || background.is_some()
+        || matte.is_some()
+        || avatar_size.is_some()
+        || format != OutputFormat::Png
+        || png_options != PngOptions::default()
+    {
+        (img, crop) = tokio::task::spawn_blocking(move || {
+            let mut frame = Frame::decode_png(&img)?;
+            if let Some((items, zoom)) = &scene {
+                overlay::draw_scene(&mut frame, items, *zoom);
+            }
+            overlay::draw_game_layer(&mut frame, &game_layer, overlay_opacity);
+            if let Some((points, hit)) = &trajectory {
+                overlay::draw_trajectory(&mut frame, points, *hit, zoom);
+            }
+            // the scene turns against the camera, magnified back to the requested zoom
+            if angle != 0.0 {
+                frame = frame.rotate(-angle.to_radians(), cover);
+            }
+            let mut crop = None;
+            if let Some(size) = avatar_size {
+                // the nameplate with its clan line and the emoticon are part of the bounds
+                if let Some(bounds) = frame.opaque_bounds() {
+                    let rect = bounds.padded_square();
+                    frame = frame.crop_padded(rect);
+                    frame = if supersample > 1 {
+                        frame.resize_lanczos(size, size)
+                    } else {
+                        frame.resize(size, size)
+                    };
+                    crop = Some(rect);
+                }
+            } else if let Some(size) = output_size {
+                frame = frame.crop_center(size.width * supersample, size.height * supersample);
+                if supersample > 1 {
+                    frame = frame.resize_lanczos(size.width, size.height);
+                }
+            }
+            // after cropping, so avatars are cropped to what differs from the background
+            if let Some(background) = background {
+                frame.fill_behind(background);
+            }
+            if let Some(matte) = matte {
+                frame.composite_over(matte);
+            }
+            let dpr = output_size.map(|size| size.dpr);
+            let img = match format {
+                OutputFormat::Png => frame.encode_png_with(dpr, fast, png_options),
+                OutputFormat::Jpeg => frame.encode_jpeg(dpr),
+                OutputFormat::Raw | OutputFormat::Pam => Ok(frame.encode_pam()),
+            }?;
+            anyhow::Ok((img, crop))
+        })
+        .await
+        .map_err(|err| RenderError::Internal(err.to_string()))?
+        .map_err(|err| RenderError::Internal(err.to_string()))?;
+        // the downscale of supersampled renders is part of the render time
+        if supersample > 1 {
+            timing.render += post_process.elapsed();
+        }
+    }
+    Ok(Rendered {
+        png: img,
+        warnings,
+        crop,
+        timing,
+        game_layer: Vec::new(),
+        trajectory: None,
+        scene: None,
+    })
+}
+
+/// A `Warning: 199` header, non printable ascii is dropped.
+fn warning_header(text: &str) -> HeaderValue {
+    let text: String = text
+        .chars()
+        .filter(|c| c.is_ascii_graphic() || *c == ' ')
+        .map(|c| if c == '"' { '\'' } else { c })
+        .collect();
+    HeaderValue::from_str(&format!("199 - \"{text}\""))
+        .unwrap_or_else(|_| HeaderValue::from_static("199 - \"invalid warning\""))
+}
+
+async fn load_report() -> impl IntoResponse {
+    Json(LOAD.report())
 }
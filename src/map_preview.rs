@@ -0,0 +1,278 @@
+use std::time::Instant;
+
+use axum::{
+    extract::RawQuery,
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use tokio::task::JoinSet;
+
+use crate::{
+    error::RenderError,
+    frame::Frame,
+    maps,
+    params::{self, ParamError, RenderParams, ZOOM_RANGE},
+    physics,
+    render_worker::Priority,
+    CANVAS_SIZE,
+};
+
+/// Upper bound of the width and height of a map preview in pixels.
+pub const MAX_SIZE: u32 = 2048;
+/// Maps that need a larger zoom to fit the canvas are rendered in tiles,
+/// otherwise details are lost and quads are culled.
+const MAX_TILE_ZOOM: f32 = 4.0;
+/// Upper bound of tiles, every tile is a render.
+const MAX_TILES: u32 = 16;
+
+/// Parameters that are picked to fit the map.
+const FITTED_PARAMS: [&str; 11] = [
+    "overview",
+    "width",
+    "height",
+    "dpr",
+    "supersample",
+    "x",
+    "y",
+    "zoom",
+    "angle",
+    "format",
+    "sheet",
+];
+
+pub struct MapPreview {
+    pub frame: Frame,
+    /// The map size in tiles
+    pub map_size: (f32, f32),
+    /// Pixels of the frame per map tile, the map's top left corner is the frame's
+    pub tile_pixels: f32,
+    pub warnings: Vec<String>,
+}
+
+impl MapPreview {
+    pub async fn encode_png(self) -> Result<Vec<u8>, RenderError> {
+        tokio::task::spawn_blocking(move || self.frame.encode_png(None, false))
+            .await
+            .map_err(|err| RenderError::Internal(err.to_string()))?
+            .map_err(|err| RenderError::Internal(err.to_string()))
+    }
+}
+
+/// How the canvases that cover the map are laid out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout {
+    zoom: f32,
+    columns: u32,
+    rows: u32,
+    /// The width and height of a canvas in tiles
+    visible: (f32, f32),
+}
+
+impl Layout {
+    fn new(map_width: f32, map_height: f32, canvas_width: u32, canvas_height: u32) -> Self {
+        let aspect = canvas_width as f32 / canvas_height as f32;
+        let (_, _, fit_zoom) = params::overview_camera(map_width, map_height, aspect);
+        let mut zoom = fit_zoom.min(MAX_TILE_ZOOM);
+        loop {
+            let visible_width = physics::visible_width(aspect, zoom);
+            let visible = (visible_width, visible_width / aspect);
+            // the fitting zoom covers the map exactly, rounding must not add a tile
+            let columns = (map_width / visible.0 - 0.001).ceil().max(1.0) as u32;
+            let rows = (map_height / visible.1 - 0.001).ceil().max(1.0) as u32;
+            if columns * rows <= MAX_TILES || zoom >= ZOOM_RANGE.max {
+                return Self {
+                    zoom,
+                    columns,
+                    rows,
+                    visible,
+                };
+            }
+            zoom = (zoom * 1.25).min(ZOOM_RANGE.max);
+        }
+    }
+
+    fn is_single(&self) -> bool {
+        self.columns * self.rows == 1
+    }
+
+    /// Canvas pixels per map tile.
+    fn pixels_per_tile(&self, canvas_width: u32) -> f32 {
+        canvas_width as f32 / self.visible.0
+    }
+}
+
+/// Renders the whole map without Tee, scaled to fit `width` x `height` keeping its aspect ratio.
+/// Large maps are rendered in tiles that are stitched together.
+pub async fn render(
+    mut pairs: Vec<(String, String)>,
+    width: Option<u32>,
+    height: Option<u32>,
+    priority: Priority,
+) -> Result<MapPreview, RenderError> {
+    let start = Instant::now();
+    let (canvas_width, canvas_height) = *CANVAS_SIZE;
+    let width = width.unwrap_or(canvas_width).clamp(1, MAX_SIZE);
+    let height = height.unwrap_or(canvas_height).clamp(1, MAX_SIZE);
+
+    pairs.retain(|(k, _)| !FITTED_PARAMS.contains(&k.as_str()));
+    pairs.push(("overview".to_string(), "true".to_string()));
+    let (params, deprecated) = RenderParams::from_pairs(pairs)?;
+    let map_name = params.map_name().to_string();
+
+    // the size is known once the map was rendered
+    let mut overview = None;
+    if maps::map_size(&map_name).is_none() {
+        overview = Some(crate::render_parsed(params.clone(), deprecated, priority, start).await?);
+    }
+    let (map_width, map_height) = maps::map_size(&map_name)
+        .ok_or_else(|| RenderError::Internal(format!("the size of map {map_name} is unknown")))?;
+    let layout = Layout::new(map_width, map_height, canvas_width, canvas_height);
+
+    let mut warnings: Vec<String> = Vec::new();
+    let mut tiles = Vec::new();
+    if layout.is_single() {
+        // the overview camera fits the map into one canvas
+        let preview = match overview {
+            Some(preview) => preview,
+            None => crate::render_parsed(params, Vec::new(), priority, start).await?,
+        };
+        warnings.extend(preview.warnings);
+        tiles.push((0, 0, preview.img));
+    } else {
+        let mut renders = JoinSet::new();
+        for row in 0..layout.rows {
+            for column in 0..layout.columns {
+                let mut tile = params.clone();
+                tile.x = Some((column as f32 + 0.5) * layout.visible.0);
+                tile.y = Some((row as f32 + 0.5) * layout.visible.1);
+                tile.zoom = Some(layout.zoom);
+                renders.spawn(async move {
+                    crate::render_parsed(tile, Vec::new(), priority, start)
+                        .await
+                        .map(|preview| (column, row, preview))
+                });
+            }
+        }
+        while let Some(tile) = renders.join_next().await {
+            let (column, row, preview) =
+                tile.map_err(|err| RenderError::Internal(err.to_string()))??;
+            for warning in preview.warnings {
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+            tiles.push((column, row, preview.img));
+        }
+    }
+
+    let pixels_per_tile = layout.pixels_per_tile(canvas_width);
+    let (frame, scale) = tokio::task::spawn_blocking(move || {
+        let map_pixels = (map_width * pixels_per_tile, map_height * pixels_per_tile);
+        // scales canvas pixels to output pixels
+        let scale = (width as f32 / map_pixels.0).min(height as f32 / map_pixels.1);
+        let output = (
+            ((map_pixels.0 * scale).round() as u32).clamp(1, width),
+            ((map_pixels.1 * scale).round() as u32).clamp(1, height),
+        );
+
+        let frame = if layout.is_single() {
+            let (_, _, png) = &tiles[0];
+            // the map is centered in the canvas
+            Frame::decode_png(png)?
+                .crop_center(
+                    (map_pixels.0.round() as u32).clamp(1, canvas_width),
+                    (map_pixels.1.round() as u32).clamp(1, canvas_height),
+                )
+                .resize(output.0, output.1)
+        } else {
+            // tiles are scaled before stitching, the full resolution could be huge
+            let edge =
+                |index: u32, canvas: u32| (index as f32 * canvas as f32 * scale).round() as u32;
+            let mut frame = Frame::transparent(
+                edge(layout.columns, canvas_width),
+                edge(layout.rows, canvas_height),
+            );
+            for (column, row, png) in &tiles {
+                let (x, y) = (edge(*column, canvas_width), edge(*row, canvas_height));
+                let tile_width = edge(column + 1, canvas_width) - x;
+                let tile_height = edge(row + 1, canvas_height) - y;
+                if tile_width == 0 || tile_height == 0 {
+                    continue;
+                }
+                let tile = Frame::decode_png(png)?.resize(tile_width, tile_height);
+                frame.paste(&tile, x, y);
+            }
+            frame.crop(0, 0, output.0.min(frame.width), output.1.min(frame.height))
+        };
+        anyhow::Ok((frame, scale))
+    })
+    .await
+    .map_err(|err| RenderError::Internal(err.to_string()))?
+    .map_err(|err| RenderError::Internal(err.to_string()))?;
+
+    Ok(MapPreview {
+        frame,
+        map_size: (map_width, map_height),
+        tile_pixels: pixels_per_tile * scale,
+        warnings,
+    })
+}
+
+/// `GET /map_preview`, the whole map as png thumbnail, see [`render`].
+pub async fn map_preview(RawQuery(query): RawQuery) -> Response {
+    let preview = async {
+        let pairs: Vec<(String, String)> =
+            serde_urlencoded::from_str(query.as_deref().unwrap_or_default())
+                .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        let (width, height) = (size_param(&pairs, "width")?, size_param(&pairs, "height")?);
+        let mut preview = render(pairs, width, height, Priority::Normal).await?;
+        let map_size = preview.map_size;
+        let warnings = std::mem::take(&mut preview.warnings);
+        Ok((preview.encode_png().await?, map_size, warnings))
+    };
+    match preview.await {
+        Ok((png, map_size, warnings)) => png_response(png, map_size, &warnings),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// A pixel size of the query, e.g. `width`.
+pub fn size_param(
+    pairs: &[(String, String)],
+    field: &'static str,
+) -> Result<Option<u32>, RenderError> {
+    pairs
+        .iter()
+        .find(|(k, _)| k == field)
+        .map(|(_, value)| {
+            value.parse::<u32>().map_err(|err| {
+                ParamError {
+                    field,
+                    value: value.clone(),
+                    reason: err.to_string(),
+                    accepted: Vec::new(),
+                }
+                .into()
+            })
+        })
+        .transpose()
+}
+
+/// The png with `x-map-size` and the warnings of the render.
+pub fn png_response(png: Vec<u8>, map_size: (f32, f32), warnings: &[String]) -> Response {
+    let mut response = (
+        [(header::CONTENT_TYPE, HeaderValue::from_static("image/png"))],
+        png,
+    )
+        .into_response();
+    let (map_width, map_height) = map_size;
+    if let Ok(size) = HeaderValue::from_str(&format!("{map_width}x{map_height}")) {
+        response.headers_mut().insert("x-map-size", size);
+    }
+    for warning in warnings {
+        response
+            .headers_mut()
+            .append(header::WARNING, crate::warning_header(warning));
+    }
+    response
+}
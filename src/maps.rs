@@ -0,0 +1,261 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{response::IntoResponse, Json};
+use urlencoding::encode;
+
+use crate::{
+    download::{self, Download},
+    error::RenderError,
+};
+
+/// `MAX_LOADED_MAPS` maps kept loaded on the gpu, the least recently
+/// used one is unloaded first (4 is default)
+pub static MAX_LOADED_MAPS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MAX_LOADED_MAPS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(4)
+        .max(1)
+});
+
+/// Downloading of missing maps.
+/// - `MAP_DOWNLOAD_ENABLED` (true is default)
+/// - `MAP_DOWNLOAD_URL` where `{name}` is replaced by the map name
+///   (`https://maps.ddnet.org/{name}.twmap` is default)
+/// - `MAP_DOWNLOAD_MAX_MB` (32 is default)
+/// - `MAP_DOWNLOAD_TIMEOUT_SECS` (30 is default)
+struct MapDownloadConfig {
+    enabled: bool,
+    url: String,
+    max_bytes: u64,
+    timeout: Duration,
+}
+
+static MAP_DOWNLOAD: LazyLock<MapDownloadConfig> = LazyLock::new(|| {
+    let enabled: bool = std::env::var("MAP_DOWNLOAD_ENABLED")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true);
+    let url = std::env::var("MAP_DOWNLOAD_URL")
+        .unwrap_or_else(|_| "https://maps.ddnet.org/{name}.twmap".to_string());
+    let max_mb: u64 = std::env::var("MAP_DOWNLOAD_MAX_MB")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(32);
+    let timeout: u64 = std::env::var("MAP_DOWNLOAD_TIMEOUT_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(30);
+    MapDownloadConfig {
+        enabled,
+        url,
+        max_bytes: max_mb * 1024 * 1024,
+        timeout: Duration::from_secs(timeout),
+    }
+});
+
+/// The map directory relative to the data directory.
+pub const MAPS_PATH: &str = "map/maps";
+
+fn maps_dir() -> PathBuf {
+    let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string());
+    PathBuf::from(data_dir).join(MAPS_PATH)
+}
+
+/// Map names are file stems, anything that could leave
+/// the map directory is rejected.
+pub fn valid_map_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name.trim() == name
+        && !name.contains("..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ' '))
+}
+
+/// Width and height in tiles of every map that was loaded, from its physics layer.
+static MAP_SIZES: LazyLock<parking_lot::Mutex<HashMap<String, (f32, f32)>>> =
+    LazyLock::new(Default::default);
+
+/// Called by the render thread whenever it renders a map.
+pub fn record_size(name: &str, width: f32, height: f32) {
+    MAP_SIZES.lock().insert(name.to_string(), (width, height));
+}
+
+/// The size in tiles of a map that was rendered before.
+pub fn map_size(name: &str) -> Option<(f32, f32)> {
+    MAP_SIZES.lock().get(name).copied()
+}
+
+/// How often the render thread advances loading maps while no job arrives.
+pub const LOAD_POLL: Duration = Duration::from_millis(1);
+
+/// How long renders outside of the queue wait for their maps, e.g. at startup or shutdown.
+pub const LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maps the renderers are loading, with when the load started.
+static LOADING: LazyLock<parking_lot::Mutex<HashMap<String, Instant>>> =
+    LazyLock::new(Default::default);
+/// How long the last load of every map took, to estimate the progress of the next one.
+static LOAD_TIMES: LazyLock<parking_lot::Mutex<HashMap<String, Duration>>> =
+    LazyLock::new(Default::default);
+
+/// Called by the render thread when it starts loading a map.
+pub fn load_started(name: &str) {
+    LOADING
+        .lock()
+        .entry(name.to_string())
+        .or_insert_with(Instant::now);
+}
+
+/// Called by the render thread once a map is loaded.
+pub fn load_finished(name: &str) {
+    if let Some(started) = LOADING.lock().remove(name) {
+        LOAD_TIMES
+            .lock()
+            .insert(name.to_string(), started.elapsed());
+    }
+}
+
+/// Called by the render thread if it dropped a map before it was loaded.
+pub fn load_stopped(name: &str) {
+    LOADING.lock().remove(name);
+}
+
+/// How long the map has been loading and the estimated progress (0..1).
+/// The progress is only known for maps that were loaded before,
+/// the renderer doesn't report it.
+fn load_progress(name: &str) -> Option<(Duration, Option<f64>)> {
+    let elapsed = LOADING.lock().get(name)?.elapsed();
+    let progress = LOAD_TIMES
+        .lock()
+        .get(name)
+        .map(|last| (elapsed.as_secs_f64() / last.as_secs_f64().max(0.001)).min(0.99));
+    Some((elapsed, progress))
+}
+
+/// The response to a render that doesn't wait for its map, see `map_wait`.
+pub fn still_loading(name: &str) -> RenderError {
+    let (elapsed, progress) = load_progress(name).unwrap_or_default();
+    // once the map took as long as last time, it's likely done soon
+    let retry_after = LOAD_TIMES
+        .lock()
+        .get(name)
+        .map_or(Duration::from_secs(1), |last| last.saturating_sub(elapsed))
+        .max(Duration::from_secs(1));
+    RenderError::MapLoading {
+        name: name.to_string(),
+        progress,
+        retry_after,
+    }
+}
+
+/// `GET /maps/loading`, the maps that are loading with their elapsed time and estimated progress.
+pub async fn loading() -> impl IntoResponse {
+    let names: Vec<String> = LOADING.lock().keys().cloned().collect();
+    let maps: Vec<serde_json::Value> = names
+        .iter()
+        .filter_map(|name| {
+            let (elapsed, progress) = load_progress(name)?;
+            Some(serde_json::json!({
+                "map_name": name,
+                "elapsed_ms": elapsed.as_millis() as u64,
+                "progress": progress,
+            }))
+        })
+        .collect();
+    Json(maps)
+}
+
+/// The map file on disk.
+pub fn disk_path(name: &str) -> PathBuf {
+    maps_dir().join(format!("{name}.twmap"))
+}
+
+/// The path to load the map from, relative to the data directory.
+pub fn map_path(name: &str) -> PathBuf {
+    Path::new(MAPS_PATH).join(format!("{name}.twmap"))
+}
+
+/// Downloads a map that is not on disk from the map mirror.
+pub async fn fetch_map(name: &str) -> Result<(), RenderError> {
+    if !MAP_DOWNLOAD.enabled || !valid_map_name(name) {
+        return Err(RenderError::MapNotFound(name.to_string()));
+    }
+    let url = MAP_DOWNLOAD.url.replace("{name}", &encode(name));
+    let path = disk_path(name);
+    match download::download(&url, &path, MAP_DOWNLOAD.max_bytes, MAP_DOWNLOAD.timeout).await {
+        Ok(Download::Stored) => Ok(()),
+        Ok(Download::NotFound) => Err(RenderError::MapNotFound(name.to_string())),
+        Err(err) => Err(RenderError::Upstream(format!(
+            "downloading map {name} failed: {err}"
+        ))),
+    }
+}
+
+pub fn list_maps() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(maps_dir()) else {
+        return Vec::new();
+    };
+    let mut maps: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "twmap") {
+                path.file_stem()?.to_str().map(|s| s.to_string())
+            } else {
+                None
+            }
+        })
+        .filter(|name| valid_map_name(name))
+        .collect();
+    maps.sort();
+    maps
+}
+
+/// Returns up to `max` map names containing `filter`, ignoring case,
+/// names starting with it first.
+pub fn search<'a>(maps: &'a [String], filter: &str, max: usize) -> Vec<&'a str> {
+    let filter = filter.to_lowercase();
+    let (mut prefixed, mut contained): (Vec<&str>, Vec<&str>) = maps
+        .iter()
+        .map(|name| name.as_str())
+        .filter(|name| name.to_lowercase().contains(&filter))
+        .partition(|name| name.to_lowercase().starts_with(&filter));
+    prefixed.append(&mut contained);
+    prefixed.truncate(max);
+    prefixed
+}
+
+/// Returns up to `max` similar map names, best match first.
+/// There are few maps, so all of them are compared.
+pub fn suggest<'a>(maps: &'a [String], name: &str, max: usize) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let mut scored: Vec<(f64, &str)> = maps
+        .iter()
+        .map(|map| {
+            (
+                strsim::normalized_levenshtein(&name, &map.to_lowercase()),
+                map.as_str(),
+            )
+        })
+        .filter(|(score, _)| *score >= 0.5)
+        .collect();
+    scored.sort_by(|(s1, m1), (s2, m2)| s2.total_cmp(s1).then(m1.cmp(m2)));
+    scored.into_iter().take(max).map(|(_, map)| map).collect()
+}
+
+pub async fn maps() -> impl IntoResponse {
+    Json(
+        tokio::task::spawn_blocking(list_maps)
+            .await
+            .unwrap_or_default(),
+    )
+}
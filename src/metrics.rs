@@ -0,0 +1,182 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+    time::Duration,
+};
+
+use axum::{http::header, response::IntoResponse};
+
+use crate::{containers, load::LOAD, upstream};
+
+/// Upper bounds in seconds, renders take tens to hundreds of milliseconds.
+const DURATION_BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// A prometheus histogram of durations.
+pub struct Histogram {
+    /// Not cumulative, the count of each bucket alone, the last one is `+Inf`
+    buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len() + 1],
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let bound = DURATION_BUCKETS
+                .get(i)
+                .map_or("+Inf".to_string(), |bound| bound.to_string());
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {cumulative}");
+    }
+}
+
+/// Counters by a label value.
+pub struct LabeledCounter(LazyLock<parking_lot::Mutex<BTreeMap<&'static str, u64>>>);
+
+impl LabeledCounter {
+    const fn new() -> Self {
+        Self(LazyLock::new(Default::default))
+    }
+
+    pub fn inc(&self, label: &'static str) {
+        *self.0.lock().entry(label).or_default() += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, label: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        for (value, count) in self.0.lock().iter() {
+            let _ = writeln!(out, "{name}{{{label}=\"{value}\"}} {count}");
+        }
+    }
+}
+
+/// Previews by outcome, `ok` or the error kind.
+pub static PREVIEWS: LabeledCounter = LabeledCounter::new();
+/// Previews by render cache status.
+pub static RENDER_CACHE_REQUESTS: LabeledCounter = LabeledCounter::new();
+/// From the start of a render job until the image was captured.
+pub static RENDER_DURATION: Histogram = Histogram::new();
+/// From requesting the screenshot until the pixels were read back.
+pub static SCREENSHOT_DURATION: Histogram = Histogram::new();
+/// Requests to the player api, cached lookups are not counted.
+pub static PLAYER_API_DURATION: Histogram = Histogram::new();
+pub static PLAYER_API_ERRORS: AtomicU64 = AtomicU64::new(0);
+/// Image bytes of all previews.
+pub static BYTES_SERVED: AtomicU64 = AtomicU64::new(0);
+/// Backend calls that failed because the graphics device was lost.
+pub static DEVICE_LOST: AtomicU64 = AtomicU64::new(0);
+/// Rebuilds of the client after a lost device by outcome, `ok` or `failed`.
+pub static DEVICE_RECOVERIES: LabeledCounter = LabeledCounter::new();
+
+/// Prometheus text format, only atomics and short locks are read,
+/// so a scrape never waits for the renderer.
+pub async fn metrics() -> impl IntoResponse {
+    let report = LOAD.report();
+    let mut body = format!(
+        "# TYPE render_queue_depth gauge\n\
+        render_queue_depth {}\n\
+        # TYPE render_queue_wait_p95_seconds gauge\n\
+        render_queue_wait_p95_seconds {}\n\
+        # TYPE render_worker_utilization_ratio gauge\n\
+        render_worker_utilization_ratio {}\n\
+        # TYPE render_pressure gauge\n\
+        render_pressure {}\n\
+        # TYPE load_shedding gauge\n\
+        load_shedding {}\n\
+        # TYPE load_shedding_activations_total counter\n\
+        load_shedding_activations_total {}\n",
+        report.queue_depth,
+        report.p95_queue_wait_ms as f64 / 1000.0,
+        report.utilization / 100.0,
+        report.pressure,
+        u8::from(report.shedding),
+        report.shed_activations,
+    );
+    let _ = writeln!(
+        body,
+        "# HELP render_renderer_utilization_ratio Busy ratio of every renderer of the pool\n\
+        # TYPE render_renderer_utilization_ratio gauge"
+    );
+    for (renderer, utilization) in LOAD.renderer_utilization().iter().enumerate() {
+        let _ = writeln!(
+            body,
+            "render_renderer_utilization_ratio{{renderer=\"{renderer}\"}} {utilization}"
+        );
+    }
+    PREVIEWS.write(
+        &mut body,
+        "previews_total",
+        "outcome",
+        "Previews by outcome, ok or the error kind",
+    );
+    RENDER_CACHE_REQUESTS.write(
+        &mut body,
+        "render_cache_requests_total",
+        "status",
+        "Previews by render cache status",
+    );
+    RENDER_DURATION.write(
+        &mut body,
+        "render_duration_seconds",
+        "From the start of a render job until the image was captured",
+    );
+    SCREENSHOT_DURATION.write(
+        &mut body,
+        "screenshot_duration_seconds",
+        "From requesting the screenshot until it was read back",
+    );
+    PLAYER_API_DURATION.write(
+        &mut body,
+        "player_api_duration_seconds",
+        "Requests to the player api",
+    );
+    let _ = write!(
+        body,
+        "# TYPE player_api_errors_total counter\n\
+        player_api_errors_total {}\n\
+        # TYPE served_bytes_total counter\n\
+        served_bytes_total {}\n\
+        # TYPE device_lost_total counter\n\
+        device_lost_total {}\n",
+        PLAYER_API_ERRORS.load(Ordering::Relaxed),
+        BYTES_SERVED.load(Ordering::Relaxed),
+        DEVICE_LOST.load(Ordering::Relaxed),
+    );
+    DEVICE_RECOVERIES.write(
+        &mut body,
+        "device_recoveries_total",
+        "outcome",
+        "Rebuilds of the graphics backend after the device was lost",
+    );
+    upstream::write_metrics(&mut body);
+    containers::write_metrics(&mut body);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
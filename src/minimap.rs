@@ -0,0 +1,316 @@
+use std::{collections::HashMap, time::Instant};
+
+use axum::{
+    body::Bytes,
+    extract::RawQuery,
+    http::HeaderValue,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use crate::{
+    error::RenderError,
+    frame::Frame,
+    map_preview::{self, MapPreview},
+    params::{self, ParamError, RenderParams},
+    render_worker::Priority,
+};
+
+/// Upper bound of markers on a minimap.
+pub const MAX_MARKERS: usize = 32;
+/// The width and height of minimaps unless given.
+const DEFAULT_SIZE: u32 = 256;
+const DEFAULT_MARKER_COLOR: [u8; 4] = [255, 48, 48, 255];
+const MARKER_OUTLINE: [u8; 4] = [0, 0, 0, 200];
+
+/// The query parameters of the marker of `GET /minimap`.
+const MARKER_PARAMS: [&str; 5] = [
+    "marker_x",
+    "marker_y",
+    "marker",
+    "marker_color",
+    "marker_skin",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkerStyle {
+    Dot,
+    /// The Tee of the marker's skin
+    Tee,
+}
+
+pub const MARKER_STYLES: [MarkerStyle; 2] = [MarkerStyle::Dot, MarkerStyle::Tee];
+
+pub fn marker_style_name(style: MarkerStyle) -> &'static str {
+    match style {
+        MarkerStyle::Dot => "dot",
+        MarkerStyle::Tee => "tee",
+    }
+}
+
+pub fn parse_marker_style(value: &str) -> Option<MarkerStyle> {
+    let value = value.trim();
+    MARKER_STYLES
+        .iter()
+        .copied()
+        .find(|style| marker_style_name(*style).eq_ignore_ascii_case(value))
+}
+
+/// `rrggbb` or `rrggbbaa`, optionally with a leading `#`.
+fn parse_marker_color(value: &str) -> Option<[u8; 4]> {
+    let bytes = hex::decode(value.trim().trim_start_matches('#')).ok()?;
+    match bytes[..] {
+        [r, g, b] => Some([r, g, b, 255]),
+        [r, g, b, a] => Some([r, g, b, a]),
+        _ => None,
+    }
+}
+
+/// A marker at a map position in tiles, e.g. `{"x": 10, "y": 20, "style": "tee", "skin": "santa"}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Marker {
+    pub x: f32,
+    pub y: f32,
+    /// `dot` or `tee`, `dot` is default
+    pub style: Option<String>,
+    /// The color of dots as `rrggbb` or `rrggbbaa`
+    pub color: Option<String>,
+    /// The skin of tee markers, the default skin if not given
+    pub skin: Option<String>,
+}
+
+fn invalid(
+    field: &'static str,
+    value: &str,
+    reason: &str,
+    accepted: Vec<&'static str>,
+) -> RenderError {
+    ParamError {
+        field,
+        value: value.to_string(),
+        reason: reason.to_string(),
+        accepted,
+    }
+    .into()
+}
+
+impl Marker {
+    fn style(&self) -> Result<MarkerStyle, RenderError> {
+        match &self.style {
+            Some(style) => parse_marker_style(style).ok_or_else(|| {
+                invalid(
+                    "marker",
+                    style,
+                    "unknown value",
+                    MARKER_STYLES
+                        .iter()
+                        .copied()
+                        .map(marker_style_name)
+                        .collect(),
+                )
+            }),
+            None => Ok(MarkerStyle::Dot),
+        }
+    }
+
+    fn color(&self) -> Result<[u8; 4], RenderError> {
+        match &self.color {
+            Some(color) => parse_marker_color(color).ok_or_else(|| {
+                invalid(
+                    "marker_color",
+                    color,
+                    "invalid color",
+                    vec!["rrggbb", "rrggbbaa"],
+                )
+            }),
+            None => Ok(DEFAULT_MARKER_COLOR),
+        }
+    }
+}
+
+/// The marker of the query parameters, if `marker_x` and `marker_y` are given.
+fn query_marker(pairs: &[(String, String)]) -> Result<Option<Marker>, RenderError> {
+    let value = |key: &str| {
+        pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.clone())
+    };
+    let pos = |field: &'static str| -> Result<Option<f32>, RenderError> {
+        value(field)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse::<f32>()
+                    .map_err(|err| invalid(field, &value, &err.to_string(), Vec::new()))
+            })
+            .transpose()
+    };
+    match (pos("marker_x")?, pos("marker_y")?) {
+        (Some(x), Some(y)) => Ok(Some(Marker {
+            x,
+            y,
+            style: value("marker"),
+            color: value("marker_color"),
+            skin: value("marker_skin"),
+        })),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err(invalid("marker_y", "", "missing", Vec::new())),
+        (None, Some(_)) => Err(invalid("marker_x", "", "missing", Vec::new())),
+    }
+}
+
+pub struct Minimap {
+    pub png: Vec<u8>,
+    /// The map size in tiles
+    pub map_size: (f32, f32),
+    /// Indices of markers outside of the map, they are drawn at its edge
+    pub clamped: Vec<usize>,
+    pub warnings: Vec<String>,
+}
+
+/// Renders the map overview (see [`map_preview::render`]) with markers drawn at their map positions.
+pub async fn render(
+    mut pairs: Vec<(String, String)>,
+    markers: Vec<Marker>,
+    priority: Priority,
+) -> Result<Minimap, RenderError> {
+    if markers.len() > MAX_MARKERS {
+        return Err(RenderError::InvalidQuery(format!(
+            "at most {MAX_MARKERS} markers are allowed"
+        )));
+    }
+    let styled = markers
+        .iter()
+        .map(|marker| Ok((marker.style()?, marker.color()?)))
+        .collect::<Result<Vec<_>, RenderError>>()?;
+
+    let width = map_preview::size_param(&pairs, "width")?.unwrap_or(DEFAULT_SIZE);
+    let height = map_preview::size_param(&pairs, "height")?.unwrap_or(DEFAULT_SIZE);
+    pairs.retain(|(k, _)| !MARKER_PARAMS.contains(&k.as_str()));
+    let MapPreview {
+        mut frame,
+        map_size,
+        tile_pixels,
+        warnings,
+    } = map_preview::render(pairs, Some(width), Some(height), priority).await?;
+
+    let short_side = frame.width.min(frame.height) as f32;
+    let dot_radius = (short_side / 48.0).max(3.0);
+    let tee_size = ((short_side / 8.0) as u32).clamp(16, 64);
+
+    // every skin is rendered once
+    let mut tees: HashMap<Option<String>, Frame> = HashMap::new();
+    for (marker, (style, _)) in markers.iter().zip(&styled) {
+        if *style != MarkerStyle::Tee || tees.contains_key(&marker.skin) {
+            continue;
+        }
+        let mut avatar = vec![
+            ("preset".to_string(), "avatar".to_string()),
+            ("avatar_size".to_string(), tee_size.to_string()),
+        ];
+        if let Some(skin) = &marker.skin {
+            avatar.push(("skin".to_string(), skin.clone()));
+        }
+        let (params, _) = RenderParams::from_pairs(avatar)?;
+        let preview = crate::render_parsed(params, Vec::new(), priority, Instant::now()).await?;
+        let tee = Frame::decode_png(&preview.img)
+            .map_err(|err| RenderError::Internal(err.to_string()))?;
+        tees.insert(marker.skin.clone(), tee);
+    }
+
+    let (map_width, map_height) = map_size;
+    let (png, clamped) = tokio::task::spawn_blocking(move || {
+        // positions that are not a number are clamped to the top left
+        let finite = |v: f32| if v.is_finite() { v } else { -1.0 };
+        let mut clamped = Vec::new();
+        for (index, (marker, (style, color))) in markers.iter().zip(styled).enumerate() {
+            let x = finite(marker.x).clamp(0.0, map_width);
+            let y = finite(marker.y).clamp(0.0, map_height);
+            if x != marker.x || y != marker.y {
+                clamped.push(index);
+            }
+            // the same transform as the overview
+            let (x, y) = (x * tile_pixels, y * tile_pixels);
+            match style {
+                MarkerStyle::Dot => {
+                    frame.fill_circle(x, y, dot_radius + 1.0, MARKER_OUTLINE);
+                    frame.fill_circle(x, y, dot_radius, color);
+                }
+                MarkerStyle::Tee => {
+                    if let Some(tee) = tees.get(&marker.skin) {
+                        frame.blend(
+                            tee,
+                            x as i64 - tee.width as i64 / 2,
+                            y as i64 - tee.height as i64 / 2,
+                        );
+                    }
+                }
+            }
+        }
+        anyhow::Ok((frame.encode_png(None, false)?, clamped))
+    })
+    .await
+    .map_err(|err| RenderError::Internal(err.to_string()))?
+    .map_err(|err| RenderError::Internal(err.to_string()))?;
+
+    Ok(Minimap {
+        png,
+        map_size,
+        clamped,
+        warnings,
+    })
+}
+
+fn respond(minimap: Result<Minimap, RenderError>) -> Response {
+    match minimap {
+        Ok(minimap) => {
+            let mut response =
+                map_preview::png_response(minimap.png, minimap.map_size, &minimap.warnings);
+            if !minimap.clamped.is_empty() {
+                let clamped = minimap
+                    .clamped
+                    .iter()
+                    .map(|index| index.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                if let Ok(clamped) = HeaderValue::from_str(&clamped) {
+                    response.headers_mut().insert("x-markers-clamped", clamped);
+                }
+            }
+            response
+        }
+        Err(err) => err.into_response(),
+    }
+}
+
+/// `GET /minimap`, a small map overview with one marker at `marker_x`, `marker_y`.
+pub async fn minimap(RawQuery(query): RawQuery) -> Response {
+    let minimap = async {
+        let pairs: Vec<(String, String)> =
+            serde_urlencoded::from_str(query.as_deref().unwrap_or_default())
+                .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        let markers = query_marker(&pairs)?.into_iter().collect();
+        render(pairs, markers, Priority::Normal).await
+    };
+    respond(minimap.await)
+}
+
+/// `POST /minimap`, like [`minimap`] with the parameters in a JSON body
+/// and any number of markers in its `markers` array.
+pub async fn minimap_json(body: Bytes) -> Response {
+    #[derive(Deserialize)]
+    struct MinimapBody {
+        #[serde(default)]
+        markers: Vec<Marker>,
+        #[serde(flatten)]
+        params: serde_json::Map<String, serde_json::Value>,
+    }
+    let minimap = async {
+        let body: MinimapBody = serde_json::from_slice(&body)
+            .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        let pairs = params::json_pairs(body.params)?;
+        render(pairs, body.markers, Priority::Normal).await
+    };
+    respond(minimap.await)
+}
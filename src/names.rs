@@ -0,0 +1,98 @@
+use std::sync::LazyLock;
+
+use anyhow::anyhow;
+
+/// `NAMEPLATE_MAX_CHARS`: nameplates longer than this are cut with an ellipsis (16 is default).
+/// The player api gets the full name.
+static NAMEPLATE_MAX_CHARS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("NAMEPLATE_MAX_CHARS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(16)
+        .max(1)
+});
+
+/// Explicit direction marks, embeddings, overrides and isolates.
+/// Right to left names still render right to left without them.
+fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+/// Strips C0/C1 controls and bidi controls and collapses whitespace,
+/// so names can't break nameplates, log lines or urls.
+/// `None` if nothing is left.
+pub fn sanitize(name: &str) -> Option<String> {
+    let name = name
+        .split(char::is_whitespace)
+        .map(|word| {
+            word.chars()
+                .filter(|c| !c.is_control() && !is_bidi_control(*c))
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!name.is_empty()).then_some(name)
+}
+
+/// The name as shown on the nameplate, cut to [`NAMEPLATE_MAX_CHARS`].
+pub fn display_name(name: &str) -> String {
+    let max = *NAMEPLATE_MAX_CHARS;
+    if name.chars().count() <= max {
+        return name.to_string();
+    }
+    let mut display: String = name.chars().take(max - 1).collect();
+    display.push('…');
+    display
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn controls_are_stripped() {
+        assert_eq!(sanitize("nameless\0tee").as_deref(), Some("namelesstee"));
+        assert_eq!(sanitize("\u{1b}[31mred").as_deref(), Some("[31mred"));
+        assert_eq!(sanitize("c1\u{85}\u{9f}").as_deref(), Some("c1"));
+        assert_eq!(
+            sanitize("\u{202E}evil\u{202C}\u{2066}x\u{2069}").as_deref(),
+            Some("evilx")
+        );
+    }
+
+    #[test]
+    fn whitespace_is_collapsed() {
+        assert_eq!(sanitize("  two \t\n words ").as_deref(), Some("two words"));
+        assert_eq!(sanitize("line\r\nbreak").as_deref(), Some("line break"));
+        // a word of only controls leaves no double space behind
+        assert_eq!(sanitize("a \u{200F} b").as_deref(), Some("a b"));
+    }
+
+    #[test]
+    fn other_characters_are_kept() {
+        for name in ["brainless tee", "名無し", "مرحبا", "ŋ⁰_ö", "🙂"] {
+            assert_eq!(sanitize(name).as_deref(), Some(name));
+        }
+    }
+
+    #[test]
+    fn nothing_left_is_no_name() {
+        assert_eq!(sanitize(""), None);
+        assert_eq!(sanitize(" \t "), None);
+        assert_eq!(sanitize("\0\u{7f}\u{200E}"), None);
+    }
+
+    #[test]
+    fn long_names_are_cut_by_characters() {
+        let max = *NAMEPLATE_MAX_CHARS;
+        let fits = "ö".repeat(max);
+        assert_eq!(display_name(&fits), fits);
+        let cut = display_name(&"ö".repeat(max + 1));
+        assert_eq!(cut.chars().count(), max);
+        assert_eq!(cut, format!("{}…", "ö".repeat(max - 1)));
+    }
+}
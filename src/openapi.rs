@@ -0,0 +1,924 @@
+use std::sync::LazyLock;
+
+use axum::Json;
+use serde_json::{json, Value};
+
+use crate::{
+    error::RenderError,
+    map_preview,
+    minimap::MAX_MARKERS,
+    options,
+    params::{Deprecation, DEPRECATIONS, FORMATS, NAMEPLATE_SIZE_RANGE},
+    playground,
+    scene::{
+        flag_team_name, pickup_kind_name, FLAG_TEAMS, MAX_PICKUPS, MAX_PROJECTILES, PICKUP_KINDS,
+    },
+    sheet::{sheet_axis_name, MAX_CELLS, MAX_PADDING, SHEET_AXES},
+    upload,
+};
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn number() -> Value {
+    json!({ "type": "number" })
+}
+
+fn integer(min: i64, max: Option<u64>) -> Value {
+    json!({ "type": "integer", "minimum": min, "maximum": max })
+}
+
+fn one_of(values: impl IntoIterator<Item = &'static str>) -> Value {
+    json!({ "type": "string", "enum": values.into_iter().collect::<Vec<_>>() })
+}
+
+/// A `FloatRange` or a range of `GET /options`.
+fn range_schema(range: &Value) -> Value {
+    json!({
+        "type": "number",
+        "minimum": range["min"],
+        "maximum": range["max"],
+        "default": range["default"],
+    })
+}
+
+/// A color like `body_color` accepts it.
+fn color() -> Value {
+    json!({
+        "oneOf": [
+            { "type": "integer", "description": "a legacy color of the game" },
+            { "type": "string", "description": "`#rrggbb`, `#rrggbbaa` or `rgb(r,g,b)`" },
+        ]
+    })
+}
+
+/// The render parameters with the values of `GET /options`, in the order of `RenderParams`.
+/// Numbers outside of `minimum`/`maximum` are clamped.
+fn render_params(options: &Value) -> Vec<(&'static str, Value, &'static str)> {
+    let option = |name: &str| {
+        let mut schema = json!({ "type": "string", "enum": options[name]["values"] });
+        if !options[name]["default"].is_null() {
+            schema["default"] = options[name]["default"].clone();
+        }
+        schema
+    };
+    let range = |name: &str| range_schema(&options["ranges"][name]);
+    let int_range = |name: &str| {
+        let mut schema = range(name);
+        schema["type"] = "integer".into();
+        schema
+    };
+    let list = |name: &str| {
+        json!({
+            "type": "array",
+            "items": { "type": "string", "enum": options[name]["values"] },
+        })
+    };
+    vec![
+        (
+            "skin_name",
+            json!({ "type": "string", "maxLength": 24, "default": "default" }),
+            "Name of the skin to draw",
+        ),
+        (
+            "skin_url",
+            string(),
+            "Url of a skin png on an allowed host, replaces `skin_name`",
+        ),
+        (
+            "player_name",
+            json!({ "type": "string", "maxLength": 128 }),
+            "Player name to render as nameplate",
+        ),
+        (
+            "clan_name",
+            json!({ "type": "string", "maxLength": 12 }),
+            "Clan to render in a smaller line above the player name",
+        ),
+        ("nameplate", boolean(), "Whether the nameplate is rendered"),
+        (
+            "nameplate_size",
+            range_schema(&json!(NAMEPLATE_SIZE_RANGE)),
+            "Scales the nameplate text",
+        ),
+        ("friend_mark", boolean(), "Friend heart above the nameplate"),
+        (
+            "authed_mark",
+            boolean(),
+            "Shield of authed players above the nameplate",
+        ),
+        ("zoom", range("zoom"), "Camera zoom"),
+        ("angle", number(), "Camera rotation in degrees, clockwise"),
+        (
+            "x",
+            range("x"),
+            "Camera pos x in tiles, the default depends on the map",
+        ),
+        (
+            "y",
+            range("y"),
+            "Camera pos y in tiles, the default depends on the map",
+        ),
+        (
+            "tile_x",
+            number(),
+            "Camera tile x, the center of the tile, excludes x",
+        ),
+        (
+            "tile_y",
+            number(),
+            "Camera tile y, the center of the tile, excludes y",
+        ),
+        (
+            "tee_x",
+            range("tee_x"),
+            "Map pos x of the Tee, the camera center if unset",
+        ),
+        (
+            "tee_y",
+            range("tee_y"),
+            "Map pos y of the Tee, the camera center if unset",
+        ),
+        (
+            "tee_tile_x",
+            number(),
+            "Tile x of the Tee, the center of the tile, excludes tee_x",
+        ),
+        (
+            "tee_tile_y",
+            number(),
+            "Tile y of the Tee, the center of the tile, excludes tee_y",
+        ),
+        ("body_color", color(), "Color of the body"),
+        ("feet_color", color(), "Color of the feet"),
+        (
+            "ddnet_color_clamp",
+            boolean(),
+            "Darken rgb colors like the game does for legacy colors",
+        ),
+        ("dir_x", range("dir_x"), "Cursor dir x"),
+        ("dir_y", range("dir_y"), "Cursor dir y"),
+        ("eyes", option("eyes"), "Tee eyes"),
+        ("weapon", option("weapon"), "Tee weapon"),
+        ("emoticon", option("emoticon"), "Tee emoticon"),
+        (
+            "emoticon_ticks",
+            integer(0, None),
+            "Ticks since the emoticon was sent",
+        ),
+        (
+            "intra_tick",
+            json!({ "type": "number", "minimum": 0, "maximum": 1 }),
+            "Progress to the next tick, for emoticon animations",
+        ),
+        (
+            "used_air_jump",
+            boolean(),
+            "Whether the Tee used its double jump",
+        ),
+        ("in_air", boolean(), "Whether the Tee is in the air"),
+        (
+            "vel_x",
+            number(),
+            "The x velocity of the Tee in map units per tick",
+        ),
+        (
+            "vel_y",
+            number(),
+            "The y velocity of the Tee in map units per tick",
+        ),
+        (
+            "move_dir",
+            json!({ "type": "integer", "minimum": -1, "maximum": 1 }),
+            "The direction the Tee wants to move",
+        ),
+        ("buffs", list("buffs"), "Comma separated buffs and debuffs"),
+        (
+            "hook_x",
+            range("hook_x"),
+            "The x position of the hook relative to the Tee",
+        ),
+        (
+            "hook_y",
+            range("hook_y"),
+            "The y position of the hook relative to the Tee",
+        ),
+        (
+            "hook_attached",
+            boolean(),
+            "Whether `hook_x`/`hook_y` are map coordinates the hook is attached to",
+        ),
+        (
+            "hook_tile_x",
+            number(),
+            "Tile x the hook is attached to, excludes hook_x",
+        ),
+        (
+            "hook_tile_y",
+            number(),
+            "Tile y the hook is attached to, excludes hook_y",
+        ),
+        (
+            "time",
+            int_range("time"),
+            "The time of the rendering in ms, e.g. for map animations",
+        ),
+        (
+            "recoil_ticks",
+            integer(0, None),
+            "Ticks since the last attack",
+        ),
+        ("game_ticks", integer(0, None), "Ticks the game ran for"),
+        ("weapon_attack", boolean(), "Render the weapon attacking"),
+        ("weapon_muzzle", boolean(), "Render the muzzle flash"),
+        ("chat_bubble", boolean(), "Render the chat bubble"),
+        (
+            "afk",
+            boolean(),
+            "Render the afk indicator, hidden by emoticons",
+        ),
+        ("feet_flipped", boolean(), "Flip the feet"),
+        ("size", range("size"), "The size of the Tee"),
+        (
+            "flag",
+            one_of(FLAG_TEAMS.into_iter().map(flag_team_name)),
+            "A flag, carried by the Tee unless `flag_x`/`flag_y` are set",
+        ),
+        ("flag_x", range("x"), "Map pos x of the flag"),
+        ("flag_y", range("y"), "Map pos y of the flag"),
+        (
+            "pickups",
+            json!({
+                "type": "array",
+                "maxItems": MAX_PICKUPS,
+                "items": {
+                    "type": "string",
+                    "pattern": format!(
+                        "^({}):[0-9.]+:[0-9.]+$",
+                        PICKUP_KINDS.into_iter().map(pickup_kind_name).collect::<Vec<_>>().join("|")
+                    ),
+                },
+            }),
+            "Comma separated pickups as `kind:x:y` in map positions",
+        ),
+        (
+            "trajectory_sim",
+            option("trajectory_sim"),
+            "Draw the path of a projectile",
+        ),
+        ("sim_dir_x", range("dir_x"), "Direction x of the projectile"),
+        ("sim_dir_y", range("dir_y"), "Direction y of the projectile"),
+        (
+            "sim_speed",
+            range("sim_speed"),
+            "Multiplies the projectile speed",
+        ),
+        (
+            "map_name",
+            string(),
+            "Name of the map to render, `GET /maps` lists them",
+        ),
+        ("weapon_skin", string(), "Key of the weapon skin"),
+        ("hook_skin", string(), "Key of the hook skin"),
+        ("emoticon_skin", string(), "Key of the emoticon skin"),
+        ("entities", string(), "Key of the entities skin"),
+        (
+            "entities_game",
+            option("entities_game"),
+            "Which game's entities are drawn",
+        ),
+        (
+            "use_player_api",
+            boolean(),
+            "Use the player api to fetch the latest skin of the player",
+        ),
+        (
+            "provider",
+            option("provider"),
+            "The player api that is asked first",
+        ),
+        (
+            "strict",
+            boolean(),
+            "Reject unknown enum values instead of falling back to defaults",
+        ),
+        (
+            "error_on_deprecated",
+            boolean(),
+            "In strict mode, reject deprecated parameter names",
+        ),
+        ("cache", option("cache"), "Cache policy"),
+        (
+            "map_wait",
+            json!({ "type": "boolean", "default": true }),
+            "Wait for a map that is still loading, otherwise respond with 202",
+        ),
+        ("width", integer(1, None), "Output width in css pixels"),
+        ("height", integer(1, None), "Output height in css pixels"),
+        (
+            "dpr",
+            range("dpr"),
+            "Device pixel ratio, multiplies the output size",
+        ),
+        (
+            "supersample",
+            int_range("supersample"),
+            "Render at this many times the output size and downscale",
+        ),
+        ("format", option("format"), "Image format"),
+        (
+            "png_compression",
+            int_range("png_compression"),
+            "Png compression level",
+        ),
+        (
+            "quantize",
+            boolean(),
+            "Reduce pngs to a palette of 256 colors",
+        ),
+        ("preset", option("preset"), "A preset of parameters"),
+        (
+            "avatar_size",
+            integer(1, None),
+            "The side length of avatars",
+        ),
+        ("transparent", boolean(), "Render without map"),
+        ("overview", boolean(), "Render the whole map without Tee"),
+        ("layers", option("layers"), "The map passes that run"),
+        (
+            "overlay",
+            option("overlay"),
+            "Draw game tiles color coded over the map",
+        ),
+        (
+            "overlay_opacity",
+            range("overlay_opacity"),
+            "Opacity of the overlay",
+        ),
+        (
+            "sheet",
+            one_of(SHEET_AXES.into_iter().map(sheet_axis_name)),
+            "Render a sprite sheet of cells that differ in this parameter",
+        ),
+        (
+            "sheet_cells",
+            integer(1, Some(MAX_CELLS as u64)),
+            "Number of cells",
+        ),
+        ("sheet_columns", integer(1, None), "Cells per row"),
+        (
+            "sheet_padding",
+            integer(0, Some(MAX_PADDING as u64)),
+            "Pixels between cells",
+        ),
+        (
+            "sheet_labels",
+            boolean(),
+            "Label every cell with what differs",
+        ),
+        (
+            "matte",
+            json!({ "type": "string", "pattern": "^#?[0-9a-fA-F]{6}$" }),
+            "Background color the image is composited over, white for formats without alpha",
+        ),
+        (
+            "background",
+            json!({ "type": "string", "pattern": "^#?([0-9a-fA-F]{6}|[0-9a-fA-F]{8})$" }),
+            "Solid background color instead of the map",
+        ),
+    ]
+}
+
+/// The old names of the `params` that were renamed, with the schema of the new name.
+fn deprecated_params<'a>(
+    params: &'a [(&'static str, Value, &'static str)],
+) -> impl Iterator<Item = (&'static Deprecation, &'a Value)> {
+    DEPRECATIONS.iter().filter_map(|deprecation| {
+        params
+            .iter()
+            .find(|(name, _, _)| *name == deprecation.new)
+            .map(|(_, schema, _)| (deprecation, schema))
+    })
+}
+
+fn deprecated_description(deprecation: &Deprecation) -> String {
+    format!("Deprecated, use `{}`", deprecation.new)
+}
+
+fn query_params(params: &[(&'static str, Value, &'static str)]) -> Vec<Value> {
+    let deprecated = deprecated_params(params).map(|(deprecation, schema)| {
+        json!({
+            "name": deprecation.old,
+            "in": "query",
+            "description": deprecated_description(deprecation),
+            "deprecated": true,
+            "schema": schema,
+        })
+    });
+    params
+        .iter()
+        .map(|(name, schema, description)| {
+            let mut param = json!({
+                "name": name,
+                "in": "query",
+                "description": description,
+                "schema": schema,
+            });
+            // lists are comma separated
+            if schema["type"] == "array" {
+                param["style"] = "form".into();
+                param["explode"] = false.into();
+            }
+            param
+        })
+        .chain(deprecated)
+        .collect()
+}
+
+fn body_schema(
+    params: &[(&'static str, Value, &'static str)],
+    extra: impl IntoIterator<Item = (&'static str, Value)>,
+) -> Value {
+    let mut properties: serde_json::Map<String, Value> = params
+        .iter()
+        .map(|(name, schema, description)| {
+            let mut schema = schema.clone();
+            schema["description"] = (*description).into();
+            (name.to_string(), schema)
+        })
+        .collect();
+    for (deprecation, schema) in deprecated_params(params) {
+        let mut schema = schema.clone();
+        schema["description"] = deprecated_description(deprecation).into();
+        schema["deprecated"] = true.into();
+        properties.insert(deprecation.old.to_string(), schema);
+    }
+    properties.extend(
+        extra
+            .into_iter()
+            .map(|(name, schema)| (name.to_string(), schema)),
+    );
+    json!({ "type": "object", "properties": properties })
+}
+
+fn json_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": { "type": "object" } } },
+    })
+}
+
+/// The responses of an endpoint that renders, images in the `formats` or an error.
+/// `conditional` endpoints answer `If-None-Match`.
+fn image_responses(formats: &[&str], conditional: bool) -> Value {
+    let content: serde_json::Map<String, Value> = formats
+        .iter()
+        .map(|content_type| {
+            (
+                content_type.to_string(),
+                json!({ "schema": { "type": "string", "format": "binary" } }),
+            )
+        })
+        .collect();
+    let error = json!({
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Error" } },
+        },
+    });
+    let with = |description: &str| {
+        let mut response = error.clone();
+        response["description"] = description.into();
+        response
+    };
+    let mut responses = json!({
+        "200": { "description": "The image", "content": content },
+        "202": with("The map is still loading, see `Retry-After`"),
+        "400": with("Invalid parameters"),
+        "403": with("Invalid signature or a forbidden url"),
+        "404": with("Unknown skin or map"),
+        "429": with("Rate limited, see `Retry-After`"),
+        "500": with("The render failed"),
+        "502": with("The player api or a download failed"),
+        "503": with("Busy or shutting down"),
+        "504": with("The render timed out"),
+    });
+    if conditional {
+        responses["304"] = json!({ "description": "The image matches `If-None-Match`" });
+    }
+    responses
+}
+
+fn get(summary: &str, parameters: Vec<Value>, responses: Value) -> Value {
+    json!({ "summary": summary, "parameters": parameters, "responses": responses })
+}
+
+/// A GET without parameters that responds with JSON.
+fn info(summary: &str, description: &str) -> Value {
+    get(
+        summary,
+        Vec::new(),
+        json!({ "200": json_response(description) }),
+    )
+}
+
+/// A GET without parameters that responds with text of the `content_type`.
+fn text(summary: &str, content_type: &str) -> Value {
+    let mut content = serde_json::Map::new();
+    content.insert(content_type.to_string(), json!({ "schema": string() }));
+    get(
+        summary,
+        Vec::new(),
+        json!({ "200": { "description": summary, "content": content } }),
+    )
+}
+
+fn post_json(summary: &str, body: Value, responses: Value) -> Value {
+    json!({
+        "summary": summary,
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": { "schema": body } },
+        },
+        "responses": responses,
+    })
+}
+
+fn admin(summary: &str) -> Value {
+    json!({
+        "summary": summary,
+        "security": [{ "admin": [] }],
+        "responses": {
+            "200": json_response("Done"),
+            "401": { "description": "The admin token is missing or wrong" },
+        },
+    })
+}
+
+static SPEC: LazyLock<Value> = LazyLock::new(|| {
+    let options = options::values();
+    let params = render_params(&options);
+    let marker_styles = json!({ "type": "string", "enum": options["marker"]["values"] });
+    let content_types: Vec<&str> = FORMATS.into_iter().map(|f| f.content_type()).collect();
+    let png = ["image/png"];
+    let projectile = json!({
+        "type": "object",
+        "required": ["type", "x", "y"],
+        "properties": {
+            "type": { "type": "string", "enum": options["trajectory_sim"]["values"] },
+            "x": number(),
+            "y": number(),
+            "dir_x": number(),
+            "dir_y": number(),
+            "lifetime": integer(0, None),
+            "to_x": number(),
+            "to_y": number(),
+        },
+    });
+    let projectile = json!({ "type": "array", "maxItems": MAX_PROJECTILES, "items": projectile });
+    let marker = json!({
+        "type": "object",
+        "required": ["x", "y"],
+        "properties": {
+            "x": number(),
+            "y": number(),
+            "style": marker_styles,
+            "color": { "type": "string", "pattern": "^#?([0-9a-fA-F]{6}|[0-9a-fA-F]{8})$" },
+            "skin": string(),
+        },
+    });
+    let markers = json!({ "type": "array", "maxItems": MAX_MARKERS, "items": marker });
+    let mut minimap_params = params.clone();
+    minimap_params.extend([
+        ("marker_x", number(), "Map pos x of the marker"),
+        ("marker_y", number(), "Map pos y of the marker"),
+        ("marker", marker_styles.clone(), "The style of the marker"),
+        (
+            "marker_color",
+            json!({ "type": "string", "pattern": "^#?([0-9a-fA-F]{6}|[0-9a-fA-F]{8})$" }),
+            "The color of dot markers",
+        ),
+        ("marker_skin", string(), "The skin of tee markers"),
+    ]);
+    let mut map_preview_params = params.clone();
+    for (name, schema, _) in &mut map_preview_params {
+        if *name == "width" || *name == "height" {
+            *schema = integer(1, Some(map_preview::MAX_SIZE as u64));
+        }
+    }
+
+    let images = image_responses(&content_types, true);
+    let maps = image_responses(&png, false);
+    let mut paths = json!({
+        "/": {
+            "get": get("Renders a Tee on a map", query_params(&params), images.clone()),
+            "post": post_json(
+                "Renders like `GET /` with the parameters in a JSON body, \
+                which can describe projectiles",
+                body_schema(&params, [("projectiles", projectile)]),
+                image_responses(&content_types, false),
+            ),
+        },
+        "/emoticons_preview": {
+            "get": get(
+                "All emoticons next to the skin in a labeled grid",
+                query_params(&params),
+                images,
+            ),
+        },
+        "/resolve": {
+            "get": get(
+                "The parameters after defaults, clamping and the player api",
+                query_params(&params),
+                json!({ "200": json_response("The resolved parameters") }),
+            ),
+        },
+        "/map_preview": {
+            "get": get("Renders a whole map", query_params(&map_preview_params), maps.clone()),
+        },
+        "/minimap": {
+            "get": get("Renders a map with a marker", query_params(&minimap_params), maps.clone()),
+            "post": post_json(
+                "Renders a map with markers",
+                body_schema(&params, [("markers", markers)]),
+                maps,
+            ),
+        },
+        "/maps": { "get": info("The map names", "The maps") },
+        "/maps/loading": { "get": info("The maps that are loading", "The maps") },
+        "/skins": {
+            "get": get(
+                "The skins that can be rendered, paginated",
+                vec![
+                    json!({ "name": "offset", "in": "query", "schema": integer(0, None) }),
+                    json!({ "name": "limit", "in": "query", "schema": integer(0, Some(1000)) }),
+                    json!({ "name": "filter", "in": "query", "schema": string() }),
+                ],
+                json!({ "200": json_response("The skins") }),
+            ),
+        },
+        "/options": { "get": info("The values the parameters accept", "The values") },
+        "/presets": { "get": info("The presets", "The presets") },
+        "/load": { "get": info("The load of the renderers", "The load") },
+        "/version": { "get": info("The build, graphics backend and assets", "The version") },
+        "/healthz": {
+            "get": get("Liveness", Vec::new(), json!({
+                "200": json_response("Alive"),
+                "503": json_response("Stalled"),
+            })),
+        },
+        "/readyz": {
+            "get": get("Readiness", Vec::new(), json!({
+                "200": json_response("Ready"),
+                "503": json_response("Not ready"),
+            })),
+        },
+        "/metrics": { "get": text("Prometheus metrics", "text/plain") },
+        "/openapi.json": { "get": info("This description", "The OpenAPI description") },
+        "/admin/player_cache/flush": { "post": admin("Forgets a player of the player api cache") },
+        "/admin/caches": { "get": admin("Cache statistics") },
+        "/admin/caches/players/flush": { "post": admin("Flushes the player api cache") },
+        "/admin/caches/renders/flush": { "post": admin("Flushes the render cache") },
+        "/admin/skins/evict": { "post": admin("Evicts a skin from the caches") },
+        "/admin/maps/evict": { "post": admin("Evicts a map from the caches") },
+        "/admin/containers/reload": { "post": admin("Reloads the asset containers") },
+        "/admin/cameras/reload": { "post": admin("Reloads the camera file") },
+        "/admin/load/reload": { "post": admin("Reloads the load thresholds") },
+        "/admin/config": { "get": admin("The resolved settings without secrets") },
+        "/admin/support-bundle": { "get": admin("A zip of a render with its diagnostics") },
+    });
+    if upload::enabled() {
+        let form = json!({
+            "type": "object",
+            "properties": { "skin": { "type": "string", "format": "binary" } },
+        });
+        paths["/skins"]["post"] = json!({
+            "summary": "Stores a skin png temporarily and returns its name",
+            "requestBody": {
+                "required": true,
+                "content": { "multipart/form-data": { "schema": form } },
+            },
+            "responses": { "200": json_response("The name of the skin") },
+        });
+    }
+    if playground::enabled() {
+        paths["/playground"] = json!({
+            "get": text("A form for the parameters with a live preview", "text/html"),
+        });
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "dd-pg-web",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Renders Tees and maps. \
+                Numbers outside of their minimum and maximum are clamped.",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "required": ["error", "message"],
+                    "properties": {
+                        "error": { "type": "string", "enum": RenderError::KINDS },
+                        "message": string(),
+                        "field": { "type": "string", "description": "The invalid parameter" },
+                        "value": { "type": "string", "description": "The invalid value" },
+                        "accepted": {
+                            "type": "array",
+                            "items": string(),
+                            "description": "The values the parameter accepts",
+                        },
+                        "suggestions": {
+                            "type": "array",
+                            "items": string(),
+                            "description": "Similar skins",
+                        },
+                        "progress": {
+                            "type": "number",
+                            "nullable": true,
+                            "description": "How far a loading map is",
+                        },
+                    },
+                },
+            },
+            "securitySchemes": {
+                "admin": { "type": "http", "scheme": "bearer", "description": "The `ADMIN_TOKEN`" },
+            },
+        },
+    })
+});
+
+/// `GET /openapi.json`, generated from the tables of `GET /options` and the routes.
+pub async fn openapi() -> Json<Value> {
+    Json(SPEC.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::params::RenderParams;
+
+    use super::*;
+
+    /// Every object of the spec, depth first.
+    fn objects(value: &Value) -> Vec<&serde_json::Map<String, Value>> {
+        let mut objects = Vec::new();
+        let mut todo = vec![value];
+        while let Some(value) = todo.pop() {
+            match value {
+                Value::Object(object) => {
+                    objects.push(object);
+                    todo.extend(object.values());
+                }
+                Value::Array(values) => todo.extend(values),
+                _ => {}
+            }
+        }
+        objects
+    }
+
+    #[test]
+    fn render_params_are_unique() {
+        let params = render_params(&options::values());
+        let mut names = HashSet::new();
+        for (name, _, description) in &params {
+            assert!(names.insert(*name), "{name} is listed twice");
+            assert!(!description.is_empty(), "{name}");
+        }
+    }
+
+    #[test]
+    fn every_reference_resolves() {
+        for object in objects(&SPEC) {
+            let Some(reference) = object.get("$ref") else {
+                continue;
+            };
+            let pointer = reference.as_str().unwrap().strip_prefix('#').unwrap();
+            assert!(SPEC.pointer(pointer).is_some(), "{reference}");
+        }
+    }
+
+    /// An option name without values would make an enum of `null`.
+    #[test]
+    fn enums_list_values() {
+        for object in objects(&SPEC) {
+            if let Some(values) = object.get("enum") {
+                let values = values.as_array();
+                assert!(
+                    values.is_some_and(|values| !values.is_empty()),
+                    "{object:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid_values() {
+        for object in objects(&SPEC) {
+            let Some(default) = object.get("default").filter(|default| !default.is_null()) else {
+                continue;
+            };
+            if let Some(values) = object.get("enum").and_then(Value::as_array) {
+                assert!(values.contains(default), "{object:?}");
+            }
+            let bound = |key: &str| object.get(key).and_then(Value::as_f64);
+            if let (Some(default), Some(min)) = (default.as_f64(), bound("minimum")) {
+                assert!(default >= min, "{object:?}");
+            }
+            if let (Some(default), Some(max)) = (default.as_f64(), bound("maximum")) {
+                assert!(default <= max, "{object:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn documented_defaults_are_parsed() {
+        for (name, schema, _) in render_params(&options::values()) {
+            let value = match &schema["default"] {
+                Value::Null | Value::Array(_) => continue,
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            let pairs = vec![(name.to_string(), value.clone())];
+            let parsed = RenderParams::from_pairs(pairs);
+            assert!(parsed.is_ok(), "{name}={value}: {:?}", parsed.err());
+        }
+    }
+
+    #[test]
+    fn deprecated_names_are_documented() {
+        let params = render_params(&options::values());
+        for deprecation in DEPRECATIONS {
+            assert!(
+                params.iter().all(|(name, _, _)| *name != deprecation.old),
+                "{} is deprecated",
+                deprecation.old
+            );
+        }
+        let description = |name: &str| format!("Deprecated, use `{name}`");
+        for object in objects(&SPEC["paths"]) {
+            // query parameters
+            if let Some(Value::String(name)) = object.get("name") {
+                let new = DEPRECATIONS
+                    .iter()
+                    .find(|d| d.old == name.as_str())
+                    .map(|d| d.new);
+                assert_eq!(object.get("deprecated").is_some(), new.is_some(), "{name}");
+                if let Some(new) = new {
+                    assert_eq!(object["deprecated"], true);
+                    assert_eq!(object["description"], description(new));
+                }
+            }
+            // JSON bodies
+            if let Some(Value::Object(properties)) = object.get("properties") {
+                for deprecation in DEPRECATIONS {
+                    if !properties.contains_key(deprecation.new) {
+                        continue;
+                    }
+                    let old = &properties[deprecation.old];
+                    assert_eq!(old["deprecated"], true, "{}", deprecation.old);
+                    assert_eq!(old["description"], description(deprecation.new));
+                }
+            }
+        }
+        // every endpoint with the new name also lists the old one
+        for operation in objects(&SPEC["paths"]) {
+            let Some(Value::Array(parameters)) = operation.get("parameters") else {
+                continue;
+            };
+            let names: HashSet<&str> = parameters
+                .iter()
+                .filter_map(|param| param["name"].as_str())
+                .collect();
+            for deprecation in DEPRECATIONS {
+                assert_eq!(
+                    names.contains(deprecation.new),
+                    names.contains(deprecation.old),
+                    "{}",
+                    deprecation.old
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_operation_has_responses() {
+        for (path, operations) in SPEC["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                let responses = operation["responses"].as_object();
+                assert!(
+                    responses.is_some_and(|responses| !responses.is_empty()),
+                    "{method} {path}"
+                );
+            }
+        }
+    }
+}
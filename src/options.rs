@@ -0,0 +1,167 @@
+use axum::{response::IntoResponse, Json};
+use game_interface::types::emoticons::{EmoticonType, IntoEnumIterator};
+
+use crate::{
+    game_layer::{map_overlay_name, MAP_OVERLAYS},
+    maps,
+    minimap::{marker_style_name, MARKER_STYLES},
+    params::{
+        buff_name, emoticon_name, eye_name, format_name, map_layers_name, preset_name, weapon_name,
+        BUFFS, DIR_X_RANGE, DIR_Y_RANGE, DPR_RANGE, ENTITIES_GAMES, EYES, FORMATS, HOOK_RANGE,
+        MAP_LAYERS, MAX_PNG_COMPRESSION, MAX_SUPERSAMPLE, MAX_TIME, OVERLAY_OPACITY_RANGE,
+        POS_RANGE, PRESETS, SIM_SPEED_RANGE, SIZE_RANGE, WEAPONS, ZOOM_RANGE,
+    },
+    physics::{projectile_name, PROJECTILES},
+    player_api::{self, provider_name, PROVIDERS},
+    render_cache::{CachePolicy, DEFAULT_CACHE_POLICY},
+};
+
+/// The values the parameters accept, built from the tables the parser uses.
+pub async fn options() -> impl IntoResponse {
+    let maps = tokio::task::spawn_blocking(maps::list_maps)
+        .await
+        .unwrap_or_default();
+    let mut options = values();
+    options["maps"] = maps.into();
+    Json(options)
+}
+
+/// The enum values and ranges of the parameters, also used by `GET /openapi.json`.
+pub fn values() -> serde_json::Value {
+    serde_json::json!({
+        "eyes": {
+            "values": EYES.into_iter().map(eye_name).collect::<Vec<_>>(),
+            "default": "normal",
+        },
+        "weapon": {
+            "values": WEAPONS.into_iter().map(weapon_name).collect::<Vec<_>>(),
+            "default": null,
+        },
+        "emoticon": {
+            "values": EmoticonType::iter().map(emoticon_name).collect::<Vec<_>>(),
+            "default": null,
+        },
+        "buffs": {
+            "values": BUFFS.into_iter().map(buff_name).collect::<Vec<_>>(),
+            "default": [],
+        },
+        "trajectory_sim": {
+            "values": PROJECTILES.into_iter().map(projectile_name).collect::<Vec<_>>(),
+            "default": null,
+        },
+        "preset": {
+            "values": PRESETS.into_iter().map(preset_name).collect::<Vec<_>>(),
+            "default": null,
+        },
+        "format": {
+            "values": FORMATS.into_iter().map(format_name).collect::<Vec<_>>(),
+            "default": "png",
+        },
+        "entities_game": {
+            "values": ENTITIES_GAMES,
+            "default": "ddnet",
+        },
+        "provider": {
+            "values": PROVIDERS.into_iter().map(provider_name).collect::<Vec<_>>(),
+            "default": provider_name(player_api::default_provider()),
+        },
+        "layers": {
+            "values": MAP_LAYERS.into_iter().map(map_layers_name).collect::<Vec<_>>(),
+            "default": "both",
+        },
+        "overlay": {
+            "values": MAP_OVERLAYS.into_iter().map(map_overlay_name).collect::<Vec<_>>(),
+            "default": null,
+        },
+        "marker": {
+            "values": MARKER_STYLES.into_iter().map(marker_style_name).collect::<Vec<_>>(),
+            "default": "dot",
+        },
+        "cache": {
+            "values": CachePolicy::NAMES,
+            "default": DEFAULT_CACHE_POLICY.name(),
+        },
+        "ranges": {
+            "zoom": ZOOM_RANGE,
+            "x": POS_RANGE,
+            "y": POS_RANGE,
+            "tee_x": POS_RANGE,
+            "tee_y": POS_RANGE,
+            "dir_x": DIR_X_RANGE,
+            "dir_y": DIR_Y_RANGE,
+            "size": SIZE_RANGE,
+            "hook_x": HOOK_RANGE,
+            "hook_y": HOOK_RANGE,
+            "sim_speed": SIM_SPEED_RANGE,
+            "dpr": DPR_RANGE,
+            "overlay_opacity": OVERLAY_OPACITY_RANGE,
+            "png_compression": { "min": 0, "max": MAX_PNG_COMPRESSION, "default": null },
+            "supersample": { "min": 1, "max": MAX_SUPERSAMPLE, "default": 1 },
+            "time": { "min": 0, "max": MAX_TIME, "default": 0 },
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{minimap::parse_marker_style, params::RenderParams};
+
+    use super::*;
+
+    /// The listed values of an option, which are strings for every option.
+    fn listed(option: &serde_json::Value) -> Vec<&str> {
+        option["values"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| value.as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn every_listed_value_is_accepted_strictly() {
+        let values = values();
+        for (name, option) in values.as_object().unwrap() {
+            // the markers are a parameter of the minimap, not of the render
+            if name == "ranges" || name == "marker" {
+                continue;
+            }
+            let listed = listed(option);
+            assert!(!listed.is_empty(), "{name}");
+            for value in listed {
+                let pairs = vec![
+                    ("strict".to_string(), "true".to_string()),
+                    (name.clone(), value.to_string()),
+                ];
+                let (params, _) = RenderParams::from_pairs(pairs).unwrap();
+                let validated = params.validate_strict();
+                assert!(validated.is_ok(), "{name}={value}: {validated:?}");
+            }
+        }
+        for marker in listed(&values["marker"]) {
+            assert!(parse_marker_style(marker).is_some(), "{marker}");
+        }
+    }
+
+    #[test]
+    fn the_defaults_are_listed_values() {
+        for (name, option) in values().as_object().unwrap() {
+            if let Some(default) = option["default"].as_str() {
+                assert!(listed(option).contains(&default), "{name}: {default}");
+            }
+        }
+    }
+
+    #[test]
+    fn the_range_defaults_are_in_range() {
+        let values = values();
+        for (name, range) in values["ranges"].as_object().unwrap() {
+            let min = range["min"].as_f64().unwrap();
+            let max = range["max"].as_f64().unwrap();
+            assert!(min < max, "{name}");
+            if let Some(default) = range["default"].as_f64() {
+                assert!((min..=max).contains(&default), "{name}: {default}");
+            }
+        }
+    }
+}
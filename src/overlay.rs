@@ -0,0 +1,557 @@
+use math::math::vector::vec2;
+
+use crate::{
+    frame::{Frame, Rect},
+    game_layer::TileCategory,
+    physics::{self, Projectile, TILE_SIZE},
+    scene::{FlagTeam, NameplateMark, PickupKind, SceneItem, SceneProjectile},
+};
+
+const TRAJECTORY_COLOR: [u8; 4] = [255, 255, 255, 220];
+const IMPACT_COLOR: [u8; 4] = [255, 64, 64, 230];
+const LABEL_COLOR: [u8; 4] = [255, 255, 255, 255];
+const LABEL_BACKGROUND: [u8; 4] = [0, 0, 0, 160];
+const FLAG_POLE_COLOR: [u8; 4] = [96, 72, 48, 255];
+const PICKUP_OUTLINE_COLOR: [u8; 4] = [0, 0, 0, 200];
+
+/// The flag sprite of the game is 42x84 units, drawn 16 units above the flag's position.
+const FLAG_SIZE: (f32, f32) = (42.0 / TILE_SIZE, 84.0 / TILE_SIZE);
+const FLAG_RAISE: f32 = 16.0 / TILE_SIZE;
+/// Pickups are drawn as a disc of a tile.
+const PICKUP_RADIUS: f32 = 0.5;
+/// Length and width of gun and shotgun bullets and grenades in tiles.
+const BULLET_SIZE: (f32, f32) = (0.5, 0.25);
+const GRENADE_SIZE: (f32, f32) = (0.6, 0.4);
+/// Laser beams of the game are 7 units wide with a 5 units wide core.
+const LASER_WIDTH: f32 = 7.0 / TILE_SIZE;
+const LASER_CORE_WIDTH: f32 = 5.0 / TILE_SIZE;
+const LASER_COLOR: [u8; 4] = [19, 19, 64, 255];
+const LASER_CORE_COLOR: [u8; 4] = [128, 128, 255, 255];
+const FRIEND_COLOR: [u8; 4] = [255, 80, 100, 255];
+const AUTHED_COLOR: [u8; 4] = [230, 190, 60, 255];
+
+/// Glyphs are 5x7 pixels, one byte per row.
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+/// Pixels around the text of a label, in glyph pixels.
+const LABEL_MARGIN: u32 = 2;
+
+/// The height of a label of `scale`, see [`draw_label`].
+pub fn label_height(scale: u32) -> u32 {
+    (GLYPH_HEIGHT + LABEL_MARGIN * 2) * scale
+}
+
+/// Uppercase letters and digits, anything else is left blank.
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        'A' => [
+            0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'B' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+        ],
+        'C' => [
+            0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+        ],
+        'D' => [
+            0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+        ],
+        'E' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+        ],
+        'F' => [
+            0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'G' => [
+            0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+        ],
+        'H' => [
+            0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+        ],
+        'I' => [
+            0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        'J' => [
+            0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+        ],
+        'K' => [
+            0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+        ],
+        'L' => [
+            0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+        ],
+        'M' => [
+            0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+        ],
+        'N' => [
+            0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+        ],
+        'O' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'P' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+        ],
+        'Q' => [
+            0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+        ],
+        'R' => [
+            0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+        ],
+        'S' => [
+            0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+        ],
+        'T' => [
+            0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'U' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+        ],
+        'V' => [
+            0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+        ],
+        'W' => [
+            0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        '0' => [
+            0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+        ],
+        '1' => [
+            0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+        ],
+        '2' => [
+            0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+        ],
+        '3' => [
+            0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+        ],
+        '4' => [
+            0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+        ],
+        '5' => [
+            0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+        ],
+        '6' => [
+            0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+        ],
+        '7' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+        ],
+        '8' => [
+            0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+        ],
+        '9' => [
+            0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+        ],
+        _ => [0; 7],
+    }
+}
+
+/// Draws `text` centered on a strip of `width` at `x`, `y`,
+/// the strip is [`label_height`] high. Glyph pixels are `scale` pixels.
+pub fn draw_label(frame: &mut Frame, text: &str, x: u32, y: u32, width: u32, scale: u32) {
+    frame.fill_rect(
+        Rect {
+            x: x as i64,
+            y: y as i64,
+            width,
+            height: label_height(scale),
+        },
+        LABEL_BACKGROUND,
+    );
+    let advance = (GLYPH_WIDTH + 1) * scale;
+    let text_width = (text.chars().count() as u32 * advance).saturating_sub(scale);
+    let left = x as i64 + (width as i64 - text_width as i64) / 2;
+    let top = (y + LABEL_MARGIN * scale) as i64;
+    for (i, c) in text.chars().enumerate() {
+        let glyph_left = left + (i as u32 * advance) as i64;
+        for (row, bits) in glyph(c).into_iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+                frame.fill_rect(
+                    Rect {
+                        x: glyph_left + (column * scale) as i64,
+                        y: top + row as i64 * scale as i64,
+                        width: scale,
+                        height: scale,
+                    },
+                    LABEL_COLOR,
+                );
+            }
+        }
+    }
+}
+
+/// Draws a projectile path as dots, `points` are relative to the center of the frame.
+/// They are in tiles, `zoom` is the zoom the frame was rendered with.
+pub fn draw_trajectory(frame: &mut Frame, points: &[vec2], hit: bool, zoom: f32) {
+    let aspect = frame.width as f32 / frame.height as f32;
+    let pixels_per_tile = frame.width as f32 / physics::visible_width(aspect, zoom);
+    let center_x = frame.width as f32 / 2.0;
+    let center_y = frame.height as f32 / 2.0;
+    let dot_radius = (pixels_per_tile * 0.08).clamp(1.0, 8.0);
+
+    let to_frame = |p: &vec2| {
+        (
+            center_x + p.x * pixels_per_tile,
+            center_y + p.y * pixels_per_tile,
+        )
+    };
+    for point in points {
+        let (x, y) = to_frame(point);
+        frame.fill_circle(x, y, dot_radius, TRAJECTORY_COLOR);
+    }
+    if let (true, Some(last)) = (hit, points.last()) {
+        let (x, y) = to_frame(last);
+        frame.fill_circle(x, y, dot_radius * 3.0, IMPACT_COLOR);
+    }
+}
+
+fn flag_color(team: FlagTeam) -> [u8; 4] {
+    match team {
+        FlagTeam::Red => [220, 40, 40, 255],
+        FlagTeam::Blue => [40, 90, 220, 255],
+    }
+}
+
+fn pickup_color(kind: PickupKind) -> [u8; 4] {
+    match kind {
+        PickupKind::Heart => [230, 40, 60, 255],
+        PickupKind::Armor => [240, 200, 40, 255],
+        PickupKind::Ninja => [40, 40, 40, 255],
+        PickupKind::Shotgun => [160, 100, 50, 255],
+        PickupKind::Grenade => [60, 140, 60, 255],
+        PickupKind::Laser => [80, 170, 240, 255],
+    }
+}
+
+fn projectile_color(projectile: Projectile) -> [u8; 4] {
+    match projectile {
+        Projectile::Gun => [255, 220, 120, 255],
+        Projectile::Shotgun => [255, 160, 60, 255],
+        Projectile::Grenade => [60, 110, 60, 255],
+        Projectile::Laser => LASER_CORE_COLOR,
+    }
+}
+
+/// Blends a convex quad over the frame, the corners are in frame pixels and in order.
+fn fill_quad(frame: &mut Frame, corners: [(f32, f32); 4], color: [u8; 4]) {
+    let min_y = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min);
+    let max_y = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max);
+    let min_y = min_y.floor().max(0.0) as u32;
+    let max_y = (max_y.ceil().max(0.0) as u32).min(frame.height);
+    for y in min_y..max_y {
+        // the row is filled where its center is inside
+        let center = y as f32 + 0.5;
+        let mut span: Option<(f32, f32)> = None;
+        for (i, &(ax, ay)) in corners.iter().enumerate() {
+            let (bx, by) = corners[(i + 1) % corners.len()];
+            if (ay <= center) == (by <= center) {
+                continue;
+            }
+            let x = ax + (center - ay) / (by - ay) * (bx - ax);
+            span = Some(span.map_or((x, x), |(left, right)| (left.min(x), right.max(x))));
+        }
+        if let Some((left, right)) = span {
+            let (left, right) = (left.round() as i64, right.round() as i64);
+            frame.fill_rect(
+                Rect {
+                    x: left,
+                    y: y as i64,
+                    width: (right - left).max(0) as u32,
+                    height: 1,
+                },
+                color,
+            );
+        }
+    }
+}
+
+/// Blends a line of `width` with round ends over the frame, in frame pixels.
+fn fill_segment(frame: &mut Frame, from: (f32, f32), to: (f32, f32), width: f32, color: [u8; 4]) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length > 0.0 {
+        let (nx, ny) = (-dy / length * width / 2.0, dx / length * width / 2.0);
+        fill_quad(
+            frame,
+            [
+                (from.0 + nx, from.1 + ny),
+                (to.0 + nx, to.1 + ny),
+                (to.0 - nx, to.1 - ny),
+                (from.0 - nx, from.1 - ny),
+            ],
+            color,
+        );
+    }
+    frame.fill_circle(from.0, from.1, width / 2.0, color);
+    frame.fill_circle(to.0, to.1, width / 2.0, color);
+}
+
+/// Draws the heart of friends or the shield of authed players, in frame pixels.
+fn draw_mark(frame: &mut Frame, mark: NameplateMark, (x, y): (f32, f32), size: f32) {
+    let half = size / 2.0;
+    match mark {
+        NameplateMark::Friend => {
+            let radius = size * 0.27;
+            frame.fill_circle(x - half / 2.0, y - half * 0.2, radius, FRIEND_COLOR);
+            frame.fill_circle(x + half / 2.0, y - half * 0.2, radius, FRIEND_COLOR);
+            fill_quad(
+                frame,
+                [
+                    (x - half, y - half * 0.1),
+                    (x + half, y - half * 0.1),
+                    (x, y + half),
+                    (x, y + half),
+                ],
+                FRIEND_COLOR,
+            );
+        }
+        NameplateMark::Authed => {
+            let width = half * 0.8;
+            fill_quad(
+                frame,
+                [
+                    (x - width, y - half * 0.9),
+                    (x + width, y - half * 0.9),
+                    (x + width, y + half * 0.2),
+                    (x - width, y + half * 0.2),
+                ],
+                AUTHED_COLOR,
+            );
+            fill_quad(
+                frame,
+                [
+                    (x - width, y + half * 0.2),
+                    (x + width, y + half * 0.2),
+                    (x, y + half),
+                    (x, y + half),
+                ],
+                AUTHED_COLOR,
+            );
+        }
+    }
+}
+
+/// Draws flags, pickups, projectiles and nameplate marks, `zoom` is the zoom the frame was rendered with.
+/// They are drawn over the captured frame, so unlike ingame they cover the Tee.
+pub fn draw_scene(frame: &mut Frame, items: &[SceneItem], zoom: f32) {
+    let aspect = frame.width as f32 / frame.height as f32;
+    let pixels_per_tile = frame.width as f32 / physics::visible_width(aspect, zoom);
+    let center_x = frame.width as f32 / 2.0;
+    let center_y = frame.height as f32 / 2.0;
+    let to_frame = |p: vec2| {
+        (
+            center_x + p.x * pixels_per_tile,
+            center_y + p.y * pixels_per_tile,
+        )
+    };
+
+    for item in items {
+        match *item {
+            SceneItem::Flag { team, pos, angle } => {
+                let (width, height) = FLAG_SIZE;
+                let (sin, cos) = angle.sin_cos();
+                // the sprite turns around its center
+                let middle = vec2::new(pos.x, pos.y - FLAG_RAISE);
+                let at = |x: f32, y: f32| {
+                    to_frame(vec2::new(
+                        middle.x + x * cos - y * sin,
+                        middle.y + x * sin + y * cos,
+                    ))
+                };
+                let pole = width / 8.0;
+                let (left, top, bottom) = (-width / 2.0, -height / 2.0, height / 2.0);
+                fill_quad(
+                    frame,
+                    [
+                        at(left, top),
+                        at(left + pole, top),
+                        at(left + pole, bottom),
+                        at(left, bottom),
+                    ],
+                    FLAG_POLE_COLOR,
+                );
+                fill_quad(
+                    frame,
+                    [
+                        at(left + pole, top),
+                        at(width / 2.0, top),
+                        at(width / 2.0, 0.0),
+                        at(left + pole, 0.0),
+                    ],
+                    flag_color(team),
+                );
+            }
+            SceneItem::Pickup { kind, pos } => {
+                let (x, y) = to_frame(pos);
+                let radius = PICKUP_RADIUS * pixels_per_tile;
+                frame.fill_circle(x, y, radius, PICKUP_OUTLINE_COLOR);
+                frame.fill_circle(x, y, radius * 0.8, pickup_color(kind));
+            }
+            SceneItem::Projectile(SceneProjectile::Flying {
+                projectile,
+                pos,
+                angle,
+            }) => {
+                let (length, width) = if projectile == Projectile::Grenade {
+                    GRENADE_SIZE
+                } else {
+                    BULLET_SIZE
+                };
+                let (sin, cos) = angle.sin_cos();
+                let at = |x: f32, y: f32| {
+                    to_frame(vec2::new(
+                        pos.x + x * cos - y * sin,
+                        pos.y + x * sin + y * cos,
+                    ))
+                };
+                let (x, y) = (length / 2.0, width / 2.0);
+                fill_quad(
+                    frame,
+                    [at(-x, -y), at(x, -y), at(x, y), at(-x, y)],
+                    projectile_color(projectile),
+                );
+            }
+            SceneItem::Projectile(SceneProjectile::Laser { from, to }) => {
+                let (from, to) = (to_frame(from), to_frame(to));
+                fill_segment(frame, from, to, LASER_WIDTH * pixels_per_tile, LASER_COLOR);
+                fill_segment(
+                    frame,
+                    from,
+                    to,
+                    LASER_CORE_WIDTH * pixels_per_tile,
+                    LASER_CORE_COLOR,
+                );
+            }
+            SceneItem::Mark { mark, pos, size } => {
+                draw_mark(frame, mark, to_frame(pos), size * pixels_per_tile);
+            }
+        }
+    }
+}
+
+/// Draws the categorized tiles of the physics overlay with `opacity`,
+/// see [`crate::game_layer::visible_rects`].
+pub fn draw_game_layer(frame: &mut Frame, rects: &[(Rect, TileCategory)], opacity: f32) {
+    let alpha = (opacity * 255.0).round() as u8;
+    for (rect, category) in rects {
+        let [r, g, b] = category.color();
+        frame.fill_rect(*rect, [r, g, b, alpha]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(frame: &Frame, x: f32, y: f32) -> [u8; 4] {
+        let i = (y as usize * frame.width as usize + x as usize) * 4;
+        frame.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn quads_fill_like_rects() {
+        let mut quad = Frame::transparent(16, 16);
+        fill_quad(
+            &mut quad,
+            [(2.0, 3.0), (10.0, 3.0), (10.0, 12.0), (2.0, 12.0)],
+            [255, 0, 0, 255],
+        );
+        let mut rect = Frame::transparent(16, 16);
+        rect.fill_rect(
+            Rect {
+                x: 2,
+                y: 3,
+                width: 8,
+                height: 9,
+            },
+            [255, 0, 0, 255],
+        );
+        assert_eq!(quad.pixels, rect.pixels);
+    }
+
+    #[test]
+    fn pickups_are_drawn_relative_to_the_camera() {
+        let mut frame = Frame::transparent(160, 90);
+        let zoom = 0.25;
+        let pixels_per_tile = 160.0 / physics::visible_width(160.0 / 90.0, zoom);
+        let item = SceneItem::Pickup {
+            kind: PickupKind::Heart,
+            pos: vec2::new(2.0, 0.0),
+        };
+        draw_scene(&mut frame, &[item], zoom);
+        assert_eq!(
+            pixel(&frame, 80.0 + 2.0 * pixels_per_tile, 45.0),
+            pickup_color(PickupKind::Heart)
+        );
+        assert_eq!(pixel(&frame, 80.0, 45.0), [0; 4]);
+    }
+
+    #[test]
+    fn laser_beams_have_a_core() {
+        let mut frame = Frame::transparent(160, 90);
+        let zoom = 0.25;
+        let pixels_per_tile = 160.0 / physics::visible_width(160.0 / 90.0, zoom);
+        let item = SceneItem::Projectile(SceneProjectile::Laser {
+            from: vec2::new(-2.0, 0.0),
+            to: vec2::new(2.0, 0.0),
+        });
+        draw_scene(&mut frame, &[item], zoom);
+        assert_eq!(pixel(&frame, 80.0, 45.0), LASER_CORE_COLOR);
+        // the edge of the beam is outside of the core
+        let edge = 45.0 + (LASER_CORE_WIDTH + LASER_WIDTH) / 4.0 * pixels_per_tile;
+        assert_eq!(pixel(&frame, 80.0, edge), LASER_COLOR);
+        assert_eq!(pixel(&frame, 80.0, 45.0 + pixels_per_tile), [0; 4]);
+    }
+
+    #[test]
+    fn marks_are_centered_on_their_position() {
+        for (mark, color) in [
+            (NameplateMark::Friend, FRIEND_COLOR),
+            (NameplateMark::Authed, AUTHED_COLOR),
+        ] {
+            let mut frame = Frame::transparent(160, 90);
+            let pixels_per_tile = 160.0 / physics::visible_width(160.0 / 90.0, 0.5);
+            let item = SceneItem::Mark {
+                mark,
+                pos: vec2::new(0.0, -1.0),
+                size: 1.0,
+            };
+            draw_scene(&mut frame, &[item], 0.5);
+            assert_eq!(pixel(&frame, 80.0, 45.0 - pixels_per_tile), color);
+            assert_eq!(pixel(&frame, 80.0, 45.0), [0; 4]);
+        }
+    }
+
+    #[test]
+    fn flags_are_drawn_above_their_position() {
+        let mut frame = Frame::transparent(160, 90);
+        let zoom = 0.5;
+        let pixels_per_tile = 160.0 / physics::visible_width(160.0 / 90.0, zoom);
+        let item = SceneItem::Flag {
+            team: FlagTeam::Blue,
+            pos: vec2::new(0.0, 0.0),
+            angle: 0.0,
+        };
+        draw_scene(&mut frame, &[item], zoom);
+        // the cloth is in the upper half of the sprite, right of the pole
+        let cloth_y = 45.0 - (FLAG_RAISE + FLAG_SIZE.1 / 4.0) * pixels_per_tile;
+        assert_eq!(
+            pixel(&frame, 80.0 + pixels_per_tile * 0.25, cloth_y),
+            flag_color(FlagTeam::Blue)
+        );
+        assert_eq!(pixel(&frame, 80.0, 45.0 + pixels_per_tile), [0; 4]);
+    }
+}
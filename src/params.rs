@@ -0,0 +1,1908 @@
+use game_interface::types::{
+    emoticons::{EmoticonType, IntoEnumIterator},
+    network_string::NetworkString,
+    render::character::TeeEye,
+    resource_key::NetworkResourceKey,
+    weapons::WeaponType,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use math::math::{normalize, vector::vec2};
+
+use crate::{
+    cameras::{self, CameraSource},
+    error::RenderError,
+    frame::PngOptions,
+    game_layer::{map_overlay_name, MapOverlay, MAP_OVERLAYS},
+    maps, names,
+    physics::{self, projectile_name, Projectile, PROJECTILES},
+    player_api::{provider_name, Provider, PROVIDERS},
+    presets,
+    render_cache::CachePolicy,
+    scene::{
+        self, flag_team_name, parse_flag_team, parse_pickups, pickup_kind_name, Flag, Pickup,
+        ProjectileParams, SceneProjectile, FLAG_TEAMS, MAX_PROJECTILES, PICKUP_KINDS,
+    },
+    sheet::{sheet_axis_name, Sheet, SheetAxis, SHEET_AXES},
+    version,
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RenderParams {
+    /// Name of the skin to draw
+    pub skin_name: NetworkString<24>,
+    /// Url of a skin png on an allowed host, replaces `skin_name`
+    pub skin_url: Option<String>,
+    /// Optional player name to render as nameplate
+    pub player_name: Option<NetworkString<128>>,
+    /// Optional clan to render in a smaller line above the player name
+    pub clan_name: Option<NetworkString<12>>,
+    /// Whether the nameplate is rendered, the player name is still used for the player api
+    pub nameplate: Option<bool>,
+    /// Scales the nameplate text
+    pub nameplate_size: Option<f32>,
+    /// Whether the friend heart is rendered above the nameplate
+    pub friend_mark: Option<bool>,
+    /// Whether the shield of authed players is rendered above the nameplate
+    pub authed_mark: Option<bool>,
+    /// Camera zoom
+    pub zoom: Option<f32>,
+    /// Camera rotation in degrees, clockwise
+    pub angle: Option<f32>,
+    /// Camera pos x in tiles
+    pub x: Option<f32>,
+    /// Camera pos y in tiles
+    pub y: Option<f32>,
+    /// Map pos x of the Tee, the Tee is in the center of the camera if unset
+    pub tee_x: Option<f32>,
+    /// Map pos y of the Tee, the Tee is in the center of the camera if unset
+    pub tee_y: Option<f32>,
+    /// Color body, a legacy color, `#rrggbb`, `#rrggbbaa` or `rgb(r,g,b)`
+    pub body_color: Option<TeeColor>,
+    /// Color feet, see `body_color`
+    pub feet_color: Option<TeeColor>,
+    /// Whether rgb colors are darkened like the game does for legacy colors
+    pub ddnet_color_clamp: Option<bool>,
+    /// Cursor dir x
+    pub dir_x: Option<f32>,
+    /// Cursor dir y
+    pub dir_y: Option<f32>,
+    /// Tee eyes
+    pub eyes: Option<String>,
+    /// Tee weapon
+    pub weapon: Option<String>,
+    /// Tee emoticon
+    pub emoticon: Option<String>,
+    /// Ticks since the emoticon was sent, its pop and fade animation
+    pub emoticon_ticks: Option<u64>,
+    /// Progress to the next tick in 0..=1, for emoticon animations
+    pub intra_tick: Option<f32>,
+    /// Whether the Tee used its double jump
+    pub used_air_jump: Option<bool>,
+    /// Whether the Tee is in the air right now
+    pub in_air: Option<bool>,
+    /// The x velocity of the Tee in map units per tick
+    pub vel_x: Option<f32>,
+    /// The y velocity of the Tee in map units per tick
+    pub vel_y: Option<f32>,
+    /// The direction the Tee wants to move, -1, 0 or 1
+    pub move_dir: Option<i32>,
+    /// Comma separated buffs and debuffs of the Tee, e.g. `freeze,ninja`
+    pub buffs: Option<String>,
+    /// The x position of the hook relative to the Tee
+    pub hook_x: Option<f32>,
+    /// The y position of the hook relative to the Tee
+    pub hook_y: Option<f32>,
+    /// Whether `hook_x`/`hook_y` are map coordinates the hook is attached to,
+    /// instead of relative to the Tee
+    pub hook_attached: Option<bool>,
+    /// The current time of the rendering (e.g. for map animations) in ms.
+    pub time: Option<u64>,
+    /// Ticks since the last attack, renders the weapon mid-swing or with recoil
+    pub recoil_ticks: Option<u64>,
+    /// Ticks the game ran for, drives weapon animations
+    pub game_ticks: Option<u64>,
+    /// Whether the weapon is rendered attacking
+    pub weapon_attack: Option<bool>,
+    /// Whether the weapon is rendered with its muzzle flash
+    pub weapon_muzzle: Option<bool>,
+    /// Whether the chat bubble is rendered beside the Tee
+    pub chat_bubble: Option<bool>,
+    /// Whether the afk indicator is rendered beside the Tee, hidden by emoticons like ingame
+    pub afk: Option<bool>,
+    /// Whether the feet of the Tee are flipped
+    pub feet_flipped: Option<bool>,
+    /// The size of the Tee
+    pub size: Option<f32>,
+    /// A `red` or `blue` flag, carried by the Tee unless `flag_x`/`flag_y` are set
+    pub flag: Option<String>,
+    /// Map pos x of the flag
+    pub flag_x: Option<f32>,
+    /// Map pos y of the flag
+    pub flag_y: Option<f32>,
+    /// Comma separated pickups as `kind:x:y` in map positions, e.g. `heart:10:20,laser:12:20`
+    pub pickups: Option<String>,
+    /// Projectiles and laser beams, only set by the JSON render body
+    #[serde(skip)]
+    pub projectiles: Vec<ProjectileParams>,
+    /// Draw the path of a projectile, `gun`, `shotgun`, `grenade` or `laser`
+    pub trajectory_sim: Option<String>,
+    /// Direction x of the projectile, defaults to the cursor dir
+    pub sim_dir_x: Option<f32>,
+    /// Direction y of the projectile, defaults to the cursor dir
+    pub sim_dir_y: Option<f32>,
+    /// Multiplies the projectile speed (0.1..=2)
+    pub sim_speed: Option<f32>,
+
+    /// Name of the map to render
+    pub map_name: Option<String>,
+    /// Key of the weapon skin
+    pub weapon_skin: Option<String>,
+    /// Key of the hook skin
+    pub hook_skin: Option<String>,
+    /// Key of the emoticon skin
+    pub emoticon_skin: Option<String>,
+    /// Key of the entities skin
+    pub entities: Option<String>,
+    /// Which game's entities are drawn, e.g. `ddnet` or `vanilla`
+    pub entities_game: Option<String>,
+    /// Use the player api to fetch the latest
+    /// skin of the player
+    pub use_player_api: Option<bool>,
+    /// The player api that is asked first, `ddstats` or `skins_tw`
+    pub provider: Option<String>,
+    /// Reject unknown enum values instead of falling back to defaults
+    pub strict: Option<bool>,
+    /// In strict mode, reject deprecated parameter names
+    pub error_on_deprecated: Option<bool>,
+    /// Cache policy, `fresh` or `swr` (stale while revalidate)
+    pub cache: Option<String>,
+    /// Wait for a map that is still loading, otherwise respond with `202` and `Retry-After`
+    pub map_wait: Option<bool>,
+
+    /// Output width in css pixels, defaults to the canvas width
+    pub width: Option<u32>,
+    /// Output height in css pixels, defaults to the canvas height
+    pub height: Option<u32>,
+    /// Device pixel ratio (1..=3), multiplies the output size
+    /// while keeping the framing of the scene
+    pub dpr: Option<f32>,
+    /// Renders at this many times the output size (1..=4) and downscales
+    /// with a lanczos filter, which smooths the edges of small images
+    pub supersample: Option<u32>,
+    /// Image format, `png` or `jpeg`
+    pub format: Option<String>,
+    /// Png compression level (0..=9), higher is smaller but slower
+    pub png_compression: Option<u8>,
+    /// Reduce pngs to a palette of 256 colors, much smaller for mostly flat renders
+    pub quantize: Option<bool>,
+    /// `avatar` renders a square of just the Tee without map,
+    /// other presets are expanded before parsing, see `presets::expand`
+    pub preset: Option<String>,
+    /// The side length of avatars
+    pub avatar_size: Option<u32>,
+    /// Render without map, so the background is transparent
+    pub transparent: Option<bool>,
+    /// Render the whole map without Tee, the camera is picked to fit the map
+    pub overview: Option<bool>,
+    /// The map passes that run, `background`, `foreground`, `both` or `none`
+    pub layers: Option<String>,
+    /// Draw `physics` tiles color coded over the map
+    pub overlay: Option<String>,
+    /// Opacity of the overlay (0..=1)
+    pub overlay_opacity: Option<f32>,
+    /// Render a sprite sheet of cells that differ in
+    /// `direction`, `eyes`, `emoticon` or `animation`
+    pub sheet: Option<String>,
+    /// Number of cells, defaults to all eyes or emoticons and 8 otherwise
+    pub sheet_cells: Option<u32>,
+    /// Cells per row, defaults to all cells in one row
+    pub sheet_columns: Option<u32>,
+    /// Pixels between cells
+    pub sheet_padding: Option<u32>,
+    /// Label every cell with what differs
+    pub sheet_labels: Option<bool>,
+    /// Background color `#rrggbb` the image is composited over,
+    /// defaults to white for formats without alpha
+    pub matte: Option<String>,
+    /// Solid background color `#rrggbb` or `#rrggbbaa` instead of the map
+    pub background: Option<String>,
+}
+
+/// All eye variants, [`eye_name`] is exhaustive, so a new variant
+/// fails to compile until it was added here.
+pub const EYES: [TeeEye; 6] = [
+    TeeEye::Normal,
+    TeeEye::Angry,
+    TeeEye::Pain,
+    TeeEye::Happy,
+    TeeEye::Surprised,
+    TeeEye::Blink,
+];
+
+pub fn eye_name(eye: TeeEye) -> &'static str {
+    match eye {
+        TeeEye::Normal => "normal",
+        TeeEye::Angry => "angry",
+        TeeEye::Pain => "pain",
+        TeeEye::Happy => "happy",
+        TeeEye::Surprised => "surprised",
+        TeeEye::Blink => "blink",
+    }
+}
+
+/// All weapon variants, see [`EYES`].
+pub const WEAPONS: [WeaponType; 5] = [
+    WeaponType::Hammer,
+    WeaponType::Gun,
+    WeaponType::Shotgun,
+    WeaponType::Grenade,
+    WeaponType::Laser,
+];
+
+pub fn weapon_name(weapon: WeaponType) -> &'static str {
+    match weapon {
+        WeaponType::Hammer => "hammer",
+        WeaponType::Gun => "gun",
+        WeaponType::Shotgun => "shotgun",
+        WeaponType::Grenade => "grenade",
+        WeaponType::Laser => "laser",
+    }
+}
+
+pub fn emoticon_name(emoticon: EmoticonType) -> &'static str {
+    emoticon.into()
+}
+
+/// Trims and compares case insensitive.
+fn find<T: Clone>(
+    value: &str,
+    variants: impl IntoIterator<Item = T>,
+    name: impl Fn(T) -> &'static str,
+) -> Option<T> {
+    let value = value.trim();
+    variants
+        .into_iter()
+        .find(|v| name(v.clone()).eq_ignore_ascii_case(value))
+}
+
+pub fn parse_eyes(value: &str) -> Option<TeeEye> {
+    find(value, EYES, eye_name)
+}
+
+pub fn parse_weapon(value: &str) -> Option<WeaponType> {
+    find(value, WEAPONS, weapon_name)
+}
+
+pub fn parse_emoticon(value: &str) -> Option<EmoticonType> {
+    find(value, EmoticonType::iter(), emoticon_name)
+}
+
+pub fn parse_projectile(value: &str) -> Option<Projectile> {
+    find(value, PROJECTILES, projectile_name)
+}
+
+pub fn parse_provider(value: &str) -> Option<Provider> {
+    find(value, PROVIDERS, provider_name)
+}
+
+pub fn parse_map_overlay(value: &str) -> Option<MapOverlay> {
+    find(value, MAP_OVERLAYS, map_overlay_name)
+}
+
+pub fn parse_sheet_axis(value: &str) -> Option<SheetAxis> {
+    find(value, SHEET_AXES, sheet_axis_name)
+}
+
+/// The buffs and debuffs a Tee can be rendered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buff {
+    Ninja,
+    Ghost,
+    Freeze,
+}
+
+pub const BUFFS: [Buff; 3] = [Buff::Ninja, Buff::Ghost, Buff::Freeze];
+
+pub fn buff_name(buff: Buff) -> &'static str {
+    match buff {
+        Buff::Ninja => "ninja",
+        Buff::Ghost => "ghost",
+        Buff::Freeze => "freeze",
+    }
+}
+
+/// A comma separated list, fails if any buff is unknown.
+pub fn parse_buffs(value: &str) -> Option<Vec<Buff>> {
+    value
+        .split(',')
+        .filter(|buff| !buff.trim().is_empty())
+        .map(|buff| find(buff, BUFFS, buff_name))
+        .collect()
+}
+
+/// The skin ninja'd and frozen Tees are rendered with, like ingame.
+pub const NINJA_SKIN: &str = "x_ninja";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    /// Uncompressed RGBA8 pixels, the size is in the `X-Image-Width` and `X-Image-Height` headers
+    Raw,
+    /// Uncompressed RGBA8 pixels in a PAM container
+    Pam,
+}
+
+pub const FORMATS: [OutputFormat; 4] = [
+    OutputFormat::Png,
+    OutputFormat::Jpeg,
+    OutputFormat::Raw,
+    OutputFormat::Pam,
+];
+
+pub fn format_name(format: OutputFormat) -> &'static str {
+    match format {
+        OutputFormat::Png => "png",
+        OutputFormat::Jpeg => "jpeg",
+        OutputFormat::Raw => "raw",
+        OutputFormat::Pam => "pam",
+    }
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Raw => "application/octet-stream",
+            OutputFormat::Pam => "image/x-portable-arbitrarymap",
+        }
+    }
+
+    pub fn has_alpha(self) -> bool {
+        match self {
+            OutputFormat::Png | OutputFormat::Raw | OutputFormat::Pam => true,
+            OutputFormat::Jpeg => false,
+        }
+    }
+}
+
+pub fn parse_format(value: &str) -> Option<OutputFormat> {
+    // `jpg` is common enough to accept it too
+    if value.trim().eq_ignore_ascii_case("jpg") {
+        return Some(OutputFormat::Jpeg);
+    }
+    find(value, FORMATS, format_name)
+}
+
+/// The passes of the map render, the Tee is drawn between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapLayers {
+    Background,
+    Foreground,
+    Both,
+    None,
+}
+
+pub const MAP_LAYERS: [MapLayers; 4] = [
+    MapLayers::Background,
+    MapLayers::Foreground,
+    MapLayers::Both,
+    MapLayers::None,
+];
+
+pub fn map_layers_name(layers: MapLayers) -> &'static str {
+    match layers {
+        MapLayers::Background => "background",
+        MapLayers::Foreground => "foreground",
+        MapLayers::Both => "both",
+        MapLayers::None => "none",
+    }
+}
+
+impl MapLayers {
+    pub fn background(self) -> bool {
+        matches!(self, MapLayers::Background | MapLayers::Both)
+    }
+
+    pub fn foreground(self) -> bool {
+        matches!(self, MapLayers::Foreground | MapLayers::Both)
+    }
+}
+
+pub fn parse_map_layers(value: &str) -> Option<MapLayers> {
+    find(value, MAP_LAYERS, map_layers_name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// A square image cropped to the Tee, on a transparent background
+    Avatar,
+}
+
+pub const PRESETS: [Preset; 1] = [Preset::Avatar];
+
+pub fn preset_name(preset: Preset) -> &'static str {
+    match preset {
+        Preset::Avatar => "avatar",
+    }
+}
+
+pub fn parse_preset(value: &str) -> Option<Preset> {
+    find(value, PRESETS, preset_name)
+}
+
+pub const DEFAULT_AVATAR_SIZE: u32 = 256;
+
+/// `#rrggbb`, the `#` is optional.
+pub fn parse_hex_rgb(value: &str) -> Option<[u8; 3]> {
+    let hex = value.trim();
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// `#rrggbb` or `#rrggbbaa`, the `#` is optional.
+pub fn parse_hex_rgba(value: &str) -> Option<[u8; 4]> {
+    let hex = value.trim();
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() == 6 {
+        let [r, g, b] = parse_hex_rgb(hex)?;
+        return Some([r, g, b, 255]);
+    }
+    if hex.len() != 8 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?, channel(6)?])
+}
+
+/// A custom color of the Tee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TeeColor {
+    /// The packed hsl color of the game, see [`color_alpha`]
+    Legacy(i32),
+    Rgba([u8; 4]),
+}
+
+pub const COLOR_FORMATS: [&str; 4] = ["integer", "#rrggbb", "#rrggbbaa", "rgb(r,g,b)"];
+
+/// A legacy color, `#rrggbb`, `#rrggbbaa` or `rgb(r,g,b)`.
+/// Legacy colors may exceed `i32`, since an alpha is easier written unsigned.
+pub fn parse_color(value: &str) -> Option<TeeColor> {
+    let value = value.trim();
+    if let Ok(color) = value.parse::<i64>() {
+        return (i32::MIN as i64..=u32::MAX as i64)
+            .contains(&color)
+            .then_some(TeeColor::Legacy(color as i32));
+    }
+    if let Some(rgb) = value
+        .strip_prefix("rgb(")
+        .and_then(|rgb| rgb.strip_suffix(')'))
+    {
+        let channels: Vec<u8> = rgb
+            .split(',')
+            .map(|c| c.trim().parse::<u8>().ok())
+            .collect::<Option<_>>()?;
+        let [r, g, b] = channels[..] else {
+            return None;
+        };
+        return Some(TeeColor::Rgba([r, g, b, 255]));
+    }
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    match hex.len() {
+        6 => parse_hex_rgb(hex).map(|[r, g, b]| TeeColor::Rgba([r, g, b, 255])),
+        8 if hex.is_ascii() => {
+            let [r, g, b] = parse_hex_rgb(&hex[..6])?;
+            let a = u8::from_str_radix(&hex[6..], 16).ok()?;
+            Some(TeeColor::Rgba([r, g, b, a]))
+        }
+        _ => None,
+    }
+}
+
+impl<'de> Deserialize<'de> for TeeColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse_color(&value)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid color {value}")))
+    }
+}
+
+/// Colors with a lower alpha would make the Tee vanish.
+const MIN_COLOR_ALPHA: f32 = 0.1;
+
+/// The alpha of a legacy color from its top byte.
+/// Legacy colors of the game leave it 0, so 0 is opaque.
+pub fn color_alpha(color: i32) -> f32 {
+    match (color >> 24) & 0xFF {
+        0 => 1.0,
+        a => (a as f32 / 255.0).max(MIN_COLOR_ALPHA),
+    }
+}
+
+/// The alpha of a rgba color, see [`color_alpha`].
+pub fn rgba_alpha(a: u8) -> f32 {
+    (a as f32 / 255.0).max(MIN_COLOR_ALPHA)
+}
+
+/// The maximum speed of the game in map units per tick.
+const MAX_VEL: f32 = 6000.0 / 256.0;
+
+/// The tick rate the Tee is animated with.
+pub const TICKS_PER_SECOND: u64 = 50;
+
+/// The maximum output size under overload.
+pub const THUMBNAIL_SIZE: (u32, u32) = (320, 240);
+
+/// The matte of formats without alpha if none was requested.
+pub const DEFAULT_MATTE: [u8; 3] = [255, 255, 255];
+
+/// `NaN` and infinity are replaced by `fallback`.
+fn finite(value: f32, fallback: f32) -> f32 {
+    if value.is_finite() {
+        value
+    } else {
+        fallback
+    }
+}
+
+/// The range a numeric parameter is clamped to, also listed by `GET /options`.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FloatRange {
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+}
+
+impl FloatRange {
+    /// `NaN` and infinity become `fallback`, which is clamped as well.
+    pub fn sanitize(self, value: Option<f32>, fallback: f32) -> f32 {
+        finite(value.unwrap_or(self.default), fallback).clamp(self.min, self.max)
+    }
+}
+
+pub const ZOOM_RANGE: FloatRange = FloatRange {
+    min: 0.001,
+    max: 20.0,
+    default: 0.5,
+};
+/// The camera position and zoom that fit a map of `width` x `height` tiles
+/// into a canvas with the `aspect` ratio (width / height).
+pub fn overview_camera(width: f32, height: f32, aspect: f32) -> (f32, f32, f32) {
+    let visible_width = physics::visible_width(aspect, 1.0);
+    let visible_height = visible_width / aspect;
+    let zoom = (width / visible_width).max(height / visible_height);
+    (
+        width / 2.0,
+        height / 2.0,
+        ZOOM_RANGE.sanitize(Some(zoom), 1.0),
+    )
+}
+
+/// How much more of the map a camera rotated by `angle` degrees must show,
+/// so the rotated `width` x `height` image has no empty corners.
+pub fn rotation_cover(angle: f32, width: u32, height: u32) -> f32 {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (sin, cos) = (sin.abs(), cos.abs());
+    let (width, height) = (width as f32, height as f32);
+    ((width * cos + height * sin) / width).max((width * sin + height * cos) / height)
+}
+
+/// Map positions, the default depends on the map
+pub const POS_RANGE: FloatRange = FloatRange {
+    min: 0.0,
+    max: 300000.0,
+    default: 0.0,
+};
+pub const DIR_X_RANGE: FloatRange = FloatRange {
+    min: -1.0,
+    max: 1.0,
+    default: 1.0,
+};
+pub const DIR_Y_RANGE: FloatRange = FloatRange {
+    min: -1.0,
+    max: 1.0,
+    default: 0.0,
+};
+pub const SIZE_RANGE: FloatRange = FloatRange {
+    min: 0.5,
+    max: 8.0,
+    default: 2.0,
+};
+/// The hook relative to the Tee
+pub const HOOK_RANGE: FloatRange = FloatRange {
+    min: -10000.0,
+    max: 10000.0,
+    default: 0.0,
+};
+pub const SIM_SPEED_RANGE: FloatRange = FloatRange {
+    min: 0.1,
+    max: 2.0,
+    default: 1.0,
+};
+pub const NAMEPLATE_SIZE_RANGE: FloatRange = FloatRange {
+    min: 0.25,
+    max: 4.0,
+    default: 1.0,
+};
+pub const OVERLAY_OPACITY_RANGE: FloatRange = FloatRange {
+    min: 0.0,
+    max: 1.0,
+    default: 0.5,
+};
+/// Nameplates stop shrinking below this camera zoom, so they stay readable.
+const MIN_NAMEPLATE_ZOOM: f32 = 0.3;
+
+/// The camera zoom the nameplate is laid out with.
+/// The readability clamp only applies to the camera, the size scales
+/// after it, so small sizes stay small at any zoom instead of being clamped away.
+pub fn nameplate_zoom(zoom: f32, size: f32) -> f32 {
+    zoom.max(MIN_NAMEPLATE_ZOOM) * size
+}
+
+/// The clan line is drawn smaller than the name.
+pub const CLAN_NAMEPLATE_SCALE: f32 = 0.7;
+/// The distance of the clan line above the name, in tiles at camera zoom 1.
+const CLAN_LINE_SPACING: f32 = 0.6;
+
+/// Where the clan line of a nameplate at `pos` is drawn, above the name.
+/// The spacing grows with the camera zoom of the nameplate like its text.
+pub fn clan_line_pos(pos: vec2, camera_zoom: f32) -> vec2 {
+    vec2::new(pos.x, pos.y - CLAN_LINE_SPACING * camera_zoom)
+}
+
+/// The side length of the friend and authed marks, in tiles at camera zoom 1.
+pub const NAMEPLATE_MARK_SIZE: f32 = 0.5;
+
+/// Where the marks of a nameplate at `pos` are centered, a line above the clan
+/// or, without clan, above the name. Like the lines they grow with the camera zoom.
+pub fn nameplate_marks_pos(pos: vec2, camera_zoom: f32, clan: bool) -> vec2 {
+    let top = if clan {
+        clan_line_pos(pos, camera_zoom)
+    } else {
+        pos
+    };
+    vec2::new(top.x, top.y - CLAN_LINE_SPACING * camera_zoom)
+}
+
+pub const DPR_RANGE: FloatRange = FloatRange {
+    min: 1.0,
+    max: 3.0,
+    default: 1.0,
+};
+/// The best png compression level
+pub const MAX_PNG_COMPRESSION: u8 = 9;
+/// Upper bound of the supersampling factor
+pub const MAX_SUPERSAMPLE: u32 = 4;
+/// At most 1 year in ms
+pub const MAX_TIME: u64 = 31536000000;
+
+/// An invalid parameter value.
+#[derive(Debug)]
+pub struct ParamError {
+    pub field: &'static str,
+    pub value: String,
+    pub reason: String,
+    pub accepted: Vec<&'static str>,
+}
+
+fn check<T>(
+    field: &'static str,
+    value: &Option<String>,
+    parse: impl Fn(&str) -> Option<T>,
+    accepted: impl Iterator<Item = &'static str>,
+) -> Result<(), ParamError> {
+    match value {
+        Some(value) if parse(value).is_none() => Err(ParamError {
+            field,
+            value: value.clone(),
+            reason: "unknown value".to_string(),
+            accepted: accepted.collect(),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The assets a render uses beside the defaults,
+/// which are waited for before rendering.
+#[derive(Debug, Default)]
+pub struct AssetKeys {
+    pub skin: Option<String>,
+    pub weapon: Option<String>,
+    pub hook: Option<String>,
+    pub emoticon: Option<String>,
+    pub entities: Option<String>,
+}
+
+impl AssetKeys {
+    pub fn new(params: &RenderParams) -> Self {
+        Self {
+            skin: Some(params.render_skin().to_string()),
+            weapon: params.weapon_skin.clone(),
+            hook: params.hook_skin.clone(),
+            emoticon: params.emoticon_skin.clone(),
+            entities: params.entities.clone(),
+        }
+    }
+}
+
+/// Resource keys are short names, see `NetworkResourceKey`.
+pub fn parse_resource_key(value: &str) -> Option<NetworkResourceKey<24>> {
+    value.try_into().ok()
+}
+
+/// Invalid keys render the default, like an unset key.
+fn valid_key(value: &Option<String>) -> Option<&str> {
+    value
+        .as_deref()
+        .filter(|value| parse_resource_key(value).is_some())
+}
+
+/// The games entities can be drawn for.
+pub const ENTITIES_GAMES: [&str; 5] = ["ddnet", "vanilla", "race", "fng", "blockworlds"];
+
+/// The size of the image that is served.
+#[derive(Debug, Clone, Copy)]
+pub struct OutputSize {
+    /// Width in device pixels
+    pub width: u32,
+    /// Height in device pixels
+    pub height: u32,
+    pub dpr: f32,
+}
+
+impl RenderParams {
+    /// Returns `None` if the full canvas is served as is.
+    /// The output must fit into the canvas, so the dpr is reduced if needed.
+    pub fn output_size(
+        &self,
+        canvas_width: u32,
+        canvas_height: u32,
+        warnings: &mut Vec<String>,
+    ) -> Option<OutputSize> {
+        if self.width.is_none() && self.height.is_none() && self.dpr.is_none() {
+            return None;
+        }
+        let width = self.width.unwrap_or(canvas_width).clamp(1, canvas_width);
+        let height = self.height.unwrap_or(canvas_height).clamp(1, canvas_height);
+
+        let mut dpr = DPR_RANGE.sanitize(self.dpr, 1.0);
+        let max_dpr =
+            (canvas_width as f32 / width as f32).min(canvas_height as f32 / height as f32);
+        if dpr > max_dpr {
+            warnings.push(format!(
+                "dpr {dpr} exceeds the canvas size, reduced to {max_dpr:.2}"
+            ));
+            dpr = max_dpr;
+        }
+
+        Some(OutputSize {
+            width: ((width as f32 * dpr).round() as u32).min(canvas_width),
+            height: ((height as f32 * dpr).round() as u32).min(canvas_height),
+            dpr,
+        })
+    }
+
+    /// The sanitized supersampling factor, see [`Self::fit_supersample`].
+    pub fn supersample(&self) -> u32 {
+        self.supersample.unwrap_or(1).clamp(1, MAX_SUPERSAMPLE)
+    }
+
+    /// The supersampled render must fit into the canvas, so the factor is reduced if needed.
+    /// The full canvas can't be supersampled at all.
+    pub fn fit_supersample(
+        &mut self,
+        output_size: Option<OutputSize>,
+        canvas_width: u32,
+        canvas_height: u32,
+        warnings: &mut Vec<String>,
+    ) {
+        let supersample = self.supersample();
+        let max_supersample = match (self.avatar_size(), output_size) {
+            (Some(size), _) => canvas_width.min(canvas_height) / size,
+            (None, Some(size)) => (canvas_width / size.width).min(canvas_height / size.height),
+            (None, None) => 1,
+        }
+        .max(1);
+        if supersample > max_supersample {
+            warnings.push(format!(
+                "supersample {supersample} exceeds the canvas size, reduced to {max_supersample}"
+            ));
+        }
+        self.supersample = Some(supersample.min(max_supersample));
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.format
+            .as_deref()
+            .and_then(parse_format)
+            .unwrap_or(OutputFormat::Png)
+    }
+
+    /// How pngs are encoded, the compression level is clamped to 9.
+    pub fn png_options(&self) -> PngOptions {
+        PngOptions {
+            compression: self
+                .png_compression
+                .map(|level| level.min(MAX_PNG_COMPRESSION)),
+            quantize: self.quantize.unwrap_or_default(),
+        }
+    }
+
+    pub fn preset(&self) -> Option<Preset> {
+        self.preset.as_deref().and_then(parse_preset)
+    }
+
+    /// The sanitized avatar size, if the avatar preset is used.
+    pub fn avatar_size(&self) -> Option<u32> {
+        (self.preset() == Some(Preset::Avatar)).then(|| {
+            self.avatar_size
+                .unwrap_or(DEFAULT_AVATAR_SIZE)
+                .clamp(16, 1024)
+        })
+    }
+
+    /// The flag of the scene, carried if it has no position.
+    /// A missing coordinate is the camera's.
+    pub fn flag(&self) -> Option<Flag> {
+        let team = self.flag.as_deref().and_then(parse_flag_team)?;
+        let pos = (self.flag_x.is_some() || self.flag_y.is_some()).then(|| {
+            let (x, y) = self.camera_pos();
+            vec2::new(
+                POS_RANGE.sanitize(self.flag_x.or(Some(x)), x),
+                POS_RANGE.sanitize(self.flag_y.or(Some(y)), y),
+            )
+        });
+        Some(Flag { team, pos })
+    }
+
+    /// The valid pickups of the scene.
+    pub fn pickups(&self) -> Vec<Pickup> {
+        self.pickups
+            .as_deref()
+            .map(scene::pickups)
+            .unwrap_or_default()
+    }
+
+    /// The valid projectiles of the scene, moved along their path.
+    pub fn projectiles(&self) -> Vec<SceneProjectile> {
+        scene::projectiles(&self.projectiles)
+    }
+
+    /// The sanitized sprite sheet grid, if a sheet is requested.
+    pub fn sheet(&self) -> Option<Sheet> {
+        let axis = self.sheet.as_deref().and_then(parse_sheet_axis)?;
+        Some(Sheet::new(
+            axis,
+            self.sheet_cells,
+            self.sheet_columns,
+            self.sheet_padding,
+            self.sheet_labels,
+        ))
+    }
+
+    /// Whether only the map is rendered, see [`overview_camera`].
+    pub fn overview(&self) -> bool {
+        self.overview.unwrap_or_default()
+    }
+
+    /// The map passes that run, none if the map is hidden.
+    pub fn map_layers(&self) -> MapLayers {
+        if self.hide_map() {
+            return MapLayers::None;
+        }
+        self.layers
+            .as_deref()
+            .and_then(parse_map_layers)
+            .unwrap_or(MapLayers::Both)
+    }
+
+    /// The overlay drawn over the map, none without map.
+    pub fn map_overlay(&self) -> Option<MapOverlay> {
+        if self.hide_map() {
+            return None;
+        }
+        self.overlay.as_deref().and_then(parse_map_overlay)
+    }
+
+    pub fn overlay_opacity(&self) -> f32 {
+        OVERLAY_OPACITY_RANGE.sanitize(self.overlay_opacity, 0.5)
+    }
+
+    /// Whether the render waits for its map if it's still loading.
+    pub fn map_wait(&self) -> bool {
+        self.map_wait.unwrap_or(true)
+    }
+
+    /// Whether the map is left out, so the background is transparent
+    /// or the solid background color.
+    pub fn hide_map(&self) -> bool {
+        self.preset() == Some(Preset::Avatar)
+            || self.transparent.unwrap_or_default()
+            || self.background_color().is_some()
+    }
+
+    /// The requested matte, or the default one if the format has no alpha.
+    /// An opaque background needs none.
+    pub fn matte_color(&self) -> Option<[u8; 3]> {
+        let opaque = self.background_color().is_some_and(|color| color[3] == 255);
+        match self.matte.as_deref().and_then(parse_hex_rgb) {
+            Some(matte) => Some(matte),
+            None if !self.output_format().has_alpha() && !opaque => Some(DEFAULT_MATTE),
+            None => None,
+        }
+    }
+
+    /// The solid background that replaces the map.
+    pub fn background_color(&self) -> Option<[u8; 4]> {
+        self.background.as_deref().and_then(parse_hex_rgba)
+    }
+
+    /// Switches to the cheapest render under overload: the default map,
+    /// no dpr and a thumbnail sized output.
+    pub fn apply_low_cost_profile(&mut self, canvas_width: u32, canvas_height: u32) -> OutputSize {
+        self.map_name = None;
+        self.dpr = None;
+        self.supersample = None;
+        OutputSize {
+            width: self
+                .width
+                .unwrap_or(canvas_width)
+                .clamp(1, THUMBNAIL_SIZE.0.min(canvas_width)),
+            height: self
+                .height
+                .unwrap_or(canvas_height)
+                .clamp(1, THUMBNAIL_SIZE.1.min(canvas_height)),
+            dpr: 1.0,
+        }
+    }
+
+    pub fn entities_game(&self) -> &'static str {
+        self.entities_game
+            .as_deref()
+            .and_then(|game| find(game, ENTITIES_GAMES, |game| game))
+            .unwrap_or("ddnet")
+    }
+
+    /// The sanitized camera zoom, without the dpr.
+    pub fn zoom(&self) -> f32 {
+        let default = cameras::lookup(self.map_name()).and_then(|camera| camera.zoom);
+        ZOOM_RANGE.sanitize(self.zoom.or(default), 1.0)
+    }
+
+    /// The camera rotation in degrees in `[0, 360)`.
+    pub fn angle(&self) -> f32 {
+        finite(self.angle.unwrap_or_default(), 0.0).rem_euclid(360.0)
+    }
+
+    pub fn size(&self) -> f32 {
+        SIZE_RANGE.sanitize(self.size, 2.0)
+    }
+
+    /// The sanitized velocity of the Tee.
+    pub fn velocity(&self) -> vec2 {
+        vec2::new(
+            finite(self.vel_x.unwrap_or_default(), 0.0).clamp(-MAX_VEL, MAX_VEL),
+            finite(self.vel_y.unwrap_or_default(), 0.0).clamp(-MAX_VEL, MAX_VEL),
+        )
+    }
+
+    pub fn move_dir(&self) -> i32 {
+        self.move_dir.unwrap_or_default().signum()
+    }
+
+    /// Valid buffs, unknown ones are ignored.
+    pub fn buffs(&self) -> Vec<Buff> {
+        let mut buffs: Vec<Buff> = self
+            .buffs
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|buff| find(buff, BUFFS, buff_name))
+            .collect();
+        buffs.sort_by_key(|buff| *buff as u8);
+        buffs.dedup();
+        buffs
+    }
+
+    pub fn eyes(&self) -> TeeEye {
+        self.eyes
+            .as_deref()
+            .and_then(parse_eyes)
+            .unwrap_or(TeeEye::Normal)
+    }
+
+    /// The held weapon, unknown weapons are a hammer.
+    /// Ninja'd and frozen Tees can't hold their weapons.
+    pub fn weapon(&self) -> Option<WeaponType> {
+        self.weapon
+            .as_deref()
+            .map(|weapon| parse_weapon(weapon).unwrap_or(WeaponType::Hammer))
+            .filter(|_| {
+                !self
+                    .buffs()
+                    .iter()
+                    .any(|buff| matches!(buff, Buff::Ninja | Buff::Freeze))
+            })
+    }
+
+    pub fn emoticon(&self) -> Option<EmoticonType> {
+        self.emoticon.as_deref().and_then(parse_emoticon)
+    }
+
+    /// Sanitizes the player and clan name, names of only stripped
+    /// characters are removed, so they render no nameplate.
+    pub fn sanitize_names(&mut self) {
+        self.player_name = self
+            .player_name
+            .take()
+            .and_then(|name| names::sanitize(name.as_str()))
+            .and_then(|name| NetworkString::new(&name).ok());
+        self.clan_name = self
+            .clan_name
+            .take()
+            .and_then(|name| names::sanitize(name.as_str()))
+            .and_then(|name| NetworkString::new(&name).ok());
+    }
+
+    /// The player api that is asked first, the configured order otherwise.
+    pub fn provider(&self) -> Option<Provider> {
+        self.provider.as_deref().and_then(parse_provider)
+    }
+
+    /// Whether a nameplate is rendered, for a player or a clan name.
+    pub fn nameplate(&self) -> bool {
+        self.nameplate.unwrap_or(true) && (self.player_name.is_some() || self.clan_name.is_some())
+    }
+
+    pub fn nameplate_size(&self) -> f32 {
+        NAMEPLATE_SIZE_RANGE.sanitize(self.nameplate_size, 1.0)
+    }
+
+    /// Whether the afk indicator is shown, an emoticon replaces it like ingame.
+    pub fn afk(&self) -> bool {
+        self.afk.unwrap_or_default() && self.emoticon().is_none()
+    }
+
+    /// The skin that is rendered, buffs replace the skin.
+    pub fn render_skin(&self) -> &str {
+        if self
+            .buffs()
+            .iter()
+            .any(|buff| matches!(buff, Buff::Ninja | Buff::Freeze))
+        {
+            NINJA_SKIN
+        } else {
+            self.skin_name.as_str()
+        }
+    }
+
+    /// The sanitized ticks since the last attack, at most 1 second.
+    pub fn recoil_ticks(&self) -> Option<u64> {
+        self.recoil_ticks.map(|ticks| ticks.min(TICKS_PER_SECOND))
+    }
+
+    /// The sanitized emoticon ticks, an emoticon is shown for 2 seconds.
+    pub fn emoticon_ticks(&self) -> u64 {
+        self.emoticon_ticks.unwrap_or(90).min(TICKS_PER_SECOND * 2)
+    }
+
+    /// The time since the last tick.
+    pub fn intra_tick_time(&self) -> Duration {
+        let intra_tick = finite(self.intra_tick.unwrap_or_default(), 0.0).clamp(0.0, 1.0);
+        Duration::from_secs_f32(intra_tick / TICKS_PER_SECOND as f32)
+    }
+
+    /// The sanitized game ticks, at most 1 day.
+    pub fn game_ticks(&self) -> u64 {
+        self.game_ticks
+            .unwrap_or_default()
+            .min(TICKS_PER_SECOND * 60 * 60 * 24)
+    }
+
+    /// The projectile, its normalized direction and speed factor to simulate.
+    pub fn trajectory(&self) -> Option<(Projectile, vec2, f32)> {
+        let projectile = self.trajectory_sim.as_deref().and_then(parse_projectile)?;
+        let mut dir = vec2::new(
+            DIR_X_RANGE.sanitize(self.sim_dir_x.or(self.dir_x), 0.0),
+            DIR_Y_RANGE.sanitize(self.sim_dir_y.or(self.dir_y), 0.0),
+        );
+        if dir.x.abs() < 0.001 && dir.y.abs() < 0.001 {
+            dir.x = 1.0;
+        }
+        let speed = SIM_SPEED_RANGE.sanitize(self.sim_speed, 1.0);
+        Some((projectile, normalize(&dir), speed))
+    }
+
+    /// The map that is rendered.
+    pub fn map_name(&self) -> &str {
+        self.map_name.as_deref().unwrap_or(version::DEFAULT_MAP)
+    }
+
+    /// The sanitized camera position, the default depends on the map.
+    pub fn camera_pos(&self) -> (f32, f32) {
+        let (default_x, default_y) = match cameras::lookup(self.map_name()) {
+            Some(camera) => (camera.x, camera.y),
+            // the size is known once the map was rendered, see `maps::record_size`
+            None => maps::map_size(self.map_name())
+                .map_or((0.0, 0.0), |(width, height)| (width / 2.0, height / 2.0)),
+        };
+        (
+            POS_RANGE.sanitize(self.x.or(Some(default_x)), 0.0),
+            POS_RANGE.sanitize(self.y.or(Some(default_y)), 0.0),
+        )
+    }
+
+    /// Where [`Self::camera_pos`] comes from.
+    pub fn camera_source(&self) -> CameraSource {
+        if self.x.is_some() || self.y.is_some() {
+            CameraSource::Query
+        } else if cameras::lookup(self.map_name()).is_some() {
+            CameraSource::File
+        } else {
+            CameraSource::MapCenter
+        }
+    }
+
+    /// The sanitized map position of the Tee, if it's not at the camera.
+    /// A missing coordinate is the camera's.
+    pub fn tee_pos(&self) -> Option<(f32, f32)> {
+        if self.tee_x.is_none() && self.tee_y.is_none() {
+            return None;
+        }
+        let (x, y) = self.camera_pos();
+        Some((
+            POS_RANGE.sanitize(self.tee_x.or(Some(x)), x),
+            POS_RANGE.sanitize(self.tee_y.or(Some(y)), y),
+        ))
+    }
+
+    /// The hook position relative to the Tee, which is at `tee_x`, `tee_y`
+    /// in map coordinates.
+    pub fn hook_offset(&self, tee_x: f32, tee_y: f32) -> Option<vec2> {
+        let (x, y) = self.hook_x.zip(self.hook_y)?;
+        if self.hook_attached.unwrap_or_default() {
+            Some(vec2::new(
+                POS_RANGE.sanitize(Some(x), tee_x) - tee_x,
+                POS_RANGE.sanitize(Some(y), tee_y) - tee_y,
+            ))
+        } else {
+            Some(vec2::new(
+                HOOK_RANGE.sanitize(Some(x), 0.0),
+                HOOK_RANGE.sanitize(Some(y), 0.0),
+            ))
+        }
+    }
+
+    /// A canonical form of the parameters with defaults resolved and values
+    /// sanitized like while rendering, so equal images share a key.
+    /// Must be built after the player api resolved the skin.
+    pub fn cache_key(&self, output_size: Option<OutputSize>) -> String {
+        let map_name = self.map_name();
+        let (x, y) = self.camera_pos();
+        serde_json::json!({
+            "skin_name": self.skin_name.as_str(),
+            "player_name": self.player_name.as_ref().map(|name| name.as_str()),
+            "clan_name": self.clan_name.as_ref().map(|clan| clan.as_str()),
+            "nameplate": self.nameplate().then(|| (
+                self.nameplate_size(),
+                self.friend_mark.unwrap_or_default(),
+                self.authed_mark.unwrap_or_default(),
+            )),
+            "map_name": map_name,
+            "zoom": self.zoom(),
+            "angle": self.angle(),
+            // the center is only known once the map was rendered
+            "camera": match self.camera_source() {
+                CameraSource::MapCenter => serde_json::json!("map_center"),
+                _ => serde_json::json!([x, y]),
+            },
+            "dir_x": DIR_X_RANGE.sanitize(self.dir_x, 0.0),
+            "dir_y": DIR_Y_RANGE.sanitize(self.dir_y, 0.0),
+            "size": self.size(),
+            "body_color": self.body_color,
+            "feet_color": self.feet_color,
+            "preset": self.preset().map(preset_name),
+            "avatar_size": self.avatar_size(),
+            "transparent": self.hide_map(),
+            "layers": map_layers_name(self.map_layers()),
+            "overview": self.overview(),
+            "flag": self.flag().map(|flag| (
+                flag_team_name(flag.team),
+                flag.pos.map(|pos| (pos.x, pos.y)),
+            )),
+            "pickups": self
+                .pickups()
+                .iter()
+                .map(|pickup| (pickup_kind_name(pickup.kind), pickup.pos.x, pickup.pos.y))
+                .collect::<Vec<_>>(),
+            "projectiles": self
+                .projectiles()
+                .iter()
+                .map(|projectile| match projectile {
+                    SceneProjectile::Flying {
+                        projectile,
+                        pos,
+                        angle,
+                    } => (projectile_name(*projectile), pos.x, pos.y, *angle),
+                    SceneProjectile::Laser { from, to } => (
+                        projectile_name(Projectile::Laser),
+                        from.x,
+                        from.y,
+                        (to.y - from.y).atan2(to.x - from.x),
+                    ),
+                })
+                .collect::<Vec<_>>(),
+            "sheet": self.sheet().map(|sheet| (
+                sheet_axis_name(sheet.axis),
+                sheet.cells,
+                sheet.columns,
+                sheet.padding,
+                sheet.labels,
+            )),
+            "ddnet_color_clamp": self.ddnet_color_clamp.unwrap_or_default(),
+            "eyes": eye_name(self.eyes()),
+            "weapon": self.weapon().map(weapon_name),
+            "emoticon": self.emoticon().map(emoticon_name),
+            "used_air_jump": self.used_air_jump.unwrap_or_default(),
+            "in_air": self.in_air.unwrap_or_default(),
+            "vel": (self.velocity().x, self.velocity().y),
+            "move_dir": self.move_dir(),
+            "buffs": self.buffs().into_iter().map(buff_name).collect::<Vec<_>>(),
+            "tee": self.tee_pos(),
+            "hook": {
+                let (tee_x, tee_y) = self.tee_pos().unwrap_or((x, y));
+                self.hook_offset(tee_x, tee_y).map(|hook| (hook.x, hook.y))
+            },
+            "time": self.time.unwrap_or_default().min(MAX_TIME),
+            "emoticon_ticks": self.emoticon_ticks(),
+            "intra_tick": self.intra_tick_time().as_nanos() as u64,
+            "recoil_ticks": self.recoil_ticks(),
+            "game_ticks": self.game_ticks(),
+            "chat_bubble": self.chat_bubble.unwrap_or_default(),
+            "afk": self.afk(),
+            "weapon_attack": self.weapon_attack.unwrap_or_default(),
+            "weapon_muzzle": self.weapon_muzzle.unwrap_or_default(),
+            "feet_flipped": self.feet_flipped.unwrap_or_default(),
+            "trajectory": self.trajectory().map(|(projectile, dir, speed)| {
+                (projectile_name(projectile), dir.x, dir.y, speed)
+            }),
+            "weapon_skin": valid_key(&self.weapon_skin),
+            "hook_skin": valid_key(&self.hook_skin),
+            "emoticon_skin": valid_key(&self.emoticon_skin),
+            "entities": valid_key(&self.entities),
+            "entities_game": self.entities_game(),
+            "overlay": self.map_overlay().map(|overlay| {
+                (map_overlay_name(overlay), self.overlay_opacity())
+            }),
+            "output": output_size.map(|size| (size.width, size.height, size.dpr)),
+            "supersample": self.supersample(),
+            "format": format_name(self.output_format()),
+            "png": (self.output_format() == OutputFormat::Png).then(|| {
+                let options = self.png_options();
+                (options.compression, options.quantize)
+            }),
+            "matte": self.matte_color(),
+            "background": self.background_color(),
+        })
+        .to_string()
+    }
+
+    /// In strict mode unknown enum values are errors,
+    /// otherwise they fall back to their defaults while rendering.
+    pub fn validate_strict(&self) -> Result<(), ParamError> {
+        check(
+            "eyes",
+            &self.eyes,
+            parse_eyes,
+            EYES.into_iter().map(eye_name),
+        )?;
+        check(
+            "weapon",
+            &self.weapon,
+            parse_weapon,
+            WEAPONS.into_iter().map(weapon_name),
+        )?;
+        check(
+            "emoticon",
+            &self.emoticon,
+            parse_emoticon,
+            EmoticonType::iter().map(emoticon_name),
+        )?;
+        check(
+            "trajectory_sim",
+            &self.trajectory_sim,
+            parse_projectile,
+            PROJECTILES.into_iter().map(projectile_name),
+        )?;
+        check(
+            "buffs",
+            &self.buffs,
+            parse_buffs,
+            BUFFS.into_iter().map(buff_name),
+        )?;
+        check(
+            "flag",
+            &self.flag,
+            parse_flag_team,
+            FLAG_TEAMS.into_iter().map(flag_team_name),
+        )?;
+        check(
+            "pickups",
+            &self.pickups,
+            parse_pickups,
+            PICKUP_KINDS.into_iter().map(pickup_kind_name),
+        )?;
+        if self.projectiles.len() > MAX_PROJECTILES {
+            return Err(ParamError {
+                field: "projectiles",
+                value: self.projectiles.len().to_string(),
+                reason: format!("at most {MAX_PROJECTILES} projectiles"),
+                accepted: Vec::new(),
+            });
+        }
+        for projectile in &self.projectiles {
+            check(
+                "projectiles",
+                &Some(projectile.kind.clone()),
+                parse_projectile,
+                PROJECTILES.into_iter().map(projectile_name),
+            )?;
+        }
+        check(
+            "provider",
+            &self.provider,
+            parse_provider,
+            PROVIDERS.into_iter().map(provider_name),
+        )?;
+        check(
+            "layers",
+            &self.layers,
+            parse_map_layers,
+            MAP_LAYERS.into_iter().map(map_layers_name),
+        )?;
+        check(
+            "overlay",
+            &self.overlay,
+            parse_map_overlay,
+            MAP_OVERLAYS.into_iter().map(map_overlay_name),
+        )?;
+        check(
+            "sheet",
+            &self.sheet,
+            parse_sheet_axis,
+            SHEET_AXES.into_iter().map(sheet_axis_name),
+        )?;
+        check(
+            "preset",
+            &self.preset,
+            parse_preset,
+            PRESETS.into_iter().map(preset_name),
+        )?;
+        check(
+            "format",
+            &self.format,
+            parse_format,
+            FORMATS.into_iter().map(format_name),
+        )?;
+        check("matte", &self.matte, parse_hex_rgb, ["#rrggbb"].into_iter())?;
+        for (field, value) in [
+            ("weapon_skin", &self.weapon_skin),
+            ("hook_skin", &self.hook_skin),
+            ("emoticon_skin", &self.emoticon_skin),
+            ("entities", &self.entities),
+        ] {
+            check(field, value, parse_resource_key, std::iter::empty())?;
+        }
+        check(
+            "entities_game",
+            &self.entities_game,
+            |game| find(game, ENTITIES_GAMES, |game| game),
+            ENTITIES_GAMES.into_iter(),
+        )?;
+        check(
+            "cache",
+            &self.cache,
+            CachePolicy::parse,
+            CachePolicy::NAMES.into_iter(),
+        )?;
+        Ok(())
+    }
+}
+
+/// A renamed parameter, the old name keeps working but is reported.
+#[derive(Debug)]
+pub struct Deprecation {
+    pub old: &'static str,
+    pub new: &'static str,
+    /// Converts the old value into the new format, if it changed
+    pub transform: Option<fn(&str) -> String>,
+}
+
+/// The single source of truth for renamed parameters.
+pub const DEPRECATIONS: &[Deprecation] = &[
+    Deprecation {
+        old: "body",
+        new: "body_color",
+        transform: None,
+    },
+    Deprecation {
+        old: "feet",
+        new: "feet_color",
+        transform: None,
+    },
+];
+
+/// Rewrites deprecated names to their replacement before the parameters are parsed.
+/// Supplying the old and new name with different values is an error.
+pub fn apply_deprecations(
+    pairs: &mut Vec<(String, String)>,
+) -> Result<Vec<&'static Deprecation>, ParamError> {
+    let mut applied = Vec::new();
+    for deprecation in DEPRECATIONS {
+        let Some(index) = pairs.iter().position(|(k, _)| k == deprecation.old) else {
+            continue;
+        };
+        let (_, value) = pairs.remove(index);
+        let value = match deprecation.transform {
+            Some(transform) => transform(&value),
+            None => value,
+        };
+        match pairs.iter().find(|(k, _)| k == deprecation.new) {
+            Some((_, new_value)) if *new_value != value => {
+                return Err(ParamError {
+                    field: deprecation.new,
+                    value: new_value.clone(),
+                    reason: format!(
+                        "conflicts with the deprecated {} = {value}",
+                        deprecation.old
+                    ),
+                    accepted: Vec::new(),
+                });
+            }
+            Some(_) => {}
+            None => pairs.push((deprecation.new.to_string(), value)),
+        }
+        applied.push(deprecation);
+    }
+    Ok(applied)
+}
+
+/// Map positions and the tile coordinates that may replace them.
+pub const TILE_COORDINATES: [(&str, &str); 6] = [
+    ("x", "tile_x"),
+    ("y", "tile_y"),
+    ("tee_x", "tee_tile_x"),
+    ("tee_y", "tee_tile_y"),
+    ("hook_x", "hook_tile_x"),
+    ("hook_y", "hook_tile_y"),
+];
+
+/// The map position of a tile coordinate. A whole number is the center of
+/// the tile the editor shows with this number, where the game places a Tee
+/// in a tile, fractions move away from the center.
+pub fn tile_pos(tile: f32) -> f32 {
+    tile + 0.5
+}
+
+/// Rewrites tile coordinates to map positions before the parameters are parsed.
+/// A tile coordinate excludes the map position of its axis, hook tiles attach the hook.
+pub fn apply_tile_coordinates(pairs: &mut Vec<(String, String)>) -> Result<(), ParamError> {
+    let mut hook_tile = false;
+    for (pos_field, tile_field) in TILE_COORDINATES {
+        let Some(index) = pairs.iter().position(|(k, _)| k == tile_field) else {
+            continue;
+        };
+        let (_, value) = pairs.remove(index);
+        if pairs.iter().any(|(k, _)| k == pos_field) {
+            return Err(ParamError {
+                field: tile_field,
+                value,
+                reason: format!("conflicts with {pos_field}, only one of them may be set"),
+                accepted: Vec::new(),
+            });
+        }
+        let tile = value.trim().parse::<f32>().map_err(|err| ParamError {
+            field: tile_field,
+            value: value.clone(),
+            reason: err.to_string(),
+            accepted: Vec::new(),
+        })?;
+        pairs.push((pos_field.to_string(), tile_pos(tile).to_string()));
+        hook_tile |= pos_field.starts_with("hook_");
+    }
+    if hook_tile {
+        match pairs.iter().find(|(k, _)| k == "hook_attached") {
+            Some((_, value)) if value != "true" => {
+                return Err(ParamError {
+                    field: "hook_attached",
+                    value: value.clone(),
+                    reason: "hook tiles are always attached".to_string(),
+                    accepted: vec!["true"],
+                });
+            }
+            Some(_) => {}
+            None => pairs.push(("hook_attached".to_string(), "true".to_string())),
+        }
+    }
+    Ok(())
+}
+
+/// The query pairs of the parameters of a JSON body, `null` is left out.
+pub fn json_pairs(
+    params: serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<(String, String)>, RenderError> {
+    params
+        .into_iter()
+        .filter(|(_, value)| !value.is_null())
+        .map(|(k, value)| match value {
+            serde_json::Value::String(value) => Ok((k, value)),
+            serde_json::Value::Bool(_) | serde_json::Value::Number(_) => Ok((k, value.to_string())),
+            _ => Err(RenderError::InvalidQuery(format!(
+                "{k} must be a string, number or bool"
+            ))),
+        })
+        .collect()
+}
+
+impl RenderParams {
+    /// Parses the raw query string, deprecated names are rewritten first
+    /// and returned, so they can be reported.
+    pub fn from_query(query: &str) -> Result<(Self, Vec<&'static Deprecation>), RenderError> {
+        let pairs: Vec<(String, String)> = serde_urlencoded::from_str(query)
+            .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        Self::from_pairs(pairs)
+    }
+
+    /// Parses the JSON render body, an object of the query parameters
+    /// and the `projectiles` array, which has no query form.
+    pub fn from_json(body: &[u8]) -> Result<(Self, Vec<&'static Deprecation>), RenderError> {
+        #[derive(Deserialize)]
+        struct RenderBody {
+            #[serde(default)]
+            projectiles: Vec<ProjectileParams>,
+            #[serde(flatten)]
+            params: serde_json::Map<String, serde_json::Value>,
+        }
+        let body: RenderBody = serde_json::from_slice(body)
+            .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        let (mut params, deprecated) = Self::from_pairs(json_pairs(body.params)?)?;
+        params.projectiles = body.projectiles;
+        Ok((params, deprecated))
+    }
+
+    /// Like [`Self::from_query`] for decoded name value pairs.
+    pub fn from_pairs(
+        mut pairs: Vec<(String, String)>,
+    ) -> Result<(Self, Vec<&'static Deprecation>), RenderError> {
+        let deprecated = apply_deprecations(&mut pairs)?;
+        apply_tile_coordinates(&mut pairs)?;
+        presets::expand(&mut pairs)?;
+        // serde's errors don't name the field
+        for field in ["body_color", "feet_color"] {
+            if let Some((_, value)) = pairs
+                .iter()
+                .find(|(k, value)| k == field && parse_color(value).is_none())
+            {
+                return Err(ParamError {
+                    field,
+                    value: value.clone(),
+                    reason: "invalid color".to_string(),
+                    accepted: COLOR_FORMATS.to_vec(),
+                }
+                .into());
+            }
+        }
+        if let Some((_, value)) = pairs
+            .iter()
+            .find(|(k, value)| k == "background" && parse_hex_rgba(value).is_none())
+        {
+            return Err(ParamError {
+                field: "background",
+                value: value.clone(),
+                reason: "invalid color".to_string(),
+                accepted: vec!["#rrggbb", "#rrggbbaa"],
+            }
+            .into());
+        }
+        let query = serde_urlencoded::to_string(&pairs)
+            .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+        let params: Self = serde_urlencoded::from_str(&query)
+            .map_err(|err| RenderError::InvalidQuery(err.to_string()))?;
+
+        if let Some(deprecation) = deprecated.first() {
+            if params.strict.unwrap_or_default() && params.error_on_deprecated.unwrap_or_default() {
+                return Err(ParamError {
+                    field: deprecation.old,
+                    value: String::new(),
+                    reason: format!("deprecated, use {}", deprecation.new),
+                    accepted: vec![deprecation.new],
+                }
+                .into());
+            }
+        }
+        Ok((params, deprecated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn deprecated_names_are_renamed() {
+        let mut query = pairs(&[("skin_name", "pinky"), ("body", "123"), ("feet", "456")]);
+        let applied = apply_deprecations(&mut query).unwrap();
+        assert_eq!(
+            applied.iter().map(|d| d.old).collect::<Vec<_>>(),
+            ["body", "feet"]
+        );
+        assert_eq!(
+            query,
+            pairs(&[
+                ("skin_name", "pinky"),
+                ("body_color", "123"),
+                ("feet_color", "456")
+            ])
+        );
+    }
+
+    #[test]
+    fn current_names_are_not_reported() {
+        let mut query = pairs(&[("body_color", "123")]);
+        assert!(apply_deprecations(&mut query).unwrap().is_empty());
+        assert_eq!(query, pairs(&[("body_color", "123")]));
+    }
+
+    #[test]
+    fn both_names_with_the_same_value_are_accepted() {
+        let mut query = pairs(&[("body", "123"), ("body_color", "123")]);
+        let applied = apply_deprecations(&mut query).unwrap();
+        assert_eq!(applied.len(), 1);
+        assert_eq!(query, pairs(&[("body_color", "123")]));
+    }
+
+    #[test]
+    fn both_names_with_different_values_conflict() {
+        let mut query = pairs(&[("body_color", "123"), ("body", "456")]);
+        let err = apply_deprecations(&mut query).unwrap_err();
+        assert_eq!(err.field, "body_color");
+        assert_eq!(err.value, "123");
+        assert!(err.reason.contains("body = 456"), "{}", err.reason);
+    }
+
+    #[test]
+    fn deprecated_names_are_parsed_and_reported() {
+        let (params, deprecated) = RenderParams::from_query("body=123&feet_color=456").unwrap();
+        assert_eq!(params.body_color, Some(TeeColor::Legacy(123)));
+        assert_eq!(params.feet_color, Some(TeeColor::Legacy(456)));
+        assert_eq!(deprecated.len(), 1);
+        assert_eq!(deprecated[0].new, "body_color");
+    }
+
+    #[test]
+    fn deprecated_names_are_only_rejected_if_asked_to() {
+        for query in ["body=123&error_on_deprecated=true", "body=123&strict=true"] {
+            assert!(RenderParams::from_query(query).is_ok(), "{query}");
+        }
+        let err =
+            RenderParams::from_query("body=123&strict=true&error_on_deprecated=true").unwrap_err();
+        let RenderError::InvalidParam(err) = err else {
+            panic!("{err}");
+        };
+        assert_eq!(err.field, "body");
+        assert_eq!(err.accepted, ["body_color"]);
+    }
+
+    #[test]
+    fn names_are_trimmed_and_case_insensitive() {
+        assert!(matches!(parse_eyes(" HAPPY "), Some(TeeEye::Happy)));
+        assert!(matches!(parse_weapon("Laser\t"), Some(WeaponType::Laser)));
+        assert!(matches!(
+            parse_emoticon("DotDot"),
+            Some(EmoticonType::DOTDOT)
+        ));
+        assert!(parse_eyes("hap py").is_none());
+    }
+
+    #[test]
+    fn strict_validation_accepts_any_case_and_whitespace() {
+        let (params, _) =
+            RenderParams::from_query("eyes=%20HAPPY%20&weapon=Laser%09&emoticon=DotDot").unwrap();
+        assert!(params.validate_strict().is_ok());
+        let (params, _) = RenderParams::from_query("eyes=HAPPYY").unwrap();
+        assert_eq!(params.validate_strict().unwrap_err().field, "eyes");
+    }
+
+    fn matte(query: &str) -> Option<[u8; 3]> {
+        RenderParams::from_query(query).unwrap().0.matte_color()
+    }
+
+    #[test]
+    fn jpegs_get_a_white_matte_by_default() {
+        assert_eq!(matte("format=jpeg"), Some(DEFAULT_MATTE));
+        assert_eq!(matte("format=jpeg&matte=%23102030"), Some([16, 32, 48]));
+        assert_eq!(matte("format=jpeg&matte=red"), Some(DEFAULT_MATTE));
+    }
+
+    #[test]
+    fn formats_with_alpha_only_get_a_requested_matte() {
+        assert_eq!(matte(""), None);
+        assert_eq!(matte("format=pam"), None);
+        assert_eq!(matte("matte=102030"), Some([16, 32, 48]));
+    }
+
+    #[test]
+    fn an_opaque_background_needs_no_matte() {
+        assert_eq!(matte("format=jpeg&background=%23000000"), None);
+        assert_eq!(
+            matte("format=jpeg&background=%2300000080"),
+            Some(DEFAULT_MATTE)
+        );
+    }
+
+    fn low_cost(query: &str, canvas: (u32, u32)) -> (RenderParams, OutputSize) {
+        let (mut params, _) = RenderParams::from_query(query).unwrap();
+        let size = params.apply_low_cost_profile(canvas.0, canvas.1);
+        (params, size)
+    }
+
+    #[test]
+    fn the_low_cost_profile_drops_the_expensive_parts() {
+        let (params, size) = low_cost(
+            "map_name=Kobra&dpr=3&supersample=4&player_name=nameless",
+            (1920, 1080),
+        );
+        assert_eq!(params.map_name, None);
+        assert_eq!(params.dpr, None);
+        assert_eq!(params.supersample(), 1);
+        assert_eq!(
+            params.player_name.as_ref().map(|name| name.as_str()),
+            Some("nameless")
+        );
+        assert_eq!((size.width, size.height, size.dpr), (320, 240, 1.0));
+    }
+
+    #[test]
+    fn the_low_cost_profile_keeps_smaller_sizes() {
+        let (_, size) = low_cost("width=100&height=0", (1920, 1080));
+        assert_eq!((size.width, size.height), (100, 1));
+        // a canvas smaller than a thumbnail is the limit
+        let (_, size) = low_cost("", (200, 100));
+        assert_eq!((size.width, size.height), (200, 100));
+    }
+
+    #[test]
+    fn legacy_colors_without_alpha_are_opaque() {
+        assert_eq!(color_alpha(0), 1.0);
+        assert_eq!(color_alpha(0x00ff_ffff), 1.0);
+    }
+
+    #[test]
+    fn legacy_color_alphas_are_the_top_byte() {
+        assert_eq!(color_alpha(0xff12_3456u32 as i32), 1.0);
+        assert_eq!(color_alpha(0x8012_3456u32 as i32), 128.0 / 255.0);
+        assert_eq!(color_alpha(0x1a00_0000), 26.0 / 255.0);
+        // too transparent alphas are raised instead of hiding the Tee
+        assert_eq!(color_alpha(0x0100_0000), MIN_COLOR_ALPHA);
+        assert_eq!(color_alpha(0x1900_0000), MIN_COLOR_ALPHA);
+    }
+
+    #[test]
+    fn rgba_alphas_have_the_same_floor() {
+        assert_eq!(rgba_alpha(255), 1.0);
+        assert_eq!(rgba_alpha(128), 128.0 / 255.0);
+        assert_eq!(rgba_alpha(0), MIN_COLOR_ALPHA);
+    }
+
+    #[test]
+    fn names_of_only_stripped_characters_are_removed() {
+        let (mut params, _) =
+            RenderParams::from_query("player_name=%E2%80%AEnameless%0A%20tee&clan_name=%0A%09")
+                .unwrap();
+        params.sanitize_names();
+        assert_eq!(
+            params.player_name.as_ref().map(|name| name.as_str()),
+            Some("nameless tee")
+        );
+        assert!(params.clan_name.is_none());
+        assert!(params.nameplate());
+    }
+
+    #[test]
+    fn whole_tiles_are_their_center() {
+        assert_eq!(tile_pos(0.0), 0.5);
+        assert_eq!(tile_pos(12.0), 12.5);
+        assert_eq!(tile_pos(12.25), 12.75);
+        assert_eq!(tile_pos(-1.0), -0.5);
+    }
+
+    #[test]
+    fn tile_coordinates_become_map_positions() {
+        let (params, _) =
+            RenderParams::from_query("tile_x=10&tile_y=4.5&tee_tile_x=2&tee_y=7").unwrap();
+        assert_eq!((params.x, params.y), (Some(10.5), Some(5.0)));
+        assert_eq!((params.tee_x, params.tee_y), (Some(2.5), Some(7.0)));
+        assert!(params.hook_attached.is_none());
+    }
+
+    #[test]
+    fn hook_tiles_attach_the_hook() {
+        let (params, _) = RenderParams::from_query("hook_tile_x=3&hook_tile_y=1").unwrap();
+        assert_eq!((params.hook_x, params.hook_y), (Some(3.5), Some(1.5)));
+        assert_eq!(params.hook_attached, Some(true));
+
+        let mut query = pairs(&[("hook_tile_x", "3"), ("hook_attached", "false")]);
+        let err = apply_tile_coordinates(&mut query).unwrap_err();
+        assert_eq!(err.field, "hook_attached");
+    }
+
+    #[test]
+    fn a_tile_coordinate_excludes_the_map_position() {
+        for (pos_field, tile_field) in TILE_COORDINATES {
+            let mut query = pairs(&[(pos_field, "1"), (tile_field, "1")]);
+            let err = apply_tile_coordinates(&mut query).unwrap_err();
+            assert_eq!(err.field, tile_field);
+        }
+        let err = RenderParams::from_query("x=1&tile_x=2").unwrap_err();
+        assert!(matches!(err, RenderError::InvalidParam(_)), "{err}");
+    }
+
+    #[test]
+    fn tile_coordinates_must_be_numbers() {
+        let mut query = pairs(&[("tile_y", "top")]);
+        let err = apply_tile_coordinates(&mut query).unwrap_err();
+        assert_eq!((err.field, err.value.as_str()), ("tile_y", "top"));
+    }
+
+    #[test]
+    fn nameplates_stop_shrinking_at_extreme_zooms() {
+        assert_eq!(nameplate_zoom(0.01, 1.0), MIN_NAMEPLATE_ZOOM);
+        assert_eq!(nameplate_zoom(0.01, 1.0), nameplate_zoom(0.3, 1.0));
+        assert_eq!(nameplate_zoom(100.0, 1.0), 100.0);
+    }
+
+    #[test]
+    fn small_nameplate_sizes_stay_small_at_extreme_zooms() {
+        let smallest = NAMEPLATE_SIZE_RANGE.min;
+        for zoom in [0.001, 0.3, 1.0, 50.0] {
+            let default = nameplate_zoom(zoom, 1.0);
+            assert!((nameplate_zoom(zoom, smallest) - default * smallest).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn marks_are_above_the_top_line() {
+        let pos = vec2::new(3.0, 4.0);
+        let camera_zoom = nameplate_zoom(1.0, 1.0);
+        let clan = clan_line_pos(pos, camera_zoom);
+        let without_clan = nameplate_marks_pos(pos, camera_zoom, false);
+        let with_clan = nameplate_marks_pos(pos, camera_zoom, true);
+        assert!(without_clan.y < pos.y);
+        assert!(with_clan.y < clan.y);
+        assert_eq!((without_clan.x, with_clan.x), (pos.x, pos.x));
+    }
+
+    #[test]
+    fn the_clan_line_is_spaced_by_the_camera_zoom() {
+        let pos = vec2::new(3.0, 4.0);
+        let near = clan_line_pos(pos, nameplate_zoom(0.5, 1.0));
+        let far = clan_line_pos(pos, nameplate_zoom(2.0, 1.0));
+        assert_eq!((near.x, far.x), (pos.x, pos.x));
+        assert!(near.y < pos.y);
+        assert!((pos.y - far.y - (pos.y - near.y) * 4.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,134 @@
+use math::math::vector::vec2;
+
+/// Game ticks per second
+pub const TICKS_PER_SECOND: f32 = 50.0;
+/// Size of a tile in the game's pixel units
+pub const TILE_SIZE: f32 = 32.0;
+/// Upper bound of simulated steps, whatever the lifetime
+pub const MAX_STEPS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projectile {
+    Gun,
+    Shotgun,
+    Grenade,
+    Laser,
+}
+
+pub const PROJECTILES: [Projectile; 4] = [
+    Projectile::Gun,
+    Projectile::Shotgun,
+    Projectile::Grenade,
+    Projectile::Laser,
+];
+
+pub fn projectile_name(projectile: Projectile) -> &'static str {
+    match projectile {
+        Projectile::Gun => "gun",
+        Projectile::Shotgun => "shotgun",
+        Projectile::Grenade => "grenade",
+        Projectile::Laser => "laser",
+    }
+}
+
+/// The tuning values of a projectile like in the upstream game,
+/// in the game's pixel units.
+#[derive(Debug, Clone, Copy)]
+pub struct Tuning {
+    pub curvature: f32,
+    /// Pixels per second
+    pub speed: f32,
+    /// Seconds
+    pub lifetime: f32,
+}
+
+impl Projectile {
+    /// The lasers have no tuning, they fly straight, see [`LASER_REACH`].
+    pub fn tuning(self) -> Option<Tuning> {
+        match self {
+            Projectile::Gun => Some(Tuning {
+                curvature: 1.25,
+                speed: 2200.0,
+                lifetime: 2.0,
+            }),
+            Projectile::Shotgun => Some(Tuning {
+                curvature: 1.25,
+                speed: 2750.0,
+                lifetime: 0.2,
+            }),
+            Projectile::Grenade => Some(Tuning {
+                curvature: 7.0,
+                speed: 1000.0,
+                lifetime: 2.0,
+            }),
+            Projectile::Laser => None,
+        }
+    }
+}
+
+/// Laser reach in pixels
+pub const LASER_REACH: f32 = 800.0;
+
+/// The position after `time` seconds, like `CalcPos` of the game.
+pub fn position(dir: vec2, tuning: &Tuning, time: f32) -> vec2 {
+    let time = time * tuning.speed;
+    vec2::new(
+        dir.x * time,
+        dir.y * time + tuning.curvature / 10000.0 * (time * time),
+    )
+}
+
+/// Samples the path once per tick, until `hits` reports a wall
+/// or the lifetime ends. `speed` scales the tuned speed.
+/// Returns the points in tiles relative to the shooter (y points down)
+/// and whether a wall was hit.
+pub fn simulate(
+    projectile: Projectile,
+    dir: vec2,
+    speed: f32,
+    hits: impl Fn(vec2) -> bool,
+) -> (Vec<vec2>, bool) {
+    let Some(mut tuning) = projectile.tuning() else {
+        // laser: straight line, sampled every tile
+        let steps = ((LASER_REACH / TILE_SIZE) as usize).min(MAX_STEPS);
+        let mut points = Vec::with_capacity(steps + 1);
+        for step in 0..=steps {
+            let pos = vec2::new(dir.x * step as f32, dir.y * step as f32);
+            points.push(pos);
+            if hits(pos) {
+                return (points, true);
+            }
+        }
+        return (points, false);
+    };
+    tuning.speed *= speed;
+
+    let steps = ((tuning.lifetime * TICKS_PER_SECOND) as usize).min(MAX_STEPS);
+    let mut points = Vec::with_capacity(steps + 1);
+    for tick in 0..=steps {
+        let pos = position(dir, &tuning, tick as f32 / TICKS_PER_SECOND);
+        let pos = vec2::new(pos.x / TILE_SIZE, pos.y / TILE_SIZE);
+        points.push(pos);
+        if hits(pos) {
+            return (points, true);
+        }
+    }
+    (points, false)
+}
+
+/// The width of the visible world in tiles, like `calc_screen_params` of the game.
+pub fn visible_width(aspect: f32, zoom: f32) -> f32 {
+    const AMOUNT: f32 = 1150.0 * 1000.0;
+    const MAX_WIDTH: f32 = 1500.0;
+    const MAX_HEIGHT: f32 = 1050.0;
+    let mut width = (AMOUNT * aspect).sqrt();
+    let mut height = width / aspect;
+    if width > MAX_WIDTH {
+        width = MAX_WIDTH;
+        height = width / aspect;
+    }
+    if height > MAX_HEIGHT {
+        width = MAX_HEIGHT * aspect;
+    }
+    width * zoom / TILE_SIZE
+}
@@ -0,0 +1,508 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::Ordering, LazyLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{async_trait, http::StatusCode};
+use game_interface::types::network_string::NetworkString;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use urlencoding::encode;
+
+use crate::{error::RenderError, metrics, params::parse_provider, upstream};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Skin {
+    #[serde(rename = "skin_name")]
+    pub name: NetworkString<24>,
+    #[serde(alias = "skin_color_body", alias = "body_color")]
+    pub color_body: Option<i32>,
+    #[serde(alias = "skin_color_feet", alias = "feet_color")]
+    pub color_feet: Option<i32>,
+}
+
+/// - `PLAYER_API_CACHE_TTL_SECS`: how long a player's skin is reused
+///   before the player api is asked again (600 is default)
+/// - `PLAYER_API_NEGATIVE_TTL_SECS`: same for players that were not found (60 is default)
+static TTL: LazyLock<(Duration, Duration)> = LazyLock::new(|| {
+    let ttl: u64 = std::env::var("PLAYER_API_CACHE_TTL_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(600);
+    let negative_ttl: u64 = std::env::var("PLAYER_API_NEGATIVE_TTL_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(60);
+    (Duration::from_secs(ttl), Duration::from_secs(negative_ttl))
+});
+
+/// A cached lookup, `Err` if no provider knew the player.
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    at: Instant,
+    skin: Result<Skin, String>,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self, now: Instant) -> bool {
+        let (ttl, negative_ttl) = *TTL;
+        let ttl = if self.skin.is_ok() { ttl } else { negative_ttl };
+        now.saturating_duration_since(self.at) < ttl
+    }
+}
+
+static CACHE: LazyLock<parking_lot::Mutex<HashMap<String, CacheEntry>>> =
+    LazyLock::new(Default::default);
+
+fn normalize(player_name: &str) -> String {
+    player_name.trim().to_lowercase()
+}
+
+/// A player api that knows the current skin of players.
+#[async_trait]
+pub trait PlayerSkinProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// `Ok(None)` if the player is unknown.
+    async fn resolve(&self, player_name: &str) -> Result<Option<Skin>, RenderError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Ddstats,
+    SkinsTw,
+}
+
+pub const PROVIDERS: [Provider; 2] = [Provider::Ddstats, Provider::SkinsTw];
+
+pub fn provider_name(provider: Provider) -> &'static str {
+    match provider {
+        Provider::Ddstats => "ddstats",
+        Provider::SkinsTw => "skins_tw",
+    }
+}
+
+/// - `PLAYER_API_PROVIDERS` comma separated providers in the order they are asked,
+///   the next one is asked if a provider fails or doesn't know the player
+///   (`ddstats,skins_tw` is default)
+/// - `PLAYER_API_DDSTATS_TIMEOUT_SECS`, `PLAYER_API_SKINS_TW_TIMEOUT_SECS` (5 is default)
+static PROVIDER_ORDER: LazyLock<Vec<Provider>> = LazyLock::new(|| {
+    let mut order: Vec<Provider> = Vec::new();
+    for provider in std::env::var("PLAYER_API_PROVIDERS")
+        .unwrap_or_else(|_| "ddstats,skins_tw".to_string())
+        .split(',')
+    {
+        match parse_provider(provider) {
+            Some(provider) if !order.contains(&provider) => order.push(provider),
+            Some(_) => {}
+            None => println!("unknown player api provider {provider}"),
+        }
+    }
+    if order.is_empty() {
+        order.push(Provider::Ddstats);
+    }
+    order
+});
+
+/// The provider that is asked first without `provider` parameter.
+pub fn default_provider() -> Provider {
+    PROVIDER_ORDER[0]
+}
+
+fn timeout(var: &str) -> Duration {
+    let secs: u64 = std::env::var(var)
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(5);
+    Duration::from_secs(secs)
+}
+
+/// Fetches `url`, `Ok(None)` if the player api doesn't know the player.
+async fn fetch_json(
+    provider: &'static str,
+    url: &str,
+    timeout: Duration,
+) -> Result<Option<String>, RenderError> {
+    let fetch = async {
+        let res = upstream::get(url).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        anyhow::Ok(Some(res.error_for_status()?.text().await?))
+    };
+    tokio::time::timeout(timeout, fetch)
+        .await
+        .map_err(|_| RenderError::Upstream(format!("{provider} timed out")))?
+        .map_err(|err| RenderError::Upstream(format!("{provider} failed: {err}")))
+}
+
+/// The profiles of ddstats.tw.
+struct Ddstats {
+    /// The url encoded player name is appended
+    url: String,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl PlayerSkinProvider for Ddstats {
+    fn name(&self) -> &'static str {
+        provider_name(Provider::Ddstats)
+    }
+
+    async fn resolve(&self, player_name: &str) -> Result<Option<Skin>, RenderError> {
+        let url = format!("{}{}", self.url, encode(player_name));
+        let Some(text) = fetch_json(self.name(), &url, self.timeout).await? else {
+            return Ok(None);
+        };
+        parse_skin(self.name(), &text)
+    }
+}
+
+/// The player api of skins.tw.
+struct SkinsTw {
+    /// The url encoded player name is appended
+    url: String,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl PlayerSkinProvider for SkinsTw {
+    fn name(&self) -> &'static str {
+        provider_name(Provider::SkinsTw)
+    }
+
+    async fn resolve(&self, player_name: &str) -> Result<Option<Skin>, RenderError> {
+        let url = format!("{}{}", self.url, encode(player_name));
+        let Some(text) = fetch_json(self.name(), &url, self.timeout).await? else {
+            return Ok(None);
+        };
+        parse_skin(self.name(), &text)
+    }
+}
+
+/// Objects the player may be nested in.
+const PLAYER_KEYS: [&str; 2] = ["profile", "player"];
+/// Keys of the skin name in a player, the skin may be an object of its own instead.
+const PLAYER_SKIN_KEYS: [&str; 2] = ["skin_name", "skin"];
+/// Keys of the skin name in a skin object.
+const SKIN_NAME_KEYS: [&str; 2] = ["name", "skin_name"];
+const BODY_COLOR_KEYS: [&str; 3] = ["color_body", "skin_color_body", "body_color"];
+const FEET_COLOR_KEYS: [&str; 3] = ["color_feet", "skin_color_feet", "feet_color"];
+
+/// Finds the skin in a player api response, the player may be nested under
+/// `profile` or `player` and the skin may be an object of its own.
+/// Unknown fields are ignored, `Ok(None)` if there is no skin.
+fn parse_skin(provider: &'static str, text: &str) -> Result<Option<Skin>, RenderError> {
+    let invalid = |err: String| RenderError::PlayerApiInvalid(format!("{provider}: {err}"));
+    let root: Value = serde_json::from_str(text).map_err(|err| invalid(err.to_string()))?;
+    let players = std::iter::once(&root).chain(PLAYER_KEYS.iter().filter_map(|key| root.get(key)));
+    for player in players {
+        let (skin, name_keys) = match player.get("skin") {
+            Some(skin) if skin.is_object() => (skin, SKIN_NAME_KEYS),
+            _ => (player, PLAYER_SKIN_KEYS),
+        };
+        let Some(name) = name_keys
+            .iter()
+            .find_map(|key| skin.get(key)?.as_str())
+            .filter(|name| !name.is_empty())
+        else {
+            continue;
+        };
+        let name =
+            NetworkString::new(name).map_err(|err| invalid(format!("skin {name}: {err}")))?;
+        return Ok(Some(Skin {
+            name,
+            color_body: color(skin, &BODY_COLOR_KEYS).map_err(invalid)?,
+            color_feet: color(skin, &FEET_COLOR_KEYS).map_err(invalid)?,
+        }));
+    }
+    Ok(None)
+}
+
+/// A legacy color as number or string of a number, also unsigned.
+/// Missing colors are the skin's original colors.
+fn color(skin: &Value, keys: &[&str]) -> Result<Option<i32>, String> {
+    let Some((key, value)) = keys
+        .iter()
+        .find_map(|key| Some((key, skin.get(key)?)))
+        .filter(|(_, value)| !value.is_null())
+    else {
+        return Ok(None);
+    };
+    let color = match value {
+        Value::Number(color) => color.as_i64(),
+        Value::String(color) => color.trim().parse::<i64>().ok(),
+        _ => None,
+    };
+    color
+        .filter(|color| (i32::MIN as i64..=u32::MAX as i64).contains(color))
+        .map(|color| Some(color as u32 as i32))
+        .ok_or_else(|| format!("{key} {value} is not a color"))
+}
+
+/// `PLAYER_API_DDSTATS_URL`, `PLAYER_API_SKINS_TW_URL` the urls the player name is appended to,
+/// e.g. for a mirror
+static DDSTATS: LazyLock<Ddstats> = LazyLock::new(|| Ddstats {
+    url: std::env::var("PLAYER_API_DDSTATS_URL")
+        .unwrap_or_else(|_| "https://ddstats.tw/profile/json?player=".to_string()),
+    timeout: timeout("PLAYER_API_DDSTATS_TIMEOUT_SECS"),
+});
+static SKINS_TW: LazyLock<SkinsTw> = LazyLock::new(|| SkinsTw {
+    url: std::env::var("PLAYER_API_SKINS_TW_URL")
+        .unwrap_or_else(|_| "https://skins.tw/api/player/".to_string()),
+    timeout: timeout("PLAYER_API_SKINS_TW_TIMEOUT_SECS"),
+});
+
+fn provider(provider: Provider) -> &'static dyn PlayerSkinProvider {
+    match provider {
+        Provider::Ddstats => &*DDSTATS,
+        Provider::SkinsTw => &*SKINS_TW,
+    }
+}
+
+/// The providers in the order they are asked, `preferred` first.
+fn chain(preferred: Option<Provider>) -> Vec<Provider> {
+    preferred
+        .into_iter()
+        .chain(PROVIDER_ORDER.iter().copied())
+        .fold(Vec::new(), |mut chain, provider| {
+            if !chain.contains(&provider) {
+                chain.push(provider);
+            }
+            chain
+        })
+}
+
+/// Resolves the current skin of a player, asking the providers in order
+/// until one knows the player. Results are cached per player name and provider.
+pub async fn lookup(player_name: &str, preferred: Option<Provider>) -> Result<Skin, RenderError> {
+    let chain = chain(preferred);
+    let key = format!("{}:{}", provider_name(chain[0]), normalize(player_name));
+    if let Some(entry) = CACHE.lock().get(&key) {
+        if entry.is_fresh(Instant::now()) {
+            return entry.skin.clone().map_err(RenderError::Upstream);
+        }
+    }
+
+    let start = Instant::now();
+    let mut errors: Vec<RenderError> = Vec::new();
+    let mut skin = None;
+    for kind in chain {
+        match provider(kind).resolve(player_name).await {
+            Ok(Some(found)) => {
+                skin = Some(found);
+                break;
+            }
+            Ok(None) => {}
+            Err(err) => {
+                metrics::PLAYER_API_ERRORS.fetch_add(1, Ordering::Relaxed);
+                errors.push(err);
+            }
+        }
+    }
+    metrics::PLAYER_API_DURATION.observe(start.elapsed());
+
+    // failures are not cached, the next request may succeed
+    let skin = match skin {
+        Some(skin) => Ok(skin),
+        None if errors.is_empty() => Err(format!("player {player_name} not found")),
+        None => {
+            let invalid = errors
+                .iter()
+                .all(|err| matches!(err, RenderError::PlayerApiInvalid(_)));
+            let errors = errors
+                .iter()
+                .map(|err| match err {
+                    RenderError::PlayerApiInvalid(err) => err.clone(),
+                    err => err.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            // parse failures stay distinguishable from unreachable providers
+            return Err(if invalid {
+                RenderError::PlayerApiInvalid(errors)
+            } else {
+                RenderError::Upstream(errors)
+            });
+        }
+    };
+
+    let now = Instant::now();
+    let mut cache = CACHE.lock();
+    cache.retain(|_, entry| entry.is_fresh(now));
+    cache.insert(
+        key,
+        CacheEntry {
+            at: now,
+            skin: skin.clone(),
+        },
+    );
+    skin.map_err(RenderError::Upstream)
+}
+
+/// Removes a player from the cache of every provider, returns whether it was cached.
+pub fn flush(player_name: &str) -> bool {
+    let name = normalize(player_name);
+    let mut cache = CACHE.lock();
+    let len = cache.len();
+    cache.retain(|key, _| key.split_once(':').map(|(_, cached)| cached) != Some(name.as_str()));
+    cache.len() < len
+}
+
+/// Empties the cache, returns how many entries it had.
+pub fn flush_all() -> usize {
+    let mut cache = CACHE.lock();
+    let len = cache.len();
+    cache.clear();
+    len
+}
+
+/// The number of cached lookups, including expired ones.
+pub fn cache_len() -> usize {
+    CACHE.lock().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skin(json: &str) -> Skin {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn entries_expire_after_their_ttl() {
+        let (ttl, negative_ttl) = *TTL;
+        let at = Instant::now();
+        let found = CacheEntry {
+            at,
+            skin: Ok(skin(r#"{"skin_name": "default"}"#)),
+        };
+        assert!(found.is_fresh(at));
+        assert!(found.is_fresh(at + ttl - Duration::from_millis(1)));
+        assert!(!found.is_fresh(at + ttl));
+
+        let not_found = CacheEntry {
+            at,
+            skin: Err("player nameless tee not found".to_string()),
+        };
+        assert!(not_found.is_fresh(at + negative_ttl - Duration::from_millis(1)));
+        assert!(!not_found.is_fresh(at + negative_ttl));
+    }
+
+    #[test]
+    fn flushing_removes_the_player_of_every_provider() {
+        let cached = CacheEntry {
+            at: Instant::now(),
+            skin: Err("not found".to_string()),
+        };
+        CACHE.lock().extend([
+            ("ddstats:flushed tee".to_string(), cached.clone()),
+            ("skins_tw:flushed tee".to_string(), cached.clone()),
+            ("ddstats:kept tee".to_string(), cached),
+        ]);
+        assert!(flush(" Flushed Tee"));
+        assert!(!flush("flushed tee"));
+        let cache = CACHE.lock();
+        assert!(!cache.contains_key("skins_tw:flushed tee"));
+        assert!(cache.contains_key("ddstats:kept tee"));
+    }
+
+    #[test]
+    fn colors_are_read_by_every_alias() {
+        for json in [
+            r#"{"skin_name": "pinky", "color_body": 1, "color_feet": 2}"#,
+            r#"{"skin_name": "pinky", "skin_color_body": 1, "skin_color_feet": 2}"#,
+            r#"{"skin_name": "pinky", "body_color": 1, "feet_color": 2}"#,
+        ] {
+            let skin = skin(json);
+            assert_eq!(skin.name.as_str(), "pinky", "{json}");
+            assert_eq!(
+                (skin.color_body, skin.color_feet),
+                (Some(1), Some(2)),
+                "{json}"
+            );
+        }
+        let skin = skin(r#"{"skin_name": "pinky"}"#);
+        assert_eq!((skin.color_body, skin.color_feet), (None, None));
+    }
+
+    #[test]
+    fn nested_skins_are_found() {
+        for json in [
+            r#"{"skin_name": "pinky", "skin_color_body": "1"}"#,
+            r#"{"profile": {"skin": "pinky", "skin_color_body": 1}}"#,
+            r#"{"player": {"skin": {"name": "pinky", "color_body": 1}}}"#,
+        ] {
+            let skin = parse_skin("test", json).unwrap().unwrap();
+            assert_eq!(skin.name.as_str(), "pinky", "{json}");
+            assert_eq!(skin.color_body, Some(1), "{json}");
+        }
+        assert!(parse_skin("test", r#"{"profile": {"points": 1}}"#)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn unsigned_colors_wrap_around() {
+        let skin = parse_skin(
+            "test",
+            r#"{"skin_name": "pinky", "color_body": 4294967295}"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(skin.color_body, Some(-1));
+    }
+
+    #[test]
+    fn invalid_responses_are_player_api_errors() {
+        for json in [
+            "<html>maintenance</html>",
+            r#"{"skin_name": "pinky", "color_body": "blue"}"#,
+            r#"{"skin_name": "pinky", "color_body": 4294967296}"#,
+        ] {
+            let err = parse_skin("test", json).unwrap_err();
+            assert!(matches!(err, RenderError::PlayerApiInvalid(_)), "{json}");
+        }
+    }
+
+    fn fixture(name: &str) -> Result<Option<Skin>, RenderError> {
+        let path = format!(
+            "{}/tests/fixtures/player_api/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        parse_skin("test", &std::fs::read_to_string(path).unwrap())
+    }
+
+    #[test]
+    fn recorded_responses_are_parsed() {
+        let skin = fixture("ddstats.json").unwrap().unwrap();
+        assert_eq!(skin.name.as_str(), "pinky");
+        assert_eq!(
+            (skin.color_body, skin.color_feet),
+            (Some(2817920), Some(10076256))
+        );
+
+        let skin = fixture("skins_tw.json").unwrap().unwrap();
+        assert_eq!(skin.name.as_str(), "santa_pinky");
+        assert_eq!((skin.color_body, skin.color_feet), (Some(-65536), None));
+
+        let skin = fixture("profile.json").unwrap().unwrap();
+        assert_eq!(skin.name.as_str(), "default");
+        assert_eq!((skin.color_body, skin.color_feet), (Some(65408), None));
+    }
+
+    #[test]
+    fn a_player_without_skin_is_not_found() {
+        assert!(fixture("no_skin.json").unwrap().is_none());
+    }
+
+    #[test]
+    fn an_error_page_is_an_invalid_response() {
+        let err = fixture("maintenance.html").unwrap_err();
+        assert!(matches!(err, RenderError::PlayerApiInvalid(_)), "{err}");
+        assert!(err.to_string().contains("test"), "{err}");
+    }
+}
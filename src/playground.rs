@@ -0,0 +1,22 @@
+use std::sync::LazyLock;
+
+use anyhow::anyhow;
+use axum::response::Html;
+
+/// `PLAYGROUND_ENABLED` serves the playground at `GET /playground` (true is default)
+static PLAYGROUND_ENABLED: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("PLAYGROUND_ENABLED")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true)
+});
+
+pub fn enabled() -> bool {
+    *PLAYGROUND_ENABLED
+}
+
+/// `GET /playground`, a form for the render parameters with a live preview
+/// and the resolved parameters of `/resolve`. It has no external assets.
+pub async fn playground() -> Html<&'static str> {
+    Html(include_str!("playground.html"))
+}
@@ -0,0 +1,147 @@
+use std::{sync::LazyLock, time::Duration};
+
+use anyhow::anyhow;
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Migrations by schema version, applied in order.
+/// The schema version is sqlite's `user_version`.
+const MIGRATIONS: &[&str] = &["CREATE TABLE user_prefs (
+    user_id INTEGER PRIMARY KEY,
+    player_name TEXT,
+    skin_name TEXT,
+    body_color TEXT,
+    feet_color TEXT,
+    eyes TEXT,
+    map_name TEXT
+)"];
+
+/// Preferences of discord users.
+/// `PREFS_DB` path of the sqlite database (`prefs.sqlite` is default),
+/// preferences are disabled if it can't be opened.
+static DB: LazyLock<Option<parking_lot::Mutex<Connection>>> = LazyLock::new(|| {
+    let path = std::env::var("PREFS_DB").unwrap_or_else(|_| "prefs.sqlite".to_string());
+    match open(&path) {
+        Ok(conn) => Some(parking_lot::Mutex::new(conn)),
+        Err(err) => {
+            println!("user preferences are disabled, opening {path} failed: {err}");
+            None
+        }
+    }
+});
+
+fn open(path: &str) -> rusqlite::Result<Connection> {
+    let mut conn = Connection::open(path)?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    migrate(&mut conn)?;
+    Ok(conn)
+}
+
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+    let tx = conn.transaction()?;
+    for migration in &MIGRATIONS[version..] {
+        tx.execute_batch(migration)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()
+}
+
+/// What a discord user renders with, unless the command says otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Prefs {
+    /// The linked player, rendered by `/skin` without `player_name`
+    pub player_name: Option<String>,
+    pub skin_name: Option<String>,
+    pub body_color: Option<String>,
+    pub feet_color: Option<String>,
+    pub eyes: Option<String>,
+    pub map_name: Option<String>,
+}
+
+impl Prefs {
+    /// The render parameters, the player and skin name are handled by the caller.
+    pub fn pairs(&self) -> Vec<(String, String)> {
+        [
+            ("body_color", &self.body_color),
+            ("feet_color", &self.feet_color),
+            ("eyes", &self.eyes),
+            ("map_name", &self.map_name),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.as_ref().map(|v| (k.to_string(), v.clone())))
+        .collect()
+    }
+}
+
+/// Runs `f` on the database without blocking the runtime.
+async fn with_db<T: Send + 'static>(
+    f: impl FnOnce(&Connection) -> rusqlite::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    tokio::task::spawn_blocking(move || {
+        let db = DB
+            .as_ref()
+            .ok_or_else(|| anyhow!("user preferences are disabled"))?;
+        let conn = db.lock();
+        f(&conn).map_err(|err| anyhow!(err))
+    })
+    .await?
+}
+
+pub async fn get(user_id: u64) -> anyhow::Result<Option<Prefs>> {
+    with_db(move |conn| {
+        conn.query_row(
+            "SELECT player_name, skin_name, body_color, feet_color, eyes, map_name
+            FROM user_prefs WHERE user_id = ?1",
+            params![user_id as i64],
+            |row| {
+                Ok(Prefs {
+                    player_name: row.get(0)?,
+                    skin_name: row.get(1)?,
+                    body_color: row.get(2)?,
+                    feet_color: row.get(3)?,
+                    eyes: row.get(4)?,
+                    map_name: row.get(5)?,
+                })
+            },
+        )
+        .optional()
+    })
+    .await
+}
+
+/// Replaces all preferences of the user.
+pub async fn set(user_id: u64, prefs: Prefs) -> anyhow::Result<()> {
+    with_db(move |conn| {
+        conn.execute(
+            "INSERT OR REPLACE INTO user_prefs
+            (user_id, player_name, skin_name, body_color, feet_color, eyes, map_name)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                user_id as i64,
+                prefs.player_name,
+                prefs.skin_name,
+                prefs.body_color,
+                prefs.feet_color,
+                prefs.eyes,
+                prefs.map_name,
+            ],
+        )
+        .map(|_| ())
+    })
+    .await
+}
+
+/// Whether the user had preferences.
+pub async fn remove(user_id: u64) -> anyhow::Result<bool> {
+    with_db(move |conn| {
+        conn.execute(
+            "DELETE FROM user_prefs WHERE user_id = ?1",
+            params![user_id as i64],
+        )
+        .map(|removed| removed > 0)
+    })
+    .await
+}
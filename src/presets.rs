@@ -0,0 +1,119 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use axum::{response::IntoResponse, Json};
+
+use crate::params::{parse_preset, preset_name, ParamError, PRESETS};
+
+/// Named bundles of parameters, explicit parameters override them.
+/// `PRESETS_FILE` json file of presets, e.g. `{"discord": {"zoom": 0.25}}`,
+/// that replace the built-in ones (`presets.json` is default)
+pub static PARAM_PRESETS: LazyLock<BTreeMap<String, Vec<(String, String)>>> = LazyLock::new(|| {
+    let mut presets = builtin();
+    let path = std::env::var("PRESETS_FILE").unwrap_or_else(|_| "presets.json".to_string());
+    match std::fs::read_to_string(&path) {
+        Ok(file) => match parse(&file) {
+            Ok(file_presets) => presets.extend(file_presets),
+            Err(err) => println!("presets file {path} is invalid: {err}"),
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => println!("presets file {path} can't be read: {err}"),
+    }
+    presets
+});
+
+/// The preset the discord bot renders with.
+pub const DISCORD_PRESET: &str = "discord";
+
+fn builtin() -> BTreeMap<String, Vec<(String, String)>> {
+    let discord = [
+        ("zoom", "0.25"),
+        ("x", "17.0"),
+        ("y", "25.5"),
+        ("weapon", "hammer"),
+        ("emoticon", "hearts"),
+        ("eyes", "happy"),
+    ];
+    BTreeMap::from([(
+        DISCORD_PRESET.to_string(),
+        discord
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    )])
+}
+
+fn parse(file: &str) -> anyhow::Result<BTreeMap<String, Vec<(String, String)>>> {
+    let presets: BTreeMap<String, BTreeMap<String, serde_json::Value>> =
+        serde_json::from_str(file)?;
+    presets
+        .into_iter()
+        .map(|(name, params)| {
+            let params = params
+                .into_iter()
+                .map(|(k, v)| match v {
+                    serde_json::Value::String(v) => Ok((k, v)),
+                    v @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_)) => {
+                        Ok((k, v.to_string()))
+                    }
+                    v => Err(anyhow::anyhow!("{name}.{k}: {v} is not a parameter value")),
+                })
+                .collect::<anyhow::Result<_>>()?;
+            Ok((name, params))
+        })
+        .collect()
+}
+
+/// Replaces the `preset` parameter by the parameters of its preset,
+/// unless they were given explicitly. Built-in render presets like `avatar` are kept.
+pub fn expand(pairs: &mut Vec<(String, String)>) -> Result<(), ParamError> {
+    let Some(index) = pairs.iter().position(|(k, _)| k == "preset") else {
+        return Ok(());
+    };
+    let name = pairs[index].1.trim().to_string();
+    if parse_preset(&name).is_some() {
+        return Ok(());
+    }
+    let Some(params) = PARAM_PRESETS.get(&name) else {
+        return Err(ParamError {
+            field: "preset",
+            value: name,
+            reason: "unknown preset".to_string(),
+            accepted: available(),
+        });
+    };
+    pairs.remove(index);
+    for (k, v) in params {
+        if !pairs.iter().any(|(key, _)| key == k) {
+            pairs.push((k.clone(), v.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn available() -> Vec<&'static str> {
+    PRESETS
+        .into_iter()
+        .map(preset_name)
+        .chain(PARAM_PRESETS.keys().map(|name| name.as_str()))
+        .collect()
+}
+
+/// Lists the presets with their parameters.
+pub async fn presets() -> impl IntoResponse {
+    let presets: BTreeMap<&str, BTreeMap<&str, &str>> = PARAM_PRESETS
+        .iter()
+        .map(|(name, params)| {
+            (
+                name.as_str(),
+                params
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str()))
+                    .collect(),
+            )
+        })
+        .collect();
+    Json(serde_json::json!({
+        "render_presets": PRESETS.into_iter().map(preset_name).collect::<Vec<_>>(),
+        "presets": presets,
+    }))
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use crate::frame::Frame;
+
+/// Colors of an indexed png.
+pub const MAX_COLORS: usize = 256;
+
+/// A frame reduced to a palette, every pixel is an index into it.
+pub struct Indexed {
+    pub palette: Vec<[u8; 4]>,
+    pub indices: Vec<u8>,
+}
+
+impl Indexed {
+    /// The palette as png `PLTE` and `tRNS` chunks, `tRNS` is empty if everything is opaque.
+    pub fn chunks(&self) -> (Vec<u8>, Vec<u8>) {
+        let plte = self
+            .palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect();
+        let trns = if self.palette.iter().all(|c| c[3] == 255) {
+            Vec::new()
+        } else {
+            self.palette.iter().map(|c| c[3]).collect()
+        };
+        (plte, trns)
+    }
+}
+
+/// A box of the median cut, a range of `colors`.
+struct ColorBox {
+    start: usize,
+    end: usize,
+}
+
+impl ColorBox {
+    /// The channel with the widest range and that range.
+    fn widest(&self, colors: &[([u8; 4], u32)]) -> (usize, u8) {
+        (0..4)
+            .map(|c| {
+                let (min, max) = colors[self.start..self.end]
+                    .iter()
+                    .fold((255, 0), |(min, max), (color, _)| {
+                        (color[c].min(min), color[c].max(max))
+                    });
+                (c, max - min)
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap_or((0, 0))
+    }
+
+    /// The count weighted average color.
+    fn average(&self, colors: &[([u8; 4], u32)]) -> [u8; 4] {
+        let mut sum = [0u64; 4];
+        let mut count = 0u64;
+        for (color, n) in &colors[self.start..self.end] {
+            for c in 0..4 {
+                sum[c] += color[c] as u64 * *n as u64;
+            }
+            count += *n as u64;
+        }
+        sum.map(|s| (s as f64 / count.max(1) as f64).round() as u8)
+    }
+}
+
+/// Median cut of the distinct colors into at most [`MAX_COLORS`] colors.
+fn palette(frame: &Frame) -> Vec<[u8; 4]> {
+    let mut counts: HashMap<[u8; 4], u32> = HashMap::new();
+    for p in frame.pixels.chunks_exact(4) {
+        // fully transparent pixels are all the same
+        let color = if p[3] == 0 {
+            [0; 4]
+        } else {
+            [p[0], p[1], p[2], p[3]]
+        };
+        *counts.entry(color).or_default() += 1;
+    }
+    let mut colors: Vec<([u8; 4], u32)> = counts.into_iter().collect();
+    if colors.len() <= MAX_COLORS {
+        return colors.into_iter().map(|(color, _)| color).collect();
+    }
+
+    let mut boxes = vec![ColorBox {
+        start: 0,
+        end: colors.len(),
+    }];
+    while boxes.len() < MAX_COLORS {
+        // the box with the widest channel is split at the median pixel
+        let Some((index, channel)) = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.end - b.start > 1)
+            .map(|(index, b)| (index, b.widest(&colors)))
+            .max_by_key(|(_, (_, range))| *range)
+            .map(|(index, (channel, _))| (index, channel))
+        else {
+            break;
+        };
+        let ColorBox { start, end } = boxes.swap_remove(index);
+        colors[start..end].sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = colors[start..end].iter().map(|(_, n)| *n as u64).sum();
+        let mut seen = 0;
+        let mut median = start + 1;
+        for (i, (_, n)) in colors[start..end - 1].iter().enumerate() {
+            seen += *n as u64;
+            median = start + i + 1;
+            if seen * 2 >= total {
+                break;
+            }
+        }
+        boxes.push(ColorBox { start, end: median });
+        boxes.push(ColorBox { start: median, end });
+    }
+    boxes.iter().map(|b| b.average(&colors)).collect()
+}
+
+fn nearest(palette: &[[u8; 4]], color: [f32; 4]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .map(|(index, p)| {
+            let distance: f32 = (0..4)
+                .map(|c| {
+                    let d = p[c] as f32 - color[c];
+                    d * d
+                })
+                .sum();
+            (index, distance)
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(index, _)| index)
+}
+
+/// Reduces the frame to at most [`MAX_COLORS`] colors.
+/// Frames with more colors are dithered (Floyd-Steinberg), so gradients don't band.
+pub fn quantize(frame: &Frame) -> Indexed {
+    let palette = palette(frame);
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    let exact: HashMap<[u8; 4], u8> = palette
+        .iter()
+        .enumerate()
+        .map(|(index, color)| (*color, index as u8))
+        .collect();
+
+    let mut indices = Vec::with_capacity(width * height);
+    let mut error = vec![[0.0f32; 4]; width * (height + 1) + 1];
+    let mut cache: HashMap<[u8; 4], u8> = HashMap::new();
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let p = &frame.pixels[i * 4..i * 4 + 4];
+            let pixel = if p[3] == 0 {
+                [0; 4]
+            } else {
+                [p[0], p[1], p[2], p[3]]
+            };
+            if let Some(index) = exact.get(&pixel).filter(|_| error[i] == [0.0; 4]) {
+                indices.push(*index);
+                continue;
+            }
+            let wanted: [f32; 4] =
+                std::array::from_fn(|c| (pixel[c] as f32 + error[i][c]).clamp(0.0, 255.0));
+            let key = wanted.map(|c| c.round() as u8);
+            let index = *cache
+                .entry(key)
+                .or_insert_with(|| nearest(&palette, wanted) as u8);
+            indices.push(index);
+
+            let chosen = palette[index as usize];
+            let diff: [f32; 4] = std::array::from_fn(|c| wanted[c] - chosen[c] as f32);
+            let mut spread = |index: usize, weight: f32| {
+                for c in 0..4 {
+                    error[index][c] += diff[c] * weight;
+                }
+            };
+            if x + 1 < width {
+                spread(i + 1, 7.0 / 16.0);
+            }
+            if y + 1 < height {
+                if x > 0 {
+                    spread(i + width - 1, 3.0 / 16.0);
+                }
+                spread(i + width, 5.0 / 16.0);
+                if x + 1 < width {
+                    spread(i + width + 1, 1.0 / 16.0);
+                }
+            }
+        }
+    }
+    Indexed { palette, indices }
+}
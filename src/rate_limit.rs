@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{error::RenderError, signed_url::SignedRequest};
+
+/// Buckets are pruned once there are more than this many clients.
+const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+/// A token bucket per client, which is the ip for http requests.
+pub struct RateLimiter<K = IpAddr> {
+    /// Tokens refilled per second
+    rate: f64,
+    /// Maximum tokens per bucket
+    burst: f64,
+    buckets: parking_lot::Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(rate: f64, burst: f64) -> Self {
+        Self {
+            rate: rate.max(f64::EPSILON),
+            burst: burst.max(1.0),
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token, or returns how long to wait for the next one.
+    pub fn check(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > MAX_BUCKETS {
+            // full buckets carry no information
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.saturating_duration_since(bucket.last).as_secs_f64() * rate
+                    < burst
+            });
+        }
+        let bucket = buckets.entry(key).or_insert(TokenBucket {
+            tokens: self.burst,
+            last: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+}
+
+/// - `RATE_LIMIT_PER_SEC` requests per second and client (2 is default)
+/// - `RATE_LIMIT_BURST` requests a client can do at once (10 is default)
+pub static RATE_LIMIT: LazyLock<RateLimiter> = LazyLock::new(|| {
+    let rate: f64 = std::env::var("RATE_LIMIT_PER_SEC")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<f64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(2.0);
+    let burst: f64 = std::env::var("RATE_LIMIT_BURST")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<f64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(10.0);
+    RateLimiter::new(rate, burst)
+});
+
+/// `TRUST_FORWARDED_FOR`: whether the server runs behind a proxy
+/// that sets `X-Forwarded-For` (false is default)
+static TRUST_FORWARDED_FOR: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var("TRUST_FORWARDED_FOR")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(false)
+});
+
+/// The ip of the client, the last `X-Forwarded-For` entry
+/// is the one our proxy added, so it's the only trustworthy one.
+pub fn client_ip(headers: &HeaderMap, peer: Option<SocketAddr>) -> Option<IpAddr> {
+    if *TRUST_FORWARDED_FOR {
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .last();
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    peer.map(|peer| peer.ip())
+}
+
+/// Rate limits by client ip, signed requests are exempt.
+pub async fn limit<B>(req: Request<B>, next: Next<B>) -> Response {
+    if req.extensions().get::<SignedRequest>().is_none() {
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+        if let Some(ip) = client_ip(req.headers(), peer) {
+            if let Err(retry_after) = RATE_LIMIT.check(ip, Instant::now()) {
+                return RenderError::RateLimited { retry_after }.into_response();
+            }
+        }
+    }
+    next.run(req).await
+}
@@ -0,0 +1,317 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use hashlink::LruCache;
+
+use crate::in_flight::{InFlight, InFlightGuard};
+
+/// - `RENDER_CACHE_MB` the size of the render cache (64 is default, 0 disables it)
+/// - `RENDER_CACHE_TTL_SECS` how long a cached image is fresh (300 is default)
+/// - `RENDER_CACHE_STALE_SECS` how long an image may be served stale
+///   after it expired, with the `swr` policy (3600 is default)
+pub static RENDER_CACHE: LazyLock<RenderCache> = LazyLock::new(|| {
+    let mb: usize = std::env::var("RENDER_CACHE_MB")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(64);
+    let ttl: u64 = std::env::var("RENDER_CACHE_TTL_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(300);
+    let stale: u64 = std::env::var("RENDER_CACHE_STALE_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(3600);
+    RenderCache::new(
+        mb * 1024 * 1024,
+        Duration::from_secs(ttl),
+        Duration::from_secs(stale),
+    )
+});
+
+/// `RENDER_CACHE_POLICY` the policy if the request has no `cache` parameter
+/// (`fresh` is default)
+pub static DEFAULT_CACHE_POLICY: LazyLock<CachePolicy> = LazyLock::new(|| {
+    std::env::var("RENDER_CACHE_POLICY")
+        .ok()
+        .and_then(|policy| CachePolicy::parse(&policy))
+        .unwrap_or(CachePolicy::Fresh)
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Expired images are rendered again before responding
+    Fresh,
+    /// Expired images are served stale while they are rendered again
+    /// in the background
+    StaleWhileRevalidate,
+}
+
+impl CachePolicy {
+    pub const NAMES: [&'static str; 2] = ["fresh", "swr"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fresh => "fresh",
+            Self::StaleWhileRevalidate => "swr",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fresh" => Some(Self::Fresh),
+            "swr" => Some(Self::StaleWhileRevalidate),
+            _ => None,
+        }
+    }
+}
+
+pub enum Lookup {
+    Fresh(Arc<Vec<u8>>),
+    /// Expired, but within the staleness window
+    Stale(Arc<Vec<u8>>),
+    Miss,
+}
+
+struct Entry {
+    img: Arc<Vec<u8>>,
+    inserted: Instant,
+}
+
+struct CacheState {
+    entries: LruCache<String, Entry>,
+    bytes: usize,
+}
+
+/// Encoded images by their canonical parameters,
+/// bounded by the total size of the images.
+pub struct RenderCache {
+    max_bytes: usize,
+    ttl: Duration,
+    stale: Duration,
+    state: parking_lot::Mutex<CacheState>,
+    /// Keys that are rendered in the background right now
+    revalidating: InFlight<String, (), ()>,
+}
+
+/// Marks a key as revalidating until dropped.
+pub type Revalidation = InFlightGuard<'static, String, (), ()>;
+
+impl RenderCache {
+    pub fn new(max_bytes: usize, ttl: Duration, stale: Duration) -> Self {
+        Self {
+            max_bytes,
+            ttl,
+            stale,
+            state: parking_lot::Mutex::new(CacheState {
+                entries: LruCache::new_unbounded(),
+                bytes: 0,
+            }),
+            revalidating: Default::default(),
+        }
+    }
+
+    /// Entries past the staleness window are removed.
+    pub fn lookup(&self, key: &str, now: Instant) -> Lookup {
+        let mut state = self.state.lock();
+        let Some(entry) = state.entries.get(key) else {
+            return Lookup::Miss;
+        };
+        let age = now.saturating_duration_since(entry.inserted);
+        if age <= self.ttl {
+            Lookup::Fresh(entry.img.clone())
+        } else if age <= self.ttl + self.stale {
+            Lookup::Stale(entry.img.clone())
+        } else {
+            if let Some(entry) = state.entries.remove(key) {
+                state.bytes -= entry.img.len();
+            }
+            Lookup::Miss
+        }
+    }
+
+    /// Images larger than the whole cache are not stored.
+    pub fn insert(&self, key: String, img: Arc<Vec<u8>>) {
+        if img.len() > self.max_bytes {
+            return;
+        }
+        let mut state = self.state.lock();
+        state.bytes += img.len();
+        if let Some(old) = state.entries.insert(
+            key,
+            Entry {
+                img,
+                inserted: Instant::now(),
+            },
+        ) {
+            state.bytes -= old.img.len();
+        }
+        while state.bytes > self.max_bytes {
+            let Some((_, old)) = state.entries.remove_lru() else {
+                break;
+            };
+            state.bytes -= old.img.len();
+        }
+    }
+
+    /// Removes the entries whose key matches, returns how many were removed.
+    pub fn evict(&self, matches: impl Fn(&str) -> bool) -> usize {
+        let mut state = self.state.lock();
+        let keys: Vec<String> = state
+            .entries
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| matches(key))
+            .cloned()
+            .collect();
+        for key in &keys {
+            if let Some(entry) = state.entries.remove(key) {
+                state.bytes -= entry.img.len();
+            }
+        }
+        keys.len()
+    }
+
+    /// Removes the entries that were rendered with this value of a parameter,
+    /// e.g. `skin_name`, see `RenderParams::cache_key`.
+    pub fn evict_param(&self, field: &str, value: &str) -> usize {
+        self.evict(|key| {
+            serde_json::from_str::<serde_json::Value>(key)
+                .ok()
+                .and_then(|key| key.get(field)?.as_str().map(|v| v == value))
+                .unwrap_or_default()
+        })
+    }
+
+    /// The number of entries, their total size and the size limit in bytes.
+    pub fn stats(&self) -> (usize, usize, usize) {
+        let state = self.state.lock();
+        (state.entries.len(), state.bytes, self.max_bytes)
+    }
+
+    /// Returns `None` if the key is already revalidating.
+    pub fn start_revalidation(&'static self, key: &str) -> Option<Revalidation> {
+        self.revalidating.try_insert(key.to_string(), (), ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(10);
+    const STALE: Duration = Duration::from_secs(20);
+
+    fn img(len: usize) -> Arc<Vec<u8>> {
+        Arc::new(vec![0; len])
+    }
+
+    fn is_fresh(lookup: Lookup) -> bool {
+        matches!(lookup, Lookup::Fresh(_))
+    }
+
+    fn is_stale(lookup: Lookup) -> bool {
+        matches!(lookup, Lookup::Stale(_))
+    }
+
+    fn is_miss(lookup: Lookup) -> bool {
+        matches!(lookup, Lookup::Miss)
+    }
+
+    #[test]
+    fn entries_turn_stale_after_the_ttl() {
+        let cache = RenderCache::new(100, TTL, STALE);
+        let now = Instant::now();
+        cache.insert("a".to_string(), img(10));
+        assert!(is_fresh(cache.lookup("a", now)));
+        assert!(is_stale(
+            cache.lookup("a", now + TTL + Duration::from_secs(1))
+        ));
+        assert!(is_stale(cache.lookup("a", now + TTL + STALE)));
+        assert_eq!(cache.stats(), (1, 10, 100));
+    }
+
+    #[test]
+    fn entries_past_the_staleness_window_are_removed() {
+        let cache = RenderCache::new(100, TTL, STALE);
+        let now = Instant::now();
+        cache.insert("a".to_string(), img(10));
+        let expired = now + TTL + STALE + Duration::from_secs(1);
+        assert!(is_miss(cache.lookup("a", expired)));
+        assert_eq!(cache.stats(), (0, 0, 100));
+        assert!(is_miss(cache.lookup("a", now)));
+    }
+
+    #[test]
+    fn the_least_recently_used_entries_are_evicted() {
+        let cache = RenderCache::new(100, TTL, STALE);
+        let now = Instant::now();
+        cache.insert("a".to_string(), img(40));
+        cache.insert("b".to_string(), img(40));
+        // a is used more recently than b now
+        assert!(is_fresh(cache.lookup("a", now)));
+        cache.insert("c".to_string(), img(40));
+        assert!(is_fresh(cache.lookup("a", now)));
+        assert!(is_miss(cache.lookup("b", now)));
+        assert!(is_fresh(cache.lookup("c", now)));
+        assert_eq!(cache.stats(), (2, 80, 100));
+    }
+
+    #[test]
+    fn replaced_entries_are_not_counted_twice() {
+        let cache = RenderCache::new(100, TTL, STALE);
+        cache.insert("a".to_string(), img(40));
+        cache.insert("a".to_string(), img(30));
+        assert_eq!(cache.stats(), (1, 30, 100));
+    }
+
+    #[test]
+    fn images_larger_than_the_cache_are_not_stored() {
+        let cache = RenderCache::new(100, TTL, STALE);
+        cache.insert("a".to_string(), img(60));
+        cache.insert("b".to_string(), img(101));
+        assert_eq!(cache.stats(), (1, 60, 100));
+
+        let disabled = RenderCache::new(0, TTL, STALE);
+        disabled.insert("a".to_string(), img(1));
+        assert_eq!(disabled.stats(), (0, 0, 0));
+    }
+
+    #[test]
+    fn entries_are_evicted_by_parameter() {
+        let cache = RenderCache::new(100, TTL, STALE);
+        cache.insert(r#"{"skin_name":"pinky","zoom":1}"#.to_string(), img(1));
+        cache.insert(r#"{"skin_name":"pinky","zoom":2}"#.to_string(), img(1));
+        cache.insert(r#"{"skin_name":"default"}"#.to_string(), img(1));
+        cache.insert("not json".to_string(), img(1));
+        assert_eq!(cache.evict_param("skin_name", "pinky"), 2);
+        assert_eq!(cache.evict_param("skin_name", "pinky"), 0);
+        assert_eq!(cache.stats(), (2, 2, 100));
+    }
+
+    #[test]
+    fn a_key_is_revalidated_once_at_a_time() {
+        let cache: &'static RenderCache = Box::leak(Box::new(RenderCache::new(100, TTL, STALE)));
+        let revalidation = cache.start_revalidation("a").unwrap();
+        assert!(cache.start_revalidation("a").is_none());
+        let other = cache.start_revalidation("b");
+        assert!(other.is_some());
+        drop(revalidation);
+        assert!(cache.start_revalidation("a").is_some());
+    }
+
+    #[test]
+    fn policies_are_parsed_by_name() {
+        for name in CachePolicy::NAMES {
+            assert_eq!(CachePolicy::parse(name).unwrap().name(), name);
+        }
+        assert_eq!(
+            CachePolicy::parse(" SWR "),
+            Some(CachePolicy::StaleWhileRevalidate)
+        );
+        assert_eq!(CachePolicy::parse("stale"), None);
+    }
+}
@@ -0,0 +1,625 @@
+use std::{
+    sync::{Arc, LazyLock, OnceLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use math::math::vector::vec2;
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::error::Elapsed,
+};
+
+use crate::{
+    error::RenderError,
+    frame::Rect,
+    game_layer::TileCategory,
+    load::{QueuedJob, LOAD},
+    params::RenderParams,
+    scene::SceneItem,
+    shutdown,
+};
+
+/// `RENDER_QUEUE_DEPTH`: jobs that may wait for the render thread,
+/// further requests are rejected as busy (32 is default)
+static RENDER_QUEUE_DEPTH: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("RENDER_QUEUE_DEPTH")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(32)
+        .max(1)
+});
+
+/// `RENDERER_POOL_SIZE`: renderers that render in parallel from the same queue, each on its
+/// own thread with its own graphics backend (1 is default). Every renderer loads its own
+/// containers and up to `MAX_LOADED_MAPS` maps, so their memory grows with the pool size.
+pub static RENDERER_POOL_SIZE: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("RENDERER_POOL_SIZE")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(1)
+        .max(1)
+});
+
+/// `None` once closed, the render threads stop when the queues are empty.
+static RENDER_JOBS: parking_lot::Mutex<Option<JobSenders>> = parking_lot::const_mutex(None);
+
+/// Which queue a job waits in, high priority jobs are rendered first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    #[default]
+    Normal,
+    /// Someone is waiting interactively, e.g. a discord command
+    High,
+    /// Nobody waits for the result, e.g. refreshing a stale cached image.
+    /// Only rendered while the other queues are empty
+    Batch,
+}
+
+#[derive(Clone)]
+struct JobSenders {
+    high: mpsc::Sender<RenderJob>,
+    normal: mpsc::Sender<RenderJob>,
+    batch: mpsc::Sender<RenderJob>,
+    /// One per renderer, every renderer runs every maintenance task
+    maintenance: Vec<mpsc::Sender<MaintenanceJob>>,
+}
+
+/// The render queues, shared by all renderers.
+struct RenderQueues {
+    high: mpsc::Receiver<RenderJob>,
+    normal: mpsc::Receiver<RenderJob>,
+    batch: mpsc::Receiver<RenderJob>,
+}
+
+/// A render thread's end of the queues.
+pub struct JobReceiver {
+    /// Which renderer of the pool this is
+    index: usize,
+    renders: Arc<tokio::sync::Mutex<RenderQueues>>,
+    maintenance: mpsc::Receiver<MaintenanceJob>,
+    /// Waits for either queue on the render thread
+    rt: tokio::runtime::Runtime,
+}
+
+/// What the render thread does next.
+pub enum Job {
+    Render(RenderJob),
+    Maintain(MaintenanceJob),
+}
+
+impl JobReceiver {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The next job, maintenance first since it's quick, then renders by priority.
+    /// Renderers take turns waiting for renders, so each job is rendered once.
+    /// `None` once the queues were closed and are empty.
+    pub fn blocking_recv(&mut self) -> Option<Job> {
+        self.recv(None).unwrap_or_default()
+    }
+
+    /// Like [`Self::blocking_recv`], but gives up once no job arrived within `timeout`.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<Job>, Elapsed> {
+        self.recv(Some(timeout))
+    }
+
+    fn recv(&mut self, timeout: Option<Duration>) -> Result<Option<Job>, Elapsed> {
+        let Self {
+            renders,
+            maintenance,
+            rt,
+            ..
+        } = self;
+        let render = async {
+            let mut renders = renders.lock().await;
+            let RenderQueues {
+                high,
+                normal,
+                batch,
+            } = &mut *renders;
+            tokio::select! {
+                biased;
+                Some(job) = high.recv() => Some(job),
+                Some(job) = normal.recv() => Some(job),
+                Some(job) = batch.recv() => Some(job),
+                else => None,
+            }
+        };
+        let next = async {
+            tokio::select! {
+                biased;
+                Some(job) = maintenance.recv() => Some(Job::Maintain(job)),
+                job = render => job.map(Job::Render),
+            }
+        };
+        rt.block_on(async {
+            match timeout {
+                Some(timeout) => tokio::time::timeout(timeout, next).await,
+                None => Ok(next.await),
+            }
+        })
+    }
+}
+
+/// Changes of the client that must not happen during a render.
+#[derive(Debug, Clone)]
+pub enum Maintenance {
+    /// Unloads a map, all maps if `None`
+    EvictMaps(Option<String>),
+    /// Unloads the skins that no render uses right now
+    UnloadSkins,
+    /// Recreates the client, which loads the default containers again
+    ReloadContainers,
+    /// Reports what the client has loaded
+    Stats,
+}
+
+pub struct MaintenanceJob {
+    pub task: Maintenance,
+    pub sender: oneshot::Sender<Result<serde_json::Value, String>>,
+}
+
+/// The captured png and anything that degraded the render.
+#[derive(Debug)]
+pub struct Rendered {
+    pub png: Vec<u8>,
+    pub warnings: Vec<String>,
+    /// The part of the frame the image was cropped to by post processing
+    pub crop: Option<Rect>,
+    pub timing: RenderTiming,
+    /// The physics overlay in frame pixels, drawn by post processing
+    pub game_layer: Vec<(Rect, TileCategory)>,
+    /// The projectile path in tiles relative to the shooter and whether it hit a wall,
+    /// drawn by post processing
+    pub trajectory: Option<(Vec<vec2>, bool)>,
+    /// Flags, pickups and projectiles in tiles relative to the camera and the zoom they were rendered with,
+    /// drawn by post processing
+    pub scene: Option<(Vec<SceneItem>, f32)>,
+}
+
+/// Where the time of a render job went.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderTiming {
+    /// Waiting for the render thread
+    pub queue_wait: Duration,
+    /// From the start of the job until the image was captured
+    pub render: Duration,
+}
+
+pub struct RenderJob {
+    pub params: RenderParams,
+    pub queued: QueuedJob,
+    /// Shared by everything the render waits for
+    pub deadline: Instant,
+    /// Set by the render thread once it picked up the job
+    pub started: Arc<OnceLock<Instant>>,
+    pub sender: oneshot::Sender<Result<Rendered, RenderError>>,
+}
+
+/// Creates the job queues for `renderers` render threads, each owns one of the receivers.
+pub fn init(renderers: usize) -> Vec<JobReceiver> {
+    let (senders, receivers) = queues(renderers);
+    if RENDER_JOBS.lock().replace(senders).is_some() {
+        panic!("the render worker was initialized twice");
+    }
+    receivers
+}
+
+fn queues(renderers: usize) -> (JobSenders, Vec<JobReceiver>) {
+    let (high, high_receiver) = mpsc::channel(*RENDER_QUEUE_DEPTH);
+    let (normal, normal_receiver) = mpsc::channel(*RENDER_QUEUE_DEPTH);
+    let (batch, batch_receiver) = mpsc::channel(*RENDER_QUEUE_DEPTH);
+    let renders = Arc::new(tokio::sync::Mutex::new(RenderQueues {
+        high: high_receiver,
+        normal: normal_receiver,
+        batch: batch_receiver,
+    }));
+    let mut maintenance = Vec::new();
+    let mut receivers = Vec::new();
+    for index in 0..renderers.max(1) {
+        let (sender, receiver) = mpsc::channel(*RENDER_QUEUE_DEPTH);
+        maintenance.push(sender);
+        receivers.push(JobReceiver {
+            index,
+            renders: renders.clone(),
+            maintenance: receiver,
+            rt: tokio::runtime::Builder::new_current_thread()
+                .enable_time()
+                .build()
+                .expect("creating the render queue runtime failed"),
+        });
+    }
+    let senders = JobSenders {
+        high,
+        normal,
+        batch,
+        maintenance,
+    };
+    (senders, receivers)
+}
+
+/// Rejects further jobs, queued jobs are still rendered.
+pub fn close() {
+    RENDER_JOBS.lock().take();
+}
+
+/// Queues the params for rendering and waits for the png.
+pub async fn render(params: RenderParams, priority: Priority) -> Result<Rendered, RenderError> {
+    if shutdown::is_shutting_down() {
+        return Err(RenderError::ShuttingDown);
+    }
+    let senders = RENDER_JOBS
+        .lock()
+        .clone()
+        .ok_or_else(|| RenderError::Internal("the render worker is not running".to_string()))?;
+    submit(&senders, params, priority).await
+}
+
+/// Queues the params in the queue of the priority and waits for the png.
+async fn submit(
+    senders: &JobSenders,
+    params: RenderParams,
+    priority: Priority,
+) -> Result<Rendered, RenderError> {
+    let jobs = match priority {
+        Priority::High => &senders.high,
+        Priority::Normal => &senders.normal,
+        Priority::Batch => &senders.batch,
+    };
+    let (sender, receiver) = oneshot::channel();
+    let enqueued = Instant::now();
+    let started = Arc::new(OnceLock::new());
+    jobs.try_send(RenderJob {
+        params,
+        queued: LOAD.enqueue(),
+        deadline: enqueued + *RENDER_TIMEOUT,
+        started: started.clone(),
+        sender,
+    })
+    .map_err(|err| match err {
+        mpsc::error::TrySendError::Full(_) => RenderError::Busy,
+        mpsc::error::TrySendError::Closed(_) if shutdown::is_shutting_down() => {
+            RenderError::ShuttingDown
+        }
+        mpsc::error::TrySendError::Closed(_) => {
+            RenderError::Internal("the render worker stopped".to_string())
+        }
+    })?;
+    // the job is skipped if the request gave up before it started
+    let mut rendered = tokio::time::timeout(*RENDER_TIMEOUT, receiver)
+        .await
+        .map_err(|_| RenderError::Timeout(*RENDER_TIMEOUT))?
+        .map_err(|_| RenderError::Gpu("the screenshot was never taken".to_string()))??;
+    let started = started.get().copied().unwrap_or(enqueued);
+    rendered.timing = RenderTiming {
+        queue_wait: started.saturating_duration_since(enqueued),
+        render: started.elapsed(),
+    };
+    Ok(rendered)
+}
+
+/// Passes the result of a render on to the request. The screenshot is normally taken
+/// before the render returns, otherwise a thread waits for it.
+pub fn forward(
+    mut receiver: oneshot::Receiver<Result<Rendered, RenderError>>,
+    sender: oneshot::Sender<Result<Rendered, RenderError>>,
+) {
+    match receiver.try_recv() {
+        Ok(result) => {
+            let _ = sender.send(result);
+        }
+        // the request sees the dropped sender as a gpu error
+        Err(oneshot::error::TryRecvError::Closed) => {}
+        Err(oneshot::error::TryRecvError::Empty) => {
+            std::thread::spawn(move || {
+                if let Ok(result) = receiver.blocking_recv() {
+                    let _ = sender.send(result);
+                }
+            });
+        }
+    }
+}
+
+/// Queues the maintenance task for every renderer, which runs it between renders.
+/// Returns the result of every renderer by its index in the pool.
+pub async fn maintain(task: Maintenance) -> Result<Vec<serde_json::Value>, RenderError> {
+    let senders = RENDER_JOBS
+        .lock()
+        .clone()
+        .ok_or_else(|| RenderError::Internal("the render worker is not running".to_string()))?;
+    let mut receivers = Vec::new();
+    for maintenance in &senders.maintenance {
+        let (sender, receiver) = oneshot::channel();
+        maintenance
+            .try_send(MaintenanceJob {
+                task: task.clone(),
+                sender,
+            })
+            .map_err(|err| match err {
+                mpsc::error::TrySendError::Full(_) => RenderError::Busy,
+                mpsc::error::TrySendError::Closed(_) => {
+                    RenderError::Internal("the render worker stopped".to_string())
+                }
+            })?;
+        receivers.push(receiver);
+    }
+    // recreating the client takes about as long as a stuck render may,
+    // the renderers run the task in parallel
+    let deadline = tokio::time::Instant::now() + *RENDER_HARD_LIMIT;
+    let mut results = Vec::new();
+    for receiver in receivers {
+        let result = tokio::time::timeout_at(deadline, receiver)
+            .await
+            .map_err(|_| RenderError::Timeout(*RENDER_HARD_LIMIT))?
+            .map_err(|_| RenderError::Internal("the maintenance was never done".to_string()))?
+            .map_err(RenderError::Internal)?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// - `RENDER_TIMEOUT_MS` how long a request waits for its render (10000 is default)
+/// - `RENDER_HARD_LIMIT_MS` a render taking longer is considered stuck (30000 is default)
+static RENDER_TIMEOUT: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_millis(
+        std::env::var("RENDER_TIMEOUT_MS")
+            .map_err(|err| anyhow!(err))
+            .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+            .unwrap_or(10000),
+    )
+});
+static RENDER_HARD_LIMIT: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_millis(
+        std::env::var("RENDER_HARD_LIMIT_MS")
+            .map_err(|err| anyhow!(err))
+            .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+            .unwrap_or(30000),
+    )
+});
+
+#[derive(Debug, Default)]
+struct WatchdogState {
+    /// When the current job started
+    started: Option<Instant>,
+    /// Whether the current or a previous job exceeded the hard limit
+    stuck: bool,
+}
+
+/// Watches the render thread from a separate thread.
+/// A stuck driver call can't be interrupted, so a stuck job is reported
+/// and the graphics backend is reinitialized once it returns.
+/// If it never returns the process exits, so the supervisor can restart it.
+pub struct Watchdog {
+    state: Arc<parking_lot::Mutex<WatchdogState>>,
+}
+
+/// Marks a job as running until dropped.
+pub struct Watched<'a> {
+    watchdog: &'a Watchdog,
+}
+
+impl Drop for Watched<'_> {
+    fn drop(&mut self) {
+        self.watchdog.state.lock().started = None;
+    }
+}
+
+impl Watchdog {
+    pub fn spawn() -> Self {
+        let watchdog = Self {
+            state: Default::default(),
+        };
+        let state = watchdog.state.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let mut state = state.lock();
+            let Some(started) = state.started else {
+                continue;
+            };
+            let elapsed = started.elapsed();
+            if elapsed > *RENDER_HARD_LIMIT * 3 {
+                println!("render job stuck for {elapsed:?}, exiting");
+                std::process::exit(1);
+            } else if elapsed > *RENDER_HARD_LIMIT && !state.stuck {
+                println!("render job stuck for {elapsed:?}");
+                state.stuck = true;
+            }
+        });
+        watchdog
+    }
+
+    pub fn watch(&self) -> Watched<'_> {
+        self.state.lock().started = Some(Instant::now());
+        Watched { watchdog: self }
+    }
+
+    /// Whether a job got stuck since the last call.
+    pub fn take_stuck(&self) -> bool {
+        std::mem::take(&mut self.state.lock().stuck)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread::JoinHandle};
+
+    use tokio::task::JoinSet;
+
+    use super::*;
+
+    fn params(time: u64) -> RenderParams {
+        RenderParams {
+            time: Some(time),
+            ..Default::default()
+        }
+    }
+
+    /// Answers render jobs with their `time` as png like the render thread,
+    /// returns the rendered times in order once the queues are closed.
+    fn spawn_worker(mut receiver: JobReceiver) -> JoinHandle<Vec<u64>> {
+        std::thread::spawn(move || {
+            let mut rendered = Vec::new();
+            while let Some(job) = receiver.blocking_recv() {
+                let Job::Render(job) = job else {
+                    continue;
+                };
+                let _running = job.queued.start(receiver.index());
+                let _ = job.started.set(Instant::now());
+                let time = job.params.time.unwrap_or_default();
+                rendered.push(time);
+                let _ = job.sender.send(Ok(Rendered {
+                    png: time.to_le_bytes().to_vec(),
+                    warnings: Vec::new(),
+                    crop: None,
+                    timing: Default::default(),
+                    game_layer: Vec::new(),
+                    trajectory: None,
+                    scene: None,
+                }));
+            }
+            rendered
+        })
+    }
+
+    /// Jobs waiting in the queue of the priority.
+    fn queued(senders: &JobSenders, priority: Priority) -> usize {
+        let jobs = match priority {
+            Priority::High => &senders.high,
+            Priority::Normal => &senders.normal,
+            Priority::Batch => &senders.batch,
+        };
+        jobs.max_capacity() - jobs.capacity()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_submissions_get_their_own_render() {
+        const TASKS: u64 = 16;
+        const RENDERS: u64 = 64;
+        let (senders, receivers) = queues(4);
+        let workers: Vec<_> = receivers.into_iter().map(spawn_worker).collect();
+        let senders = Arc::new(senders);
+        let mut tasks = JoinSet::new();
+        for task in 0..TASKS {
+            let senders = senders.clone();
+            tasks.spawn(async move {
+                for time in task * RENDERS..(task + 1) * RENDERS {
+                    let priority = if time % 3 == 0 {
+                        Priority::High
+                    } else {
+                        Priority::Normal
+                    };
+                    loop {
+                        match submit(&senders, params(time), priority).await {
+                            Ok(rendered) => {
+                                assert_eq!(rendered.png, time.to_le_bytes());
+                                break;
+                            }
+                            // more requests than the queues hold, like a client retrying later
+                            Err(RenderError::Busy) => tokio::task::yield_now().await,
+                            Err(err) => panic!("render {time} failed: {err}"),
+                        }
+                    }
+                }
+            });
+        }
+        while let Some(res) = tasks.join_next().await {
+            res.unwrap();
+        }
+        drop(senders);
+        // every job was rendered once by one of the renderers
+        let mut rendered: Vec<u64> = workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect();
+        rendered.sort_unstable();
+        assert_eq!(rendered, (0..TASKS * RENDERS).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn high_priority_jobs_are_rendered_first() {
+        let (senders, mut receivers) = queues(1);
+        let senders = Arc::new(senders);
+        let mut requests = JoinSet::new();
+        for time in 0..8 {
+            let senders = senders.clone();
+            let priority = if time % 2 == 0 {
+                Priority::Normal
+            } else {
+                Priority::High
+            };
+            requests.spawn(async move { submit(&senders, params(time), priority).await });
+        }
+        // everything is queued before the render thread starts
+        while queued(&senders, Priority::High) + queued(&senders, Priority::Normal) < 8 {
+            tokio::task::yield_now().await;
+        }
+        let worker = spawn_worker(receivers.remove(0));
+        while let Some(res) = requests.join_next().await {
+            res.unwrap().unwrap();
+        }
+        drop(senders);
+        let rendered = worker.join().unwrap();
+        let (high, normal) = rendered.split_at(4);
+        assert!(high.iter().all(|time| time % 2 == 1), "{rendered:?}");
+        assert!(normal.iter().all(|time| time % 2 == 0), "{rendered:?}");
+    }
+
+    #[tokio::test]
+    async fn batch_jobs_are_rendered_last() {
+        let (senders, mut receivers) = queues(1);
+        let senders = Arc::new(senders);
+        let mut requests = JoinSet::new();
+        let priorities = [Priority::Batch, Priority::Normal, Priority::High];
+        for time in 0..9 {
+            let senders = senders.clone();
+            let priority = priorities[time as usize % 3];
+            requests.spawn(async move { submit(&senders, params(time), priority).await });
+        }
+        while priorities
+            .iter()
+            .map(|priority| queued(&senders, *priority))
+            .sum::<usize>()
+            < 9
+        {
+            tokio::task::yield_now().await;
+        }
+        let worker = spawn_worker(receivers.remove(0));
+        while let Some(res) = requests.join_next().await {
+            res.unwrap().unwrap();
+        }
+        drop(senders);
+        let rendered = worker.join().unwrap();
+        let (high, rest) = rendered.split_at(3);
+        let (normal, batch) = rest.split_at(3);
+        assert!(high.iter().all(|time| time % 3 == 2), "{rendered:?}");
+        assert!(normal.iter().all(|time| time % 3 == 1), "{rendered:?}");
+        assert!(batch.iter().all(|time| time % 3 == 0), "{rendered:?}");
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_is_busy_until_rendered() {
+        let (senders, mut receivers) = queues(1);
+        let senders = Arc::new(senders);
+        let depth = *RENDER_QUEUE_DEPTH;
+        let mut requests = JoinSet::new();
+        for time in 0..depth as u64 {
+            let senders = senders.clone();
+            requests.spawn(async move { submit(&senders, params(time), Priority::Normal).await });
+        }
+        while queued(&senders, Priority::Normal) < depth {
+            tokio::task::yield_now().await;
+        }
+        let rejected = submit(&senders, params(depth as u64), Priority::Normal).await;
+        assert!(matches!(rejected, Err(RenderError::Busy)), "{rejected:?}");
+
+        // the queued jobs are still rendered
+        let worker = spawn_worker(receivers.remove(0));
+        while let Some(res) = requests.join_next().await {
+            res.unwrap().unwrap();
+        }
+        drop(senders);
+        assert_eq!(worker.join().unwrap().len(), depth);
+    }
+}
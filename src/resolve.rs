@@ -0,0 +1,225 @@
+use axum::{extract::RawQuery, Json};
+use game_interface::types::network_string::NetworkString;
+
+use crate::{
+    color::{self, TeePart},
+    error::RenderError,
+    maps,
+    params::{self, Deprecation, ParamError, RenderParams, TeeColor},
+    player_api,
+    skins::{self, SkinResolution},
+    upload,
+};
+
+/// The skin the skin container renders instead of unknown skins.
+const DEFAULT_SKIN: &str = "default";
+
+/// The parameters of a request after sanitization and skin resolution.
+/// Renders and `GET /resolve` share it, so both agree on the final values.
+pub struct Resolved {
+    pub params: RenderParams,
+    pub deprecated: Vec<&'static Deprecation>,
+    pub strict: bool,
+    pub warnings: Vec<String>,
+    /// The skin is not on disk, so the default skin is rendered
+    pub skin_missing: bool,
+    pub skin_suggestions: Vec<String>,
+    /// Whether the skin was resolved by the player api
+    pub skin_from_player_api: bool,
+    /// Why the player api didn't resolve the skin
+    pub player_api_error: Option<RenderError>,
+    pub skin_resolution: SkinResolution,
+}
+
+impl Resolved {
+    /// The skin key that is actually rendered.
+    pub fn rendered_skin(&self) -> &str {
+        let skin = self.params.render_skin();
+        if self.skin_missing && skin == self.params.skin_name.as_str() {
+            DEFAULT_SKIN
+        } else {
+            skin
+        }
+    }
+}
+
+/// Parses the query and resolves the skin, see [`resolve_parsed`].
+pub async fn resolve(
+    query: &str,
+    mut timed: impl FnMut(&'static str),
+) -> Result<Resolved, RenderError> {
+    let (params, deprecated) = RenderParams::from_query(query)?;
+    timed("parse");
+    resolve_parsed(params, deprecated, timed).await
+}
+
+/// Resolves the skin, `timed` is called after each step.
+/// Unknown skins are downloaded, but maps are only validated.
+pub async fn resolve_parsed(
+    mut params: RenderParams,
+    deprecated: Vec<&'static Deprecation>,
+    mut timed: impl FnMut(&'static str),
+) -> Result<Resolved, RenderError> {
+    let mut warnings: Vec<String> = deprecated
+        .iter()
+        .map(|deprecation| {
+            format!(
+                "parameter {} is deprecated, use {}",
+                deprecation.old, deprecation.new
+            )
+        })
+        .collect();
+
+    let strict = params.strict.unwrap_or_default();
+    if strict {
+        params.validate_strict()?;
+    }
+    // before the names are looked up, logged or rendered
+    params.sanitize_names();
+
+    let mut skin_from_player_api = false;
+    let mut player_api_error = None;
+    if params.use_player_api.is_some_and(|b| b) {
+        if let Some(player_name) = &params.player_name {
+            match player_api::lookup(player_name.as_str(), params.provider()).await {
+                Ok(skin) => {
+                    params.skin_name = skin.name;
+                    params.body_color = skin.color_body.map(TeeColor::Legacy);
+                    params.feet_color = skin.color_feet.map(TeeColor::Legacy);
+                    skin_from_player_api = true;
+                }
+                // only strict requests fail, others render with the requested skin
+                Err(RenderError::Upstream(err)) if strict => {
+                    return Err(RenderError::Upstream(format!("player api failed: {err}")))
+                }
+                Err(err) if strict => return Err(err),
+                Err(err) => {
+                    warnings.push(format!("player api failed: {err}"));
+                    player_api_error = Some(err);
+                }
+            }
+        }
+        timed("player_api");
+    }
+
+    if let Some(url) = &params.skin_url {
+        let name = upload::skin_from_url(url).await?;
+        params.skin_name = NetworkString::new(&name)
+            .map_err(|err| RenderError::Internal(format!("skin name {name}: {err}")))?;
+        timed("skin_url");
+    }
+
+    let mut skin_index = skins::skin_index().await;
+    let mut skin_resolution = SkinResolution::Local;
+    if !skin_index.is_empty() && !skin_index.contains(params.skin_name.as_str()) {
+        match skins::download_skin(params.skin_name.as_str()).await {
+            Ok(()) => {
+                skin_resolution = SkinResolution::Downloaded;
+                skin_index = skins::skin_index().await;
+            }
+            Err(err) => {
+                skin_resolution = SkinResolution::Failed;
+                warnings.push(format!("skin download failed: {err}"));
+            }
+        }
+    }
+    // without a listing the skin container reports missing skins itself
+    let skin_missing = !skin_index.is_empty() && !skin_index.contains(params.skin_name.as_str());
+    let mut skin_suggestions = Vec::new();
+    if skin_missing {
+        skin_suggestions = skin_index.suggest(params.skin_name.as_str(), 3);
+        let err = RenderError::SkinNotFound {
+            name: params.skin_name.as_str().to_string(),
+            suggestions: skin_suggestions.clone(),
+        };
+        if strict {
+            return Err(err);
+        }
+        warnings.push(err.to_string());
+    }
+    timed("skin_index");
+
+    if let Some(map_name) = &params.map_name {
+        if !maps::valid_map_name(map_name) {
+            return Err(ParamError {
+                field: "map_name",
+                value: map_name.clone(),
+                reason: "invalid map name".to_string(),
+                accepted: Vec::new(),
+            }
+            .into());
+        }
+    }
+
+    Ok(Resolved {
+        params,
+        deprecated,
+        strict,
+        warnings,
+        skin_missing,
+        skin_suggestions,
+        skin_from_player_api,
+        player_api_error,
+        skin_resolution,
+    })
+}
+
+fn color_json(
+    color: Option<TeeColor>,
+    part: TeePart,
+    ddnet_color_clamp: bool,
+) -> serde_json::Value {
+    match color {
+        Some(color) => serde_json::json!({
+            "legacy": match color {
+                TeeColor::Legacy(color) => Some(color),
+                TeeColor::Rgba(_) => None,
+            },
+            "hex": color::render_color_hex(color, part, ddnet_color_clamp),
+        }),
+        // the skin's own colors
+        None => serde_json::Value::Null,
+    }
+}
+
+/// Resolves the parameters like a render and returns the final values without rendering.
+pub async fn resolve_params(
+    RawQuery(query): RawQuery,
+) -> Result<Json<serde_json::Value>, RenderError> {
+    let resolved = resolve(query.as_deref().unwrap_or_default(), |_| {}).await?;
+    let params = &resolved.params;
+    let clamp = params.ddnet_color_clamp.unwrap_or_default();
+    let (x, y) = params.camera_pos();
+    Ok(Json(serde_json::json!({
+        "skin": {
+            "requested": params.skin_name.as_str(),
+            "rendered": resolved.rendered_skin(),
+            "fallback": resolved.skin_missing,
+            "resolution": resolved.skin_resolution.name(),
+            "from_player_api": resolved.skin_from_player_api,
+            "player_api_error": resolved.player_api_error.as_ref().map(|err| serde_json::json!({
+                "error": err.kind(),
+                "message": err.to_string(),
+            })),
+            "suggestions": resolved.skin_suggestions,
+        },
+        "body_color": color_json(params.body_color, TeePart::Body, clamp),
+        "feet_color": color_json(params.feet_color, TeePart::Feet, clamp),
+        "camera": {
+            "x": x,
+            "y": y,
+            "zoom": params.zoom(),
+            "source": params.camera_source().name(),
+        },
+        "tee": params.tee_pos(),
+        "map_name": params.map_name(),
+        "eyes": params::eye_name(params.eyes()),
+        "weapon": params.weapon().map(params::weapon_name),
+        "emoticon": params.emoticon().map(params::emoticon_name),
+        "buffs": params.buffs().into_iter().map(params::buff_name).collect::<Vec<_>>(),
+        "preset": params.preset().map(params::preset_name),
+        "format": params::format_name(params.output_format()),
+        "strict": resolved.strict,
+        "warnings": resolved.warnings,
+    })))
+}
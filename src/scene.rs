@@ -0,0 +1,322 @@
+use math::math::{normalize, vector::vec2};
+use serde::Deserialize;
+
+use crate::{
+    params::{parse_projectile, DIR_X_RANGE, DIR_Y_RANGE, POS_RANGE},
+    physics::{self, Projectile, LASER_REACH, TICKS_PER_SECOND, TILE_SIZE},
+};
+
+/// Upper bound of pickups in a scene.
+pub const MAX_PICKUPS: usize = 32;
+/// Upper bound of projectiles and laser beams in a scene.
+pub const MAX_PROJECTILES: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagTeam {
+    Red,
+    Blue,
+}
+
+pub const FLAG_TEAMS: [FlagTeam; 2] = [FlagTeam::Red, FlagTeam::Blue];
+
+pub fn flag_team_name(team: FlagTeam) -> &'static str {
+    match team {
+        FlagTeam::Red => "red",
+        FlagTeam::Blue => "blue",
+    }
+}
+
+/// A flag at a map position or carried by the Tee.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flag {
+    pub team: FlagTeam,
+    /// The map position in tiles, `None` if carried
+    pub pos: Option<vec2>,
+}
+
+/// Carried flags stand on the back of the Tee like ingame,
+/// in tiles relative to the Tee.
+pub const FLAG_CARRY_OFFSET: (f32, f32) = (-0.25, -0.25);
+/// Carried flags lean back, in radians.
+pub const FLAG_CARRY_ANGLE: f32 = -std::f32::consts::FRAC_PI_4 / 2.0;
+
+impl Flag {
+    /// Where the flag is drawn, carried flags lean away from the cursor.
+    pub fn render_pos(&self, tee_pos: vec2, dir_x: f32) -> (vec2, f32) {
+        match self.pos {
+            Some(pos) => (pos, 0.0),
+            None => {
+                let side = if dir_x < 0.0 { -1.0 } else { 1.0 };
+                (
+                    vec2::new(
+                        tee_pos.x + FLAG_CARRY_OFFSET.0 * side,
+                        tee_pos.y + FLAG_CARRY_OFFSET.1,
+                    ),
+                    FLAG_CARRY_ANGLE * side,
+                )
+            }
+        }
+    }
+}
+
+/// The Tee size the game's indicator offsets are measured at, in tiles.
+const DEFAULT_TEE_SIZE: f32 = 2.0;
+/// The chat bubble and afk indicator are beside the Tee, unlike emoticons above it.
+/// In the game's pixel units relative to the Tee.
+const INDICATOR_OFFSET: (f32, f32) = (24.0, -40.0);
+/// The game draws emoticons this far above their position, in its pixel units.
+const EMOTICON_OFFSET: f32 = 23.0 + 32.0;
+/// Indicators are rendered as emoticons of this age in ticks, after they popped up.
+pub const INDICATOR_TICKS: u64 = 25;
+/// The afk indicator is an emoticon 5 ticks before it is gone,
+/// which the game draws half faded out.
+pub const AFK_TICKS: u64 = 95;
+
+/// Where the emoticon of the chat bubble or afk indicator is rendered, in tiles,
+/// so it is beside the Tee. The offset scales with the Tee, the emoticon keeps its size.
+pub fn indicator_pos(tee_pos: vec2, tee_size: f32) -> vec2 {
+    let scale = tee_size / DEFAULT_TEE_SIZE / TILE_SIZE;
+    vec2::new(
+        tee_pos.x + INDICATOR_OFFSET.0 * scale,
+        tee_pos.y + INDICATOR_OFFSET.1 * scale + EMOTICON_OFFSET / TILE_SIZE,
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickupKind {
+    Heart,
+    Armor,
+    Ninja,
+    Shotgun,
+    Grenade,
+    Laser,
+}
+
+pub const PICKUP_KINDS: [PickupKind; 6] = [
+    PickupKind::Heart,
+    PickupKind::Armor,
+    PickupKind::Ninja,
+    PickupKind::Shotgun,
+    PickupKind::Grenade,
+    PickupKind::Laser,
+];
+
+pub fn pickup_kind_name(kind: PickupKind) -> &'static str {
+    match kind {
+        PickupKind::Heart => "heart",
+        PickupKind::Armor => "armor",
+        PickupKind::Ninja => "ninja",
+        PickupKind::Shotgun => "shotgun",
+        PickupKind::Grenade => "grenade",
+        PickupKind::Laser => "laser",
+    }
+}
+
+fn find<T: Copy>(value: &str, variants: &[T], name: impl Fn(T) -> &'static str) -> Option<T> {
+    let value = value.trim();
+    variants
+        .iter()
+        .copied()
+        .find(|v| name(*v).eq_ignore_ascii_case(value))
+}
+
+pub fn parse_flag_team(value: &str) -> Option<FlagTeam> {
+    find(value, &FLAG_TEAMS, flag_team_name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pickup {
+    pub kind: PickupKind,
+    /// The map position in tiles
+    pub pos: vec2,
+}
+
+/// One pickup as `kind:x:y`, e.g. `heart:10.5:20`.
+fn parse_pickup(value: &str) -> Option<Pickup> {
+    let mut parts = value.split(':');
+    let kind = find(parts.next()?, &PICKUP_KINDS, pickup_kind_name)?;
+    let x = parts.next()?.trim().parse::<f32>().ok()?;
+    let y = parts.next()?.trim().parse::<f32>().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Pickup {
+        kind,
+        pos: vec2::new(
+            POS_RANGE.sanitize(Some(x), 0.0),
+            POS_RANGE.sanitize(Some(y), 0.0),
+        ),
+    })
+}
+
+/// A comma separated list of pickups, fails if any pickup is invalid.
+pub fn parse_pickups(value: &str) -> Option<Vec<Pickup>> {
+    value
+        .split(',')
+        .filter(|pickup| !pickup.trim().is_empty())
+        .map(parse_pickup)
+        .collect()
+}
+
+/// The valid pickups of the list, at most [`MAX_PICKUPS`].
+pub fn pickups(value: &str) -> Vec<Pickup> {
+    value
+        .split(',')
+        .filter_map(parse_pickup)
+        .take(MAX_PICKUPS)
+        .collect()
+}
+
+/// A part of the scene like post processing draws it, see `overlay::draw_scene`.
+/// Positions are in tiles relative to the camera.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneItem {
+    /// A flag standing on `pos`, leaning by `angle` in radians
+    Flag {
+        team: FlagTeam,
+        pos: vec2,
+        angle: f32,
+    },
+    Pickup {
+        kind: PickupKind,
+        pos: vec2,
+    },
+    Projectile(SceneProjectile),
+    /// A mark of the nameplate centered on `pos`, `size` tiles wide
+    Mark {
+        mark: NameplateMark,
+        pos: vec2,
+        size: f32,
+    },
+}
+
+/// The marks of the game above nameplates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameplateMark {
+    Friend,
+    Authed,
+}
+
+/// A projectile of the `projectiles` array of the JSON render body, e.g.
+/// `{"type": "grenade", "x": 10, "y": 20, "dir_x": 1, "dir_y": -0.5, "lifetime": 12}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProjectileParams {
+    /// `gun`, `shotgun`, `grenade` or `laser`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Map pos x where it was fired
+    pub x: f32,
+    /// Map pos y where it was fired
+    pub y: f32,
+    /// Direction x it was fired in
+    pub dir_x: Option<f32>,
+    /// Direction y it was fired in
+    pub dir_y: Option<f32>,
+    /// Ticks since it was fired, moves it along its path and spins grenades
+    pub lifetime: Option<u64>,
+    /// Map pos x where a laser beam ends, defaults to the laser's reach
+    pub to_x: Option<f32>,
+    /// Map pos y where a laser beam ends
+    pub to_y: Option<f32>,
+}
+
+/// A projectile like it is drawn, in tiles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SceneProjectile {
+    /// A gun, shotgun or grenade projectile, rotated by `angle` in radians
+    Flying {
+        projectile: Projectile,
+        pos: vec2,
+        angle: f32,
+    },
+    Laser {
+        from: vec2,
+        to: vec2,
+    },
+}
+
+impl SceneProjectile {
+    /// The projectile moved by `offset` tiles.
+    pub fn moved(self, offset: vec2) -> Self {
+        let moved = |pos: vec2| vec2::new(pos.x + offset.x, pos.y + offset.y);
+        match self {
+            Self::Flying {
+                projectile,
+                pos,
+                angle,
+            } => Self::Flying {
+                projectile,
+                pos: moved(pos),
+                angle,
+            },
+            Self::Laser { from, to } => Self::Laser {
+                from: moved(from),
+                to: moved(to),
+            },
+        }
+    }
+}
+
+/// Grenades spin twice per second like ingame.
+const GRENADE_SPIN: f32 = std::f32::consts::TAU * 2.0;
+
+impl ProjectileParams {
+    /// Sanitizes the projectile and moves it along its path, like `CalcPos` of the game.
+    /// `None` if the type is unknown.
+    pub fn scene_projectile(&self) -> Option<SceneProjectile> {
+        let projectile = parse_projectile(&self.kind)?;
+        let start = vec2::new(
+            POS_RANGE.sanitize(Some(self.x), 0.0),
+            POS_RANGE.sanitize(Some(self.y), 0.0),
+        );
+        let mut dir = vec2::new(
+            DIR_X_RANGE.sanitize(self.dir_x, 0.0),
+            DIR_Y_RANGE.sanitize(self.dir_y, 0.0),
+        );
+        if dir.x.abs() < 0.001 && dir.y.abs() < 0.001 {
+            dir.x = 1.0;
+        }
+        let dir = normalize(&dir);
+
+        let Some(tuning) = projectile.tuning() else {
+            let reach = LASER_REACH / TILE_SIZE;
+            let to = vec2::new(
+                POS_RANGE.sanitize(self.to_x.or(Some(start.x + dir.x * reach)), start.x),
+                POS_RANGE.sanitize(self.to_y.or(Some(start.y + dir.y * reach)), start.y),
+            );
+            return Some(SceneProjectile::Laser { from: start, to });
+        };
+        // projectiles are gone after their lifetime
+        let ticks = self
+            .lifetime
+            .unwrap_or_default()
+            .min((tuning.lifetime * TICKS_PER_SECOND) as u64);
+        let time = ticks as f32 / TICKS_PER_SECOND;
+        let offset = physics::position(dir, &tuning, time);
+        let pos = vec2::new(
+            start.x + offset.x / TILE_SIZE,
+            start.y + offset.y / TILE_SIZE,
+        );
+        let angle = if projectile == Projectile::Grenade {
+            time * GRENADE_SPIN
+        } else {
+            // along the curve, like the velocity of the game
+            let next = physics::position(dir, &tuning, time + 1.0 / TICKS_PER_SECOND);
+            (next.y - offset.y).atan2(next.x - offset.x)
+        };
+        Some(SceneProjectile::Flying {
+            projectile,
+            pos,
+            angle,
+        })
+    }
+}
+
+/// The projectiles with known types, at most [`MAX_PROJECTILES`].
+pub fn projectiles(projectiles: &[ProjectileParams]) -> Vec<SceneProjectile> {
+    projectiles
+        .iter()
+        .filter_map(ProjectileParams::scene_projectile)
+        .take(MAX_PROJECTILES)
+        .collect()
+}
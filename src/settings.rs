@@ -0,0 +1,349 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use axum::Json;
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Text,
+    /// Comma separated values
+    List,
+    Bool,
+    Int {
+        max: u64,
+    },
+    Float,
+    /// Text that is never logged or served
+    Secret,
+}
+
+const UINT: Kind = Kind::Int { max: u64::MAX };
+
+/// Every environment variable the app reads, they are documented where they are read.
+const SETTINGS: &[(&str, Kind)] = &[
+    // server
+    (
+        "PORT",
+        Kind::Int {
+            max: u16::MAX as u64,
+        },
+    ),
+    ("BIND", Kind::List),
+    ("TRUST_FORWARDED_FOR", Kind::Bool),
+    ("CORS_ORIGINS", Kind::List),
+    ("CORS_MAX_AGE_SECS", UINT),
+    ("PLAYGROUND_ENABLED", Kind::Bool),
+    ("SHUTDOWN_DRAIN_SECS", UINT),
+    ("ADMIN_TOKEN", Kind::Secret),
+    ("ALLOW_ANONYMOUS", Kind::Bool),
+    ("SIGNING_KEYS", Kind::Secret),
+    ("SIGNING_KEYS_REVOKED", Kind::Secret),
+    ("SIGNING_CLOCK_SKEW", UINT),
+    // images
+    (
+        "WIDTH",
+        Kind::Int {
+            max: u32::MAX as u64,
+        },
+    ),
+    (
+        "HEIGHT",
+        Kind::Int {
+            max: u32::MAX as u64,
+        },
+    ),
+    ("DATA_DIR", Kind::Text),
+    ("NAMEPLATE_MAX_CHARS", UINT),
+    ("PRESETS_FILE", Kind::Text),
+    ("CAMERAS_FILE", Kind::Text),
+    // rendering
+    ("RENDER_QUEUE_DEPTH", UINT),
+    ("RENDERER_POOL_SIZE", UINT),
+    ("WARM_UP", Kind::Bool),
+    ("GPU_DEVICE", Kind::Text),
+    ("GPU_SOFTWARE_FALLBACK", Kind::Bool),
+    ("GPU_SOFTWARE_ICD", Kind::Text),
+    ("RENDER_TIMEOUT_MS", UINT),
+    ("RENDER_HARD_LIMIT_MS", UINT),
+    ("LOAD_QUEUE_FULL", UINT),
+    ("LOAD_QUEUE_WAIT_FULL_MS", UINT),
+    ("LOAD_DEGRADED_THRESHOLD", Kind::Float),
+    ("LOAD_SHED_ENABLED", Kind::Bool),
+    ("LOAD_SHED_ENGAGE", Kind::Float),
+    ("LOAD_SHED_DISENGAGE", Kind::Float),
+    ("HEALTH_PROBE_INTERVAL_SECS", UINT),
+    ("HEALTH_PROBE_MAX_AGE_SECS", UINT),
+    ("HEALTH_STALL_SECS", UINT),
+    ("DEVICE_RECOVERY_MAX", UINT),
+    ("DEVICE_RECOVERY_WINDOW_SECS", UINT),
+    // rate limits
+    ("RATE_LIMIT_PER_SEC", Kind::Float),
+    ("RATE_LIMIT_BURST", Kind::Float),
+    // caches
+    ("RENDER_CACHE_MB", UINT),
+    ("RENDER_CACHE_TTL_SECS", UINT),
+    ("RENDER_CACHE_STALE_SECS", UINT),
+    ("RENDER_CACHE_POLICY", Kind::Text),
+    ("HTTP_CACHE_MAX_AGE_SECS", UINT),
+    ("HTTP_CACHE_PLAYER_API_MAX_AGE_SECS", UINT),
+    ("MAX_LOADED_MAPS", UINT),
+    ("CONTAINER_KEEP_ALIVE_SECS", UINT),
+    ("CONTAINER_MAX_ENTRIES", UINT),
+    // maps and skins
+    ("MAP_DOWNLOAD_ENABLED", Kind::Bool),
+    ("MAP_DOWNLOAD_URL", Kind::Text),
+    ("MAP_DOWNLOAD_MAX_MB", UINT),
+    ("MAP_DOWNLOAD_TIMEOUT_SECS", UINT),
+    ("SKIN_DOWNLOAD_ENABLED", Kind::Bool),
+    ("SKIN_DOWNLOAD_URLS", Kind::List),
+    ("SKIN_DOWNLOAD_TIMEOUT_SECS", UINT),
+    ("SKIN_UPLOAD_ENABLED", Kind::Bool),
+    ("SKIN_UPLOAD_MAX_KB", UINT),
+    ("SKIN_UPLOAD_TTL_SECS", UINT),
+    ("SKIN_UPLOAD_PER_IP", UINT),
+    ("SKIN_URL_HOSTS", Kind::List),
+    ("SKIN_URL_TIMEOUT_SECS", UINT),
+    // upstream and player apis
+    ("UPSTREAM_CONNECT_TIMEOUT_SECS", UINT),
+    ("UPSTREAM_REQUEST_TIMEOUT_SECS", UINT),
+    ("UPSTREAM_HOST_TIMEOUTS", Kind::List),
+    ("UPSTREAM_BREAKER_FAILURES", UINT),
+    ("UPSTREAM_BREAKER_OPEN_SECS", UINT),
+    ("PLAYER_API_PROVIDERS", Kind::List),
+    ("PLAYER_API_CACHE_TTL_SECS", UINT),
+    ("PLAYER_API_NEGATIVE_TTL_SECS", UINT),
+    ("PLAYER_API_DDSTATS_TIMEOUT_SECS", UINT),
+    ("PLAYER_API_SKINS_TW_TIMEOUT_SECS", UINT),
+    ("PLAYER_API_DDSTATS_URL", Kind::Text),
+    ("PLAYER_API_SKINS_TW_URL", Kind::Text),
+    // discord
+    ("DISCORD_TOKEN", Kind::Secret),
+    ("GUILD_ID", Kind::List),
+    ("DISCORD_GLOBAL_COMMANDS", Kind::Bool),
+    ("DISCORD_MAINTAINERS", Kind::List),
+    ("DISCORD_MODERATOR_ROLES", Kind::List),
+    ("DISCORD_COOLDOWN_SECS", Kind::Float),
+    ("DISCORD_COOLDOWN_BURST", Kind::Float),
+    ("DISCORD_GUILD_PER_MIN", Kind::Float),
+    ("DISCORD_GUILD_BURST", Kind::Float),
+    ("PREFS_DB", Kind::Text),
+    // logging
+    ("RUST_LOG", Kind::Text),
+    ("VK_ICD_FILENAMES", Kind::Text),
+    ("DDPG_CONFIG", Kind::Text),
+];
+
+fn find(name: &str) -> Option<(&'static str, Kind)> {
+    SETTINGS
+        .iter()
+        .copied()
+        .find(|(setting, _)| *setting == name)
+}
+
+fn validate(name: &str, kind: Kind, value: &str) -> Result<(), String> {
+    let value = value.trim();
+    match kind {
+        Kind::Text | Kind::List | Kind::Secret => Ok(()),
+        Kind::Bool => value
+            .parse::<bool>()
+            .map(|_| ())
+            .map_err(|_| format!("{name}: {value:?} is not true or false")),
+        Kind::Int { max } => match value.parse::<u64>() {
+            Ok(int) if int <= max => Ok(()),
+            Ok(_) => Err(format!("{name}: {value} is larger than {max}")),
+            Err(_) => Err(format!("{name}: {value:?} is not a positive integer")),
+        },
+        Kind::Float => match value.parse::<f64>() {
+            Ok(float) if float.is_finite() => Ok(()),
+            _ => Err(format!("{name}: {value:?} is not a number")),
+        },
+    }
+}
+
+/// Where a setting's value comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    File,
+    Env,
+}
+
+/// The resolved settings, see [`load`].
+#[derive(Debug, Default)]
+pub struct Config {
+    /// The config file, if one was read
+    file: Option<PathBuf>,
+    values: BTreeMap<&'static str, (String, Source)>,
+}
+
+impl Config {
+    /// The settings as JSON without the values of secrets.
+    pub fn redacted(&self) -> serde_json::Value {
+        let settings: serde_json::Map<String, serde_json::Value> = self
+            .values
+            .iter()
+            .map(|(name, (value, source))| {
+                let value = match find(name) {
+                    Some((_, Kind::Secret)) => "<redacted>".to_string(),
+                    _ => value.clone(),
+                };
+                let source = match source {
+                    Source::File => "file",
+                    Source::Env => "env",
+                };
+                (
+                    name.to_string(),
+                    serde_json::json!({ "value": value, "source": source }),
+                )
+            })
+            .collect();
+        serde_json::json!({ "file": self.file, "settings": settings })
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// A toml value as environment variable, arrays are comma separated.
+fn env_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(env_value)
+            .collect::<Option<Vec<_>>>()
+            .map(|values| values.join(",")),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// The settings of the config file at `path`, a missing file is only a problem if it's `explicit`.
+fn read_file(
+    path: &Path,
+    explicit: bool,
+    problems: &mut Vec<String>,
+) -> (Option<PathBuf>, BTreeMap<&'static str, String>) {
+    let mut file_values = BTreeMap::new();
+    let mut file = None;
+    match std::fs::read_to_string(path) {
+        Ok(content) => {
+            file = Some(path.to_path_buf());
+            match content.parse::<toml::Table>() {
+                Ok(table) => {
+                    for (key, value) in table {
+                        let Some((name, _)) = find(&key.to_ascii_uppercase()) else {
+                            problems.push(format!("{}: unknown setting {key}", path.display()));
+                            continue;
+                        };
+                        match env_value(&value) {
+                            Some(value) => {
+                                file_values.insert(name, value);
+                            }
+                            None => problems.push(format!(
+                                "{}: {key} must be a string, number, bool or array",
+                                path.display()
+                            )),
+                        }
+                    }
+                }
+                Err(err) => problems.push(format!("{}: {err}", path.display())),
+            }
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound && !explicit => {}
+        Err(err) => problems.push(format!("{} can't be read: {err}", path.display())),
+    }
+    (file, file_values)
+}
+
+/// Reads the config file, its keys are the environment variables in lower case,
+/// e.g. `port = 3002` or `guild_id = [123, 456]`. The environment overrides the file.
+/// `path` is `--config`, otherwise `DDPG_CONFIG` or `config.toml` if it exists.
+/// Must run before other threads start, since it sets environment variables.
+pub fn load(path: Option<&Path>) -> Result<&'static Config, Vec<String>> {
+    let explicit = path
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var("DDPG_CONFIG").ok().map(PathBuf::from));
+    let path = explicit
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("config.toml"));
+
+    let mut problems = Vec::new();
+    let (file, mut file_values) = read_file(&path, explicit.is_some(), &mut problems);
+
+    let mut config = Config {
+        file,
+        values: BTreeMap::new(),
+    };
+    for &(name, kind) in SETTINGS {
+        let (value, source) = match std::env::var(name) {
+            Ok(value) => (value, Source::Env),
+            Err(_) => match file_values.remove(name) {
+                Some(value) => {
+                    // the modules read their settings from the environment
+                    std::env::set_var(name, &value);
+                    (value, Source::File)
+                }
+                None => continue,
+            },
+        };
+        if let Err(problem) = validate(name, kind, &value) {
+            problems.push(problem);
+        }
+        config.values.insert(name, (value, source));
+    }
+
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// Resolves `names` again like [`load`], for settings that can change at runtime.
+/// Settings that came from the environment keep their value, the others are read
+/// from the config file again. Unset settings are missing.
+/// `GET /admin/config` keeps showing the values of the start.
+pub fn reread(names: &[&str]) -> Result<BTreeMap<&'static str, String>, Vec<String>> {
+    let config = CONFIG.get();
+    let mut problems = Vec::new();
+    let mut file_values = match config.and_then(|config| config.file.as_deref()) {
+        Some(path) => read_file(path, true, &mut problems).1,
+        None => BTreeMap::new(),
+    };
+    let mut values = BTreeMap::new();
+    for (name, kind) in names.iter().filter_map(|name| find(name)) {
+        let from_env = config
+            .and_then(|config| config.values.get(name))
+            .is_some_and(|(_, source)| *source == Source::Env);
+        let value = match from_env {
+            true => std::env::var(name).ok(),
+            false => file_values.remove(name),
+        };
+        let Some(value) = value else {
+            continue;
+        };
+        match validate(name, kind, &value) {
+            Ok(()) => {
+                values.insert(name, value);
+            }
+            Err(problem) => problems.push(problem),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(values)
+}
+
+/// `GET /admin/config`, the resolved settings without secrets.
+pub async fn config() -> Json<serde_json::Value> {
+    Json(
+        CONFIG
+            .get()
+            .map(Config::redacted)
+            .unwrap_or_else(|| Config::default().redacted()),
+    )
+}
@@ -0,0 +1,292 @@
+use game_interface::types::emoticons::{EmoticonType, IntoEnumIterator};
+use tokio::task::JoinSet;
+
+use crate::{
+    error::RenderError,
+    frame::Frame,
+    overlay,
+    params::{self, OutputFormat, OutputSize, RenderParams, EYES},
+    physics::TICKS_PER_SECOND,
+    render_worker::{Priority, RenderTiming, Rendered},
+};
+
+/// Upper bound of cells, every cell is a render.
+pub const MAX_CELLS: u32 = 16;
+/// Upper bound of the padding between cells in pixels.
+pub const MAX_PADDING: u32 = 64;
+/// Cells of the axes that have no natural count.
+const DEFAULT_CELLS: u32 = 8;
+/// Glyph pixels of labels are this many pixels.
+const LABEL_SCALE: u32 = 2;
+
+/// The defaults of `GET /emoticons_preview`, a labeled grid of all emoticons close to the Tee.
+pub const EMOTICONS_PREVIEW: [(&str, &str); 7] = [
+    ("sheet_labels", "true"),
+    ("sheet_columns", "4"),
+    ("sheet_padding", "8"),
+    ("transparent", "true"),
+    ("zoom", "0.3"),
+    ("width", "256"),
+    ("height", "256"),
+];
+
+/// Adds the parameters of `/emoticons_preview` unless they were given,
+/// the sheet is always a sheet of emoticons.
+pub fn emoticons_preview_pairs(pairs: &mut Vec<(String, String)>) {
+    pairs.retain(|(k, _)| k != "sheet");
+    pairs.push((
+        "sheet".to_string(),
+        sheet_axis_name(SheetAxis::Emoticon).to_string(),
+    ));
+    for (k, v) in EMOTICONS_PREVIEW {
+        if !pairs.iter().any(|(key, _)| key == k) {
+            pairs.push((k.to_string(), v.to_string()));
+        }
+    }
+}
+
+/// The parameter that differs between the cells of a sprite sheet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SheetAxis {
+    /// Cursor directions, clockwise starting to the right
+    Direction,
+    Eyes,
+    Emoticon,
+    /// Frames over one second
+    Animation,
+}
+
+pub const SHEET_AXES: [SheetAxis; 4] = [
+    SheetAxis::Direction,
+    SheetAxis::Eyes,
+    SheetAxis::Emoticon,
+    SheetAxis::Animation,
+];
+
+pub fn sheet_axis_name(axis: SheetAxis) -> &'static str {
+    match axis {
+        SheetAxis::Direction => "direction",
+        SheetAxis::Eyes => "eyes",
+        SheetAxis::Emoticon => "emoticon",
+        SheetAxis::Animation => "animation",
+    }
+}
+
+impl SheetAxis {
+    /// The most cells that differ, e.g. there are only 6 eyes.
+    fn max_cells(self) -> u32 {
+        match self {
+            SheetAxis::Direction | SheetAxis::Animation => MAX_CELLS,
+            SheetAxis::Eyes => EYES.len() as u32,
+            SheetAxis::Emoticon => EmoticonType::iter().count() as u32,
+        }
+        .min(MAX_CELLS)
+    }
+
+    fn default_cells(self) -> u32 {
+        match self {
+            SheetAxis::Direction | SheetAxis::Animation => DEFAULT_CELLS,
+            SheetAxis::Eyes | SheetAxis::Emoticon => self.max_cells(),
+        }
+    }
+
+    /// What differs in the cell `index` of `count` cells.
+    fn label(self, index: u32, count: u32) -> String {
+        let progress = index as f32 / count as f32;
+        match self {
+            SheetAxis::Direction => format!("{}", (progress * 360.0).round()),
+            SheetAxis::Eyes => params::eye_name(EYES[index as usize]).to_string(),
+            SheetAxis::Emoticon => EmoticonType::iter()
+                .nth(index as usize)
+                .map(|emoticon| params::emoticon_name(emoticon).to_string())
+                .unwrap_or_default(),
+            SheetAxis::Animation => format!("{}ms", (progress * 1000.0) as u64),
+        }
+    }
+
+    /// Sets the parameter of the cell `index` of `count` cells.
+    fn apply(self, params: &mut RenderParams, index: u32, count: u32) {
+        let progress = index as f32 / count as f32;
+        match self {
+            SheetAxis::Direction => {
+                let angle = progress * std::f32::consts::TAU;
+                params.dir_x = Some(angle.cos());
+                params.dir_y = Some(angle.sin());
+            }
+            SheetAxis::Eyes => {
+                params.eyes = Some(params::eye_name(EYES[index as usize]).to_string());
+            }
+            SheetAxis::Emoticon => {
+                params.emoticon = EmoticonType::iter()
+                    .nth(index as usize)
+                    .map(|emoticon| params::emoticon_name(emoticon).to_string());
+            }
+            SheetAxis::Animation => {
+                let ticks = (progress * TICKS_PER_SECOND) as u64;
+                params.time = Some(params.time.unwrap_or_default() + (progress * 1000.0) as u64);
+                params.game_ticks = Some(params.game_ticks() + ticks);
+                params.emoticon_ticks = Some(params.emoticon_ticks() + ticks);
+            }
+        }
+    }
+}
+
+/// A grid of cells, filled row by row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sheet {
+    pub axis: SheetAxis,
+    pub cells: u32,
+    pub columns: u32,
+    /// Pixels between cells
+    pub padding: u32,
+    /// Whether every cell is labeled with what differs below it
+    pub labels: bool,
+}
+
+impl Sheet {
+    /// Sanitizes the requested grid.
+    pub fn new(
+        axis: SheetAxis,
+        cells: Option<u32>,
+        columns: Option<u32>,
+        padding: Option<u32>,
+        labels: Option<bool>,
+    ) -> Self {
+        let cells = cells
+            .unwrap_or(axis.default_cells())
+            .clamp(1, axis.max_cells());
+        Self {
+            axis,
+            cells,
+            columns: columns.unwrap_or(cells).clamp(1, cells),
+            padding: padding.unwrap_or_default().min(MAX_PADDING),
+            labels: labels.unwrap_or_default(),
+        }
+    }
+
+    pub fn rows(&self) -> u32 {
+        self.cells.div_ceil(self.columns)
+    }
+
+    fn label_height(&self) -> u32 {
+        if self.labels {
+            overlay::label_height(LABEL_SCALE)
+        } else {
+            0
+        }
+    }
+
+    /// The position of a cell in the sheet, its label is below.
+    fn cell_pos(&self, index: u32, cell_width: u32, cell_height: u32) -> (u32, u32) {
+        (
+            index % self.columns * (cell_width + self.padding),
+            index / self.columns * (cell_height + self.label_height() + self.padding),
+        )
+    }
+
+    pub fn size(&self, cell_width: u32, cell_height: u32) -> (u32, u32) {
+        (
+            self.columns * (cell_width + self.padding) - self.padding,
+            self.rows() * (cell_height + self.label_height() + self.padding) - self.padding,
+        )
+    }
+}
+
+/// Renders every cell and composites them into one image.
+/// Cells are rendered as png and the sheet is encoded in the requested format.
+pub async fn render(
+    params: RenderParams,
+    sheet: Sheet,
+    output_size: Option<OutputSize>,
+    fast: bool,
+    priority: Priority,
+) -> Result<Rendered, RenderError> {
+    let format = params.output_format();
+    let matte = params.matte_color();
+    let png_options = params.png_options();
+
+    let mut cells = JoinSet::new();
+    for index in 0..sheet.cells {
+        let mut cell = params.clone();
+        cell.sheet = None;
+        cell.format = None;
+        cell.matte = None;
+        cell.png_compression = None;
+        cell.quantize = None;
+        sheet.axis.apply(&mut cell, index, sheet.cells);
+        cells.spawn(async move {
+            crate::render_single(cell, output_size, fast, priority)
+                .await
+                .map(|rendered| (index, rendered))
+        });
+    }
+    let mut rendered = Vec::with_capacity(sheet.cells as usize);
+    while let Some(cell) = cells.join_next().await {
+        rendered.push(cell.map_err(|err| RenderError::Internal(err.to_string()))??);
+    }
+    rendered.sort_by_key(|(index, _)| *index);
+
+    let mut warnings: Vec<String> = Vec::new();
+    let mut timing = RenderTiming::default();
+    let mut pngs = Vec::with_capacity(rendered.len());
+    for (index, cell) in rendered {
+        for warning in cell.warnings {
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        timing.queue_wait = timing.queue_wait.max(cell.timing.queue_wait);
+        timing.render += cell.timing.render;
+        pngs.push((index, cell.png));
+    }
+
+    let img = tokio::task::spawn_blocking(move || {
+        let frames = pngs
+            .iter()
+            .map(|(index, png)| Ok((*index, Frame::decode_png(png)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        // all cells are rendered with the same size
+        let (cell_width, cell_height) = frames
+            .first()
+            .map_or((1, 1), |(_, frame)| (frame.width, frame.height));
+        let (width, height) = sheet.size(cell_width, cell_height);
+        let mut frame = Frame::transparent(width, height);
+        for (index, cell) in &frames {
+            let (x, y) = sheet.cell_pos(*index, cell_width, cell_height);
+            frame.paste(cell, x, y);
+            if sheet.labels {
+                let label = sheet.axis.label(*index, sheet.cells);
+                overlay::draw_label(
+                    &mut frame,
+                    &label,
+                    x,
+                    y + cell_height,
+                    cell_width,
+                    LABEL_SCALE,
+                );
+            }
+        }
+        if let Some(matte) = matte {
+            frame.composite_over(matte);
+        }
+        let dpr = output_size.map(|size| size.dpr);
+        match format {
+            OutputFormat::Png => frame.encode_png_with(dpr, fast, png_options),
+            OutputFormat::Jpeg => frame.encode_jpeg(dpr),
+            OutputFormat::Raw | OutputFormat::Pam => Ok(frame.encode_pam()),
+        }
+    })
+    .await
+    .map_err(|err| RenderError::Internal(err.to_string()))?
+    .map_err(|err| RenderError::Internal(err.to_string()))?;
+
+    Ok(Rendered {
+        png: img,
+        warnings,
+        crop: None,
+        timing,
+        game_layer: Vec::new(),
+        trajectory: None,
+        scene: None,
+    })
+}
@@ -0,0 +1,66 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use tokio::sync::Notify;
+
+use crate::render_worker;
+
+/// `SHUTDOWN_DRAIN_SECS`: how long queued renders may take after
+/// a shutdown signal before the process exits anyway (30 is default)
+static SHUTDOWN_DRAIN: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_secs(
+        std::env::var("SHUTDOWN_DRAIN_SECS")
+            .map_err(|err| anyhow!(err))
+            .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+            .unwrap_or(30),
+    )
+});
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static SHUTDOWN: Notify = Notify::const_new();
+
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::SeqCst)
+}
+
+/// Resolves once a shutdown was requested.
+pub async fn requested() {
+    let notified = SHUTDOWN.notified();
+    if is_shutting_down() {
+        return;
+    }
+    notified.await;
+}
+
+/// Waits for SIGTERM or SIGINT, then stops accepting render jobs.
+/// Queued jobs are still rendered, until the drain timeout exits the process.
+pub async fn listen() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("listening for SIGTERM failed");
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c.await;
+
+    println!("shutting down, draining the render queue");
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    render_worker::close();
+    SHUTDOWN.notify_waiters();
+
+    tokio::time::sleep(*SHUTDOWN_DRAIN).await;
+    println!("draining took longer than {:?}, exiting", *SHUTDOWN_DRAIN);
+    std::process::exit(1);
+}
@@ -0,0 +1,347 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::LazyLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use axum::{
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The key id that is used if a signed url does not specify `kid`.
+pub const DEFAULT_KEY_ID: &str = "default";
+
+/// Marker inserted into the request extensions if the request
+/// carried a valid signature.
+#[derive(Debug, Clone)]
+pub struct SignedRequest {
+    pub key_id: String,
+}
+
+pub struct SigningConfig {
+    /// Key id => secret
+    keys: HashMap<String, Vec<u8>>,
+    /// Key ids that are no longer accepted, even if still configured
+    revoked: HashSet<String>,
+    /// How far the `exp` timestamp may lie in the past
+    clock_skew: Duration,
+    /// Whether requests without a signature are allowed at all
+    allow_anonymous: bool,
+}
+
+/// - `SIGNING_KEYS`: comma separated list of `kid:secret` pairs
+/// - `SIGNING_KEYS_REVOKED`: comma separated list of key ids
+/// - `SIGNING_CLOCK_SKEW`: tolerance in seconds (30 is default)
+/// - `ALLOW_ANONYMOUS`: whether unsigned requests are allowed (true is default)
+pub static SIGNING: LazyLock<SigningConfig> = LazyLock::new(|| {
+    let keys = std::env::var("SIGNING_KEYS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|key| {
+            let (kid, secret) = key.trim().split_once(':')?;
+            Some((kid.to_string(), secret.as_bytes().to_vec()))
+        })
+        .collect();
+    let revoked = std::env::var("SIGNING_KEYS_REVOKED")
+        .unwrap_or_default()
+        .split(',')
+        .map(|kid| kid.trim().to_string())
+        .filter(|kid| !kid.is_empty())
+        .collect();
+    let clock_skew: u64 = std::env::var("SIGNING_CLOCK_SKEW")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(30);
+    let allow_anonymous: bool = std::env::var("ALLOW_ANONYMOUS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true);
+
+    SigningConfig {
+        keys,
+        revoked,
+        clock_skew: Duration::from_secs(clock_skew),
+        allow_anonymous,
+    }
+});
+
+/// Builds the string the signature is computed over:
+/// all raw `key=value` pairs except `sig`, sorted.
+pub fn canonical_query(query: &str) -> String {
+    let mut pairs: Vec<&str> = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .filter(|pair| pair.split('=').next() != Some("sig"))
+        .collect();
+    pairs.sort_unstable();
+    pairs.join("&")
+}
+
+fn mac(secret: &[u8], canonical: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(canonical.as_bytes());
+    mac
+}
+
+/// Signs the canonical form of `query`, which must already contain
+/// `exp` (and `kid` if not the default key).
+/// The result is appended as `&sig=<hex>`.
+pub fn sign(secret: &[u8], query: &str) -> String {
+    hex::encode(mac(secret, &canonical_query(query)).finalize().into_bytes())
+}
+
+fn query_value<'a>(query: &'a str, key: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(k, v)| (k == key).then_some(v))
+}
+
+impl SigningConfig {
+    /// Adds `exp`, `kid` unless it's the default key and `sig` to `query`.
+    pub fn sign_query(&self, kid: &str, query: &str, exp: u64) -> anyhow::Result<String> {
+        if self.revoked.contains(kid) {
+            return Err(anyhow!("signing key {kid} was revoked"));
+        }
+        let secret = self
+            .keys
+            .get(kid)
+            .ok_or_else(|| anyhow!("unknown signing key {kid}"))?;
+        let mut query = if query.is_empty() {
+            format!("exp={exp}")
+        } else {
+            format!("{query}&exp={exp}")
+        };
+        if kid != DEFAULT_KEY_ID {
+            query.push_str(&format!("&kid={kid}"));
+        }
+        let sig = sign(secret, &query);
+        Ok(format!("{query}&sig={sig}"))
+    }
+
+    /// Returns `Ok(None)` if the query is not signed,
+    /// `Ok(Some(kid))` if the signature is valid.
+    pub fn verify(&self, query: &str, now: SystemTime) -> anyhow::Result<Option<String>> {
+        let Some(sig) = query_value(query, "sig") else {
+            return Ok(None);
+        };
+        let kid = query_value(query, "kid").unwrap_or(DEFAULT_KEY_ID);
+        if self.revoked.contains(kid) {
+            return Err(anyhow!("signing key {kid} was revoked"));
+        }
+        let secret = self
+            .keys
+            .get(kid)
+            .ok_or_else(|| anyhow!("unknown signing key {kid}"))?;
+
+        let exp: u64 = query_value(query, "exp")
+            .ok_or_else(|| anyhow!("signed url is missing exp"))?
+            .parse()
+            .map_err(|_| anyhow!("exp must be a unix timestamp"))?;
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        if Duration::from_secs(exp) + self.clock_skew < now {
+            return Err(anyhow!("signed url expired"));
+        }
+
+        let sig = hex::decode(sig).map_err(|_| anyhow!("sig must be hex encoded"))?;
+        mac(secret, &canonical_query(query))
+            .verify_slice(&sig)
+            .map_err(|_| anyhow!("invalid signature"))?;
+
+        Ok(Some(kid.to_string()))
+    }
+}
+
+/// Verifies signed urls before any parameter parsing happens.
+pub async fn verify_signature<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let res = SIGNING.verify(req.uri().query().unwrap_or_default(), SystemTime::now());
+    match res {
+        Ok(Some(key_id)) => {
+            req.extensions_mut().insert(SignedRequest { key_id });
+        }
+        Ok(None) => {
+            if !SIGNING.allow_anonymous {
+                return (StatusCode::UNAUTHORIZED, "A signed url is required").into_response();
+            }
+        }
+        Err(err) => {
+            return (StatusCode::FORBIDDEN, err.to_string()).into_response();
+        }
+    }
+    let signed = req.extensions().get::<SignedRequest>().cloned();
+    let res = next.run(req).await;
+    // which keys are still used, e.g. before one is revoked
+    if let Some(signed) = signed {
+        log::info!(
+            target: "request",
+            key_id = signed.key_id.as_str(),
+            status = res.status().as_u16();
+            "signed request"
+        );
+    }
+    res
+}
+
+/// Signatures only cover the query, so requests with a body
+/// are only allowed if unsigned requests are.
+pub async fn require_anonymous<B>(req: Request<B>, next: Next<B>) -> Response {
+    if !SIGNING.allow_anonymous {
+        return (
+            StatusCode::UNAUTHORIZED,
+            "A signed url is required, request bodies can't be signed",
+        )
+            .into_response();
+    }
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn config(keys: &[(&str, &str)], revoked: &[&str]) -> SigningConfig {
+        SigningConfig {
+            keys: keys
+                .iter()
+                .map(|(kid, secret)| (kid.to_string(), secret.as_bytes().to_vec()))
+                .collect(),
+            revoked: revoked.iter().map(|kid| kid.to_string()).collect(),
+            clock_skew: Duration::from_secs(30),
+            allow_anonymous: true,
+        }
+    }
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn signed(secret: &str, query: &str) -> String {
+        format!("{query}&sig={}", sign(secret.as_bytes(), query))
+    }
+
+    #[test]
+    fn unsigned_queries_are_anonymous() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret")], &[]);
+        assert_eq!(config.verify("skin=x&exp=1", at(NOW)).unwrap(), None);
+    }
+
+    #[test]
+    fn valid_signatures_are_accepted() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret")], &[]);
+        let query = signed("secret", &format!("skin=x&exp={}", NOW + 60));
+        assert_eq!(
+            config.verify(&query, at(NOW)).unwrap().as_deref(),
+            Some(DEFAULT_KEY_ID)
+        );
+    }
+
+    #[test]
+    fn the_parameter_order_is_not_signed() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret")], &[]);
+        let sig = sign(b"secret", &format!("skin=x&zoom=2&exp={}", NOW + 60));
+        let query = format!("exp={}&sig={sig}&zoom=2&skin=x", NOW + 60);
+        assert!(config.verify(&query, at(NOW)).unwrap().is_some());
+    }
+
+    #[test]
+    fn tampered_parameters_are_rejected() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret")], &[]);
+        let query = signed("secret", &format!("skin=x&exp={}", NOW + 60));
+        for tampered in [
+            query.replace("skin=x", "skin=y"),
+            format!("{query}&zoom=2"),
+            query.replace("skin=x&", ""),
+            query.replace(&format!("exp={}", NOW + 60), &format!("exp={}", NOW + 3600)),
+        ] {
+            let err = config.verify(&tampered, at(NOW)).unwrap_err();
+            assert_eq!(err.to_string(), "invalid signature", "{tampered}");
+        }
+    }
+
+    #[test]
+    fn malformed_signatures_are_rejected() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret")], &[]);
+        let exp = NOW + 60;
+        assert!(config
+            .verify(&format!("exp={exp}&sig=xyz"), at(NOW))
+            .is_err());
+        assert!(config.verify("sig=00", at(NOW)).is_err());
+        assert!(config.verify("exp=soon&sig=00", at(NOW)).is_err());
+    }
+
+    #[test]
+    fn expired_signatures_are_rejected_after_the_clock_skew() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret")], &[]);
+        let query = signed("secret", &format!("skin=x&exp={NOW}"));
+        assert!(config.verify(&query, at(NOW + 30)).unwrap().is_some());
+        let err = config.verify(&query, at(NOW + 31)).unwrap_err();
+        assert_eq!(err.to_string(), "signed url expired");
+    }
+
+    #[test]
+    fn keys_can_be_rotated() {
+        let exp = NOW + 60;
+        let old = signed("old secret", &format!("skin=x&exp={exp}&kid=old"));
+        let new = signed("new secret", &format!("skin=x&exp={exp}&kid=new"));
+
+        // both keys are accepted while the rotation is running
+        let rotating = config(&[("old", "old secret"), ("new", "new secret")], &[]);
+        assert_eq!(
+            rotating.verify(&old, at(NOW)).unwrap().as_deref(),
+            Some("old")
+        );
+        assert_eq!(
+            rotating.verify(&new, at(NOW)).unwrap().as_deref(),
+            Some("new")
+        );
+
+        // a url can't claim another key than it was signed with
+        let claimed = old.replace("kid=old", "kid=new");
+        assert!(rotating.verify(&claimed, at(NOW)).is_err());
+
+        let revoked = config(&[("old", "old secret"), ("new", "new secret")], &["old"]);
+        let err = revoked.verify(&old, at(NOW)).unwrap_err();
+        assert_eq!(err.to_string(), "signing key old was revoked");
+        assert!(revoked.verify(&new, at(NOW)).unwrap().is_some());
+
+        let removed = config(&[("new", "new secret")], &[]);
+        let err = removed.verify(&old, at(NOW)).unwrap_err();
+        assert_eq!(err.to_string(), "unknown signing key old");
+    }
+
+    #[test]
+    fn signed_queries_verify() {
+        let config = config(&[(DEFAULT_KEY_ID, "secret"), ("site1", "other")], &[]);
+        let query = config
+            .sign_query(DEFAULT_KEY_ID, "skin=x", NOW + 60)
+            .unwrap();
+        assert!(!query.contains("kid="));
+        assert_eq!(
+            config.verify(&query, at(NOW)).unwrap().as_deref(),
+            Some(DEFAULT_KEY_ID)
+        );
+        let query = config.sign_query("site1", "", NOW + 60).unwrap();
+        assert_eq!(
+            config.verify(&query, at(NOW)).unwrap().as_deref(),
+            Some("site1")
+        );
+    }
+
+    #[test]
+    fn only_active_keys_sign() {
+        let config = config(&[("old", "old secret")], &["old"]);
+        let err = config.sign_query("old", "skin=x", NOW).unwrap_err();
+        assert_eq!(err.to_string(), "signing key old was revoked");
+        let err = config.sign_query("new", "skin=x", NOW).unwrap_err();
+        assert_eq!(err.to_string(), "unknown signing key new");
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::{extract::Query, response::IntoResponse, Json};
+use client_containers::skins::SKIN_CONTAINER_PATH;
+use game_interface::types::resource_key::NetworkResourceKey;
+use serde::{Deserialize, Serialize};
+use urlencoding::encode;
+
+use crate::{
+    containers::{self, ContainerKind},
+    download::{self, Download},
+};
+
+/// How long a skin listing is reused before the filesystem is read again.
+const LISTING_TTL: Duration = Duration::from_secs(30);
+/// Minimum similarity for a skin name to be suggested.
+const SUGGESTION_THRESHOLD: f64 = 0.5;
+
+/// Skin names with a trigram index for "did you mean" suggestions.
+#[derive(Debug, Default)]
+pub struct SkinIndex {
+    /// Sorted skin names
+    names: Vec<String>,
+    /// Size on disk in bytes, same order as `names`
+    sizes: Vec<u64>,
+    /// Normalized names, same order as `names`
+    normalized: Vec<String>,
+    /// Number of trigrams per name, same order as `names`
+    trigram_counts: Vec<usize>,
+    /// Trigram => indices into `names`
+    trigrams: HashMap<[char; 3], Vec<usize>>,
+}
+
+/// Lowercase and treat `_`, `-` and spaces the same.
+fn normalize(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c {
+            '_' | '-' => ' ',
+            c => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn trigrams(normalized: &str) -> Vec<[char; 3]> {
+    let padded: Vec<char> = std::iter::once(' ')
+        .chain(normalized.chars())
+        .chain(std::iter::once(' '))
+        .collect();
+    let mut res: Vec<[char; 3]> = padded.windows(3).map(|w| [w[0], w[1], w[2]]).collect();
+    res.sort_unstable();
+    res.dedup();
+    res
+}
+
+impl SkinIndex {
+    /// `skins` are names with their size on disk.
+    pub fn new(mut skins: Vec<(String, u64)>) -> Self {
+        skins.sort();
+        skins.dedup_by(|(n1, _), (n2, _)| n1 == n2);
+        let (names, sizes): (Vec<String>, Vec<u64>) = skins.into_iter().unzip();
+        let normalized: Vec<String> = names.iter().map(|name| normalize(name)).collect();
+        let mut trigram_counts = Vec::with_capacity(names.len());
+        let mut index: HashMap<[char; 3], Vec<usize>> = Default::default();
+        for (i, name) in normalized.iter().enumerate() {
+            let name_trigrams = trigrams(name);
+            trigram_counts.push(name_trigrams.len());
+            for trigram in name_trigrams {
+                index.entry(trigram).or_default().push(i);
+            }
+        }
+        Self {
+            names,
+            sizes,
+            normalized,
+            trigram_counts,
+            trigrams: index,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// All skins in order with their size.
+    pub fn skins(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.names
+            .iter()
+            .map(|name| name.as_str())
+            .zip(self.sizes.iter().copied())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.names
+            .binary_search_by(|n| n.as_str().cmp(name))
+            .is_ok()
+    }
+
+    /// Returns up to `max` skin names containing `filter`, ignoring case,
+    /// names starting with it first.
+    pub fn search(&self, filter: &str, max: usize) -> Vec<&str> {
+        let filter = normalize(filter);
+        let (mut prefixed, mut contained) = (Vec::new(), Vec::new());
+        for (name, normalized) in self.names.iter().zip(&self.normalized) {
+            if normalized.starts_with(&filter) {
+                prefixed.push(name.as_str());
+                if prefixed.len() == max {
+                    break;
+                }
+            } else if contained.len() < max && normalized.contains(&filter) {
+                contained.push(name.as_str());
+            }
+        }
+        prefixed.extend(contained);
+        prefixed.truncate(max);
+        prefixed
+    }
+
+    /// Returns up to `max` similar skin names, best match first.
+    /// Candidates are preselected by shared trigrams, very short names
+    /// share too few trigrams, so they are compared against all names.
+    pub fn suggest(&self, name: &str, max: usize) -> Vec<String> {
+        let name = normalize(name);
+        let name_trigrams = trigrams(&name);
+
+        let mut shared: HashMap<usize, usize> = Default::default();
+        if name.chars().count() < 4 {
+            shared.extend((0..self.names.len()).map(|i| (i, 0)));
+        }
+        for trigram in &name_trigrams {
+            for &i in self.trigrams.get(trigram).into_iter().flatten() {
+                *shared.entry(i).or_default() += 1;
+            }
+        }
+
+        let mut scored: Vec<(f64, usize)> = shared
+            .into_iter()
+            .map(|(i, shared)| {
+                let union = name_trigrams.len() + self.trigram_counts[i] - shared;
+                let jaccard = shared as f64 / union.max(1) as f64;
+                let levenshtein = strsim::normalized_levenshtein(&name, &self.normalized[i]);
+                (jaccard.max(levenshtein), i)
+            })
+            .filter(|(score, _)| *score >= SUGGESTION_THRESHOLD)
+            .collect();
+        scored.sort_by(|(s1, i1), (s2, i2)| s2.total_cmp(s1).then(i1.cmp(i2)));
+        scored
+            .into_iter()
+            .take(max)
+            .map(|(_, i)| self.names[i].clone())
+            .collect()
+    }
+}
+
+/// The directory the skin container loads from.
+pub fn skins_dir() -> PathBuf {
+    let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string());
+    let container_path: &Path = SKIN_CONTAINER_PATH.as_ref();
+    PathBuf::from(data_dir).join(container_path)
+}
+
+/// Skins are either directories or single png files,
+/// the size of a directory is the size of its files.
+fn read_skins() -> Vec<(String, u64)> {
+    let Ok(entries) = std::fs::read_dir(skins_dir()) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.is_dir() {
+                let size = std::fs::read_dir(&path)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter_map(|file| file.metadata().ok())
+                    .filter(|metadata| metadata.is_file())
+                    .map(|metadata| metadata.len())
+                    .sum();
+                Some((path.file_name()?.to_str()?.to_string(), size))
+            } else if path.extension().is_some_and(|ext| ext == "png") {
+                let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+                Some((path.file_stem()?.to_str()?.to_string(), size))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+static SKIN_INDEX: LazyLock<parking_lot::Mutex<Option<(Instant, Arc<SkinIndex>)>>> =
+    LazyLock::new(Default::default);
+
+/// Forces the next [`skin_index`] call to read the filesystem.
+pub fn invalidate_skin_index() {
+    *SKIN_INDEX.lock() = None;
+}
+
+/// The cached skin listing, refreshed after [`LISTING_TTL`].
+pub async fn skin_index() -> Arc<SkinIndex> {
+    if let Some((at, index)) = SKIN_INDEX.lock().as_ref() {
+        if at.elapsed() < LISTING_TTL {
+            return index.clone();
+        }
+    }
+    let index = Arc::new(
+        tokio::task::spawn_blocking(|| SkinIndex::new(read_skins()))
+            .await
+            .unwrap_or_default(),
+    );
+    *SKIN_INDEX.lock() = Some((Instant::now(), index.clone()));
+    index
+}
+
+/// Downloading of unknown skins.
+/// - `SKIN_DOWNLOAD_ENABLED` (true is default)
+/// - `SKIN_DOWNLOAD_URLS` comma separated urls that are tried in order, `{name}` is
+///   replaced by the skin name (`https://ddnet.org/skins/skin/{name}.png` is default)
+/// - `SKIN_DOWNLOAD_TIMEOUT_SECS` (10 is default)
+struct SkinDownloadConfig {
+    enabled: bool,
+    urls: Vec<String>,
+    timeout: Duration,
+}
+
+static SKIN_DOWNLOAD: LazyLock<SkinDownloadConfig> = LazyLock::new(|| {
+    let enabled: bool = std::env::var("SKIN_DOWNLOAD_ENABLED")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true);
+    let urls = std::env::var("SKIN_DOWNLOAD_URLS")
+        .unwrap_or_else(|_| "https://ddnet.org/skins/skin/{name}.png".to_string())
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect();
+    let timeout: u64 = std::env::var("SKIN_DOWNLOAD_TIMEOUT_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(10);
+    SkinDownloadConfig {
+        enabled,
+        urls,
+        timeout: Duration::from_secs(timeout),
+    }
+});
+
+/// Skins are small, anything bigger is not a skin.
+const MAX_SKIN_BYTES: u64 = 2 * 1024 * 1024;
+
+/// How an unknown skin was resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkinResolution {
+    Local,
+    Downloaded,
+    /// The default skin is rendered instead
+    Failed,
+}
+
+impl SkinResolution {
+    pub fn name(self) -> &'static str {
+        match self {
+            SkinResolution::Local => "local",
+            SkinResolution::Downloaded => "downloaded",
+            SkinResolution::Failed => "failed",
+        }
+    }
+}
+
+/// Downloads a skin that is not on disk into the skin directory,
+/// where the skin container picks it up.
+/// The name must be a valid resource key before it's used in a url or path.
+pub async fn download_skin(name: &str) -> anyhow::Result<()> {
+    if !SKIN_DOWNLOAD.enabled {
+        return Err(anyhow!("skin downloads are disabled"));
+    }
+    if NetworkResourceKey::<24>::try_from(name).is_err()
+        || name.contains(['/', '\\'])
+        || name.contains("..")
+    {
+        return Err(anyhow!("invalid skin name"));
+    }
+    let path = skins_dir().join(format!("{name}.png"));
+    let mut last_err = anyhow!("skin {name} not found");
+    for url in &SKIN_DOWNLOAD.urls {
+        let url = url.replace("{name}", &encode(name));
+        match download::download(&url, &path, MAX_SKIN_BYTES, SKIN_DOWNLOAD.timeout).await {
+            Ok(Download::Stored) => {
+                invalidate_skin_index();
+                return Ok(());
+            }
+            Ok(Download::NotFound) => {}
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SkinsQuery {
+    offset: Option<usize>,
+    /// At most [`MAX_SKINS_LIMIT`]
+    limit: Option<usize>,
+    /// Case insensitive substring of the name
+    filter: Option<String>,
+}
+
+const MAX_SKINS_LIMIT: usize = 1000;
+
+#[derive(Debug, Serialize)]
+struct SkinEntry<'a> {
+    name: &'a str,
+    size: u64,
+    loaded: bool,
+}
+
+/// Lists the skins that can be rendered, paginated.
+pub async fn skins(Query(query): Query<SkinsQuery>) -> impl IntoResponse {
+    let index = skin_index().await;
+    let filter = query.filter.as_deref().map(normalize);
+    let matching: Vec<(&str, u64)> = index
+        .skins()
+        .filter(|(name, _)| {
+            filter
+                .as_deref()
+                .map_or(true, |filter| normalize(name).contains(filter))
+        })
+        .collect();
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(100).min(MAX_SKINS_LIMIT);
+    let skins: Vec<SkinEntry> = matching
+        .iter()
+        .skip(offset)
+        .take(limit)
+        .map(|&(name, size)| SkinEntry {
+            name,
+            size,
+            loaded: containers::is_loaded(ContainerKind::Skins, name),
+        })
+        .collect();
+    Json(serde_json::json!({
+        "total": matching.len(),
+        "offset": offset,
+        "skins": skins,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(names: &[&str]) -> SkinIndex {
+        SkinIndex::new(names.iter().map(|name| (name.to_string(), 0)).collect())
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(normalize(" Santa_Pinky-X "), "santa pinky x");
+        assert_eq!(normalize("ÄRGER"), "ärger");
+    }
+
+    #[test]
+    fn trigrams_are_padded_and_unique() {
+        assert_eq!(
+            trigrams("aaaa"),
+            vec![[' ', 'a', 'a'], ['a', 'a', ' '], ['a', 'a', 'a']]
+        );
+        assert_eq!(trigrams("a"), vec![[' ', 'a', ' ']]);
+        assert_eq!(trigrams(""), Vec::<[char; 3]>::new());
+    }
+
+    #[test]
+    fn the_index_is_sorted_and_unique() {
+        let index = SkinIndex::new(vec![
+            ("twinbop".to_string(), 2),
+            ("default".to_string(), 1),
+            ("twinbop".to_string(), 3),
+        ]);
+        assert_eq!(
+            index.skins().collect::<Vec<_>>(),
+            [("default", 1), ("twinbop", 2)]
+        );
+        assert!(index.contains("twinbop"));
+        assert!(!index.contains("Twinbop"));
+    }
+
+    #[test]
+    fn typos_are_suggested() {
+        let index = index(&["default", "bluekitty", "nanami", "santa_pinky", "twinbop"]);
+        assert_eq!(index.suggest("defualt", 3), ["default"]);
+        assert_eq!(index.suggest("bluekity", 3), ["bluekitty"]);
+        assert_eq!(index.suggest("twinbopp", 3), ["twinbop"]);
+    }
+
+    #[test]
+    fn suggestions_ignore_case() {
+        let index = index(&["default", "bluekitty", "twinbop"]);
+        assert_eq!(index.suggest("DEFAULT", 3), ["default"]);
+        assert_eq!(index.suggest("BlueKitty", 3), ["bluekitty"]);
+    }
+
+    #[test]
+    fn underscores_and_spaces_are_the_same() {
+        let index = index(&["santa_pinky", "coala_bluestripe", "default"]);
+        assert_eq!(index.suggest("santa pinky", 3), ["santa_pinky"]);
+        assert_eq!(index.suggest("santa-pinky", 3), ["santa_pinky"]);
+        assert_eq!(index.suggest("coala bluestripe", 3), ["coala_bluestripe"]);
+    }
+
+    #[test]
+    fn short_names_are_compared_to_every_skin() {
+        let index = index(&["x_ninja", "ninja", "default"]);
+        assert_eq!(index.suggest("nija", 1), ["ninja"]);
+        assert_eq!(index.suggest("ix", 3), Vec::<String>::new());
+    }
+
+    #[test]
+    fn dissimilar_names_are_not_suggested() {
+        let index = index(&["default", "bluekitty", "twinbop"]);
+        assert!(index.suggest("pinky", 3).is_empty());
+        assert!(index.suggest("", 3).is_empty());
+    }
+
+    #[test]
+    fn the_best_matches_come_first() {
+        let index = index(&["brownbear", "brownbear_2", "bluebear"]);
+        assert_eq!(index.suggest("brownbear", 2), ["brownbear", "brownbear_2"]);
+        assert_eq!(index.suggest("brownbear", 1), ["brownbear"]);
+        assert!(index.suggest("brownbear", 0).is_empty());
+    }
+
+    #[test]
+    fn the_search_prefers_prefixes() {
+        let index = index(&["bear_brown", "brownbear", "default", "polar bear"]);
+        assert_eq!(
+            index.search("bear", 5),
+            ["bear_brown", "brownbear", "polar bear"]
+        );
+        assert_eq!(index.search("BEAR", 1), ["bear_brown"]);
+        assert_eq!(index.search("polar_bear", 5), ["polar bear"]);
+    }
+}
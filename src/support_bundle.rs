@@ -0,0 +1,85 @@
+use std::io::{Cursor, Write};
+
+use axum::{
+    extract::RawQuery,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{error::RenderError, params, render_preview, Preview};
+
+/// Renders the query and returns a zip with the image and everything
+/// needed to reproduce it. Only data that is explicitly added here ends up
+/// in the bundle, the raw query (signatures) and tokens never do.
+pub async fn support_bundle(RawQuery(query): RawQuery) -> Response {
+    match create(query.as_deref().unwrap_or_default()).await {
+        Ok(zip) => (
+            [
+                (header::CONTENT_TYPE, "application/zip"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"support-bundle.zip\"",
+                ),
+            ],
+            zip,
+        )
+            .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// The zip of [`support_bundle`].
+pub async fn create(query: &str) -> Result<Vec<u8>, RenderError> {
+    create_from(render_preview(query).await?).await
+}
+
+/// The zip of a preview that was rendered already.
+pub async fn create_from(preview: Preview) -> Result<Vec<u8>, RenderError> {
+    tokio::task::spawn_blocking(move || bundle(&preview))
+        .await
+        .map_err(|err| RenderError::Internal(err.to_string()))?
+        .map_err(|err| RenderError::Internal(err.to_string()))
+}
+
+fn bundle(preview: &Preview) -> anyhow::Result<Vec<u8>> {
+    let params: serde_json::Value = serde_json::from_str(&preview.params)?;
+    let provenance = serde_json::json!({
+        "skin_source": if preview.skin_from_player_api { "player_api" } else { "request" },
+        "skin_found": preview.skin_suggestions.is_empty(),
+        "skin_resolved": preview.skin_resolution.name(),
+        "map_name": preview.map_name,
+        "cache": preview.cache.name(),
+    });
+    let version = serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": crate::version::GIT_COMMIT,
+    });
+    let timings: serde_json::Map<String, serde_json::Value> = preview
+        .timings
+        .iter()
+        .map(|(step, duration)| (step.to_string(), (duration.as_secs_f64() * 1000.0).into()))
+        .collect();
+
+    // the image is compressed already
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let image_name = format!("preview.{}", params::format_name(preview.format));
+    let files: [(&str, Vec<u8>); 6] = [
+        (&image_name, preview.img.clone()),
+        ("params.json", serde_json::to_vec_pretty(&params)?),
+        (
+            "warnings.json",
+            serde_json::to_vec_pretty(&preview.warnings)?,
+        ),
+        ("provenance.json", serde_json::to_vec_pretty(&provenance)?),
+        ("version.json", serde_json::to_vec_pretty(&version)?),
+        ("timings_ms.json", serde_json::to_vec_pretty(&timings)?),
+    ];
+    for (name, data) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(&data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
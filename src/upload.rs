@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::LazyLock,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use axum::{
+    extract::{ConnectInfo, Multipart},
+    http::HeaderMap,
+    Json,
+};
+use hashlink::LruCache;
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+use crate::{download, error::RenderError, frame::Frame, params::ParamError, rate_limit, skins};
+
+/// Uploaded skins and skins from urls are named `upload_<hash>`,
+/// so they never shadow real skins.
+const UPLOAD_PREFIX: &str = "upload_";
+
+/// Skin uploads.
+/// - `SKIN_UPLOAD_ENABLED` (true is default)
+/// - `SKIN_UPLOAD_MAX_KB` maximum size of a skin (256 is default)
+/// - `SKIN_UPLOAD_TTL_SECS` how long an uploaded skin is kept (3600 is default)
+/// - `SKIN_UPLOAD_PER_IP` concurrent uploads per client ip (2 is default)
+struct SkinUploadConfig {
+    enabled: bool,
+    max_bytes: usize,
+    ttl: Duration,
+    per_ip: usize,
+}
+
+static SKIN_UPLOAD: LazyLock<SkinUploadConfig> = LazyLock::new(|| {
+    let enabled: bool = std::env::var("SKIN_UPLOAD_ENABLED")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<bool>().map_err(|err| anyhow!(err)))
+        .unwrap_or(true);
+    let max_kb: usize = std::env::var("SKIN_UPLOAD_MAX_KB")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(256);
+    let ttl: u64 = std::env::var("SKIN_UPLOAD_TTL_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(3600);
+    let per_ip: usize = std::env::var("SKIN_UPLOAD_PER_IP")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<usize>().map_err(|err| anyhow!(err)))
+        .unwrap_or(2);
+    SkinUploadConfig {
+        enabled,
+        max_bytes: max_kb * 1024,
+        ttl: Duration::from_secs(ttl),
+        per_ip: per_ip.max(1),
+    }
+});
+
+/// Running uploads by client ip.
+static UPLOADS: LazyLock<parking_lot::Mutex<HashMap<IpAddr, usize>>> =
+    LazyLock::new(Default::default);
+
+/// Counts as running upload until dropped.
+struct UploadSlot(Option<IpAddr>);
+
+impl UploadSlot {
+    fn acquire(ip: Option<IpAddr>) -> Option<Self> {
+        let Some(ip) = ip else {
+            return Some(Self(None));
+        };
+        let mut uploads = UPLOADS.lock();
+        let running = uploads.entry(ip).or_default();
+        if *running >= SKIN_UPLOAD.per_ip {
+            return None;
+        }
+        *running += 1;
+        Some(Self(Some(ip)))
+    }
+}
+
+impl Drop for UploadSlot {
+    fn drop(&mut self) {
+        if let Some(ip) = self.0 {
+            let mut uploads = UPLOADS.lock();
+            if let Some(running) = uploads.get_mut(&ip) {
+                *running -= 1;
+                if *running == 0 {
+                    uploads.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+pub fn enabled() -> bool {
+    SKIN_UPLOAD.enabled
+}
+
+fn invalid_skin(reason: String) -> RenderError {
+    ParamError {
+        field: "skin",
+        value: String::new(),
+        reason,
+        accepted: vec!["png"],
+    }
+    .into()
+}
+
+/// 0.6 skins are 256x128, high resolution skins keep the ratio.
+fn valid_skin_size(width: u32, height: u32) -> bool {
+    width == height * 2 && width % 256 == 0 && width <= 2048
+}
+
+/// 0.7 skins are split into square part images, which the container can't load.
+fn is_07_part(width: u32, height: u32) -> bool {
+    width == height && width % 256 == 0
+}
+
+/// Stores the png of the `skin` field as temporary skin
+/// and returns its name, which can be rendered like any skin.
+pub async fn upload_skin(
+    // unix socket listeners have no peer address
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<serde_json::Value>, RenderError> {
+    let Some(_slot) = UploadSlot::acquire(rate_limit::client_ip(
+        &headers,
+        peer.map(|ConnectInfo(peer)| peer),
+    )) else {
+        return Err(RenderError::RateLimited {
+            retry_after: Duration::from_secs(1),
+        });
+    };
+
+    let mut png = None;
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| RenderError::InvalidQuery(err.to_string()))?
+    {
+        if field.name() != Some("skin") {
+            continue;
+        }
+        let mut data = Vec::new();
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|err| RenderError::InvalidQuery(err.to_string()))?
+        {
+            data.extend_from_slice(&chunk);
+            if data.len() > SKIN_UPLOAD.max_bytes {
+                return Err(invalid_skin(format!(
+                    "larger than {} KiB",
+                    SKIN_UPLOAD.max_bytes / 1024
+                )));
+            }
+        }
+        png = Some(data);
+        break;
+    }
+    let png = png.ok_or_else(|| invalid_skin("missing".to_string()))?;
+
+    let name = install_skin(png).await?;
+
+    Ok(Json(serde_json::json!({
+        "skin_name": name,
+        "expires_in_secs": SKIN_UPLOAD.ttl.as_secs(),
+    })))
+}
+
+/// Validates the png, converts it to rgba and stores it under
+/// a name derived from its content, so equal skins share a name.
+async fn install_skin(png: Vec<u8>) -> Result<String, RenderError> {
+    let png = tokio::task::spawn_blocking(move || {
+        let frame = Frame::decode_png(&png).map_err(|err| invalid_skin(err.to_string()))?;
+        if is_07_part(frame.width, frame.height) {
+            return Err(invalid_skin(
+                "0.7 skin parts are not supported, expected a 0.6 skin".to_string(),
+            ));
+        }
+        if !valid_skin_size(frame.width, frame.height) {
+            return Err(invalid_skin(format!(
+                "{}x{} is not a skin size, expected 256x128 or a multiple",
+                frame.width, frame.height
+            )));
+        }
+        // old skins are often indexed, grayscale or without alpha,
+        // the container only loads 8 bit rgba
+        frame
+            .encode_png(None, false)
+            .map_err(|err| RenderError::Internal(err.to_string()))
+    })
+    .await
+    .map_err(|err| RenderError::Internal(err.to_string()))??;
+
+    let hash = hex::encode(Sha256::digest(&png));
+    let name = format!("{UPLOAD_PREFIX}{}", &hash[..16]);
+    let path = skins::skins_dir().join(format!("{name}.png"));
+    let store = async {
+        tokio::fs::create_dir_all(skins::skins_dir()).await?;
+        let tmp = path.with_extension("upload");
+        tokio::fs::write(&tmp, &png).await?;
+        tokio::fs::rename(&tmp, &path).await
+    };
+    store
+        .await
+        .map_err(|err| RenderError::Internal(format!("storing the skin failed: {err}")))?;
+    skins::invalidate_skin_index();
+    Ok(name)
+}
+
+/// Skins from urls.
+/// - `SKIN_URL_HOSTS` comma separated hosts skins may be fetched from,
+///   subdomains included (`ddnet.org,skins.tw` is default)
+/// - `SKIN_URL_TIMEOUT_SECS` (10 is default)
+struct SkinUrlConfig {
+    hosts: Vec<String>,
+    timeout: Duration,
+}
+
+static SKIN_URL: LazyLock<SkinUrlConfig> = LazyLock::new(|| {
+    let hosts = std::env::var("SKIN_URL_HOSTS")
+        .unwrap_or_else(|_| "ddnet.org,skins.tw".to_string())
+        .split(',')
+        .map(|host| host.trim().to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect();
+    let timeout: u64 = std::env::var("SKIN_URL_TIMEOUT_SECS")
+        .map_err(|err| anyhow!(err))
+        .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+        .unwrap_or(10);
+    SkinUrlConfig {
+        hosts,
+        timeout: Duration::from_secs(timeout),
+    }
+});
+
+/// Skin names of fetched urls, so a url is only fetched once.
+static SKIN_URLS: LazyLock<parking_lot::Mutex<LruCache<String, String>>> =
+    LazyLock::new(|| parking_lot::Mutex::new(LruCache::new(1024)));
+
+fn allowed_host(url: &Url) -> bool {
+    let Some(host) = url.host_str().map(|host| host.to_ascii_lowercase()) else {
+        return false;
+    };
+    matches!(url.scheme(), "http" | "https")
+        && SKIN_URL.hosts.iter().any(|allowed| {
+            host == *allowed
+                || host
+                    .strip_suffix(allowed.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+}
+
+/// Fetches the skin at `url` and returns the name it's rendered with.
+/// Only allowlisted hosts are fetched.
+pub async fn skin_from_url(url: &str) -> Result<String, RenderError> {
+    let parsed = Url::parse(url).map_err(|err| ParamError {
+        field: "skin_url",
+        value: url.to_string(),
+        reason: err.to_string(),
+        accepted: Vec::new(),
+    })?;
+    if !allowed_host(&parsed) {
+        return Err(RenderError::Forbidden(format!(
+            "skins are not fetched from {}",
+            parsed.host_str().unwrap_or_default()
+        )));
+    }
+
+    let cached = SKIN_URLS.lock().get(url).cloned();
+    if let Some(name) = cached {
+        // the skin may have expired since
+        let path = skins::skins_dir().join(format!("{name}.png"));
+        if tokio::fs::try_exists(path).await.unwrap_or_default() {
+            return Ok(name);
+        }
+    }
+
+    let png = tokio::time::timeout(
+        SKIN_URL.timeout,
+        download::fetch_bytes(url, SKIN_UPLOAD.max_bytes as u64),
+    )
+    .await
+    .map_err(|_| RenderError::Upstream(format!("fetching {url} timed out")))?
+    .map_err(|err| RenderError::Upstream(format!("fetching {url} failed: {err}")))?
+    .ok_or_else(|| RenderError::SkinNotFound {
+        name: url.to_string(),
+        suggestions: Vec::new(),
+    })?;
+    let name = install_skin(png).await?;
+    SKIN_URLS.lock().insert(url.to_string(), name.clone());
+    Ok(name)
+}
+
+/// Removes uploaded skins older than the ttl, forever.
+/// Uses the modification time, so skins of a previous run expire as well.
+pub async fn collect_expired() {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let removed = tokio::task::spawn_blocking(remove_expired)
+            .await
+            .unwrap_or_default();
+        if removed > 0 {
+            println!("removed {removed} expired skin uploads");
+            skins::invalidate_skin_index();
+        }
+    }
+}
+
+fn remove_expired() -> usize {
+    let Ok(entries) = std::fs::read_dir(skins::skins_dir()) else {
+        return 0;
+    };
+    let now = SystemTime::now();
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(UPLOAD_PREFIX))
+        })
+        .filter(|entry| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    now.duration_since(modified).unwrap_or_default() > SKIN_UPLOAD.ttl
+                })
+        })
+        .filter(|entry| std::fs::remove_file(entry.path()).is_ok())
+        .count()
+}
@@ -0,0 +1,189 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fmt::Write,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use reqwest::{Response, StatusCode, Url};
+
+use crate::HTTP;
+
+/// Resilience of upstream http calls, the player apis and skin and map downloads.
+/// - `UPSTREAM_CONNECT_TIMEOUT_SECS` (3 is default) and `UPSTREAM_REQUEST_TIMEOUT_SECS`
+///   (30 is default) of the shared http client
+/// - `UPSTREAM_HOST_TIMEOUTS` comma separated `host:secs` until a host's response must start,
+///   e.g. `ddstats.tw:2,skins.tw:3`, other hosts only have the request timeout
+/// - `UPSTREAM_BREAKER_FAILURES` failures in a row that open the circuit of a host (5 is default)
+/// - `UPSTREAM_BREAKER_OPEN_SECS` how long requests to an open circuit fail immediately (30 is default)
+pub struct UpstreamConfig {
+    pub connect_timeout: Duration,
+    pub request_timeout: Duration,
+    host_timeouts: HashMap<String, Duration>,
+    breaker_failures: u32,
+    breaker_open: Duration,
+}
+
+pub static UPSTREAM: LazyLock<UpstreamConfig> = LazyLock::new(|| {
+    let secs = |var: &str, default: u64| -> u64 {
+        std::env::var(var)
+            .map_err(|err| anyhow!(err))
+            .and_then(|s| s.parse::<u64>().map_err(|err| anyhow!(err)))
+            .unwrap_or(default)
+    };
+    let host_timeouts = std::env::var("UPSTREAM_HOST_TIMEOUTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let (host, secs) = entry.trim().rsplit_once(':')?;
+            let secs = secs.parse::<u64>().ok()?;
+            Some((host.to_ascii_lowercase(), Duration::from_secs(secs)))
+        })
+        .collect();
+    UpstreamConfig {
+        connect_timeout: Duration::from_secs(secs("UPSTREAM_CONNECT_TIMEOUT_SECS", 3)),
+        request_timeout: Duration::from_secs(secs("UPSTREAM_REQUEST_TIMEOUT_SECS", 30)),
+        host_timeouts,
+        breaker_failures: secs("UPSTREAM_BREAKER_FAILURES", 5).max(1) as u32,
+        breaker_open: Duration::from_secs(secs("UPSTREAM_BREAKER_OPEN_SECS", 30)),
+    }
+});
+
+/// The retry waits this long plus up to the same again.
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Retries of transient errors.
+pub static RETRIES: AtomicU64 = AtomicU64::new(0);
+/// Requests that failed immediately because the circuit of the host was open.
+pub static SHORT_CIRCUITS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default)]
+struct Breaker {
+    /// Failures in a row
+    failures: u32,
+    open_until: Option<Instant>,
+}
+
+static BREAKERS: LazyLock<parking_lot::Mutex<BTreeMap<String, Breaker>>> =
+    LazyLock::new(Default::default);
+
+/// Whether requests to the host fail immediately.
+/// After the open time one request is let through, its failure opens the circuit again.
+fn is_open(host: &str, now: Instant) -> bool {
+    BREAKERS
+        .lock()
+        .get(host)
+        .and_then(|breaker| breaker.open_until)
+        .is_some_and(|open_until| now < open_until)
+}
+
+fn record(host: &str, success: bool, now: Instant) {
+    let mut breakers = BREAKERS.lock();
+    let breaker = breakers.entry(host.to_string()).or_default();
+    if success {
+        *breaker = Breaker::default();
+        return;
+    }
+    breaker.failures += 1;
+    if breaker.failures >= UPSTREAM.breaker_failures {
+        if !breaker
+            .open_until
+            .is_some_and(|open_until| now < open_until)
+        {
+            println!(
+                "circuit of {host} opened after {} failures",
+                breaker.failures
+            );
+        }
+        breaker.open_until = Some(now + UPSTREAM.breaker_open);
+    }
+}
+
+/// Server errors and rate limits may pass, unlike other statuses.
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+fn jittered_backoff() -> Duration {
+    let jitter = RandomState::new().build_hasher().finish() % 1000;
+    RETRY_BACKOFF + RETRY_BACKOFF * jitter as u32 / 1000
+}
+
+/// Sends a get request to `url`. Connection errors, timeouts and transient statuses
+/// are retried once with jittered backoff and count as failure of the host's circuit.
+/// Other statuses are returned, e.g. a 404 is a valid answer.
+pub async fn get(url: &str) -> anyhow::Result<Response> {
+    let host = Url::parse(url)?
+        .host_str()
+        .map(|host| host.to_ascii_lowercase())
+        .ok_or_else(|| anyhow!("{url} has no host"))?;
+    if is_open(&host, Instant::now()) {
+        SHORT_CIRCUITS.fetch_add(1, Ordering::Relaxed);
+        return Err(anyhow!("{host} is unavailable, retrying later"));
+    }
+    let timeout = UPSTREAM
+        .host_timeouts
+        .get(&host)
+        .copied()
+        .unwrap_or(UPSTREAM.request_timeout);
+
+    let mut retried = false;
+    loop {
+        let res = tokio::time::timeout(timeout, HTTP.get(url).send())
+            .await
+            .map_err(|_| anyhow!("{host} timed out after {}s", timeout.as_secs_f64()))
+            .and_then(|res| res.map_err(|err| anyhow!(err)))
+            .and_then(|res| {
+                if is_transient(res.status()) {
+                    Err(anyhow!("{host} responded {}", res.status()))
+                } else {
+                    Ok(res)
+                }
+            });
+        match res {
+            Err(_) if !retried => {
+                retried = true;
+                RETRIES.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(jittered_backoff()).await;
+            }
+            res => {
+                record(&host, res.is_ok(), Instant::now());
+                return res;
+            }
+        }
+    }
+}
+
+/// `upstream_circuit_open{host}` for every requested host and the retry counters.
+pub fn write_metrics(out: &mut String) {
+    let now = Instant::now();
+    let _ = writeln!(
+        out,
+        "# HELP upstream_circuit_open Whether requests to the host fail immediately\n\
+        # TYPE upstream_circuit_open gauge"
+    );
+    for (host, breaker) in BREAKERS.lock().iter() {
+        let open = breaker
+            .open_until
+            .is_some_and(|open_until| now < open_until);
+        let _ = writeln!(
+            out,
+            "upstream_circuit_open{{host=\"{host}\"}} {}",
+            u8::from(open)
+        );
+    }
+    let _ = write!(
+        out,
+        "# TYPE upstream_retries_total counter\n\
+        upstream_retries_total {}\n\
+        # TYPE upstream_short_circuits_total counter\n\
+        upstream_short_circuits_total {}\n",
+        RETRIES.load(Ordering::Relaxed),
+        SHORT_CIRCUITS.load(Ordering::Relaxed),
+    );
+}
@@ -0,0 +1,76 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use axum::Json;
+use client_containers::{
+    emoticons::EMOTICONS_CONTAINER_PATH, hooks::HOOK_CONTAINER_PATH, weapons::WEAPON_CONTAINER_PATH,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{gpu, maps, skins};
+
+/// The map the client loads at startup.
+pub const DEFAULT_MAP: &str = "ctf1";
+
+/// The git commit of the build, embedded by `build.rs`.
+pub const GIT_COMMIT: &str = env!("GIT_COMMIT");
+
+/// The first 7 characters of [`GIT_COMMIT`], e.g. for error messages.
+pub fn short_commit() -> &'static str {
+    GIT_COMMIT.get(..7).unwrap_or(GIT_COMMIT)
+}
+
+/// The sha256 of the default map file, hashed once.
+fn default_map_hash() -> Option<String> {
+    static HASH: OnceLock<Option<String>> = OnceLock::new();
+    HASH.get_or_init(|| {
+        let map = std::fs::read(maps::disk_path(DEFAULT_MAP)).ok()?;
+        Some(hex::encode(Sha256::digest(map)))
+    })
+    .clone()
+}
+
+/// Assets of a container are directories or single png files, like skins.
+fn count_assets(container_path: &Path) -> usize {
+    let data_dir = std::env::var("DATA_DIR").unwrap_or_else(|_| "data".to_string());
+    let Ok(entries) = std::fs::read_dir(PathBuf::from(data_dir).join(container_path)) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            let path = entry.path();
+            path.is_dir() || path.extension().is_some_and(|ext| ext == "png")
+        })
+        .count()
+}
+
+/// `GET /version`, the build, the graphics backend in use and the assets on disk.
+pub async fn version() -> Json<serde_json::Value> {
+    let skins = skins::skin_index().await.skins().count();
+    let (default_map_hash, emoticons, weapons, hooks) = tokio::task::spawn_blocking(|| {
+        (
+            default_map_hash(),
+            count_assets(EMOTICONS_CONTAINER_PATH.as_ref()),
+            count_assets(WEAPON_CONTAINER_PATH.as_ref()),
+            count_assets(HOOK_CONTAINER_PATH.as_ref()),
+        )
+    })
+    .await
+    .unwrap_or_default();
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "commit": GIT_COMMIT,
+        "built_at": env!("BUILD_TIMESTAMP").parse::<u64>().ok(),
+        "graphics": gpu::info(),
+        "default_map": { "name": DEFAULT_MAP, "sha256": default_map_hash },
+        "assets": {
+            "skins": skins,
+            "emoticons": emoticons,
+            "weapons": weapons,
+            "hooks": hooks,
+        },
+    }))
+}